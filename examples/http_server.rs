@@ -0,0 +1,28 @@
+use std::net::TcpListener;
+
+use uvc::{Context, DeviceQuery, FormatSelector, FrameFormat};
+
+fn main() {
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "0.0.0.0:8080".to_string());
+
+    let ctx = Context::new().expect("Could not create context");
+    let dev = ctx
+        .find(&DeviceQuery::new())
+        .expect("Could not find device");
+    let devh = dev.open().expect("Could not open device");
+
+    // Prefer MJPEG, which is sent without encoding the frames again
+    let format = FormatSelector::new()
+        .format(FrameFormat::MJPEG)
+        .format(FrameFormat::Any)
+        .best(&devh)
+        .expect("Could not find a format");
+
+    let listener = TcpListener::bind(&address).expect("Could not listen");
+    println!("Serving {:?} on http://{}", format, address);
+    uvc::HttpServer::new(&devh, format)
+        .serve(listener)
+        .expect("Server failed");
+}
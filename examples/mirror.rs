@@ -4,7 +4,7 @@ use std::error::Error;
 use std::sync::{Arc, Mutex};
 
 use glium::Surface;
-use uvc::{Context, Frame};
+use uvc::{Context, DeviceQuery, FormatSelector, Frame, FrameFormat};
 
 fn frame_to_raw_image(
     frame: &Frame,
@@ -37,7 +37,7 @@ fn callback_frame_to_image(
 fn main() {
     let ctx = Context::new().expect("Could not create context");
     let dev = ctx
-        .find_device(None, None, None)
+        .find(&DeviceQuery::new())
         .expect("Could not find device");
 
     let description = dev.description().unwrap();
@@ -59,15 +59,14 @@ fn main() {
 
     let devh = dev.open().expect("Could not open device");
 
-    let format = devh
-        .get_preferred_format(|x, y| {
-            if x.fps >= y.fps && x.width * x.height >= y.width * y.height {
-                x
-            } else {
-                y
-            }
-        })
-        .unwrap();
+    // Only formats `to_rgb` can convert
+    let format = FormatSelector::new()
+        .format(FrameFormat::MJPEG)
+        .format(FrameFormat::YUYV)
+        .format(FrameFormat::UYVY)
+        .min_fps(15)
+        .best(&devh)
+        .expect("No usable format");
 
     println!("Best format found: {:?}", format);
     let mut streamh = devh.get_stream_handle_with_format(format).unwrap();
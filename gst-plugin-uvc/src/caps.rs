@@ -0,0 +1,265 @@
+use uvc::{Backend, FrameFormat, FrameInterval, FrameIntervals, StreamFormat};
+
+/// Media type and `format` field of the caps of every format GStreamer can carry
+///
+/// Formats without an entry, such as those with an unknown GUID, are not offered.
+const FORMATS: &[(FrameFormat, &str, Option<&str>)] = &[
+    (FrameFormat::MJPEG, "image/jpeg", None),
+    (FrameFormat::H264, "video/x-h264", None),
+    (FrameFormat::HEVC, "video/x-h265", None),
+    (FrameFormat::YUYV, "video/x-raw", Some("YUY2")),
+    (FrameFormat::UYVY, "video/x-raw", Some("UYVY")),
+    (FrameFormat::NV12, "video/x-raw", Some("NV12")),
+    (FrameFormat::I420, "video/x-raw", Some("I420")),
+    (FrameFormat::YV12, "video/x-raw", Some("YV12")),
+    (FrameFormat::P010, "video/x-raw", Some("P010_10LE")),
+    (FrameFormat::RGB, "video/x-raw", Some("RGB")),
+    (FrameFormat::BGR, "video/x-raw", Some("BGR")),
+    (FrameFormat::RGBP, "video/x-raw", Some("RGB16")),
+    (FrameFormat::GRAY8, "video/x-raw", Some("GRAY8")),
+    (FrameFormat::GRAY16, "video/x-raw", Some("GRAY16_LE")),
+    (FrameFormat::SGRBG8, "video/x-bayer", Some("grbg")),
+    (FrameFormat::SGBRG8, "video/x-bayer", Some("gbrg")),
+    (FrameFormat::SRGGB8, "video/x-bayer", Some("rggb")),
+    (FrameFormat::SBGGR8, "video/x-bayer", Some("bggr")),
+];
+
+/// Caps structure of `format`, without size and rate
+fn builder(format: FrameFormat) -> Option<gst::structure::Builder> {
+    let &(_, name, field) = FORMATS.iter().find(|(known, ..)| *known == format)?;
+    let mut builder = gst::Structure::builder(name);
+    if let Some(field) = field {
+        builder = builder.field("format", field);
+    }
+    if format == FrameFormat::H264 || format == FrameFormat::HEVC {
+        builder = builder
+            .field("stream-format", "byte-stream")
+            .field("alignment", "au");
+    }
+    Some(builder)
+}
+
+fn fraction(interval: FrameInterval) -> gst::Fraction {
+    let (numerator, denominator) = interval.rate();
+    gst::Fraction::new(numerator as i32, denominator as i32)
+}
+
+/// Every format GStreamer can carry, in any size and at any rate
+pub fn template() -> gst::Caps {
+    let mut caps = gst::Caps::new_empty();
+    let caps_mut = caps.get_mut().unwrap();
+    for &(format, ..) in FORMATS {
+        if let Some(builder) = builder(format) {
+            caps_mut.append_structure(
+                builder
+                    .field("width", gst::IntRange::new(1, i32::from(u16::MAX)))
+                    .field("height", gst::IntRange::new(1, i32::from(u16::MAX)))
+                    .field(
+                        "framerate",
+                        gst::FractionRange::new(
+                            gst::Fraction::new(0, 1),
+                            gst::Fraction::new(i32::MAX, 1),
+                        ),
+                    )
+                    .build(),
+            );
+        }
+    }
+    caps
+}
+
+/// Formats, sizes and rates the camera offers, in the order of its descriptors
+///
+/// Discrete intervals are listed as exact fractions, such as `30000/1001`,
+/// continuous ranges become a fraction range. A camera whose descriptors
+/// can not be read offers nothing.
+pub fn device<B: Backend + ?Sized>(camera: &B) -> gst::Caps {
+    let mut caps = gst::Caps::new_empty();
+    let caps_mut = caps.get_mut().unwrap();
+    for format_desc in camera.descriptors().unwrap_or_default() {
+        for frame_desc in format_desc.frames() {
+            let Some(builder) = builder(format_desc.frame_format()) else {
+                continue;
+            };
+            let builder = builder
+                .field("width", i32::from(frame_desc.width()))
+                .field("height", i32::from(frame_desc.height()));
+            let structure = match frame_desc.frame_intervals() {
+                FrameIntervals::Discrete([]) => continue,
+                FrameIntervals::Discrete(&[interval]) => builder
+                    .field("framerate", fraction(FrameInterval(interval)))
+                    .build(),
+                FrameIntervals::Discrete(intervals) => builder
+                    .field(
+                        "framerate",
+                        gst::List::new(
+                            intervals
+                                .iter()
+                                .map(|&interval| fraction(FrameInterval(interval))),
+                        ),
+                    )
+                    .build(),
+                // The longest interval is the lowest rate
+                FrameIntervals::Continuous { min, max, .. } => builder
+                    .field(
+                        "framerate",
+                        gst::FractionRange::new(
+                            fraction(FrameInterval(max)),
+                            fraction(FrameInterval(min)),
+                        ),
+                    )
+                    .build(),
+            };
+            caps_mut.append_structure(structure);
+        }
+    }
+    caps
+}
+
+/// Stream format requested by fixed caps
+pub fn stream_format(caps: &gst::CapsRef) -> Option<StreamFormat> {
+    let structure = caps.structure(0)?;
+    let field = structure.get::<&str>("format").ok();
+    let &(format, ..) = FORMATS.iter().find(|&&(_, name, known)| {
+        structure.name() == name && (known.is_none() || known == field)
+    })?;
+    let framerate = structure.get::<gst::Fraction>("framerate").ok()?;
+    Some(StreamFormat {
+        width: u32::try_from(structure.get::<i32>("width").ok()?).ok()?,
+        height: u32::try_from(structure.get::<i32>("height").ok()?).ok()?,
+        interval: FrameInterval::from_rate(
+            u32::try_from(framerate.numer()).ok()?,
+            u32::try_from(framerate.denom()).ok()?,
+        ),
+        format,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use gst_check::Harness;
+    use uvc::{FormatDescription, FrameDescription, Guid, VirtualCamera};
+
+    use super::*;
+
+    fn fps(fps: u32) -> gst::Fraction {
+        fraction(FrameInterval::from_fps(fps))
+    }
+
+    fn ntsc() -> gst::Fraction {
+        fraction(FrameInterval::from_rate(30000, 1001))
+    }
+
+    /// Camera offering MJPEG at two rates, NV12 at a continuous range and a format of unknown GUID
+    fn camera() -> VirtualCamera {
+        VirtualCamera::new()
+            .descriptor(
+                FormatDescription::new(FrameFormat::MJPEG)
+                    .with_frame(FrameDescription::discrete(
+                        1280,
+                        720,
+                        [
+                            FrameInterval::from_fps(30),
+                            FrameInterval::from_rate(30000, 1001),
+                        ],
+                    ))
+                    .with_frame(FrameDescription::discrete(
+                        640,
+                        480,
+                        [FrameInterval::from_fps(60)],
+                    )),
+            )
+            .descriptor(FormatDescription::new(FrameFormat::NV12).with_frame(
+                FrameDescription::continuous(
+                    320,
+                    240,
+                    FrameInterval::from_fps(120),
+                    FrameInterval::from_fps(5),
+                    1,
+                ),
+            ))
+            .descriptor(
+                FormatDescription::new(FrameFormat::Uncompressed)
+                    .with_guid(Guid::from_fourcc(*b"ABCD"))
+                    .with_frame(FrameDescription::discrete(
+                        64,
+                        64,
+                        [FrameInterval::from_fps(30)],
+                    )),
+            )
+    }
+
+    #[test]
+    fn device_caps() {
+        gst_check::init().unwrap();
+        let caps = device(&camera());
+        // The format of unknown GUID is left out
+        let expected = gst::Caps::builder_full()
+            .structure(
+                gst::Structure::builder("image/jpeg")
+                    .field("width", 1280)
+                    .field("height", 720)
+                    .field("framerate", gst::List::new([fps(30), ntsc()]))
+                    .build(),
+            )
+            .structure(
+                gst::Structure::builder("image/jpeg")
+                    .field("width", 640)
+                    .field("height", 480)
+                    .field("framerate", fps(60))
+                    .build(),
+            )
+            .structure(
+                gst::Structure::builder("video/x-raw")
+                    .field("format", "NV12")
+                    .field("width", 320)
+                    .field("height", 240)
+                    .field("framerate", gst::FractionRange::new(fps(5), fps(120)))
+                    .build(),
+            )
+            .build();
+        assert_eq!(caps, expected);
+        assert!(caps.is_subset(&template()));
+    }
+
+    #[test]
+    fn negotiated_caps_select_a_format() {
+        gst_check::init().unwrap();
+        let caps = device(&camera());
+        let mut raw = caps.intersect(&gst::Caps::builder("video/x-raw").build());
+        raw.fixate();
+
+        // Negotiated through an element, as downstream of the source
+        let mut harness = Harness::new_parse("capsfilter caps=video/x-raw");
+        harness.play();
+        harness.set_src_caps(raw.clone());
+        harness.push(gst::Buffer::new()).unwrap();
+        let negotiated = harness.sinkpad().unwrap().current_caps().unwrap();
+        assert_eq!(negotiated, raw);
+        assert_eq!(
+            stream_format(&negotiated),
+            Some(StreamFormat {
+                width: 320,
+                height: 240,
+                interval: FrameInterval::from_fps(5),
+                format: FrameFormat::NV12,
+            })
+        );
+
+        let mjpeg = gst::Caps::builder("image/jpeg")
+            .field("width", 1280)
+            .field("height", 720)
+            .field("framerate", ntsc())
+            .build();
+        assert!(mjpeg.is_subset(&caps));
+        assert_eq!(
+            stream_format(&mjpeg),
+            Some(StreamFormat {
+                width: 1280,
+                height: 720,
+                interval: FrameInterval::from_rate(30000, 1001),
+                format: FrameFormat::MJPEG,
+            })
+        );
+    }
+}
@@ -0,0 +1,25 @@
+//! GStreamer plugin with `uvcrssrc`, a source streaming UVC cameras through `libuvc`
+//!
+//! ```text
+//! gst-launch-1.0 uvcrssrc vendor-id=0x046d ! image/jpeg,width=1280,height=720 ! jpegdec ! autovideosink
+//! ```
+use gst::glib;
+
+mod caps;
+mod uvcrssrc;
+
+fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    uvcrssrc::register(plugin)
+}
+
+gst::plugin_define!(
+    uvcrs,
+    env!("CARGO_PKG_DESCRIPTION"),
+    plugin_init,
+    concat!(env!("CARGO_PKG_VERSION"), "-", env!("COMMIT_ID")),
+    "MIT/X11",
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_REPOSITORY"),
+    env!("BUILD_REL_DATE")
+);
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::Duration;
+
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_base::prelude::*;
+use gst_base::subclass::base_src::CreateSuccess;
+use gst_base::subclass::prelude::*;
+
+use uvc::{
+    Backend, Control, DeviceQuery, Overflow, OwnedContext, OwnedDeviceHandle, OwnedFrame,
+    OwnedStream, Receiver, StreamFormat,
+};
+
+use crate::caps;
+
+static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+    gst::DebugCategory::new(
+        "uvcrssrc",
+        gst::DebugColorFlags::empty(),
+        Some("UVC source through libuvc"),
+    )
+});
+
+/// Frames queued between the stream callback and `create`
+const QUEUE: usize = 4;
+/// How often a waiting `create` checks whether it should give up
+const POLL: Duration = Duration::from_millis(100);
+/// How often a stream checks whether its device was unplugged, ending the pipeline with an error
+const UNPLUG_POLL: Duration = Duration::from_millis(500);
+
+/// Name of the property of a control, such as `exposure-abs`
+fn property_name(control: Control) -> String {
+    control.name().replace('_', "-")
+}
+
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    vendor_id: u32,
+    product_id: u32,
+    serial: Option<String>,
+    /// Values of the controls set through properties, applied when the device opens
+    controls: HashMap<Control, i64>,
+}
+
+struct State {
+    // Declared first, so the stream stops before the handle may close
+    stream: Option<(OwnedStream, StreamFormat)>,
+    devh: OwnedDeviceHandle,
+}
+
+/// Frames are received outside the state lock, `create` blocks on them
+type Frames = Option<Receiver<OwnedFrame>>;
+
+/// Image data of a frame, returned to its pool when the buffer is freed
+struct FrameMemory(OwnedFrame);
+
+impl AsRef<[u8]> for FrameMemory {
+    fn as_ref(&self) -> &[u8] {
+        self.0.to_bytes()
+    }
+}
+
+#[derive(Default)]
+pub struct UvcRsSrc {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+    frames: Mutex<Frames>,
+    flushing: AtomicBool,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl UvcRsSrc {
+    fn open(&self) -> Result<OwnedDeviceHandle, gst::ErrorMessage> {
+        let settings = lock(&self.settings).clone();
+        let mut query = DeviceQuery::new();
+        if let Ok(vendor_id) = u16::try_from(settings.vendor_id) {
+            if vendor_id != 0 {
+                query = query.vendor_id(vendor_id);
+            }
+        }
+        if let Ok(product_id) = u16::try_from(settings.product_id) {
+            if product_id != 0 {
+                query = query.product_id(product_id);
+            }
+        }
+        if let Some(serial) = &settings.serial {
+            query = query.serial_number(serial);
+        }
+
+        let ctx = OwnedContext::new().map_err(|err| {
+            gst::error_msg!(
+                gst::LibraryError::Init,
+                ["Could not create context: {}", err]
+            )
+        })?;
+        let device = ctx.find(&query).map_err(|err| {
+            gst::error_msg!(
+                gst::ResourceError::NotFound,
+                ["Could not find device: {}", err]
+            )
+        })?;
+        // libuvc detaches the kernel driver from the interface it claims
+        let devh = device
+            .open()
+            .map_err(|err| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenRead,
+                    ["Could not open device: {}", err]
+                )
+            })?
+            .detect_unplug(UNPLUG_POLL);
+
+        for (&control, &value) in &settings.controls {
+            if let Err(err) = devh.handle().set_control(control, value) {
+                gst::warning!(CAT, imp = self, "Could not set {}: {}", control.name(), err);
+            }
+        }
+        Ok(devh)
+    }
+
+    fn stop_stream(&self) {
+        *lock(&self.frames) = None;
+        if let Some(state) = lock(&self.state).as_mut() {
+            state.stream = None;
+        }
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for UvcRsSrc {
+    const NAME: &'static str = "GstUvcRsSrc";
+    type Type = super::UvcRsSrc;
+    type ParentType = gst_base::PushSrc;
+}
+
+impl ObjectImpl for UvcRsSrc {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+            let mut properties = vec![
+                glib::ParamSpecUInt::builder("vendor-id")
+                    .nick("Vendor id")
+                    .blurb("USB vendor id of the device, 0 for any")
+                    .maximum(u32::from(u16::MAX))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("product-id")
+                    .nick("Product id")
+                    .blurb("USB product id of the device, 0 for any")
+                    .maximum(u32::from(u16::MAX))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("serial")
+                    .nick("Serial number")
+                    .blurb("Glob the serial number of the device must match")
+                    .mutable_ready()
+                    .build(),
+            ];
+            properties.extend(Control::ALL.into_iter().map(|control| {
+                glib::ParamSpecInt64::builder(&property_name(control))
+                    .nick(control.name())
+                    .blurb("Value of the camera control, read from the device once open")
+                    .mutable_playing()
+                    .build()
+            }));
+            properties
+        });
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        let mut settings = lock(&self.settings);
+        match pspec.name() {
+            "vendor-id" => settings.vendor_id = value.get().expect("type checked upstream"),
+            "product-id" => settings.product_id = value.get().expect("type checked upstream"),
+            "serial" => settings.serial = value.get().expect("type checked upstream"),
+            name => {
+                let Some(control) = Control::ALL
+                    .into_iter()
+                    .find(|&control| property_name(control) == name)
+                else {
+                    unreachable!("unknown property {name}")
+                };
+                let value = value.get().expect("type checked upstream");
+                settings.controls.insert(control, value);
+                drop(settings);
+                if let Some(state) = lock(&self.state).as_ref() {
+                    if let Err(err) = state.devh.handle().set_control(control, value) {
+                        gst::warning!(CAT, imp = self, "Could not set {}: {}", control.name(), err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        let settings = lock(&self.settings);
+        match pspec.name() {
+            "vendor-id" => settings.vendor_id.to_value(),
+            "product-id" => settings.product_id.to_value(),
+            "serial" => settings.serial.to_value(),
+            name => {
+                let Some(control) = Control::ALL
+                    .into_iter()
+                    .find(|&control| property_name(control) == name)
+                else {
+                    unreachable!("unknown property {name}")
+                };
+                let current = lock(&self.state)
+                    .as_ref()
+                    .and_then(|state| state.devh.handle().control(control).ok());
+                current
+                    .or_else(|| settings.controls.get(&control).copied())
+                    .unwrap_or_default()
+                    .to_value()
+            }
+        }
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+        let obj = self.obj();
+        obj.set_live(true);
+        obj.set_format(gst::Format::Time);
+    }
+}
+
+impl GstObjectImpl for UvcRsSrc {}
+
+impl ElementImpl for UvcRsSrc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "UVC Source",
+                "Source/Video",
+                "Streams a UVC camera through libuvc, without the kernel driver",
+                "Magnus Ulimoen <flymagnus@gmail.com>",
+            )
+        });
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+            vec![gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps::template(),
+            )
+            .unwrap()]
+        });
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BaseSrcImpl for UvcRsSrc {
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        let devh = self.open()?;
+        gst::info!(CAT, imp = self, "Opened {:?}", devh.handle());
+        *lock(&self.state) = Some(State { stream: None, devh });
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        self.stop_stream();
+        *lock(&self.state) = None;
+        Ok(())
+    }
+
+    fn caps(&self, filter: Option<&gst::Caps>) -> Option<gst::Caps> {
+        let caps = match lock(&self.state).as_ref() {
+            Some(state) => caps::device(state.devh.handle()),
+            None => self.obj().src_pad().pad_template_caps(),
+        };
+        Some(match filter {
+            Some(filter) => filter.intersect_with_mode(&caps, gst::CapsIntersectMode::First),
+            None => caps,
+        })
+    }
+
+    fn set_caps(&self, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        let format = caps::stream_format(caps)
+            .ok_or_else(|| gst::loggable_error!(CAT, "Unsupported caps {}", caps))?;
+        self.stop_stream();
+
+        let mut state = lock(&self.state);
+        let state = state
+            .as_mut()
+            .ok_or_else(|| gst::loggable_error!(CAT, "Device not open"))?;
+        let (stream, frames) = state
+            .devh
+            .start_stream_channel(format, QUEUE, Overflow::DropOldest)
+            .map_err(|err| gst::loggable_error!(CAT, "Could not start {:?}: {}", format, err))?;
+        gst::info!(CAT, imp = self, "Streaming {:?}", format);
+        state.stream = Some((stream, format));
+        *lock(&self.frames) = Some(frames);
+        Ok(())
+    }
+
+    fn query(&self, query: &mut gst::QueryRef) -> bool {
+        if let gst::QueryViewMut::Latency(query) = query.view_mut() {
+            let state = lock(&self.state);
+            let Some((_, format)) = state.as_ref().and_then(|state| state.stream.as_ref()) else {
+                return false;
+            };
+            let interval =
+                gst::ClockTime::from_nseconds(format.interval.as_duration().as_nanos() as u64);
+            query.set(true, interval, gst::ClockTime::NONE);
+            return true;
+        }
+        BaseSrcImplExt::parent_query(self, query)
+    }
+
+    fn unlock(&self) -> Result<(), gst::ErrorMessage> {
+        self.flushing.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn unlock_stop(&self) -> Result<(), gst::ErrorMessage> {
+        self.flushing.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl PushSrcImpl for UvcRsSrc {
+    fn create(
+        &self,
+        _buffer: Option<&mut gst::BufferRef>,
+    ) -> Result<CreateSuccess, gst::FlowError> {
+        let frame = {
+            let frames = lock(&self.frames);
+            let frames = frames.as_ref().ok_or(gst::FlowError::NotNegotiated)?;
+            loop {
+                if self.flushing.load(Ordering::SeqCst) {
+                    return Err(gst::FlowError::Flushing);
+                }
+                match frames.recv_timeout(POLL) {
+                    Ok(frame) => break frame,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        gst::element_imp_error!(
+                            self,
+                            gst::ResourceError::Read,
+                            ["The stream stopped, the device may be disconnected"]
+                        );
+                        return Err(gst::FlowError::Error);
+                    }
+                }
+            }
+        };
+
+        let interval = lock(&self.state)
+            .as_ref()
+            .and_then(|state| state.stream.as_ref())
+            .map(|(_, format)| format.interval.as_duration());
+        // Stamped when the device finished the frame, rather than when it got here
+        let pts = self.obj().current_running_time().map(|now| {
+            let latency = frame.capture_latency().unwrap_or_default();
+            now.saturating_sub(gst::ClockTime::from_nseconds(latency.as_nanos() as u64))
+        });
+
+        let sequence = frame.sequence();
+        let mut buffer = gst::Buffer::from_slice(FrameMemory(frame));
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(pts);
+            buffer.set_duration(
+                interval.map(|interval| gst::ClockTime::from_nseconds(interval.as_nanos() as u64)),
+            );
+            buffer.set_offset(u64::from(sequence));
+        }
+        Ok(CreateSuccess::NewBuffer(buffer))
+    }
+}
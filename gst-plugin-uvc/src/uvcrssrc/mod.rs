@@ -0,0 +1,19 @@
+use gst::glib;
+use gst::prelude::*;
+
+mod imp;
+
+glib::wrapper! {
+    /// Source streaming a UVC camera through `libuvc`
+    pub struct UvcRsSrc(ObjectSubclass<imp::UvcRsSrc>)
+        @extends gst_base::PushSrc, gst_base::BaseSrc, gst::Element, gst::Object;
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "uvcrssrc",
+        gst::Rank::NONE,
+        UvcRsSrc::static_type(),
+    )
+}
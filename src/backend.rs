@@ -0,0 +1,585 @@
+use uvc_sys::*;
+
+use crate::color::ColorMatching;
+use crate::device::{DeviceHandle, FrameIntervals, UsbSpeed};
+use crate::error::{Error, ErrorKind, Operation, Result};
+use crate::formats::{FrameFormat, FrameInterval, Guid, StreamFormat};
+use crate::frame::Frame;
+use crate::interlace::Interlace;
+use crate::stats::StreamStats;
+use crate::streaming::{
+    start_streaming, ActiveStream, ChannelStream, PanicPolicy, StreamOptions, StreamStatus,
+};
+use crate::usb::UsbDevice;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Control which can be read and written through a `Backend`
+///
+/// Values are the raw values of the UVC request, enumerated controls
+/// such as `AeMode` use the codes of the specification.
+pub enum Control {
+    ScanningMode,
+    AeMode,
+    AePriority,
+    ExposureAbs,
+    ExposureRel,
+    FocusAbs,
+    Brightness,
+    Contrast,
+    Gain,
+}
+
+impl Control {
+    /// Every control, in declaration order
+    pub const ALL: [Control; 9] = [
+        Control::ScanningMode,
+        Control::AeMode,
+        Control::AePriority,
+        Control::ExposureAbs,
+        Control::ExposureRel,
+        Control::FocusAbs,
+        Control::Brightness,
+        Control::Contrast,
+        Control::Gain,
+    ];
+
+    /// Name used in errors
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Control::ScanningMode => "scanning_mode",
+            Control::AeMode => "ae_mode",
+            Control::AePriority => "ae_priority",
+            Control::ExposureAbs => "exposure_abs",
+            Control::ExposureRel => "exposure_rel",
+            Control::FocusAbs => "focus_abs",
+            Control::Brightness => "brightness",
+            Control::Contrast => "contrast",
+            Control::Gain => "gain",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Values a control accepts, as reported by `GET_MIN`, `GET_MAX`, `GET_RES` and `GET_DEF`
+pub struct ControlRange {
+    pub min: i64,
+    pub max: i64,
+    pub step: i64,
+    pub default: i64,
+}
+
+impl ControlRange {
+    /// Whether `value` lies within the range and on a step
+    #[must_use]
+    pub fn contains(&self, value: i64) -> bool {
+        (self.min..=self.max).contains(&value)
+            && (self.step <= 1 || (value - self.min) % self.step == 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Owned copy of a format descriptor and its frame descriptors
+///
+/// Unlike `FormatDescriptor` it doesn't borrow from a device handle, so
+/// a `VirtualCamera` can be given the descriptors of a real camera.
+///
+/// ```
+/// use uvc::{FormatDescription, FrameDescription, FrameFormat, FrameInterval};
+///
+/// let format = FormatDescription::new(FrameFormat::NV12)
+///     .with_frame(FrameDescription::discrete(
+///         1920,
+///         1080,
+///         [FrameInterval::from_fps(30), FrameInterval::from_fps(15)],
+///     ))
+///     .with_frame(FrameDescription::continuous(
+///         640,
+///         480,
+///         FrameInterval::from_fps(60),
+///         FrameInterval::from_fps(5),
+///         1,
+///     ));
+/// assert_eq!(format.frames()[1].frame_index(), 2);
+/// assert_eq!(format.guid().and_then(|guid| guid.fourcc()), Some(*b"NV12"));
+/// ```
+pub struct FormatDescription {
+    format_index: u8,
+    format: FrameFormat,
+    guid: Option<Guid>,
+    color_matching: ColorMatching,
+    interlace: Interlace,
+    frames: Vec<FrameDescription>,
+}
+
+impl FormatDescription {
+    /// Format without frames, with the GUID `format` is sent with
+    ///
+    /// MJPEG has a format descriptor of its own, without a GUID.
+    #[must_use]
+    pub fn new(format: FrameFormat) -> Self {
+        FormatDescription {
+            format_index: 1,
+            format,
+            guid: match format {
+                FrameFormat::MJPEG => None,
+                _ => format.guid(),
+            },
+            color_matching: ColorMatching::default(),
+            interlace: Interlace::default(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Sends this GUID instead, such as one unknown to the crate
+    ///
+    /// Like `FormatDescriptor::frame_format`, the format becomes `FrameFormat::Uncompressed`
+    /// or `FrameFormat::FrameBased` if the GUID is unknown.
+    #[must_use]
+    pub fn with_guid(mut self, guid: Guid) -> Self {
+        self.guid = Some(guid);
+        self.format = guid
+            .frame_format()
+            .unwrap_or(if self.format.is_compressed() {
+                FrameFormat::FrameBased
+            } else {
+                FrameFormat::Uncompressed
+            });
+        self
+    }
+
+    /// Declares a colour space, as a colour matching descriptor does
+    #[must_use]
+    pub fn with_color_matching(mut self, color_matching: ColorMatching) -> Self {
+        self.color_matching = color_matching;
+        self
+    }
+
+    /// Declares interlaced frames
+    #[must_use]
+    pub fn with_interlace(mut self, interlace: Interlace) -> Self {
+        self.interlace = interlace;
+        self
+    }
+
+    /// Adds a frame size, numbered after the ones added before
+    #[must_use]
+    pub fn with_frame(mut self, mut frame: FrameDescription) -> Self {
+        frame.frame_index = self.frames.len() as u8 + 1;
+        self.frames.push(frame);
+        self
+    }
+
+    /// Index of this format, as used in a `StreamControl`
+    #[must_use]
+    pub fn format_index(&self) -> u8 {
+        self.format_index
+    }
+
+    /// GUID of uncompressed and frame based formats
+    #[must_use]
+    pub fn guid(&self) -> Option<Guid> {
+        self.guid
+    }
+
+    /// Format of the frames described
+    #[must_use]
+    pub fn frame_format(&self) -> FrameFormat {
+        self.format
+    }
+
+    /// Colour space of the format
+    #[must_use]
+    pub fn color_matching(&self) -> ColorMatching {
+        self.color_matching
+    }
+
+    /// Interlacing of the frames described
+    #[must_use]
+    pub fn interlace(&self) -> Interlace {
+        self.interlace
+    }
+
+    /// Frame sizes of the format
+    #[must_use]
+    pub fn frames(&self) -> &[FrameDescription] {
+        &self.frames
+    }
+
+    /// Adds the size and interval of `format`, to the discrete frame of that size if there is one
+    pub(crate) fn add_stream_format(&mut self, format: &StreamFormat) {
+        let (width, height) = (format.width as u16, format.height as u16);
+        let interval = format.interval.as_100ns();
+        for frame in &mut self.frames {
+            if let Intervals::Discrete(intervals) = &mut frame.intervals {
+                if frame.width == width && frame.height == height {
+                    if !intervals.contains(&interval) {
+                        intervals.push(interval);
+                    }
+                    return;
+                }
+            }
+        }
+        let mut frame = FrameDescription::discrete(width, height, [format.interval]);
+        frame.frame_index = self.frames.len() as u8 + 1;
+        self.frames.push(frame);
+    }
+
+    pub(crate) fn set_format_index(&mut self, format_index: u8) {
+        self.format_index = format_index;
+    }
+
+    /// Every frame size and interval, continuous ranges contribute their shortest and longest interval
+    pub(crate) fn stream_formats(&self) -> impl Iterator<Item = StreamFormat> + '_ {
+        let format = self.format;
+        self.frames.iter().flat_map(move |frame| {
+            let intervals = match frame.frame_intervals() {
+                FrameIntervals::Discrete(intervals) => intervals.to_vec(),
+                FrameIntervals::Continuous { min, max, .. } => vec![min, max],
+            };
+            intervals.into_iter().map(move |interval| StreamFormat {
+                width: u32::from(frame.width),
+                height: u32::from(frame.height),
+                interval: FrameInterval(interval),
+                format,
+            })
+        })
+    }
+
+    /// Whether a frame of this description has the size of `format`, and accepts its interval
+    pub(crate) fn offers(&self, format: &StreamFormat) -> bool {
+        self.format == format.format
+            && self.frames.iter().any(|frame| {
+                u32::from(frame.width) == format.width
+                    && u32::from(frame.height) == format.height
+                    && frame.frame_intervals().contains(format.interval)
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Intervals {
+    Discrete(Vec<u32>),
+    Continuous { min: u32, max: u32, step: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Owned copy of a frame descriptor, a frame size of a `FormatDescription`
+pub struct FrameDescription {
+    frame_index: u8,
+    width: u16,
+    height: u16,
+    default_interval: FrameInterval,
+    intervals: Intervals,
+}
+
+impl FrameDescription {
+    /// Frame size offered at a list of intervals, the first being the default
+    #[must_use]
+    pub fn discrete<I>(width: u16, height: u16, intervals: I) -> Self
+    where
+        I: IntoIterator<Item = FrameInterval>,
+    {
+        let intervals: Vec<u32> = intervals.into_iter().map(|x| x.as_100ns()).collect();
+        FrameDescription {
+            frame_index: 1,
+            width,
+            height,
+            default_interval: FrameInterval(intervals.first().copied().unwrap_or(0)),
+            intervals: Intervals::Discrete(intervals),
+        }
+    }
+
+    /// Frame size offered at any interval from `min` to `max`, in multiples of `step` above `min`
+    #[must_use]
+    pub fn continuous(
+        width: u16,
+        height: u16,
+        min: FrameInterval,
+        max: FrameInterval,
+        step: u32,
+    ) -> Self {
+        FrameDescription {
+            frame_index: 1,
+            width,
+            height,
+            default_interval: min,
+            intervals: Intervals::Continuous {
+                min: min.as_100ns(),
+                max: max.as_100ns(),
+                step,
+            },
+        }
+    }
+
+    #[must_use]
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    #[must_use]
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Index of this frame within its format, as used in a `StreamControl`
+    #[must_use]
+    pub fn frame_index(&self) -> u8 {
+        self.frame_index
+    }
+
+    /// Interval the device uses unless asked otherwise
+    #[must_use]
+    pub fn default_interval(&self) -> FrameInterval {
+        self.default_interval
+    }
+
+    /// Intervals the device accepts for this frame size
+    #[must_use]
+    pub fn frame_intervals(&self) -> FrameIntervals<'_> {
+        match &self.intervals {
+            Intervals::Discrete(intervals) => FrameIntervals::Discrete(intervals),
+            &Intervals::Continuous { min, max, step } => {
+                FrameIntervals::Continuous { min, max, step }
+            }
+        }
+    }
+}
+
+/// Boxed frame callback of a `Backend`
+pub type FrameCallback = Box<dyn FnMut(&Frame) + Send>;
+
+/// Running stream of a `Backend`
+///
+/// Dropping the stream stops it
+pub trait BackendStream {
+    /// Statistics of the frames delivered so far
+    fn stats(&self) -> StreamStats;
+    /// Whether the stream still delivers frames
+    fn status(&self) -> StreamStatus;
+    /// Blocks until the stream ended, returning the payload of a panic which ended it
+    fn join(self: Box<Self>) -> std::thread::Result<()>;
+    /// Runs `hook` once the stream ended, or right away if it already has
+    ///
+    /// The hook may run on the thread calling the frame callback, so it must not block.
+    fn on_end(&self, hook: Box<dyn FnOnce() + Send>);
+}
+
+impl<'a, U> BackendStream for ActiveStream<'a, U> {
+    fn stats(&self) -> StreamStats {
+        ActiveStream::stats(self)
+    }
+
+    fn status(&self) -> StreamStatus {
+        ActiveStream::status(self)
+    }
+
+    fn join(self: Box<Self>) -> std::thread::Result<()> {
+        ActiveStream::join(*self)
+    }
+
+    fn on_end(&self, hook: Box<dyn FnOnce() + Send>) {
+        ActiveStream::on_end(self, hook);
+    }
+}
+
+impl<'a> BackendStream for ChannelStream<'a> {
+    fn stats(&self) -> StreamStats {
+        ChannelStream::stats(self)
+    }
+
+    fn status(&self) -> StreamStatus {
+        ChannelStream::status(self)
+    }
+
+    fn join(self: Box<Self>) -> std::thread::Result<()> {
+        ChannelStream::join(*self)
+    }
+
+    fn on_end(&self, hook: Box<dyn FnOnce() + Send>) {
+        ChannelStream::on_end(self, hook);
+    }
+}
+
+/// Device and stream operations of an opened camera
+///
+/// Implemented by `DeviceHandle` for cameras reached through `libuvc`,
+/// and by `VirtualCamera` for tests which must run without hardware.
+/// Code written against this trait can be exercised with either.
+///
+/// # Safety
+///
+/// Once a stream returned by `start_stream` is dropped, its callback must have been
+/// dropped and must not be called anymore. `scope` relies on this to hand out
+/// callbacks which borrow from the stack.
+pub unsafe trait Backend {
+    /// Format and frame descriptors of the camera, in the order the camera lists them
+    fn descriptors(&self) -> Result<Vec<FormatDescription>>;
+    /// Formats the camera offers
+    ///
+    /// Continuous interval ranges contribute their shortest and longest interval
+    fn formats(&self) -> Result<Vec<StreamFormat>> {
+        Ok(self
+            .descriptors()?
+            .iter()
+            .flat_map(FormatDescription::stream_formats)
+            .collect())
+    }
+    /// Speed the camera is connected at, `UsbSpeed::Unknown` if it has no bus
+    fn speed(&self) -> UsbSpeed {
+        UsbSpeed::Unknown
+    }
+    /// Current value of a control
+    fn control(&self, control: Control) -> Result<i64>;
+    /// Values a control accepts
+    fn control_range(&self, control: Control) -> Result<ControlRange>;
+    /// Changes the value of a control
+    fn set_control(&self, control: Control, value: i64) -> Result<()>;
+    /// Negotiates `format` and starts streaming, calling `cb` for every frame
+    ///
+    /// This function is non-blocking
+    fn start_stream(
+        &self,
+        format: StreamFormat,
+        policy: PanicPolicy,
+        cb: FrameCallback,
+    ) -> Result<Box<dyn BackendStream + '_>>;
+}
+
+/// Reads a control with the given request
+unsafe fn get_control(
+    devh: *mut uvc_device_handle,
+    control: Control,
+    req: uvc_req_code,
+) -> Result<i64> {
+    macro_rules! get {
+        ($func:ident, $ty:ty) => {{
+            let mut value = <$ty>::default();
+            Error::check($func(devh, &mut value, req)).map(|()| i64::from(value))
+        }};
+    }
+    let value = match control {
+        Control::ScanningMode => get!(uvc_get_scanning_mode, u8),
+        Control::AeMode => get!(uvc_get_ae_mode, u8),
+        Control::AePriority => get!(uvc_get_ae_priority, u8),
+        Control::ExposureAbs => get!(uvc_get_exposure_abs, u32),
+        Control::ExposureRel => get!(uvc_get_exposure_rel, i8),
+        Control::FocusAbs => get!(uvc_get_focus_abs, u16),
+        Control::Brightness => get!(uvc_get_brightness, i16),
+        Control::Contrast => get!(uvc_get_contrast, u16),
+        Control::Gain => get!(uvc_get_gain, u16),
+    };
+    value.map_err(|err| {
+        err.during(Operation::GetControl {
+            name: control.name(),
+        })
+    })
+}
+
+// Dropping an `ActiveStream` stops `libuvc` streaming and frees the callback.
+// Its streams do not poll for being unplugged, see `StreamHandle::detect_unplug`.
+unsafe impl<'a> Backend for DeviceHandle<'a> {
+    fn descriptors(&self) -> Result<Vec<FormatDescription>> {
+        Ok(self
+            .supported_formats()
+            .map(|desc| {
+                let frames = desc
+                    .supported_formats()
+                    .map(|frame| FrameDescription {
+                        frame_index: frame.frame_index(),
+                        width: frame.width(),
+                        height: frame.height(),
+                        default_interval: frame.default_interval(),
+                        intervals: match frame.frame_intervals() {
+                            FrameIntervals::Discrete(intervals) => {
+                                Intervals::Discrete(intervals.to_vec())
+                            }
+                            FrameIntervals::Continuous { min, max, step } => {
+                                Intervals::Continuous { min, max, step }
+                            }
+                        },
+                    })
+                    .collect();
+                FormatDescription {
+                    format_index: desc.format_index(),
+                    format: desc.frame_format(),
+                    guid: desc.guid(),
+                    color_matching: desc.color_matching(),
+                    interlace: desc.interlace(),
+                    frames,
+                }
+            })
+            .collect())
+    }
+
+    fn speed(&self) -> UsbSpeed {
+        UsbSpeed::from_libusb(
+            UsbDevice::from_handle(unsafe { uvc_get_libusb_handle(self.devh.as_ptr()) }).speed(),
+        )
+    }
+
+    fn control(&self, control: Control) -> Result<i64> {
+        unsafe { get_control(self.devh.as_ptr(), control, uvc_req_code_UVC_GET_CUR) }
+    }
+
+    fn control_range(&self, control: Control) -> Result<ControlRange> {
+        let devh = self.devh.as_ptr();
+        unsafe {
+            Ok(ControlRange {
+                min: get_control(devh, control, uvc_req_code_UVC_GET_MIN)?,
+                max: get_control(devh, control, uvc_req_code_UVC_GET_MAX)?,
+                step: get_control(devh, control, uvc_req_code_UVC_GET_RES)?,
+                default: get_control(devh, control, uvc_req_code_UVC_GET_DEF)?,
+            })
+        }
+    }
+
+    fn set_control(&self, control: Control, value: i64) -> Result<()> {
+        let operation = Operation::SetControl {
+            name: control.name(),
+            value,
+        };
+        let devh = self.devh.as_ptr();
+        macro_rules! set {
+            ($func:ident) => {
+                match value.try_into() {
+                    Ok(value) => unsafe { $func(devh, value) },
+                    Err(_) => return Err(Error::from(ErrorKind::InvalidParam).during(operation)),
+                }
+            };
+        }
+        let err = match control {
+            Control::ScanningMode => set!(uvc_set_scanning_mode),
+            Control::AeMode => set!(uvc_set_ae_mode),
+            Control::AePriority => set!(uvc_set_ae_priority),
+            Control::ExposureAbs => set!(uvc_set_exposure_abs),
+            Control::ExposureRel => set!(uvc_set_exposure_rel),
+            Control::FocusAbs => set!(uvc_set_focus_abs),
+            Control::Brightness => set!(uvc_set_brightness),
+            Control::Contrast => set!(uvc_set_contrast),
+            Control::Gain => set!(uvc_set_gain),
+        };
+        Error::check(err).map_err(|err| err.during(operation))
+    }
+
+    fn start_stream(
+        &self,
+        format: StreamFormat,
+        policy: PanicPolicy,
+        mut cb: FrameCallback,
+    ) -> Result<Box<dyn BackendStream + '_>> {
+        let mut control = self.get_stream_control_with_format(format)?;
+        let options = StreamOptions {
+            policy,
+            unplug: None,
+        };
+        let stream = start_streaming(
+            self,
+            &mut control,
+            options,
+            move |frame, _: &mut ()| cb(frame),
+            (),
+        )?;
+        Ok(Box::new(stream))
+    }
+}
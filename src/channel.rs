@@ -0,0 +1,391 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::mpsc::{RecvError, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+/// What happens to a frame arriving while the channel is full
+pub enum Overflow {
+    /// Wait for the receiver, holding up the stream, which then loses frames in `libuvc`
+    Block,
+    /// Drop the arriving frame
+    DropNewest,
+    /// Drop the oldest queued frame, so the receiver always gets the latest frames
+    #[default]
+    DropOldest,
+}
+
+#[derive(Debug)]
+struct State<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+    sender_closed: bool,
+    receiver_closed: bool,
+    dropped: u64,
+    /// Task waiting in `Receiver::poll_recv`
+    waker: Option<Waker>,
+}
+
+impl<T> State<T> {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Queue<T> {
+    state: Mutex<State<T>>,
+    readable: Condvar,
+    writable: Condvar,
+}
+
+impl<T> Queue<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Creates a bounded channel, holding at least one item
+pub(crate) fn channel<T>(capacity: usize, overflow: Overflow) -> (Sender<T>, Receiver<T>) {
+    let queue = Arc::new(Queue {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity.max(1)),
+            capacity: capacity.max(1),
+            sender_closed: false,
+            receiver_closed: false,
+            dropped: 0,
+            waker: None,
+        }),
+        readable: Condvar::new(),
+        writable: Condvar::new(),
+    });
+    (
+        Sender {
+            queue: Arc::clone(&queue),
+            overflow,
+        },
+        Receiver { queue },
+    )
+}
+
+#[derive(Debug)]
+/// Sending half of a channel, used from the stream callback
+///
+/// The channel ends when `close` is called.
+pub(crate) struct Sender<T> {
+    queue: Arc<Queue<T>>,
+    overflow: Overflow,
+}
+
+impl<T> Sender<T> {
+    /// Queues `item` according to the overflow policy
+    pub(crate) fn send(&self, item: T) {
+        let mut state = self.queue.lock();
+        if state.receiver_closed || state.sender_closed {
+            return;
+        }
+        if state.items.len() >= state.capacity {
+            match self.overflow {
+                Overflow::Block => {
+                    state = self
+                        .queue
+                        .writable
+                        .wait_while(state, |state| {
+                            state.items.len() >= state.capacity
+                                && !state.receiver_closed
+                                && !state.sender_closed
+                        })
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                    if state.receiver_closed || state.sender_closed {
+                        return;
+                    }
+                }
+                Overflow::DropNewest => {
+                    state.dropped += 1;
+                    return;
+                }
+                Overflow::DropOldest => {
+                    state.items.pop_front();
+                    state.dropped += 1;
+                }
+            }
+        }
+        state.items.push_back(item);
+        state.wake();
+        self.queue.readable.notify_one();
+    }
+
+    /// Ends the channel, also releasing a blocked `send`
+    pub(crate) fn close(&self) {
+        let mut state = self.queue.lock();
+        state.sender_closed = true;
+        state.wake();
+        drop(state);
+        self.queue.readable.notify_all();
+        self.queue.writable.notify_all();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            queue: Arc::clone(&self.queue),
+            overflow: self.overflow,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Receiving half of a frame channel
+///
+/// Once the stream has stopped and the queued items are received,
+/// `recv` returns an error.
+pub struct Receiver<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Blocks until an item arrives, or the stream stops
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self
+            .queue
+            .readable
+            .wait_while(self.queue.lock(), |state| {
+                state.items.is_empty() && !state.sender_closed
+            })
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let item = state.items.pop_front().ok_or(RecvError)?;
+        self.queue.writable.notify_one();
+        Ok(item)
+    }
+
+    /// Receives an item if one is queued
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.queue.lock();
+        match state.items.pop_front() {
+            Some(item) => {
+                self.queue.writable.notify_one();
+                Ok(item)
+            }
+            None if state.sender_closed => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Blocks until an item arrives, the stream stops, or `timeout` passes
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.queue.lock();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.queue.writable.notify_one();
+                return Ok(item);
+            }
+            if state.sender_closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self
+                .queue
+                .readable
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    /// Receives an item if one is queued, otherwise wakes the task of `cx` when one arrives
+    ///
+    /// Only the task of the latest call is woken.
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        let mut state = self.queue.lock();
+        match state.items.pop_front() {
+            Some(item) => {
+                self.queue.writable.notify_one();
+                Poll::Ready(Ok(item))
+            }
+            None if state.sender_closed => Poll::Ready(Err(RecvError)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Waits for an item in an async task, or for the stream to stop
+    ///
+    /// The future works with any executor, it doesn't block the thread.
+    ///
+    /// ```no_run
+    /// # async fn count(frames: uvc::Receiver<uvc::OwnedFrame>) {
+    /// while let Ok(frame) = frames.recv_async().await {
+    ///     println!("{} bytes", frame.to_bytes().len());
+    /// }
+    /// # }
+    /// ```
+    pub fn recv_async(&self) -> impl Future<Output = Result<T, RecvError>> + '_ {
+        std::future::poll_fn(move |cx| self.poll_recv(cx))
+    }
+
+    /// Iterates over the items until the stream stops
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.recv().ok())
+    }
+
+    /// Number of queued items
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.queue.lock().items.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Items dropped by the overflow policy so far
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.queue.lock().dropped
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.queue.lock().receiver_closed = true;
+        self.queue.writable.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn block_waits_for_the_receiver() {
+        let (sender, receiver) = channel(2, Overflow::Block);
+        sender.send(1);
+        sender.send(2);
+        let blocked = thread::spawn(move || {
+            sender.send(3);
+            sender
+        });
+        thread::sleep(Duration::from_millis(20));
+        assert!(!blocked.is_finished());
+        assert_eq!(receiver.len(), 2);
+
+        assert_eq!(receiver.recv(), Ok(1));
+        let sender = blocked.join().unwrap();
+        assert_eq!(receiver.try_recv(), Ok(2));
+        assert_eq!(receiver.try_recv(), Ok(3));
+        assert_eq!(receiver.dropped(), 0);
+        drop(sender);
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_queue() {
+        let (sender, receiver) = channel(2, Overflow::DropNewest);
+        for item in 1..=5 {
+            sender.send(item);
+        }
+        assert_eq!(receiver.dropped(), 3);
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(receiver.try_recv(), Ok(2));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_latest() {
+        let (sender, receiver) = channel(2, Overflow::DropOldest);
+        for item in 1..=5 {
+            sender.send(item);
+        }
+        assert_eq!(receiver.dropped(), 3);
+        assert_eq!(receiver.try_recv(), Ok(4));
+        assert_eq!(receiver.try_recv(), Ok(5));
+        assert!(receiver.is_empty());
+    }
+
+    #[test]
+    fn zero_capacity_holds_one_item() {
+        let (sender, receiver) = channel(0, Overflow::DropOldest);
+        sender.send(1);
+        sender.send(2);
+        assert_eq!(receiver.len(), 1);
+        assert_eq!(receiver.try_recv(), Ok(2));
+    }
+
+    #[test]
+    fn close_drains_then_disconnects() {
+        let (sender, receiver) = channel(4, Overflow::Block);
+        sender.send(1);
+        sender.send(2);
+        sender.close();
+        sender.send(3);
+
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Ok(2));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+        assert_eq!(receiver.recv(), Err(RecvError));
+        assert_eq!(receiver.iter().count(), 0);
+    }
+
+    #[test]
+    fn close_wakes_a_blocked_receiver() {
+        let (sender, receiver) = channel::<u32>(1, Overflow::Block);
+        let waiting = thread::spawn(move || receiver.recv());
+        thread::sleep(Duration::from_millis(20));
+        sender.close();
+        assert_eq!(waiting.join().unwrap(), Err(RecvError));
+    }
+
+    #[test]
+    fn close_releases_a_blocked_sender() {
+        let (sender, receiver) = channel(1, Overflow::Block);
+        sender.send(1);
+        let closer = sender.clone();
+        let blocked = thread::spawn(move || sender.send(2));
+        thread::sleep(Duration::from_millis(20));
+        closer.close();
+        blocked.join().unwrap();
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn dropped_receiver_releases_a_blocked_sender() {
+        let (sender, receiver) = channel(1, Overflow::Block);
+        sender.send(1);
+        let blocked = thread::spawn(move || {
+            sender.send(2);
+            sender.send(3);
+        });
+        thread::sleep(Duration::from_millis(20));
+        drop(receiver);
+        blocked.join().unwrap();
+    }
+
+    #[test]
+    fn recv_timeout_times_out() {
+        let (_sender, receiver) = channel::<u32>(1, Overflow::Block);
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+    }
+}
@@ -0,0 +1,461 @@
+use std::ptr::NonNull;
+use std::sync::OnceLock;
+
+use crate::device::FormatDescriptor;
+use crate::usb::UsbDevice;
+
+use uvc_sys::*;
+
+const CS_INTERFACE: u8 = 0x24;
+const VS_COLORFORMAT: u8 = 0x0d;
+/// Subtypes of format descriptors, which are followed by their frames and colour matching
+const VS_FORMAT_SUBTYPES: [u8; 8] = [0x04, 0x06, 0x0a, 0x0c, 0x10, 0x12, 0x13, 0x16];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Chromaticity of the primaries and white point
+pub enum ColorPrimaries {
+    Unspecified,
+    /// BT.709 and sRGB
+    Bt709,
+    Bt470M,
+    Bt470BG,
+    Smpte170M,
+    Smpte240M,
+    Other(u8),
+}
+
+impl From<u8> for ColorPrimaries {
+    fn from(code: u8) -> Self {
+        match code {
+            0 => ColorPrimaries::Unspecified,
+            1 => ColorPrimaries::Bt709,
+            2 => ColorPrimaries::Bt470M,
+            3 => ColorPrimaries::Bt470BG,
+            4 => ColorPrimaries::Smpte170M,
+            5 => ColorPrimaries::Smpte240M,
+            x => ColorPrimaries::Other(x),
+        }
+    }
+}
+
+impl From<ColorPrimaries> for u8 {
+    fn from(value: ColorPrimaries) -> u8 {
+        match value {
+            ColorPrimaries::Unspecified => 0,
+            ColorPrimaries::Bt709 => 1,
+            ColorPrimaries::Bt470M => 2,
+            ColorPrimaries::Bt470BG => 3,
+            ColorPrimaries::Smpte170M => 4,
+            ColorPrimaries::Smpte240M => 5,
+            ColorPrimaries::Other(x) => x,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Opto-electronic transfer characteristics of the source
+pub enum TransferCharacteristics {
+    Unspecified,
+    Bt709,
+    Bt470M,
+    Bt470BG,
+    Smpte170M,
+    Smpte240M,
+    Linear,
+    Srgb,
+    Other(u8),
+}
+
+impl From<u8> for TransferCharacteristics {
+    fn from(code: u8) -> Self {
+        match code {
+            0 => TransferCharacteristics::Unspecified,
+            1 => TransferCharacteristics::Bt709,
+            2 => TransferCharacteristics::Bt470M,
+            3 => TransferCharacteristics::Bt470BG,
+            4 => TransferCharacteristics::Smpte170M,
+            5 => TransferCharacteristics::Smpte240M,
+            6 => TransferCharacteristics::Linear,
+            7 => TransferCharacteristics::Srgb,
+            x => TransferCharacteristics::Other(x),
+        }
+    }
+}
+
+impl From<TransferCharacteristics> for u8 {
+    fn from(value: TransferCharacteristics) -> u8 {
+        match value {
+            TransferCharacteristics::Unspecified => 0,
+            TransferCharacteristics::Bt709 => 1,
+            TransferCharacteristics::Bt470M => 2,
+            TransferCharacteristics::Bt470BG => 3,
+            TransferCharacteristics::Smpte170M => 4,
+            TransferCharacteristics::Smpte240M => 5,
+            TransferCharacteristics::Linear => 6,
+            TransferCharacteristics::Srgb => 7,
+            TransferCharacteristics::Other(x) => x,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Matrix used to derive luma and chroma from RGB
+pub enum MatrixCoefficients {
+    Unspecified,
+    Bt709,
+    Fcc,
+    Bt470BG,
+    /// BT.601, the default for UVC devices
+    Smpte170M,
+    Smpte240M,
+    Other(u8),
+}
+
+impl From<u8> for MatrixCoefficients {
+    fn from(code: u8) -> Self {
+        match code {
+            0 => MatrixCoefficients::Unspecified,
+            1 => MatrixCoefficients::Bt709,
+            2 => MatrixCoefficients::Fcc,
+            3 => MatrixCoefficients::Bt470BG,
+            4 => MatrixCoefficients::Smpte170M,
+            5 => MatrixCoefficients::Smpte240M,
+            x => MatrixCoefficients::Other(x),
+        }
+    }
+}
+
+impl From<MatrixCoefficients> for u8 {
+    fn from(value: MatrixCoefficients) -> u8 {
+        match value {
+            MatrixCoefficients::Unspecified => 0,
+            MatrixCoefficients::Bt709 => 1,
+            MatrixCoefficients::Fcc => 2,
+            MatrixCoefficients::Bt470BG => 3,
+            MatrixCoefficients::Smpte170M => 4,
+            MatrixCoefficients::Smpte240M => 5,
+            MatrixCoefficients::Other(x) => x,
+        }
+    }
+}
+
+impl MatrixCoefficients {
+    /// Luma weights `(Kr, Kb)`, unknown matrices use BT.601
+    fn weights(self) -> (f32, f32) {
+        match self {
+            MatrixCoefficients::Bt709 => (0.2126, 0.0722),
+            MatrixCoefficients::Fcc => (0.30, 0.11),
+            MatrixCoefficients::Smpte240M => (0.212, 0.087),
+            _ => (0.299, 0.114),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Contents of a colour matching descriptor
+pub struct ColorMatching {
+    pub primaries: ColorPrimaries,
+    pub transfer: TransferCharacteristics,
+    pub matrix: MatrixCoefficients,
+}
+
+/// Values the UVC specification assumes when a format has no colour matching descriptor
+impl Default for ColorMatching {
+    fn default() -> Self {
+        ColorMatching {
+            primaries: ColorPrimaries::Bt709,
+            transfer: TransferCharacteristics::Bt709,
+            matrix: MatrixCoefficients::Smpte170M,
+        }
+    }
+}
+
+/// Colour matching descriptors, with the index of the format each one follows
+fn parse_color_matching(mut extra: &[u8]) -> Vec<(u8, ColorMatching)> {
+    let mut found = Vec::new();
+    let mut current_format = None;
+    while extra.len() >= 3 {
+        let len = usize::from(extra[0]);
+        if len < 3 || len > extra.len() {
+            break;
+        }
+        let (desc, rest) = extra.split_at(len);
+        extra = rest;
+
+        if desc[1] != CS_INTERFACE {
+            continue;
+        }
+        if VS_FORMAT_SUBTYPES.contains(&desc[2]) && len >= 4 {
+            current_format = Some(desc[3]);
+        } else if desc[2] == VS_COLORFORMAT && len >= 6 {
+            if let Some(format_index) = current_format.take() {
+                found.push((
+                    format_index,
+                    ColorMatching {
+                        primaries: desc[3].into(),
+                        transfer: desc[4].into(),
+                        matrix: desc[5].into(),
+                    },
+                ));
+            }
+        }
+    }
+    found
+}
+
+unsafe impl Send for ColorMatchings {}
+unsafe impl Sync for ColorMatchings {}
+#[derive(Debug)]
+/// Colour matching descriptors of an open device, read from its configuration on first use
+pub(crate) struct ColorMatchings {
+    devh: NonNull<uvc_device_handle>,
+    /// Keyed by the number of the streaming interface and the index of the format
+    formats: OnceLock<Vec<((u8, u8), ColorMatching)>>,
+}
+
+impl ColorMatchings {
+    pub(crate) fn new(devh: NonNull<uvc_device_handle>) -> Self {
+        ColorMatchings {
+            devh,
+            formats: OnceLock::new(),
+        }
+    }
+
+    /// Colour matching of the format with the given index on the given interface
+    ///
+    /// Without an interface number, the first format with the index is used.
+    fn get(&self, interface: Option<u8>, format_index: u8) -> ColorMatching {
+        self.formats
+            .get_or_init(|| {
+                let device =
+                    UsbDevice::from_handle(unsafe { uvc_get_libusb_handle(self.devh.as_ptr()) });
+                let Some(config) = device.active_config() else {
+                    return Vec::new();
+                };
+                config
+                    .video_streaming_extras()
+                    .flat_map(|(number, extra)| {
+                        parse_color_matching(extra)
+                            .into_iter()
+                            .map(move |(index, color)| ((number, index), color))
+                    })
+                    .collect()
+            })
+            .iter()
+            .find(|&&(key, _)| belongs_to(key, interface, format_index))
+            .map_or_else(ColorMatching::default, |&(_, color)| color)
+    }
+}
+
+/// Whether colour matchings keyed by `(interface number, format index)` belong to a format
+fn belongs_to((number, index): (u8, u8), interface: Option<u8>, format_index: u8) -> bool {
+    index == format_index && interface.is_none_or(|interface| number == interface)
+}
+
+impl<'a> FormatDescriptor<'a> {
+    /// Colour space of the format, as declared by its colour matching descriptor
+    ///
+    /// Formats without a descriptor use the defaults of the UVC specification.
+    #[must_use]
+    pub fn color_matching(&self) -> ColorMatching {
+        self.color_matchings
+            .get(self.interface_number(), self.format_index())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Byte order of packed 4:2:2 data
+pub(crate) enum Packed422 {
+    Yuyv,
+    Uyvy,
+}
+
+/// Converts full range packed 4:2:2 data to 8 bit RGB, or BGR if `bgr` is set
+pub(crate) fn yuv422_to_rgb(
+    src: &[u8],
+    dst: &mut [u8],
+    layout: Packed422,
+    matrix: MatrixCoefficients,
+    bgr: bool,
+) {
+    let (kr, kb) = matrix.weights();
+    let kg = 1.0 - kr - kb;
+    // 14 bit fixed point, as in the converters of `libuvc`
+    let fixed = |x: f32| (x * 16384.0).round() as i32;
+    let v_r = fixed(2.0 * (1.0 - kr));
+    let u_b = fixed(2.0 * (1.0 - kb));
+    let u_g = fixed(2.0 * kb * (1.0 - kb) / kg);
+    let v_g = fixed(2.0 * kr * (1.0 - kr) / kg);
+
+    let (y0, u, y1, v) = match layout {
+        Packed422::Yuyv => (0, 1, 2, 3),
+        Packed422::Uyvy => (1, 0, 3, 2),
+    };
+    let clamp = |x: i32| x.clamp(0, 255) as u8;
+    for (yuv, rgb) in src.chunks_exact(4).zip(dst.chunks_exact_mut(6)) {
+        let cb = i32::from(yuv[u]) - 128;
+        let cr = i32::from(yuv[v]) - 128;
+        let r = (v_r * cr) >> 14;
+        let g = (-u_g * cb - v_g * cr) >> 14;
+        let b = (u_b * cb) >> 14;
+        for (pixel, luma) in rgb.chunks_exact_mut(3).zip([yuv[y0], yuv[y1]]) {
+            let luma = i32::from(luma);
+            let (first, last) = if bgr { (b, r) } else { (r, b) };
+            pixel[0] = clamp(luma + first);
+            pixel[1] = clamp(luma + g);
+            pixel[2] = clamp(luma + last);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Class specific descriptor with the given subtype and body
+    fn descriptor(subtype: u8, body: &[u8]) -> Vec<u8> {
+        let mut desc = vec![body.len() as u8 + 3, CS_INTERFACE, subtype];
+        desc.extend_from_slice(body);
+        desc
+    }
+
+    #[test]
+    fn color_matching_follows_its_format() {
+        let extra = [
+            descriptor(0x01, &[1, 0, 0, 0x81, 0, 0, 0, 0, 0, 0]), // input header
+            descriptor(0x0d, &[1, 1, 1]),                         // before any format
+            descriptor(0x04, &[1, 1, 0, 0, 0, 0, 0, 0, 0, 0]),    // uncompressed, index 1
+            descriptor(0x05, &[1, 0, 0x80, 0x02, 0xe0, 0x01]),    // its frame
+            descriptor(0x0d, &[1, 1, 4]),
+            descriptor(0x06, &[2, 1, 0, 1, 0, 0, 0, 0]), // MJPEG, index 2, no colour
+            descriptor(0x07, &[1, 0, 0x80, 0x02, 0xe0, 0x01]),
+            vec![7, 0x05, 0x81, 0x05, 0, 0x0c, 1], // endpoint, not class specific
+            descriptor(0x10, &[3, 1, 0, 0, 0, 0, 0, 0]), // frame based, index 3
+            descriptor(0x0d, &[0x05, 0x06, 0x05]),
+        ]
+        .concat();
+
+        assert_eq!(
+            parse_color_matching(&extra),
+            [
+                (
+                    1,
+                    ColorMatching {
+                        primaries: ColorPrimaries::Bt709,
+                        transfer: TransferCharacteristics::Bt709,
+                        matrix: MatrixCoefficients::Smpte170M,
+                    }
+                ),
+                (
+                    3,
+                    ColorMatching {
+                        primaries: ColorPrimaries::Smpte240M,
+                        transfer: TransferCharacteristics::Linear,
+                        matrix: MatrixCoefficients::Smpte240M,
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn formats_of_several_interfaces() {
+        // Both interfaces number their formats from 1
+        assert!(belongs_to((1, 1), Some(1), 1));
+        assert!(!belongs_to((1, 1), Some(2), 1));
+        assert!(belongs_to((2, 1), Some(2), 1));
+        assert!(!belongs_to((2, 2), Some(2), 1));
+        assert!(belongs_to((2, 1), None, 1));
+    }
+
+    #[test]
+    fn malformed_descriptors() {
+        assert!(parse_color_matching(&[]).is_empty());
+        // A length shorter than the header stops parsing
+        let extra = [
+            descriptor(0x04, &[1]),
+            vec![2, CS_INTERFACE],
+            descriptor(0x0d, &[1, 1, 1]),
+        ]
+        .concat();
+        assert!(parse_color_matching(&extra).is_empty());
+        // A descriptor running past the end is ignored
+        let mut extra = [descriptor(0x04, &[1]), descriptor(0x0d, &[1, 1, 1])].concat();
+        extra.pop();
+        assert!(parse_color_matching(&extra).is_empty());
+        // Too short to hold the three codes
+        let extra = [descriptor(0x04, &[1]), descriptor(0x0d, &[1, 1])].concat();
+        assert!(parse_color_matching(&extra).is_empty());
+    }
+
+    #[test]
+    fn codes_round_trip() {
+        for code in 0..=255u8 {
+            assert_eq!(u8::from(ColorPrimaries::from(code)), code);
+            assert_eq!(u8::from(TransferCharacteristics::from(code)), code);
+            assert_eq!(u8::from(MatrixCoefficients::from(code)), code);
+        }
+    }
+
+    fn convert(yuyv: [u8; 4], matrix: MatrixCoefficients) -> [u8; 3] {
+        let mut rgb = [0; 6];
+        yuv422_to_rgb(&yuyv, &mut rgb, Packed422::Yuyv, matrix, false);
+        assert_eq!(rgb[..3], rgb[3..], "both pixels share their chroma");
+        [rgb[0], rgb[1], rgb[2]]
+    }
+
+    fn assert_close(actual: [u8; 3], expected: [u8; 3]) {
+        for (a, e) in actual.iter().zip(&expected) {
+            assert!(a.abs_diff(*e) <= 1, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn bt601_known_values() {
+        let bt601 = MatrixCoefficients::Smpte170M;
+        assert_eq!(convert([128, 128, 128, 128], bt601), [128, 128, 128]);
+        assert_eq!(convert([0, 128, 0, 128], bt601), [0, 0, 0]);
+        assert_eq!(convert([255, 128, 255, 128], bt601), [255, 255, 255]);
+        // Red and blue, as encoded by full range BT.601
+        assert_close(convert([76, 85, 76, 255], bt601), [254, 0, 0]);
+        assert_close(convert([29, 255, 29, 107], bt601), [0, 0, 254]);
+        // Unknown matrices fall back to BT.601
+        assert_eq!(
+            convert([76, 85, 76, 255], MatrixCoefficients::Other(9)),
+            convert([76, 85, 76, 255], bt601)
+        );
+    }
+
+    #[test]
+    fn bt709_known_values() {
+        let bt709 = MatrixCoefficients::Bt709;
+        assert_eq!(convert([128, 128, 128, 128], bt709), [128, 128, 128]);
+        // Red as encoded by full range BT.709
+        assert_close(convert([54, 99, 54, 255], bt709), [254, 0, 0]);
+        // BT.601 red decoded with the wrong matrix
+        assert_close(convert([76, 85, 76, 255], bt709), [255, 25, 0]);
+    }
+
+    #[test]
+    fn layouts_and_bgr() {
+        let bt601 = MatrixCoefficients::Smpte170M;
+        let mut rgb = [0; 6];
+        yuv422_to_rgb(&[85, 76, 255, 76], &mut rgb, Packed422::Uyvy, bt601, false);
+        assert_eq!(rgb[..3], convert([76, 85, 76, 255], bt601));
+
+        let mut bgr = [0; 6];
+        yuv422_to_rgb(&[76, 85, 76, 255], &mut bgr, Packed422::Yuyv, bt601, true);
+        assert_eq!([bgr[2], bgr[1], bgr[0]], convert([76, 85, 76, 255], bt601));
+
+        // The two luma samples of a pair keep their own brightness
+        let mut rgb = [0; 6];
+        yuv422_to_rgb(
+            &[16, 128, 235, 128],
+            &mut rgb,
+            Packed422::Yuyv,
+            bt601,
+            false,
+        );
+        assert_eq!(rgb, [16, 16, 16, 235, 235, 235]);
+    }
+}
@@ -1,7 +1,8 @@
 use uvc_sys::*;
 
-use crate::device::{Device, DeviceList};
-use crate::error::{Error, Result};
+use crate::device::{Device, DeviceList, PortPath};
+use crate::error::{Error, ErrorKind, Operation, Result, ResultExt};
+use crate::query::DeviceQuery;
 
 use std::ffi::CString;
 use std::marker::PhantomData;
@@ -14,6 +15,7 @@ unsafe impl<'a> Sync for Context<'a> {}
 /// Contains the `libuvc` context
 pub struct Context<'a> {
     ctx: NonNull<uvc_context>,
+    usb_ctx: NonNull<libusb_context>,
     _ctx: PhantomData<&'a uvc_context>,
 }
 
@@ -21,6 +23,7 @@ impl<'a> Drop for Context<'a> {
     fn drop(&mut self) {
         unsafe {
             uvc_exit(self.ctx.as_ptr());
+            libusb_exit(self.usb_ctx.as_ptr());
         }
     }
 }
@@ -29,16 +32,25 @@ impl<'a> Context<'a> {
     /// Creates a new context
     pub fn new() -> Result<Self> {
         unsafe {
+            // libuvc only runs its event handling thread for a libusb context it
+            // creates itself, so it gets its own. The second context is only used
+            // to look up topology and descriptors libuvc does not expose.
+            let mut usb_ctx = std::mem::MaybeUninit::<*mut libusb_context>::uninit();
+            Error::check(libusb_init(usb_ctx.as_mut_ptr())).during(Operation::Init)?;
+            let usb_ctx = NonNull::new(usb_ctx.assume_init()).unwrap();
+
             let mut ctx = std::mem::MaybeUninit::<*mut uvc_context>::uninit();
-            let err = uvc_init(ctx.as_mut_ptr(), std::ptr::null_mut()).into();
-            if err == Error::Success {
-                Ok(Context {
-                    ctx: NonNull::new(ctx.assume_init()).unwrap(),
-                    _ctx: PhantomData,
-                })
-            } else {
-                Err(err)
+            if let Err(err) = Error::check(uvc_init(ctx.as_mut_ptr(), std::ptr::null_mut()))
+                .during(Operation::Init)
+            {
+                libusb_exit(usb_ctx.as_ptr());
+                return Err(err);
             }
+            Ok(Context {
+                ctx: NonNull::new(ctx.assume_init()).unwrap(),
+                usb_ctx,
+                _ctx: PhantomData,
+            })
         }
     }
 
@@ -46,17 +58,47 @@ impl<'a> Context<'a> {
     pub fn devices(&'a self) -> Result<DeviceList<'a>> {
         unsafe {
             let mut list = std::mem::MaybeUninit::<*mut *mut uvc_device>::uninit();
-            let err = uvc_get_device_list(self.ctx.as_ptr(), list.as_mut_ptr()).into();
-            if err != Error::Success {
-                return Err(err);
-            }
+            Error::check(uvc_get_device_list(self.ctx.as_ptr(), list.as_mut_ptr()))
+                .during(Operation::EnumerateDevices)?;
 
-            Ok(DeviceList::new(NonNull::new(list.assume_init()).unwrap()))
+            Ok(DeviceList::new(
+                NonNull::new(list.assume_init()).unwrap(),
+                self.usb_ctx,
+            ))
         }
     }
 
+    /// All devices matching the query
+    ///
+    /// Devices are ordered by bus and port, so repeated queries return
+    /// the same order as long as the devices stay connected to the same ports.
+    pub fn query(&'a self, query: &DeviceQuery) -> Result<Vec<Device<'a>>> {
+        let mut devices: Vec<_> = self
+            .devices()?
+            .filter(|dev| {
+                dev.description()
+                    .is_ok_and(|desc| query.matches_description(&desc))
+            })
+            .filter(|dev| query.matches_location(dev))
+            .filter(|dev| !query.needs_handle() || query.matches_formats(dev))
+            .collect();
+
+        devices.sort_by_cached_key(|dev| (dev.port_path().ok(), dev.device_address()));
+
+        Ok(devices)
+    }
+
+    /// The first device matching the query
+    pub fn find(&'a self, query: &DeviceQuery) -> Result<Device<'a>> {
+        self.query(query)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::from(ErrorKind::NotFound).during(Operation::FindDevice))
+    }
+
     /// Find a device based on informations about the device
     /// Pass None to all fields to get a default device
+    #[deprecated(note = "use `Context::find` with a `DeviceQuery`")]
     pub fn find_device(
         &'a self,
         vendor_id: Option<c_int>,
@@ -65,19 +107,29 @@ impl<'a> Context<'a> {
     ) -> Result<Device<'a>> {
         unsafe {
             let mut device = std::mem::MaybeUninit::<*mut uvc_device>::uninit();
-            let cstr = serial_number.map(|v| CString::new(v).unwrap());
-            let err = uvc_find_device(
+            let cstr = serial_number
+                .map(CString::new)
+                .transpose()
+                .map_err(|_| Error::from(ErrorKind::InvalidParam).during(Operation::FindDevice))?;
+            Error::check(uvc_find_device(
                 self.ctx.as_ptr(),
                 device.as_mut_ptr(),
                 vendor_id.unwrap_or(0),
                 product_id.unwrap_or(0),
-                cstr.map_or(std::ptr::null(), |v| v.as_ptr()),
-            )
-            .into();
-            if err != Error::Success {
-                return Err(err);
-            }
-            Ok(Device::from_raw(device.assume_init()))
+                cstr.as_ref().map_or(std::ptr::null(), |v| v.as_ptr()),
+            ))
+            .during(Operation::FindDevice)?;
+            Ok(Device::from_raw(device.assume_init(), self.usb_ctx))
         }
     }
+
+    /// Find the device plugged into the given physical port
+    ///
+    /// Unlike the serial number, the port path tells identical devices apart,
+    /// as long as they are not moved to a different socket.
+    pub fn find_by_port_path(&'a self, port_path: &PortPath) -> Result<Device<'a>> {
+        self.devices()?
+            .find(|dev| dev.port_path().as_ref() == Ok(port_path))
+            .ok_or_else(|| Error::from(ErrorKind::NotFound).during(Operation::FindDevice))
+    }
 }
@@ -1,5 +1,5 @@
 use crate::device::DeviceHandle;
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorKind, Operation, Result, ResultExt};
 use uvc_sys::*;
 
 #[derive(Copy, Clone, Debug)]
@@ -26,126 +26,169 @@ impl<'a> DeviceHandle<'a> {
     pub fn scanning_mode(&self) -> Result<ScanningMode> {
         unsafe {
             let mut mode = std::mem::MaybeUninit::uninit();
-            let err = uvc_get_scanning_mode(
+            Error::check(uvc_get_scanning_mode(
                 self.devh.as_ptr(),
                 mode.as_mut_ptr(),
                 uvc_req_code_UVC_GET_CUR,
-            )
-            .into();
-            if err != Error::Success {
-                return Err(err);
-            }
+            ))
+            .during(Operation::GetControl {
+                name: "scanning_mode",
+            })?;
             match mode.assume_init() {
                 0 => Ok(ScanningMode::Interlaced),
                 1 => Ok(ScanningMode::Progressive),
-                _ => Err(Error::Other),
+                _ => Err(Error::from(ErrorKind::Other).during(Operation::GetControl {
+                    name: "scanning_mode",
+                })),
             }
         }
     }
     pub fn ae_mode(&self) -> Result<AutoExposureMode> {
         unsafe {
             let mut mode = std::mem::MaybeUninit::uninit();
-            let err = uvc_get_ae_mode(
+            Error::check(uvc_get_ae_mode(
                 self.devh.as_ptr(),
                 mode.as_mut_ptr(),
                 uvc_req_code_UVC_GET_CUR,
-            )
-            .into();
-            if err != Error::Success {
-                return Err(err);
-            }
+            ))
+            .during(Operation::GetControl { name: "ae_mode" })?;
             match mode.assume_init() {
                 1 => Ok(AutoExposureMode::Manual),
                 2 => Ok(AutoExposureMode::Auto),
                 4 => Ok(AutoExposureMode::ShutterPriority),
                 8 => Ok(AutoExposureMode::AperturePriority),
-                _ => Err(Error::Other),
+                _ => {
+                    Err(Error::from(ErrorKind::Other)
+                        .during(Operation::GetControl { name: "ae_mode" }))
+                }
             }
         }
     }
     pub fn ae_priority(&self) -> Result<AutoExposurePriority> {
         unsafe {
             let mut priority = std::mem::MaybeUninit::uninit();
-            let err = uvc_get_ae_priority(
+            Error::check(uvc_get_ae_priority(
                 self.devh.as_ptr(),
                 priority.as_mut_ptr(),
                 uvc_req_code_UVC_GET_CUR,
-            )
-            .into();
-            if err != Error::Success {
-                return Err(err);
-            }
+            ))
+            .during(Operation::GetControl {
+                name: "ae_priority",
+            })?;
             match priority.assume_init() {
                 0 => Ok(AutoExposurePriority::Constant),
                 1 => Ok(AutoExposurePriority::Variable),
-                _ => Err(Error::Other),
+                _ => Err(Error::from(ErrorKind::Other).during(Operation::GetControl {
+                    name: "ae_priority",
+                })),
             }
         }
     }
     pub fn exposure_abs(&self) -> Result<u32> {
         unsafe {
             let mut time = std::mem::MaybeUninit::uninit();
-            let err = uvc_get_exposure_abs(
+            Error::check(uvc_get_exposure_abs(
                 self.devh.as_ptr(),
                 time.as_mut_ptr(),
                 uvc_req_code_UVC_GET_CUR,
-            )
-            .into();
-            if err == Error::Success {
-                Ok(time.assume_init())
-            } else {
-                Err(err)
-            }
+            ))
+            .during(Operation::GetControl {
+                name: "exposure_abs",
+            })?;
+            Ok(time.assume_init())
         }
     }
     pub fn exposure_rel(&self) -> Result<i8> {
         unsafe {
             let mut step = std::mem::MaybeUninit::uninit();
-            let err = uvc_get_exposure_rel(
+            Error::check(uvc_get_exposure_rel(
                 self.devh.as_ptr(),
                 step.as_mut_ptr(),
                 uvc_req_code_UVC_GET_CUR,
-            )
-            .into();
-            if err == Error::Success {
-                Ok(step.assume_init())
-            } else {
-                Err(err)
-            }
+            ))
+            .during(Operation::GetControl {
+                name: "exposure_rel",
+            })?;
+            Ok(step.assume_init())
         }
     }
     pub fn focus_abs(&self) -> Result<u16> {
         unsafe {
             let mut focus = std::mem::MaybeUninit::uninit();
-            let err = uvc_get_focus_abs(
+            Error::check(uvc_get_focus_abs(
                 self.devh.as_ptr(),
                 focus.as_mut_ptr(),
                 uvc_req_code_UVC_GET_CUR,
-            )
-            .into();
-            if err == Error::Success {
-                Ok(focus.assume_init())
-            } else {
-                Err(err)
-            }
+            ))
+            .during(Operation::GetControl { name: "focus_abs" })?;
+            Ok(focus.assume_init())
         }
     }
     pub fn focus_rel(&self) -> Result<(i8, u8)> {
         unsafe {
             let mut focus_rel = std::mem::MaybeUninit::uninit();
             let mut speed = std::mem::MaybeUninit::uninit();
-            let err = uvc_get_focus_rel(
+            Error::check(uvc_get_focus_rel(
                 self.devh.as_ptr(),
                 focus_rel.as_mut_ptr(),
                 speed.as_mut_ptr(),
                 uvc_req_code_UVC_GET_CUR,
-            )
-            .into();
-            if err == Error::Success {
-                Ok((focus_rel.assume_init(), speed.assume_init()))
-            } else {
-                Err(err)
-            }
+            ))
+            .during(Operation::GetControl { name: "focus_rel" })?;
+            Ok((focus_rel.assume_init(), speed.assume_init()))
         }
     }
+
+    fn set_control(name: &'static str, value: i64, err: uvc_error_t) -> Result<()> {
+        Error::check(err).during(Operation::SetControl { name, value })
+    }
+    pub fn set_scanning_mode(&self, mode: ScanningMode) -> Result<()> {
+        let mode = match mode {
+            ScanningMode::Interlaced => 0,
+            ScanningMode::Progressive => 1,
+        };
+        Self::set_control("scanning_mode", mode.into(), unsafe {
+            uvc_set_scanning_mode(self.devh.as_ptr(), mode)
+        })
+    }
+    pub fn set_ae_mode(&self, mode: AutoExposureMode) -> Result<()> {
+        let mode = match mode {
+            AutoExposureMode::Manual => 1,
+            AutoExposureMode::Auto => 2,
+            AutoExposureMode::ShutterPriority => 4,
+            AutoExposureMode::AperturePriority => 8,
+        };
+        Self::set_control("ae_mode", mode.into(), unsafe {
+            uvc_set_ae_mode(self.devh.as_ptr(), mode)
+        })
+    }
+    pub fn set_ae_priority(&self, priority: AutoExposurePriority) -> Result<()> {
+        let priority = match priority {
+            AutoExposurePriority::Constant => 0,
+            AutoExposurePriority::Variable => 1,
+        };
+        Self::set_control("ae_priority", priority.into(), unsafe {
+            uvc_set_ae_priority(self.devh.as_ptr(), priority)
+        })
+    }
+    pub fn set_exposure_abs(&self, time: u32) -> Result<()> {
+        Self::set_control("exposure_abs", time.into(), unsafe {
+            uvc_set_exposure_abs(self.devh.as_ptr(), time)
+        })
+    }
+    pub fn set_exposure_rel(&self, step: i8) -> Result<()> {
+        Self::set_control("exposure_rel", step.into(), unsafe {
+            uvc_set_exposure_rel(self.devh.as_ptr(), step)
+        })
+    }
+    pub fn set_focus_abs(&self, focus: u16) -> Result<()> {
+        Self::set_control("focus_abs", focus.into(), unsafe {
+            uvc_set_focus_abs(self.devh.as_ptr(), focus)
+        })
+    }
+    pub fn set_focus_rel(&self, focus_rel: i8, speed: u8) -> Result<()> {
+        Self::set_control("focus_rel", focus_rel.into(), unsafe {
+            uvc_set_focus_rel(self.devh.as_ptr(), focus_rel, speed)
+        })
+    }
 }
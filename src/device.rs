@@ -1,12 +1,20 @@
 use std::ffi::CStr;
+use std::fmt;
 use std::marker::PhantomData;
+use std::os::raw::{c_char, c_void};
 use std::ptr::NonNull;
 use std::slice;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
 
-use crate::error::{Error, Result};
-use crate::formats::{FrameFormat, StreamFormat};
+use crate::color::ColorMatchings;
+use crate::error::{Error, ErrorKind, Operation, Result, ResultExt};
+use crate::formats::{FrameFormat, FrameInterval, Guid, StreamFormat};
+use crate::stream_control::StreamControl;
 use crate::streaming::StreamHandle;
+use crate::strings::{decode_utf8, StringDecoding};
+use crate::usb::UsbDevice;
 use uvc_sys::*;
 
 unsafe impl<'a> Send for DeviceList<'a> {}
@@ -16,6 +24,7 @@ unsafe impl<'a> Sync for DeviceList<'a> {}
 pub struct DeviceList<'a> {
     start: *mut *mut uvc_device,
     list: NonNull<*mut uvc_device>,
+    usb_ctx: NonNull<libusb_context>,
     _ph: PhantomData<&'a &'a uvc_device>,
 }
 
@@ -26,10 +35,11 @@ impl<'a> Drop for DeviceList<'a> {
 }
 
 impl<'a> DeviceList<'a> {
-    pub(crate) fn new(list: NonNull<*mut uvc_device>) -> Self {
+    pub(crate) fn new(list: NonNull<*mut uvc_device>, usb_ctx: NonNull<libusb_context>) -> Self {
         Self {
             start: list.as_ptr(),
             list,
+            usb_ctx,
             _ph: PhantomData,
         }
     }
@@ -44,7 +54,7 @@ impl<'a> Iterator for DeviceList<'a> {
             return None;
         }
 
-        let device = unsafe { Device::from_raw(*item) };
+        let device = unsafe { Device::from_raw(*item, self.usb_ctx) };
         self.list = unsafe { NonNull::new(self.list.as_ptr().add(1)).unwrap() };
 
         Some(device)
@@ -57,6 +67,8 @@ unsafe impl<'a> Sync for Device<'a> {}
 /// Device that can be opened
 pub struct Device<'a> {
     dev: NonNull<uvc_device>,
+    usb_ctx: NonNull<libusb_context>,
+    usb: OnceLock<Option<UsbDevice>>,
     _dev: PhantomData<&'a uvc_device>,
 }
 
@@ -67,9 +79,11 @@ impl<'a> Drop for Device<'a> {
 }
 
 impl<'a> Device<'a> {
-    pub(crate) unsafe fn from_raw(dev: *mut uvc_device) -> Self {
+    pub(crate) unsafe fn from_raw(dev: *mut uvc_device, usb_ctx: NonNull<libusb_context>) -> Self {
         Device {
             dev: NonNull::new(dev).unwrap(),
+            usb_ctx,
+            usb: OnceLock::new(),
             _dev: PhantomData,
         }
     }
@@ -77,77 +91,64 @@ impl<'a> Device<'a> {
     pub fn open(&'a self) -> Result<DeviceHandle<'a>> {
         unsafe {
             let mut devh = std::mem::MaybeUninit::uninit();
-            let err = uvc_open(self.dev.as_ptr(), devh.as_mut_ptr()).into();
-            match err {
-                Error::Success => Ok(DeviceHandle {
-                    devh: NonNull::new(devh.assume_init()).unwrap(),
-                    _devh: PhantomData,
-                }),
-                err => Err(err),
-            }
+            Error::check(uvc_open(self.dev.as_ptr(), devh.as_mut_ptr()))
+                .during(Operation::OpenDevice)?;
+            let devh = NonNull::new(devh.assume_init()).unwrap();
+            Ok(DeviceHandle {
+                devh,
+                color_matchings: Arc::new(ColorMatchings::new(devh)),
+                open: Arc::new(Mutex::new(true)),
+                _devh: PhantomData,
+            })
         }
     }
     /// Get the description of a device
+    ///
+    /// Strings which are not valid UTF-8 are decoded lossily
     pub fn description(&self) -> Result<DeviceDescription> {
-        unsafe {
+        self.description_with(StringDecoding::Lossy)
+    }
+
+    /// Get the description of a device, decoding strings as requested
+    pub fn description_with(&self, decoding: StringDecoding) -> Result<DeviceDescription> {
+        let (vendor_id, product_id, bcd_uvc, serial_number_raw, manufacturer_raw, product_raw) = unsafe {
             let mut desc = std::mem::MaybeUninit::uninit();
-            let err = uvc_get_device_descriptor(self.dev.as_ptr(), desc.as_mut_ptr()).into();
-            if err != Error::Success {
-                return Err(err);
-            }
+            Error::check(uvc_get_device_descriptor(
+                self.dev.as_ptr(),
+                desc.as_mut_ptr(),
+            ))
+            .during(Operation::ReadDescriptor)?;
 
             let desc = desc.assume_init();
 
-            let vendor_id = (*desc).idVendor;
-            let product_id = (*desc).idProduct;
-            let bcd_uvc = (*desc).bcdUVC;
-
-            let serial_number_c_str = (*desc).serialNumber;
-            let serial_number = if serial_number_c_str.is_null() {
-                None
-            } else {
-                Some(
-                    CStr::from_ptr(serial_number_c_str)
-                        .to_owned()
-                        .into_string()
-                        .unwrap(),
-                )
-            };
-            let manufacturer_c_str = (*desc).manufacturer;
-            let manufacturer = if manufacturer_c_str.is_null() {
-                None
-            } else {
-                Some(
-                    CStr::from_ptr(manufacturer_c_str)
-                        .to_owned()
-                        .into_string()
-                        .unwrap(),
-                )
-            };
-            let product_c_str = (*desc).product;
-            let product = if product_c_str.is_null() {
-                None
-            } else {
-                Some(
-                    CStr::from_ptr(product_c_str)
-                        .to_owned()
-                        .into_string()
-                        .unwrap(),
-                )
-            };
-            let descp = Ok(DeviceDescription {
-                vendor_id,
-                product_id,
-                bcd_uvc,
-                serial_number,
-                manufacturer,
-                product,
-            });
+            let fields = (
+                (*desc).idVendor,
+                (*desc).idProduct,
+                (*desc).bcdUVC,
+                c_str_bytes((*desc).serialNumber),
+                c_str_bytes((*desc).manufacturer),
+                c_str_bytes((*desc).product),
+            );
 
             uvc_free_device_descriptor(desc);
 
-            descp
-        }
+            fields
+        };
+
+        Ok(DeviceDescription {
+            vendor_id,
+            product_id,
+            bcd_uvc,
+            serial_number: decode_utf8(serial_number_raw.as_deref(), decoding)
+                .during(Operation::ReadDescriptor)?,
+            manufacturer: decode_utf8(manufacturer_raw.as_deref(), decoding)
+                .during(Operation::ReadDescriptor)?,
+            product: decode_utf8(product_raw.as_deref(), decoding)
+                .during(Operation::ReadDescriptor)?,
+            serial_number_raw,
+            manufacturer_raw,
+            product_raw,
+        })
     }
 
     /// Bus number of which this device is connected
@@ -161,6 +162,199 @@ impl<'a> Device<'a> {
     pub fn device_address(&self) -> u8 {
         unsafe { uvc_get_device_address(self.dev.as_ptr()) }
     }
+
+    /// The `libusb` device, looked up on first use
+    fn usb_device(&self) -> Option<&UsbDevice> {
+        self.usb
+            .get_or_init(|| UsbDevice::find(self.usb_ctx, self.bus_number(), self.device_address()))
+            .as_ref()
+    }
+
+    /// Physical port the device is connected to
+    ///
+    /// Contrary to the device address, this does not change when the
+    /// device is reconnected to the same port.
+    pub fn port_path(&self) -> Result<PortPath> {
+        let usb = self
+            .usb_device()
+            .ok_or_else(|| Error::from(ErrorKind::NoDevice).during(Operation::ReadDescriptor))?;
+        Ok(PortPath::new(self.bus_number(), usb.port_numbers()))
+    }
+
+    /// Speed the device is operating at
+    #[must_use]
+    pub fn speed(&self) -> UsbSpeed {
+        self.usb_device()
+            .map_or(UsbSpeed::Unknown, |usb| UsbSpeed::from_libusb(usb.speed()))
+    }
+
+    /// Number of the video control interface of the camera function
+    #[must_use]
+    pub fn interface_number(&self) -> Option<u8> {
+        self.usb_device()?
+            .active_config()?
+            .video_control_interface()
+    }
+
+    /// Collects all identifying information of a device into an owned snapshot
+    pub fn info(&self) -> Result<DeviceInfo> {
+        let usb = self
+            .usb_device()
+            .ok_or_else(|| Error::from(ErrorKind::NoDevice).during(Operation::ReadDescriptor))?;
+        let description = self.description()?;
+
+        Ok(DeviceInfo {
+            bus_number: self.bus_number(),
+            device_address: self.device_address(),
+            port_path: PortPath::new(self.bus_number(), usb.port_numbers()),
+            speed: UsbSpeed::from_libusb(usb.speed()),
+            interface_number: usb
+                .active_config()
+                .and_then(|config| config.video_control_interface()),
+            vendor_id: description.vendor_id,
+            product_id: description.product_id,
+            bcd_uvc: description.bcd_uvc,
+            serial_number: description.serial_number,
+            manufacturer: description.manufacturer,
+            product: description.product,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// Location of a device in the USB topology, written as `bus-port.port.port`
+///
+/// A path such as `1-2.3.1` is the device on port 1 of the hub
+/// on port 3 of the hub on port 2 of the root hub of bus 1.
+pub struct PortPath {
+    bus: u8,
+    ports: Vec<u8>,
+}
+
+impl PortPath {
+    #[must_use]
+    pub fn new(bus: u8, ports: Vec<u8>) -> Self {
+        Self { bus, ports }
+    }
+
+    /// Bus number
+    #[must_use]
+    pub fn bus(&self) -> u8 {
+        self.bus
+    }
+
+    /// Ports from the root hub to the device
+    #[must_use]
+    pub fn ports(&self) -> &[u8] {
+        &self.ports
+    }
+}
+
+impl fmt::Display for PortPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.bus)?;
+        for (i, port) in self.ports.iter().enumerate() {
+            let separator = if i == 0 { '-' } else { '.' };
+            write!(f, "{}{}", separator, port)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PortPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (bus, ports) = match s.split_once('-') {
+            Some((bus, ports)) => (bus, Some(ports)),
+            None => (s, None),
+        };
+        let bus = bus
+            .trim()
+            .parse()
+            .map_err(|_| Error::from(ErrorKind::InvalidParam))?;
+        let ports = match ports {
+            Some(ports) => ports
+                .split('.')
+                .map(|port| {
+                    port.trim()
+                        .parse()
+                        .map_err(|_| Error::from(ErrorKind::InvalidParam))
+                })
+                .collect::<Result<_>>()?,
+            None => Vec::new(),
+        };
+        Ok(Self { bus, ports })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PortPath {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PortPath {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| serde::de::Error::custom(format!("invalid port path: {}", s)))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Signalling speed of a USB device
+pub enum UsbSpeed {
+    Unknown,
+    /// 1.5 Mbit/s
+    Low,
+    /// 12 Mbit/s
+    Full,
+    /// 480 Mbit/s
+    High,
+    /// 5 Gbit/s
+    Super,
+    /// 10 Gbit/s
+    SuperPlus,
+}
+
+impl UsbSpeed {
+    pub(crate) fn from_libusb(speed: std::os::raw::c_int) -> Self {
+        // Values of `enum libusb_speed`
+        match speed {
+            1 => UsbSpeed::Low,
+            2 => UsbSpeed::Full,
+            3 => UsbSpeed::High,
+            4 => UsbSpeed::Super,
+            5 => UsbSpeed::SuperPlus,
+            _ => UsbSpeed::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// Owned snapshot of the identifying information of a device
+pub struct DeviceInfo {
+    pub bus_number: u8,
+    pub device_address: u8,
+    pub port_path: PortPath,
+    pub speed: UsbSpeed,
+    pub interface_number: Option<u8>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bcd_uvc: u16,
+    pub serial_number: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
 }
 
 unsafe impl<'a> Send for DeviceHandle<'a> {}
@@ -169,6 +363,9 @@ unsafe impl<'a> Sync for DeviceHandle<'a> {}
 /// Open handle to a device
 pub struct DeviceHandle<'a> {
     pub(crate) devh: NonNull<uvc_device_handle>,
+    color_matchings: Arc<ColorMatchings>,
+    /// Cleared before the handle is closed, for threads which may outlive a leaked stream
+    pub(crate) open: Arc<Mutex<bool>>,
     _devh: PhantomData<&'a uvc_device_handle>,
 }
 
@@ -181,6 +378,7 @@ impl<'a, 'b> DeviceHandle<'a> {
 
             FormatDescriptors {
                 head: format_descs,
+                color_matchings: Arc::clone(&self.color_matchings),
                 _ph: PhantomData,
             }
         }
@@ -189,6 +387,8 @@ impl<'a, 'b> DeviceHandle<'a> {
     /// Iterates over all available formats to select the best format.
     ///
     /// f should compare (x, y) and return the preferred format.
+    /// See `FormatSelector` for declarative selection with a deterministic result.
+    /// Continuous interval ranges contribute their shortest and longest interval.
     pub fn get_preferred_format<F>(&self, f: F) -> Option<StreamFormat>
     where
         F: Fn(StreamFormat, StreamFormat) -> StreamFormat,
@@ -196,19 +396,16 @@ impl<'a, 'b> DeviceHandle<'a> {
         let mut pref_format = None;
         for i in self.supported_formats() {
             for j in i.supported_formats() {
-                for k in j.intervals() {
+                let intervals = match j.frame_intervals() {
+                    FrameIntervals::Discrete(intervals) => intervals.to_vec(),
+                    FrameIntervals::Continuous { min, max, .. } => vec![min, max],
+                };
+                for k in intervals {
                     let format = StreamFormat {
                         width: u32::from(j.width()),
                         height: u32::from(j.height()),
-                        fps: 10_000_000 / *k,
-                        format: match j.subtype() {
-                            DescriptionSubtype::FormatMJPEG | DescriptionSubtype::FrameMJPEG => {
-                                FrameFormat::MJPEG
-                            }
-                            DescriptionSubtype::FormatUncompressed
-                            | DescriptionSubtype::FrameUncompressed => FrameFormat::Uncompressed,
-                            _ => FrameFormat::Any,
-                        },
+                        interval: FrameInterval(k),
+                        format: j.frame_format(),
                     };
                     pref_format = Some(pref_format.map_or(format, |x| f(x, format)));
                 }
@@ -217,7 +414,58 @@ impl<'a, 'b> DeviceHandle<'a> {
         pref_format
     }
 
+    /// Negotiates the stream parameters for a format, without committing them
+    ///
+    /// The interval must be one the device advertises for this size,
+    /// it is not rounded to an integer rate.
+    pub fn get_stream_control_with_format(&self, format: StreamFormat) -> Result<StreamControl> {
+        let frame = self
+            .supported_formats()
+            .filter(|desc| format.format.accepts(desc.frame_format()))
+            .flat_map(|desc| desc.supported_formats())
+            .find(|frame| {
+                u32::from(frame.width()) == format.width
+                    && u32::from(frame.height()) == format.height
+                    && frame.frame_intervals().contains(format.interval)
+            })
+            .ok_or_else(|| {
+                Error::from(ErrorKind::InvalidMode).during(Operation::NegotiateFormat { format })
+            })?;
+        self.get_stream_control_with_frame(&frame, format.interval)
+            .during(Operation::NegotiateFormat { format })
+    }
+
+    /// Negotiates the stream parameters for a frame descriptor and one of its intervals
+    pub fn get_stream_control_with_frame(
+        &self,
+        frame: &FrameDescriptor,
+        interval: FrameInterval,
+    ) -> Result<StreamControl> {
+        if !frame.frame_intervals().contains(interval) {
+            return Err(Error::from(ErrorKind::InvalidMode));
+        }
+        // The indices are only meaningful on the interface the descriptor came from
+        let interface_number = frame
+            .format_descriptor()
+            .and_then(|format| format.interface_number())
+            .ok_or(ErrorKind::NotSupported)?;
+
+        let mut ctrl: uvc_stream_ctrl_t = unsafe { std::mem::zeroed() };
+        // Keep the frame interval fixed while the device fills in the rest
+        ctrl.bmHint = 1;
+        ctrl.bFormatIndex = frame.format_index();
+        ctrl.bFrameIndex = frame.frame_index();
+        ctrl.dwFrameInterval = interval.as_100ns();
+        ctrl.bInterfaceNumber = interface_number;
+
+        let mut control = StreamControl::from_raw(ctrl);
+        self.probe_stream_control(&mut control)?;
+        Ok(control)
+    }
+
     /// Creates a stream handle
+    ///
+    /// `fps` is matched against the advertised intervals rounded to whole frames per second
     pub fn get_stream_handle_with_format_size_and_fps(
         &'a self,
         format: FrameFormat,
@@ -225,26 +473,29 @@ impl<'a, 'b> DeviceHandle<'a> {
         height: u32,
         fps: u32,
     ) -> Result<StreamHandle<'a>> {
-        unsafe {
-            let mut handle = std::mem::MaybeUninit::uninit();
-            let err = uvc_get_stream_ctrl_format_size(
-                self.devh.as_ptr(),
-                handle.as_mut_ptr(),
-                format.into(),
-                width as i32,
-                height as i32,
-                fps as i32,
-            )
-            .into();
-            if err == Error::Success {
-                Ok(StreamHandle {
-                    handle: handle.assume_init(),
-                    devh: self,
-                })
-            } else {
-                Err(err)
-            }
-        }
+        let interval = self
+            .supported_formats()
+            .filter(|desc| format.accepts(desc.frame_format()))
+            .flat_map(|desc| desc.supported_formats())
+            .filter(|frame| {
+                u32::from(frame.width()) == width && u32::from(frame.height()) == height
+            })
+            .find_map(|frame| match frame.frame_intervals() {
+                FrameIntervals::Discrete(intervals) => intervals
+                    .iter()
+                    .map(|&interval| FrameInterval(interval))
+                    .find(|interval| interval.fps().round() as u32 == fps),
+                intervals @ FrameIntervals::Continuous { .. } => {
+                    Some(FrameInterval::from_fps(fps)).filter(|&i| intervals.contains(i))
+                }
+            })
+            .unwrap_or_else(|| FrameInterval::from_fps(fps));
+        self.get_stream_handle_with_format(StreamFormat {
+            width,
+            height,
+            interval,
+            format,
+        })
     }
 
     /// Creates a stream handle
@@ -252,17 +503,17 @@ impl<'a, 'b> DeviceHandle<'a> {
         &'a self,
         format: StreamFormat,
     ) -> Result<StreamHandle<'a>> {
-        self.get_stream_handle_with_format_size_and_fps(
-            format.format,
-            format.width,
-            format.height,
-            format.fps,
-        )
+        let control = self.get_stream_control_with_format(format)?;
+        Ok(self.get_stream_handle_with_control(control))
     }
 }
 
 impl<'a> Drop for DeviceHandle<'a> {
     fn drop(&mut self) {
+        *self
+            .open
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = false;
         unsafe {
             uvc_close(self.devh.as_ptr());
         }
@@ -278,13 +529,28 @@ pub struct DeviceDescription {
     pub serial_number: Option<String>,
     pub manufacturer: Option<String>,
     pub product: Option<String>,
+    /// Serial number as reported by the device, before decoding
+    pub serial_number_raw: Option<Vec<u8>>,
+    /// Manufacturer as reported by the device, before decoding
+    pub manufacturer_raw: Option<Vec<u8>>,
+    /// Product as reported by the device, before decoding
+    pub product_raw: Option<Vec<u8>>,
+}
+
+unsafe fn c_str_bytes(s: *const c_char) -> Option<Vec<u8>> {
+    if s.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(s).to_bytes().to_vec())
+    }
 }
 
 unsafe impl<'a> Send for FormatDescriptor<'a> {}
 unsafe impl<'a> Sync for FormatDescriptor<'a> {}
 /// Describes possible formats
 pub struct FormatDescriptor<'a> {
-    format_desc: NonNull<uvc_format_desc_t>,
+    pub(crate) format_desc: NonNull<uvc_format_desc_t>,
+    pub(crate) color_matchings: Arc<ColorMatchings>,
     _ph: PhantomData<&'a uvc_format_desc_t>,
 }
 
@@ -332,11 +598,21 @@ impl From<uvc_vs_desc_subtype> for DescriptionSubtype {
     }
 }
 
+/// Leading fields of `uvc_streaming_interface`, which `libuvc` only defines internally
+#[repr(C)]
+struct StreamingInterfaceHead {
+    parent: *mut c_void,
+    prev: *mut c_void,
+    next: *mut c_void,
+    interface_number: u8,
+}
+
 impl<'a> FormatDescriptor<'a> {
     #[must_use]
-    pub fn supported_formats(&self) -> FrameDescriptors {
+    pub fn supported_formats(&self) -> FrameDescriptors<'a> {
         FrameDescriptors {
             head: unsafe { (*self.format_desc.as_ptr()).frame_descs },
+            color_matchings: Arc::clone(&self.color_matchings),
             _ph: PhantomData,
         }
     }
@@ -345,6 +621,65 @@ impl<'a> FormatDescriptor<'a> {
     pub fn subtype(&self) -> DescriptionSubtype {
         unsafe { (*self.format_desc.as_ptr()).bDescriptorSubtype }.into()
     }
+
+    /// Index of this format, as used in a `StreamControl`
+    #[must_use]
+    pub fn format_index(&self) -> u8 {
+        unsafe { (*self.format_desc.as_ptr()).bFormatIndex }
+    }
+
+    /// Number of the video streaming interface offering this format
+    #[must_use]
+    pub fn interface_number(&self) -> Option<u8> {
+        let interface = unsafe { (*self.format_desc.as_ptr()).parent };
+        NonNull::new(interface.cast::<StreamingInterfaceHead>())
+            .map(|interface| unsafe { interface.as_ref().interface_number })
+    }
+
+    /// GUID of uncompressed and frame based formats
+    #[must_use]
+    pub fn guid(&self) -> Option<Guid> {
+        match self.subtype() {
+            DescriptionSubtype::FormatUncompressed | DescriptionSubtype::FormatFrameBased => {
+                Some(Guid(unsafe {
+                    (*self.format_desc.as_ptr()).__bindgen_anon_1.guidFormat
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    /// Bits per pixel as declared by uncompressed and frame based formats
+    #[must_use]
+    pub fn bits_per_pixel(&self) -> Option<u8> {
+        match self.subtype() {
+            DescriptionSubtype::FormatUncompressed | DescriptionSubtype::FormatFrameBased => {
+                Some(unsafe { (*self.format_desc.as_ptr()).__bindgen_anon_2.bBitsPerPixel })
+            }
+            _ => None,
+        }
+    }
+
+    /// Format of the frames described
+    ///
+    /// Formats with an unknown GUID are reported as `FrameFormat::Uncompressed`
+    /// or `FrameFormat::FrameBased`. Stream based formats are not parsed by `libuvc`
+    /// and never appear here.
+    #[must_use]
+    pub fn frame_format(&self) -> FrameFormat {
+        match self.subtype() {
+            DescriptionSubtype::FormatMJPEG => FrameFormat::MJPEG,
+            DescriptionSubtype::FormatUncompressed => self
+                .guid()
+                .and_then(|guid| guid.frame_format())
+                .unwrap_or(FrameFormat::Uncompressed),
+            DescriptionSubtype::FormatFrameBased => self
+                .guid()
+                .and_then(|guid| guid.frame_format())
+                .unwrap_or(FrameFormat::FrameBased),
+            _ => FrameFormat::Any,
+        }
+    }
 }
 
 unsafe impl<'a> Send for FormatDescriptors<'a> {}
@@ -352,6 +687,7 @@ unsafe impl<'a> Sync for FormatDescriptors<'a> {}
 /// Iterate to get a `FormatDescriptor`
 pub struct FormatDescriptors<'a> {
     head: *const uvc_format_desc_t,
+    color_matchings: Arc<ColorMatchings>,
     _ph: PhantomData<&'a uvc_format_desc_t>,
 }
 
@@ -364,6 +700,7 @@ impl<'a> Iterator for FormatDescriptors<'a> {
             Some(x) => {
                 let current = FormatDescriptor {
                     format_desc: x,
+                    color_matchings: Arc::clone(&self.color_matchings),
                     _ph: PhantomData,
                 };
                 self.head = unsafe { (*self.head).next };
@@ -379,6 +716,7 @@ unsafe impl<'a> Sync for FrameDescriptor<'a> {}
 /// Describes possible frames
 pub struct FrameDescriptor<'a> {
     frame_desc: NonNull<uvc_frame_desc_t>,
+    color_matchings: Arc<ColorMatchings>,
     _ph: PhantomData<&'a uvc_frame_desc_t>,
 }
 
@@ -396,22 +734,79 @@ impl<'a> FrameDescriptor<'a> {
     pub fn subtype(&self) -> DescriptionSubtype {
         unsafe { (*self.frame_desc.as_ptr()).bDescriptorSubtype }.into()
     }
-    /// Time in 100ns
+    /// Format of the frames described
+    #[must_use]
+    pub fn frame_format(&self) -> FrameFormat {
+        match self.format_descriptor() {
+            Some(format) => format.frame_format(),
+            None => match self.subtype() {
+                DescriptionSubtype::FrameMJPEG => FrameFormat::MJPEG,
+                DescriptionSubtype::FrameUncompressed => FrameFormat::Uncompressed,
+                DescriptionSubtype::FrameFrameBased => FrameFormat::FrameBased,
+                _ => FrameFormat::Any,
+            },
+        }
+    }
+
+    /// Format this frame belongs to
+    #[must_use]
+    pub fn format_descriptor(&self) -> Option<FormatDescriptor<'a>> {
+        NonNull::new(unsafe { (*self.frame_desc.as_ptr()).parent }).map(|format_desc| {
+            FormatDescriptor {
+                format_desc,
+                color_matchings: Arc::clone(&self.color_matchings),
+                _ph: PhantomData,
+            }
+        })
+    }
+
+    /// Index of this frame within its format, as used in a `StreamControl`
+    #[must_use]
+    pub fn frame_index(&self) -> u8 {
+        unsafe { (*self.frame_desc.as_ptr()).bFrameIndex }
+    }
+
+    /// Index of the format this frame belongs to
+    #[must_use]
+    pub fn format_index(&self) -> u8 {
+        self.format_descriptor()
+            .map_or(0, |format| format.format_index())
+    }
+
+    /// Interval the device uses unless asked otherwise
+    #[must_use]
+    pub fn default_interval(&self) -> FrameInterval {
+        FrameInterval(unsafe { (*self.frame_desc.as_ptr()).dwDefaultFrameInterval })
+    }
+
+    /// Discrete intervals in 100ns, empty if the device accepts a continuous range
     #[must_use]
     pub fn intervals(&self) -> &[u32] {
+        match self.frame_intervals() {
+            FrameIntervals::Discrete(intervals) => intervals,
+            FrameIntervals::Continuous { .. } => &[],
+        }
+    }
+
+    /// Intervals the device accepts for this frame size
+    #[must_use]
+    pub fn frame_intervals(&self) -> FrameIntervals<'a> {
         unsafe {
-            let intervals: *const u32 = (*self.frame_desc.as_ptr()).intervals;
-            if intervals.is_null() {
-                return &[];
+            let desc = &*self.frame_desc.as_ptr();
+            if desc.bFrameIntervalType == 0 {
+                return FrameIntervals::Continuous {
+                    min: desc.dwMinFrameInterval,
+                    max: desc.dwMaxFrameInterval,
+                    step: desc.dwFrameIntervalStep,
+                };
             }
-            let mut len = 0;
-            loop {
-                let x = *intervals.add(len);
-                if x == 0 {
-                    return slice::from_raw_parts::<'a>(intervals, len);
-                }
-                len += 1;
+            if desc.intervals.is_null() {
+                return FrameIntervals::Discrete(&[]);
             }
+            FrameIntervals::Discrete(slice::from_raw_parts::<'a>(
+                desc.intervals,
+                usize::from(desc.bFrameIntervalType),
+            ))
         }
     }
 
@@ -429,11 +824,63 @@ impl<'a> FrameDescriptor<'a> {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Frame intervals accepted for a frame size, in 100 ns units
+pub enum FrameIntervals<'a> {
+    /// A list of exact intervals
+    Discrete(&'a [u32]),
+    /// Any interval from `min` to `max`, in multiples of `step` above `min`
+    Continuous { min: u32, max: u32, step: u32 },
+}
+
+impl<'a> FrameIntervals<'a> {
+    /// Whether the device accepts this interval
+    #[must_use]
+    pub fn contains(&self, interval: FrameInterval) -> bool {
+        let interval = interval.as_100ns();
+        match *self {
+            FrameIntervals::Discrete(intervals) => intervals.contains(&interval),
+            FrameIntervals::Continuous { min, max, step } => {
+                (min..=max).contains(&interval)
+                    && (step == 0 || (interval - min).is_multiple_of(step))
+            }
+        }
+    }
+
+    /// Accepted interval closest to the requested one
+    #[must_use]
+    pub fn nearest(&self, interval: FrameInterval) -> Option<FrameInterval> {
+        let interval = interval.as_100ns();
+        match *self {
+            FrameIntervals::Discrete(intervals) => intervals
+                .iter()
+                .copied()
+                .min_by_key(|x| x.abs_diff(interval))
+                .map(FrameInterval),
+            FrameIntervals::Continuous { min, max, .. } if min > max => None,
+            FrameIntervals::Continuous { min, max, step } => {
+                let clamped = interval.clamp(min, max);
+                if step == 0 {
+                    return Some(FrameInterval(clamped));
+                }
+                let below = min + (clamped - min) / step * step;
+                let above = below.saturating_add(step);
+                if above <= max && above - clamped < clamped - below {
+                    Some(FrameInterval(above))
+                } else {
+                    Some(FrameInterval(below))
+                }
+            }
+        }
+    }
+}
+
 unsafe impl<'a> Send for FrameDescriptors<'a> {}
 unsafe impl<'a> Sync for FrameDescriptors<'a> {}
 /// Iterate to get a `FrameDescriptor`
 pub struct FrameDescriptors<'a> {
     head: *mut uvc_frame_desc_t,
+    color_matchings: Arc<ColorMatchings>,
     _ph: PhantomData<&'a uvc_frame_desc_t>,
 }
 
@@ -446,6 +893,7 @@ impl<'a> Iterator for FrameDescriptors<'a> {
             Some(x) => {
                 let current = FrameDescriptor {
                     frame_desc: x,
+                    color_matchings: Arc::clone(&self.color_matchings),
                     _ph: PhantomData,
                 };
                 unsafe { self.head = (*self.head).next };
@@ -454,3 +902,41 @@ impl<'a> Iterator for FrameDescriptors<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_path_round_trip() {
+        for text in ["1", "1-2", "3-2.3.1", "255-1.2.3.4.5.6.7"] {
+            let path: PortPath = text.parse().unwrap();
+            assert_eq!(path.to_string(), text);
+        }
+
+        let path: PortPath = "1-2.3.1".parse().unwrap();
+        assert_eq!(path.bus(), 1);
+        assert_eq!(path.ports(), &[2, 3, 1]);
+        assert_eq!(path, PortPath::new(1, vec![2, 3, 1]));
+    }
+
+    #[test]
+    fn port_path_rejects_malformed() {
+        for text in [
+            "", "-", "1-", "1-2.", "1-.2", "a-1", "1-2.x", "256-1", "1-256",
+        ] {
+            assert!(text.parse::<PortPath>().is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn port_path_orders_by_bus_then_ports() {
+        let mut paths: Vec<PortPath> = ["2-1", "1-2.1", "1-10", "1-2"]
+            .iter()
+            .map(|text| text.parse().unwrap())
+            .collect();
+        paths.sort();
+        let sorted: Vec<String> = paths.iter().map(ToString::to_string).collect();
+        assert_eq!(sorted, ["1-2", "1-2.1", "1-10", "2-1"]);
+    }
+}
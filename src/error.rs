@@ -1,13 +1,13 @@
-use std::ffi::CStr;
 use std::fmt;
 
+use crate::formats::{FrameFormat, StreamFormat};
+
 /// Result type of functions in this crate
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// Error codes from `libusb`
-#[derive(Debug, PartialEq, Copy, Clone)]
-pub enum Error {
-    Success,
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+/// Classification of errors, following the error codes of `libuvc` and `libusb`
+pub enum ErrorKind {
     Access,
     Busy,
     CallbackExists,
@@ -24,71 +24,215 @@ pub enum Error {
     Overflow,
     Pipe,
     Timeout,
+    /// A string was not encoded as expected
+    InvalidString,
     Unknown(uvc_sys::uvc_error_t),
 }
 
-impl From<uvc_sys::uvc_error_t> for Error {
-    fn from(code: uvc_sys::uvc_error_t) -> Self {
+impl ErrorKind {
+    fn from_code(code: uvc_sys::uvc_error_t) -> Self {
         match code {
-            uvc_sys::uvc_error_UVC_SUCCESS => Error::Success,
-            uvc_sys::uvc_error_UVC_ERROR_ACCESS => Error::Access,
-            uvc_sys::uvc_error_UVC_ERROR_BUSY => Error::Busy,
-            uvc_sys::uvc_error_UVC_ERROR_CALLBACK_EXISTS => Error::CallbackExists,
-            uvc_sys::uvc_error_UVC_ERROR_INTERRUPTED => Error::Interrupted,
-            uvc_sys::uvc_error_UVC_ERROR_INVALID_DEVICE => Error::InvalidDevice,
-            uvc_sys::uvc_error_UVC_ERROR_INVALID_MODE => Error::InvalidMode,
-            uvc_sys::uvc_error_UVC_ERROR_INVALID_PARAM => Error::InvalidParam,
-            uvc_sys::uvc_error_UVC_ERROR_IO => Error::IO,
-            uvc_sys::uvc_error_UVC_ERROR_NOT_FOUND => Error::NotFound,
-            uvc_sys::uvc_error_UVC_ERROR_NOT_SUPPORTED => Error::NotSupported,
-            uvc_sys::uvc_error_UVC_ERROR_NO_DEVICE => Error::NoDevice,
-            uvc_sys::uvc_error_UVC_ERROR_NO_MEM => Error::NoMem,
-            uvc_sys::uvc_error_UVC_ERROR_OTHER => Error::Other,
-            uvc_sys::uvc_error_UVC_ERROR_OVERFLOW => Error::Overflow,
-            uvc_sys::uvc_error_UVC_ERROR_PIPE => Error::Pipe,
-            uvc_sys::uvc_error_UVC_ERROR_TIMEOUT => Error::Timeout,
-            x => Error::Unknown(x),
+            uvc_sys::uvc_error_UVC_ERROR_ACCESS => ErrorKind::Access,
+            uvc_sys::uvc_error_UVC_ERROR_BUSY => ErrorKind::Busy,
+            uvc_sys::uvc_error_UVC_ERROR_CALLBACK_EXISTS => ErrorKind::CallbackExists,
+            uvc_sys::uvc_error_UVC_ERROR_INTERRUPTED => ErrorKind::Interrupted,
+            uvc_sys::uvc_error_UVC_ERROR_INVALID_DEVICE => ErrorKind::InvalidDevice,
+            uvc_sys::uvc_error_UVC_ERROR_INVALID_MODE => ErrorKind::InvalidMode,
+            uvc_sys::uvc_error_UVC_ERROR_INVALID_PARAM => ErrorKind::InvalidParam,
+            uvc_sys::uvc_error_UVC_ERROR_IO => ErrorKind::IO,
+            uvc_sys::uvc_error_UVC_ERROR_NOT_FOUND => ErrorKind::NotFound,
+            uvc_sys::uvc_error_UVC_ERROR_NOT_SUPPORTED => ErrorKind::NotSupported,
+            uvc_sys::uvc_error_UVC_ERROR_NO_DEVICE => ErrorKind::NoDevice,
+            uvc_sys::uvc_error_UVC_ERROR_NO_MEM => ErrorKind::NoMem,
+            uvc_sys::uvc_error_UVC_ERROR_OTHER => ErrorKind::Other,
+            uvc_sys::uvc_error_UVC_ERROR_OVERFLOW => ErrorKind::Overflow,
+            uvc_sys::uvc_error_UVC_ERROR_PIPE => ErrorKind::Pipe,
+            uvc_sys::uvc_error_UVC_ERROR_TIMEOUT => ErrorKind::Timeout,
+            x => ErrorKind::Unknown(x),
         }
     }
+
+    /// Whether repeating the operation may succeed
+    #[must_use]
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::Busy
+                | ErrorKind::Interrupted
+                | ErrorKind::Overflow
+                | ErrorKind::Pipe
+                | ErrorKind::Timeout
+        )
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Access => write!(f, "access denied"),
+            ErrorKind::Busy => write!(f, "resource busy"),
+            ErrorKind::CallbackExists => write!(f, "callback already exists"),
+            ErrorKind::Interrupted => write!(f, "system call interrupted"),
+            ErrorKind::InvalidDevice => write!(f, "invalid device"),
+            ErrorKind::InvalidMode => write!(f, "invalid mode"),
+            ErrorKind::InvalidParam => write!(f, "invalid parameter"),
+            ErrorKind::IO => write!(f, "input/output error"),
+            ErrorKind::NotFound => write!(f, "not found"),
+            ErrorKind::NotSupported => write!(f, "operation not supported"),
+            ErrorKind::NoDevice => write!(f, "no such device"),
+            ErrorKind::NoMem => write!(f, "insufficient memory"),
+            ErrorKind::Other => write!(f, "unknown error"),
+            ErrorKind::Overflow => write!(f, "overflow"),
+            ErrorKind::Pipe => write!(f, "pipe error"),
+            ErrorKind::Timeout => write!(f, "operation timed out"),
+            ErrorKind::InvalidString => write!(f, "invalid string encoding"),
+            ErrorKind::Unknown(code) => write!(f, "unknown error code {}", code),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Operation which failed
+pub enum Operation {
+    Init,
+    EnumerateDevices,
+    FindDevice,
+    OpenDevice,
+    ReadDescriptor,
+    ReadString { index: u8 },
+    GetControl { name: &'static str },
+    SetControl { name: &'static str, value: i64 },
+    NegotiateFormat { format: StreamFormat },
+    ProbeStreamControl,
+    StartStream,
+    ConvertFrame { from: FrameFormat, to: FrameFormat },
+    DuplicateFrame,
+    Deinterlace { format: FrameFormat },
 }
 
-impl Into<uvc_sys::uvc_error_t> for Error {
-    fn into(self) -> uvc_sys::uvc_error_t {
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::Success => uvc_sys::uvc_error_UVC_SUCCESS,
-            Error::Access => uvc_sys::uvc_error_UVC_ERROR_ACCESS,
-            Error::Busy => uvc_sys::uvc_error_UVC_ERROR_BUSY,
-            Error::CallbackExists => uvc_sys::uvc_error_UVC_ERROR_CALLBACK_EXISTS,
-            Error::Interrupted => uvc_sys::uvc_error_UVC_ERROR_INTERRUPTED,
-            Error::InvalidDevice => uvc_sys::uvc_error_UVC_ERROR_INVALID_DEVICE,
-            Error::InvalidMode => uvc_sys::uvc_error_UVC_ERROR_INVALID_MODE,
-            Error::InvalidParam => uvc_sys::uvc_error_UVC_ERROR_INVALID_PARAM,
-            Error::IO => uvc_sys::uvc_error_UVC_ERROR_IO,
-            Error::NotFound => uvc_sys::uvc_error_UVC_ERROR_NOT_FOUND,
-            Error::NotSupported => uvc_sys::uvc_error_UVC_ERROR_NOT_SUPPORTED,
-            Error::NoDevice => uvc_sys::uvc_error_UVC_ERROR_NO_DEVICE,
-            Error::NoMem => uvc_sys::uvc_error_UVC_ERROR_NO_MEM,
-            Error::Other => uvc_sys::uvc_error_UVC_ERROR_OTHER,
-            Error::Overflow => uvc_sys::uvc_error_UVC_ERROR_OVERFLOW,
-            Error::Pipe => uvc_sys::uvc_error_UVC_ERROR_PIPE,
-            Error::Timeout => uvc_sys::uvc_error_UVC_ERROR_TIMEOUT,
-            Error::Unknown(x) => x,
+            Operation::Init => write!(f, "initialising context"),
+            Operation::EnumerateDevices => write!(f, "enumerating devices"),
+            Operation::FindDevice => write!(f, "finding device"),
+            Operation::OpenDevice => write!(f, "opening device"),
+            Operation::ReadDescriptor => write!(f, "reading descriptor"),
+            Operation::ReadString { index } => write!(f, "reading string descriptor {}", index),
+            Operation::GetControl { name } => write!(f, "getting control {}", name),
+            Operation::SetControl { name, value } => {
+                write!(f, "setting control {} to {}", name, value)
+            }
+            Operation::NegotiateFormat { format } => write!(f, "negotiating format {:?}", format),
+            Operation::ProbeStreamControl => write!(f, "probing stream control"),
+            Operation::StartStream => write!(f, "starting stream"),
+            Operation::ConvertFrame { from, to } => {
+                write!(f, "converting frame from {:?} to {:?}", from, to)
+            }
+            Operation::DuplicateFrame => write!(f, "duplicating frame"),
+            Operation::Deinterlace { format } => write!(f, "deinterlacing {:?} frame", format),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Error code as returned by `libuvc` or `libusb`
+pub struct Code(pub uvc_sys::uvc_error_t);
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error code {}", self.0)
+    }
+}
+
+impl std::error::Error for Code {}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Error with the operation it occurred in
+pub struct Error {
+    kind: ErrorKind,
+    operation: Option<Operation>,
+    code: Option<Code>,
+}
+
+impl Error {
+    /// Converts a return code, `UVC_SUCCESS` and positive values are not errors
+    pub(crate) fn check(code: uvc_sys::uvc_error_t) -> Result<()> {
+        if code >= uvc_sys::uvc_error_UVC_SUCCESS {
+            Ok(())
+        } else {
+            Err(Error {
+                kind: ErrorKind::from_code(code),
+                operation: None,
+                code: Some(Code(code)),
+            })
+        }
+    }
+
+    pub(crate) fn during(mut self, operation: Operation) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+
+    /// Classification of the error
+    #[must_use]
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Operation which failed, if known
+    #[must_use]
+    pub fn operation(&self) -> Option<&Operation> {
+        self.operation.as_ref()
+    }
+
+    /// Error code returned by `libuvc` or `libusb`, if the error originated there
+    #[must_use]
+    pub fn code(&self) -> Option<Code> {
+        self.code
+    }
+
+    /// Whether repeating the operation may succeed
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error {
+            kind,
+            operation: None,
+            code: None,
         }
     }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let strerror = unsafe { uvc_sys::uvc_strerror((*self).into()) };
-        if strerror.is_null() {
-            return write!(f, "Unknown error");
+        match &self.operation {
+            Some(operation) => write!(f, "{}: {}", operation, self.kind),
+            None => write!(f, "{}", self.kind),
         }
-        let strerr = unsafe { CStr::from_ptr(strerror) }.to_str().unwrap();
-        write!(f, "{}", strerr)
     }
 }
+
 impl std::error::Error for Error {
-    fn cause(&self) -> Option<&dyn std::error::Error> {
-        None
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.code
+            .as_ref()
+            .map(|code| code as &(dyn std::error::Error + 'static))
+    }
+}
+
+/// Attaches the failed operation to errors
+pub(crate) trait ResultExt<T> {
+    fn during(self, operation: Operation) -> Result<T>;
+}
+
+impl<T> ResultExt<T> for Result<T> {
+    fn during(self, operation: Operation) -> Result<T> {
+        self.map_err(|err| err.during(operation))
     }
 }
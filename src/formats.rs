@@ -1,14 +1,102 @@
+use std::fmt;
+use std::time::Duration;
+
 use uvc_sys::*;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 /// Format one can request a stream to produce
 pub struct StreamFormat {
     pub width: u32,
     pub height: u32,
-    pub fps: u32,
+    pub interval: FrameInterval,
     pub format: FrameFormat,
 }
 
+/// Units of a frame interval per second
+const INTERVALS_PER_SECOND: u64 = 10_000_000;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+/// Time between frames in 100 ns units, as used by UVC descriptors
+///
+/// Devices advertise rates such as 29.97 fps as an exact interval (`333_667`),
+/// which an integer rate can not represent. A longer interval is a lower rate.
+pub struct FrameInterval(pub u32);
+
+impl FrameInterval {
+    /// Interval closest to the given integer rate
+    #[must_use]
+    pub fn from_fps(fps: u32) -> Self {
+        Self::from_rate(fps, 1)
+    }
+
+    /// Interval closest to a rate of `numerator / denominator` frames per second
+    ///
+    /// `from_rate(30_000, 1001)` gives the NTSC interval of 29.97 fps.
+    #[must_use]
+    pub fn from_rate(numerator: u32, denominator: u32) -> Self {
+        if numerator == 0 {
+            return FrameInterval(u32::MAX);
+        }
+        let numerator = u64::from(numerator);
+        let interval = (INTERVALS_PER_SECOND * u64::from(denominator) + numerator / 2) / numerator;
+        FrameInterval(interval.min(u64::from(u32::MAX)) as u32)
+    }
+
+    /// Interval in 100 ns units
+    #[must_use]
+    pub fn as_100ns(self) -> u32 {
+        self.0
+    }
+
+    /// Interval as a duration
+    #[must_use]
+    pub fn as_duration(self) -> Duration {
+        Duration::from_nanos(u64::from(self.0) * 100)
+    }
+
+    /// Frame rate, rounded to the precision of a float
+    #[must_use]
+    pub fn fps(self) -> f64 {
+        INTERVALS_PER_SECOND as f64 / f64::from(self.0)
+    }
+
+    /// Exact frame rate as a reduced `(numerator, denominator)` fraction
+    #[must_use]
+    pub fn rate(self) -> (u32, u32) {
+        if self.0 == 0 {
+            return (0, 1);
+        }
+        let interval = u64::from(self.0);
+        let divisor = gcd(INTERVALS_PER_SECOND, interval);
+        (
+            (INTERVALS_PER_SECOND / divisor) as u32,
+            (interval / divisor) as u32,
+        )
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+impl fmt::Display for FrameInterval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.2} fps", self.fps())
+    }
+}
+
+impl From<Duration> for FrameInterval {
+    fn from(duration: Duration) -> Self {
+        let interval = duration.as_nanos().div_ceil(100);
+        FrameInterval(interval.min(u128::from(u32::MAX)) as u32)
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 /// Format of a frame
 pub enum FrameFormat {
@@ -22,6 +110,7 @@ pub enum FrameFormat {
     BGR,
     MJPEG,
     GRAY8,
+    /// 16 bit luminance, `Y16`
     GRAY16,
     BY8,
     BA81,
@@ -29,6 +118,24 @@ pub enum FrameFormat {
     SGBRG8,
     SRGGB8,
     SBGGR8,
+    /// Y plane followed by interleaved UV at half resolution
+    NV12,
+    /// Y plane followed by U and V planes at half resolution
+    I420,
+    /// Y plane followed by V and U planes at half resolution
+    YV12,
+    /// As NV12 with 10 bits in the upper bits of 16 bit samples
+    P010,
+    /// RGB 5:6:5, 16 bits per pixel
+    RGBP,
+    /// 16 bit depth
+    Z16,
+    /// H.264 access units in Annex B format
+    H264,
+    /// H.265 access units in Annex B format
+    HEVC,
+    /// Frame based format with an unknown GUID
+    FrameBased,
     Count,
 }
 
@@ -79,8 +186,277 @@ impl Into<uvc_frame_format> for FrameFormat {
             FrameFormat::SGBRG8 => uvc_frame_format_UVC_FRAME_FORMAT_SGBRG8,
             FrameFormat::SRGGB8 => uvc_frame_format_UVC_FRAME_FORMAT_SRGGB8,
             FrameFormat::SBGGR8 => uvc_frame_format_UVC_FRAME_FORMAT_SBGGR8,
+            // Not known to `libuvc`
+            FrameFormat::NV12
+            | FrameFormat::I420
+            | FrameFormat::YV12
+            | FrameFormat::P010
+            | FrameFormat::RGBP
+            | FrameFormat::Z16 => uvc_frame_format_UVC_FRAME_FORMAT_UNCOMPRESSED,
+            FrameFormat::H264 | FrameFormat::HEVC | FrameFormat::FrameBased => {
+                uvc_frame_format_UVC_FRAME_FORMAT_COMPRESSED
+            }
             FrameFormat::Count => uvc_frame_format_UVC_FRAME_FORMAT_COUNT,
             FrameFormat::Unknown => uvc_frame_format_UVC_FRAME_FORMAT_UNKNOWN,
         }
     }
 }
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+/// Identifier of an uncompressed or frame based format, as sent in the format descriptor
+pub struct Guid(pub [u8; 16]);
+
+/// Tail shared by the GUIDs of FourCC based formats
+const FOURCC_SUFFIX: [u8; 12] = [
+    0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// Formats identified by the FourCC of their GUID
+const FOURCC_FORMATS: &[([u8; 4], FrameFormat)] = &[
+    (*b"YUY2", FrameFormat::YUYV),
+    (*b"UYVY", FrameFormat::UYVY),
+    (*b"Y800", FrameFormat::GRAY8),
+    (*b"Y8  ", FrameFormat::GRAY8),
+    (*b"Y16 ", FrameFormat::GRAY16),
+    (*b"BY8 ", FrameFormat::BY8),
+    (*b"BA81", FrameFormat::BA81),
+    (*b"GRBG", FrameFormat::SGRBG8),
+    (*b"GBRG", FrameFormat::SGBRG8),
+    (*b"RGGB", FrameFormat::SRGGB8),
+    (*b"BGGR", FrameFormat::SBGGR8),
+    (*b"NV12", FrameFormat::NV12),
+    (*b"I420", FrameFormat::I420),
+    (*b"YV12", FrameFormat::YV12),
+    (*b"P010", FrameFormat::P010),
+    (*b"RGBP", FrameFormat::RGBP),
+    (*b"Z16 ", FrameFormat::Z16),
+    (*b"MJPG", FrameFormat::MJPEG),
+    (*b"H264", FrameFormat::H264),
+    (*b"H265", FrameFormat::HEVC),
+    (*b"HEVC", FrameFormat::HEVC),
+];
+
+/// `MEDIASUBTYPE_RGB24`, which stores pixels as BGR
+const GUID_BGR24: Guid = Guid([
+    0x7d, 0xeb, 0x36, 0xe4, 0x4f, 0x52, 0xce, 0x11, 0x9f, 0x53, 0x00, 0x20, 0xaf, 0x0b, 0xa7, 0x70,
+]);
+
+impl Guid {
+    /// GUID of a FourCC based format
+    #[must_use]
+    pub fn from_fourcc(fourcc: [u8; 4]) -> Self {
+        let mut guid = [0; 16];
+        guid[..4].copy_from_slice(&fourcc);
+        guid[4..].copy_from_slice(&FOURCC_SUFFIX);
+        Guid(guid)
+    }
+
+    /// FourCC code, if this is a FourCC based GUID
+    #[must_use]
+    pub fn fourcc(&self) -> Option<[u8; 4]> {
+        if self.0[4..] == FOURCC_SUFFIX {
+            Some([self.0[0], self.0[1], self.0[2], self.0[3]])
+        } else {
+            None
+        }
+    }
+
+    /// Format identified by this GUID, if known
+    #[must_use]
+    pub fn frame_format(&self) -> Option<FrameFormat> {
+        if *self == GUID_BGR24 {
+            return Some(FrameFormat::BGR);
+        }
+        let fourcc = self.fourcc()?;
+        FOURCC_FORMATS
+            .iter()
+            .find(|(known, _)| *known == fourcc)
+            .map(|&(_, format)| format)
+    }
+}
+
+/// Formatted as `32595559-0000-0010-8000-00aa00389b71`, the first three fields are little endian
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let g = &self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-",
+            u32::from_le_bytes([g[0], g[1], g[2], g[3]]),
+            u16::from_le_bytes([g[4], g[5]]),
+            u16::from_le_bytes([g[6], g[7]]),
+        )?;
+        for (i, byte) in g[8..].iter().enumerate() {
+            if i == 2 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FrameFormat {
+    /// GUID identifying this format in descriptors
+    #[must_use]
+    pub fn guid(self) -> Option<Guid> {
+        if self == FrameFormat::BGR {
+            return Some(GUID_BGR24);
+        }
+        FOURCC_FORMATS
+            .iter()
+            .find(|&&(_, format)| format == self)
+            .map(|&(fourcc, _)| Guid::from_fourcc(fourcc))
+    }
+
+    /// Whether frames vary in size with their content
+    #[must_use]
+    pub fn is_compressed(self) -> bool {
+        matches!(
+            self,
+            FrameFormat::Compressed
+                | FrameFormat::MJPEG
+                | FrameFormat::H264
+                | FrameFormat::HEVC
+                | FrameFormat::FrameBased
+        )
+    }
+
+    /// Average number of bits per pixel, for formats with a fixed size
+    #[must_use]
+    pub fn bits_per_pixel(self) -> Option<u32> {
+        match self {
+            FrameFormat::GRAY8
+            | FrameFormat::BY8
+            | FrameFormat::BA81
+            | FrameFormat::SGRBG8
+            | FrameFormat::SGBRG8
+            | FrameFormat::SRGGB8
+            | FrameFormat::SBGGR8 => Some(8),
+            FrameFormat::NV12 | FrameFormat::I420 | FrameFormat::YV12 => Some(12),
+            FrameFormat::YUYV
+            | FrameFormat::UYVY
+            | FrameFormat::GRAY16
+            | FrameFormat::RGBP
+            | FrameFormat::Z16 => Some(16),
+            FrameFormat::RGB | FrameFormat::BGR | FrameFormat::P010 => Some(24),
+            _ => None,
+        }
+    }
+
+    /// Bytes of one line of the first plane, for frames `width` pixels wide
+    ///
+    /// Planar formats have a luma plane of one (two for `P010`) byte per pixel,
+    /// unlike `bits_per_pixel` which averages over all planes.
+    pub(crate) fn line_bytes(self, width: u32) -> Option<usize> {
+        let width = width as usize;
+        match self {
+            FrameFormat::NV12 | FrameFormat::I420 | FrameFormat::YV12 => Some(width),
+            FrameFormat::P010 => Some(2 * width),
+            _ => self.bits_per_pixel().map(|bits| width * bits as usize / 8),
+        }
+    }
+
+    /// Whether a stream of `self` can be requested from a descriptor of format `offered`
+    pub(crate) fn accepts(self, offered: FrameFormat) -> bool {
+        match self {
+            FrameFormat::Any => true,
+            FrameFormat::Uncompressed => !offered.is_compressed() && offered != FrameFormat::Any,
+            FrameFormat::Compressed => offered.is_compressed(),
+            _ => self == offered,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `YUY2` as sent in a format descriptor
+    const GUID_YUY2: [u8; 16] = [
+        0x59, 0x55, 0x59, 0x32, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b,
+        0x71,
+    ];
+
+    #[test]
+    fn fourcc_guids() {
+        let guid = Guid(GUID_YUY2);
+        assert_eq!(guid.fourcc(), Some(*b"YUY2"));
+        assert_eq!(guid.frame_format(), Some(FrameFormat::YUYV));
+        assert_eq!(Guid::from_fourcc(*b"YUY2"), guid);
+        assert_eq!(FrameFormat::YUYV.guid(), Some(guid));
+        assert_eq!(guid.to_string(), "32595559-0000-0010-8000-00aa00389b71");
+    }
+
+    #[test]
+    fn known_guids() {
+        let format = |fourcc: &[u8; 4]| Guid::from_fourcc(*fourcc).frame_format();
+        assert_eq!(format(b"NV12"), Some(FrameFormat::NV12));
+        assert_eq!(format(b"Y800"), Some(FrameFormat::GRAY8));
+        assert_eq!(format(b"Y8  "), Some(FrameFormat::GRAY8));
+        assert_eq!(format(b"Y16 "), Some(FrameFormat::GRAY16));
+        assert_eq!(format(b"Z16 "), Some(FrameFormat::Z16));
+        assert_eq!(format(b"H265"), Some(FrameFormat::HEVC));
+        assert_eq!(format(b"HEVC"), Some(FrameFormat::HEVC));
+        assert_eq!(format(b"XXXX"), None);
+
+        assert_eq!(
+            Guid(GUID_BGR24.0).to_string(),
+            "e436eb7d-524f-11ce-9f53-0020af0ba770"
+        );
+        assert_eq!(GUID_BGR24.frame_format(), Some(FrameFormat::BGR));
+        assert_eq!(GUID_BGR24.fourcc(), None);
+    }
+
+    #[test]
+    fn unknown_guid() {
+        let mut guid = GUID_YUY2;
+        guid[15] ^= 1;
+        assert_eq!(Guid(guid).fourcc(), None);
+        assert_eq!(Guid(guid).frame_format(), None);
+    }
+
+    #[test]
+    fn every_guid_maps_back() {
+        for &(_, format) in FOURCC_FORMATS {
+            let guid = format.guid().unwrap();
+            assert_eq!(guid.frame_format(), Some(format), "{:?}", format);
+        }
+        assert_eq!(FrameFormat::BGR.guid(), Some(GUID_BGR24));
+        assert_eq!(FrameFormat::Any.guid(), None);
+    }
+
+    #[test]
+    fn accepted_formats() {
+        assert!(FrameFormat::Any.accepts(FrameFormat::H264));
+        assert!(FrameFormat::Uncompressed.accepts(FrameFormat::NV12));
+        assert!(!FrameFormat::Uncompressed.accepts(FrameFormat::MJPEG));
+        assert!(FrameFormat::Compressed.accepts(FrameFormat::HEVC));
+        assert!(!FrameFormat::Compressed.accepts(FrameFormat::YUYV));
+        assert!(FrameFormat::YUYV.accepts(FrameFormat::YUYV));
+        assert!(!FrameFormat::YUYV.accepts(FrameFormat::UYVY));
+    }
+
+    #[test]
+    fn line_bytes() {
+        assert_eq!(FrameFormat::YUYV.line_bytes(640), Some(1280));
+        assert_eq!(FrameFormat::RGB.line_bytes(640), Some(1920));
+        assert_eq!(FrameFormat::GRAY8.line_bytes(641), Some(641));
+        assert_eq!(FrameFormat::NV12.line_bytes(640), Some(640));
+        assert_eq!(FrameFormat::I420.line_bytes(640), Some(640));
+        assert_eq!(FrameFormat::P010.line_bytes(640), Some(1280));
+        assert_eq!(FrameFormat::MJPEG.line_bytes(640), None);
+    }
+
+    #[test]
+    fn frame_rates() {
+        assert_eq!(FrameInterval::from_fps(30), FrameInterval(333_333));
+        assert_eq!(
+            FrameInterval::from_rate(30_000, 1001),
+            FrameInterval(333_667)
+        );
+        assert_eq!(FrameInterval(333_667).rate(), (10_000_000, 333_667));
+        assert_eq!(FrameInterval(500_000).rate(), (20, 1));
+        assert_eq!(FrameInterval::from_fps(0), FrameInterval(u32::MAX));
+    }
+}
@@ -1,37 +1,161 @@
+use std::mem::ManuallyDrop;
 use std::ptr::NonNull;
 use std::slice;
+use std::time::Duration;
 
-use crate::error::{Error, Result};
+use crate::color::{self, ColorMatching, MatrixCoefficients, Packed422};
+use crate::device::FormatDescriptor;
+use crate::error::{Error, ErrorKind, Operation, Result, ResultExt};
 use crate::formats::FrameFormat;
+use crate::interlace::Interlace;
+use crate::nal::NalUnits;
+use crate::pool::FramePool;
 
 use uvc_sys::*;
 
 unsafe impl Send for Frame {}
 unsafe impl Sync for Frame {}
+#[derive(Debug, Copy, Clone)]
+/// What the format descriptor of a stream declares about its frames
+pub(crate) struct FormatHints {
+    /// Also known for formats `libuvc` does not know
+    pub(crate) format: FrameFormat,
+    pub(crate) color_matching: ColorMatching,
+    pub(crate) interlace: Interlace,
+}
+
+impl FormatHints {
+    pub(crate) fn from_descriptor(desc: &FormatDescriptor) -> Self {
+        FormatHints {
+            format: desc.frame_format(),
+            color_matching: desc.color_matching(),
+            interlace: desc.interlace(),
+        }
+    }
+}
+
 #[derive(Debug)]
 /// Frame containing the image data
 pub struct Frame {
     frame: NonNull<uvc_frame>,
+    hints: Option<FormatHints>,
+    /// Metadata of frames not produced by `libuvc`
+    metadata: Option<Box<[u8]>>,
 }
 
 impl Frame {
     pub(crate) unsafe fn from_raw(frame: *mut uvc_frame) -> Frame {
         Frame {
             frame: NonNull::new(frame).unwrap(),
+            hints: None,
+            metadata: None,
         }
     }
 
+    pub(crate) fn with_hints(mut self, hints: Option<FormatHints>) -> Frame {
+        self.hints = hints;
+        self
+    }
+
     /// Does not initialize any data
     unsafe fn new_with_dimensions(width: u32, height: u32, components: u32) -> Self {
-        let frame = uvc_allocate_frame((width * height * components) as _);
+        let frame = allocate_frame((width * height * components) as _);
 
         Frame {
             frame: NonNull::new(frame).unwrap(),
+            hints: None,
+            metadata: None,
         }
     }
 
+    /// Allocates a packed frame with the sequence number and capture time of `self`
+    ///
+    /// The data must be filled in through `data_mut`
+    pub(crate) fn new_derived(
+        &self,
+        width: u32,
+        height: u32,
+        bytes_per_pixel: u32,
+        format: FrameFormat,
+        hints: Option<FormatHints>,
+    ) -> Frame {
+        unsafe {
+            let new_frame =
+                Frame::new_with_dimensions(width, height, bytes_per_pixel).with_hints(hints);
+            let raw = &mut *new_frame.frame.as_ptr();
+            let source = &*self.frame.as_ptr();
+            raw.width = width;
+            raw.height = height;
+            raw.frame_format = format.into();
+            raw.step = (width * bytes_per_pixel) as usize;
+            raw.sequence = source.sequence;
+            raw.capture_time = source.capture_time;
+            new_frame
+        }
+    }
+
+    /// Hints for a frame derived from this one after deinterlacing
+    pub(crate) fn progressive_hints(&self) -> Option<FormatHints> {
+        self.hints.map(|hints| FormatHints {
+            interlace: Interlace::default(),
+            ..hints
+        })
+    }
+
+    /// Frame holding a copy of `data`
+    pub(crate) fn from_bytes(
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: FrameFormat,
+        metadata: Option<Box<[u8]>>,
+    ) -> Frame {
+        unsafe {
+            let mut frame = Frame::from_raw(allocate_frame(data.len()));
+            // The buffer is uninitialized, so it is written without creating a slice
+            if !data.is_empty() {
+                std::ptr::copy_nonoverlapping(data.as_ptr(), frame.raw().data.cast(), data.len());
+            }
+            frame.metadata = metadata;
+            let raw = frame.raw_mut();
+            raw.width = width;
+            raw.height = height;
+            raw.frame_format = format.into();
+            raw.step = format.line_bytes(width).unwrap_or(0);
+            frame
+        }
+    }
+
+    pub(crate) fn raw(&self) -> &uvc_frame {
+        unsafe { self.frame.as_ref() }
+    }
+
+    pub(crate) fn raw_mut(&mut self) -> &mut uvc_frame {
+        unsafe { self.frame.as_mut() }
+    }
+
+    pub(crate) fn data_mut(&mut self) -> &mut [u8] {
+        let raw = self.raw_mut();
+        // Frames allocated without data have a null pointer
+        if raw.data.is_null() || raw.data_bytes == 0 {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(raw.data as *mut u8, raw.data_bytes) }
+    }
+
+    /// Bytes from the start of one line to the next
+    #[must_use]
+    pub fn step(&self) -> usize {
+        unsafe { (*self.frame.as_ptr()).step }
+    }
+
     /// Convert to rgb format
+    ///
+    /// YUV formats are converted with the matrix declared by the stream
     pub fn to_rgb(&self) -> Result<Frame> {
+        if let Some(frame) = self.convert_packed_422(self.color_matching().matrix, false) {
+            return frame;
+        }
         let new_frame = unsafe { Frame::new_with_dimensions(self.width(), self.height(), 3) }; // RGB -> 3 bytes
 
         let err = unsafe {
@@ -42,18 +166,30 @@ impl Frame {
                 FrameFormat::Any => uvc_any2rgb(self.frame.as_ptr(), new_frame.frame.as_ptr()),
                 _ => uvc_any2rgb(self.frame.as_ptr(), new_frame.frame.as_ptr()),
             }
-        }
-        .into();
+        };
 
-        if err == Error::Success {
-            Ok(new_frame)
-        } else {
-            Err(err)
+        Error::check(err).during(Operation::ConvertFrame {
+            from: self.format(),
+            to: FrameFormat::RGB,
+        })?;
+        Ok(new_frame)
+    }
+
+    /// Convert to rgb format, using the given matrix for YUV formats
+    pub fn to_rgb_with_matrix(&self, matrix: MatrixCoefficients) -> Result<Frame> {
+        match self.convert_packed_422(matrix, false) {
+            Some(frame) => frame,
+            None => self.to_rgb(),
         }
     }
 
     /// Convert to bgr format
+    ///
+    /// YUV formats are converted with the matrix declared by the stream
     pub fn to_bgr(&self) -> Result<Frame> {
+        if let Some(frame) = self.convert_packed_422(self.color_matching().matrix, true) {
+            return frame;
+        }
         let new_frame = unsafe { Frame::new_with_dimensions(self.width(), self.height(), 3) }; // BGR -> 3 bytes
 
         let err = unsafe {
@@ -63,25 +199,86 @@ impl Frame {
                 FrameFormat::Any => uvc_any2bgr(self.frame.as_ptr(), new_frame.frame.as_ptr()),
                 _ => uvc_any2bgr(self.frame.as_ptr(), new_frame.frame.as_ptr()),
             }
+        };
+
+        Error::check(err).during(Operation::ConvertFrame {
+            from: self.format(),
+            to: FrameFormat::BGR,
+        })?;
+        Ok(new_frame)
+    }
+
+    /// Convert to bgr format, using the given matrix for YUV formats
+    pub fn to_bgr_with_matrix(&self, matrix: MatrixCoefficients) -> Result<Frame> {
+        match self.convert_packed_422(matrix, true) {
+            Some(frame) => frame,
+            None => self.to_bgr(),
         }
-        .into();
+    }
 
-        if err == Error::Success {
-            Ok(new_frame)
+    /// Converts YUYV and UYVY, returns `None` for other formats
+    fn convert_packed_422(&self, matrix: MatrixCoefficients, bgr: bool) -> Option<Result<Frame>> {
+        let layout = match self.format() {
+            FrameFormat::YUYV => Packed422::Yuyv,
+            FrameFormat::UYVY => Packed422::Uyvy,
+            _ => return None,
+        };
+        let to = if bgr {
+            FrameFormat::BGR
         } else {
-            Err(err)
+            FrameFormat::RGB
+        };
+        let (width, height) = (self.width(), self.height());
+        let src = self.to_bytes();
+        if src.len() < (width * height * 2) as usize {
+            return Some(Err(Error::from(ErrorKind::InvalidParam).during(
+                Operation::ConvertFrame {
+                    from: self.format(),
+                    to,
+                },
+            )));
         }
+
+        let mut new_frame = self.new_derived(width, height, 3, to, None);
+        color::yuv422_to_rgb(src, new_frame.data_mut(), layout, matrix, bgr);
+        Some(Ok(new_frame))
+    }
+
+    /// Colour space declared by the stream, or the UVC defaults
+    #[must_use]
+    pub fn color_matching(&self) -> ColorMatching {
+        self.hints
+            .map(|hints| hints.color_matching)
+            .unwrap_or_default()
+    }
+
+    /// Interlacing declared by the stream, progressive if unknown
+    #[must_use]
+    pub fn interlace(&self) -> Interlace {
+        self.hints.map(|hints| hints.interlace).unwrap_or_default()
     }
 
     /// Get the raw image data
     #[must_use]
     pub fn to_bytes(&self) -> &[u8] {
-        unsafe {
-            slice::from_raw_parts(
-                (*self.frame.as_ptr()).data as *const u8,
-                (*self.frame.as_ptr()).data_bytes as _,
-            )
+        let raw = self.raw();
+        if raw.data.is_null() || raw.data_bytes == 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(raw.data as *const u8, raw.data_bytes as _) }
+    }
+
+    /// Metadata sent by the device with the frame, such as UVC 1.5 metadata payloads
+    #[must_use]
+    pub fn metadata(&self) -> &[u8] {
+        if let Some(metadata) = &self.metadata {
+            return metadata;
+        }
+        let raw = self.raw();
+        if raw.metadata.is_null() || raw.metadata_bytes == 0 {
+            return &[];
         }
+        unsafe { slice::from_raw_parts(raw.metadata as *const u8, raw.metadata_bytes) }
     }
 
     /// Width of the captured frame
@@ -99,7 +296,38 @@ impl Frame {
     /// Format of the captured frame
     #[must_use]
     pub fn format(&self) -> FrameFormat {
-        unsafe { *self.frame.as_ptr() }.frame_format.into()
+        let format = unsafe { *self.frame.as_ptr() }.frame_format.into();
+        match format {
+            FrameFormat::Any | FrameFormat::Unknown | FrameFormat::Uncompressed => {
+                self.hints.map_or(format, |hints| hints.format)
+            }
+            _ => format,
+        }
+    }
+
+    /// NAL units of an H.264 or H.265 frame, which holds an Annex B access unit
+    ///
+    /// Empty for other formats
+    #[must_use]
+    pub fn nal_units(&self) -> NalUnits<'_> {
+        match self.format() {
+            FrameFormat::H264 | FrameFormat::HEVC => NalUnits::new(self.to_bytes()),
+            _ => NalUnits::new(&[]),
+        }
+    }
+
+    /// Whether the frame can be decoded without previous frames
+    ///
+    /// True for every format without inter frame compression
+    #[must_use]
+    pub fn is_keyframe(&self) -> bool {
+        match self.format() {
+            format @ (FrameFormat::H264 | FrameFormat::HEVC) => {
+                self.nal_units().any(|unit| unit.is_keyframe(format))
+            }
+            FrameFormat::FrameBased => false,
+            _ => true,
+        }
     }
 
     /// Monotonically increasing frame number
@@ -108,22 +336,179 @@ impl Frame {
         unsafe { (*self.frame.as_ptr()).sequence }
     }
 
+    /// Time on the monotonic clock at which the device finished sending the frame
+    ///
+    /// Only available on platforms where `libuvc` stamps frames with the monotonic clock
+    #[must_use]
+    pub fn capture_time(&self) -> Option<Duration> {
+        let finished = unsafe { (*self.frame.as_ptr()).capture_time_finished };
+        if finished.tv_sec == 0 && finished.tv_nsec == 0 {
+            return None;
+        }
+        Some(Duration::new(
+            finished.tv_sec as u64,
+            finished.tv_nsec as u32,
+        ))
+    }
+
+    /// Time since the device finished sending the frame
+    ///
+    /// Only available on platforms where `libuvc` stamps frames with the monotonic clock
+    #[must_use]
+    pub fn capture_latency(&self) -> Option<Duration> {
+        monotonic_now()?.checked_sub(self.capture_time()?)
+    }
+
+    /// Moves the image data into `spare`, leaving `self` with the buffer of `spare`
+    ///
+    /// The properties of the frame are copied along.
+    ///
+    /// # Safety
+    ///
+    /// `self` must be the frame `libuvc` passes to the stream callback,
+    /// whose buffer `libuvc` grows with `realloc` as needed.
+    pub(crate) unsafe fn swap_buffer(&self, mut spare: Frame) -> Frame {
+        spare.hints = self.hints;
+        spare.metadata = match self.metadata() {
+            [] => None,
+            metadata => Some(metadata.into()),
+        };
+        // Taken after reading the metadata, which borrows the frame again
+        let source = &mut *self.frame.as_ptr();
+        let raw = spare.raw_mut();
+        std::mem::swap(&mut source.data, &mut raw.data);
+        std::mem::swap(&mut source.data_bytes, &mut raw.data_bytes);
+        raw.width = source.width;
+        raw.height = source.height;
+        raw.frame_format = source.frame_format;
+        raw.step = source.step;
+        raw.sequence = source.sequence;
+        raw.capture_time = source.capture_time;
+        raw.capture_time_finished = source.capture_time_finished;
+        raw.source = source.source;
+        raw.library_owns_data = 1;
+        spare
+    }
+
+    /// Forgets the properties of a frame whose buffer is kept for reuse
+    pub(crate) fn recycle(&mut self) {
+        self.hints = None;
+        self.metadata = None;
+    }
+
     /// Clones a frame
     pub fn duplicate(&self) -> Result<Frame> {
         unsafe {
-            let mut new_frame = Frame::from_raw(uvc_allocate_frame(0));
+            let mut new_frame = Frame::from_raw(allocate_frame(0)).with_hints(self.hints);
+            new_frame.metadata = self.metadata.clone();
 
-            let err = uvc_duplicate_frame(self.frame.as_ptr(), new_frame.frame.as_mut()).into();
-            if err != Error::Success {
-                return Err(err);
-            }
+            Error::check(uvc_duplicate_frame(
+                self.frame.as_ptr(),
+                new_frame.frame.as_mut(),
+            ))
+            .during(Operation::DuplicateFrame)?;
             Ok(new_frame)
         }
     }
 }
 
+#[derive(Debug)]
+/// Frame taken out of a stream callback, which can be kept and sent to other threads
+///
+/// A frame taken through a `FramePool` returns its buffer to the pool when dropped.
+pub struct OwnedFrame {
+    frame: ManuallyDrop<Frame>,
+    pool: Option<FramePool>,
+}
+
+impl OwnedFrame {
+    pub(crate) fn pooled(frame: Frame, pool: FramePool) -> Self {
+        OwnedFrame {
+            frame: ManuallyDrop::new(frame),
+            pool: Some(pool),
+        }
+    }
+
+    /// Takes the frame, its buffer is then no longer returned to a pool
+    #[must_use]
+    pub fn into_frame(self) -> Frame {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            drop(std::ptr::read(&this.pool));
+            ManuallyDrop::take(&mut this.frame)
+        }
+    }
+}
+
+impl Drop for OwnedFrame {
+    fn drop(&mut self) {
+        let frame = unsafe { ManuallyDrop::take(&mut self.frame) };
+        match self.pool.take() {
+            Some(pool) => pool.give_back(frame),
+            None => drop(frame),
+        }
+    }
+}
+
+impl std::ops::Deref for OwnedFrame {
+    type Target = Frame;
+
+    fn deref(&self) -> &Frame {
+        &self.frame
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn monotonic_now() -> Option<Duration> {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+        return None;
+    }
+    Some(Duration::new(now.tv_sec as u64, now.tv_nsec as u32))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn monotonic_now() -> Option<Duration> {
+    None
+}
+
 impl Drop for Frame {
     fn drop(&mut self) {
-        unsafe { uvc_free_frame(self.frame.as_ptr()) }
+        unsafe { free_frame(self.frame.as_ptr()) }
+    }
+}
+
+/// Allocates a frame with `data_bytes` of uninitialized data, like `uvc_allocate_frame`
+#[cfg(not(miri))]
+pub(crate) unsafe fn allocate_frame(data_bytes: usize) -> *mut uvc_frame {
+    uvc_allocate_frame(data_bytes)
+}
+
+#[cfg(not(miri))]
+unsafe fn free_frame(frame: *mut uvc_frame) {
+    uvc_free_frame(frame)
+}
+
+// Miri can't call into `libuvc`, these allocate the way it does, so frames
+// of a `VirtualCamera` can be checked
+#[cfg(miri)]
+pub(crate) unsafe fn allocate_frame(data_bytes: usize) -> *mut uvc_frame {
+    let frame = libc::calloc(1, std::mem::size_of::<uvc_frame>()).cast::<uvc_frame>();
+    if data_bytes > 0 {
+        (*frame).data = libc::malloc(data_bytes);
+    }
+    (*frame).data_bytes = data_bytes;
+    (*frame).library_owns_data = 1;
+    frame
+}
+
+#[cfg(miri)]
+unsafe fn free_frame(frame: *mut uvc_frame) {
+    if (*frame).data_bytes > 0 && (*frame).library_owns_data != 0 {
+        libc::free((*frame).data);
     }
+    libc::free(frame.cast());
 }
@@ -0,0 +1,498 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::backend::{Backend, BackendStream};
+use crate::channel::{channel, Overflow, Receiver, Sender};
+use crate::error::Result;
+use crate::formats::StreamFormat;
+use crate::frame::{monotonic_now, Frame};
+use crate::stats::StreamStats;
+use crate::streaming::{PanicPolicy, StreamStatus};
+
+/// Frames a camera may have waiting for its partners, before the oldest is given up
+const PENDING: usize = 16;
+
+#[derive(Debug, Copy, Clone, Default)]
+/// Clock by which a `CaptureGroup` aligns frames
+///
+/// All cameras of a group must be stamped by the same clock.
+pub enum Timestamps {
+    /// When the frame reached the callback
+    #[default]
+    Arrival,
+    /// `Frame::capture_time`, falling back to the arrival of frames without it
+    Capture,
+    /// Derived from the frame, such as from the SCR of UVC 1.5 metadata,
+    /// falling back to the arrival when `None` is returned
+    Custom(fn(&Frame) -> Option<Duration>),
+}
+
+impl Timestamps {
+    fn of(self, frame: &Frame, arrival: Duration) -> Duration {
+        match self {
+            Timestamps::Arrival => None,
+            Timestamps::Capture => frame.capture_time(),
+            Timestamps::Custom(f) => f(frame),
+        }
+        .unwrap_or(arrival)
+    }
+}
+
+#[derive(Debug)]
+/// Frames of every camera of a `CaptureGroup`, taken within the tolerance of each other
+pub struct FrameSet {
+    frames: Vec<Frame>,
+    timestamps: Vec<Duration>,
+}
+
+impl FrameSet {
+    /// Frames in the order the cameras were added to the group
+    #[must_use]
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    #[must_use]
+    pub fn into_frames(self) -> Vec<Frame> {
+        self.frames
+    }
+
+    /// Timestamps of the frames, on the clock chosen with `CaptureGroup::timestamps`
+    #[must_use]
+    pub fn timestamps(&self) -> &[Duration] {
+        &self.timestamps
+    }
+
+    /// Timestamp of the earliest frame
+    #[must_use]
+    pub fn timestamp(&self) -> Duration {
+        self.timestamps.iter().copied().min().unwrap_or_default()
+    }
+
+    /// Time between the earliest and the latest frame
+    #[must_use]
+    pub fn skew(&self) -> Duration {
+        let latest = self.timestamps.iter().copied().max().unwrap_or_default();
+        latest - self.timestamp()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Statistics of a `CaptureGroup`, the vectors hold one entry per camera
+pub struct GroupStats {
+    /// Frame sets emitted
+    pub sets: u64,
+    /// Frames given up because no frame of another camera was within the tolerance
+    pub unmatched: Vec<u64>,
+    /// Frames arriving after the frames they would have matched were used or given up
+    pub late: Vec<u64>,
+    pub streams: Vec<StreamStats>,
+}
+
+#[derive(Debug)]
+/// Aligns the frames of the cameras by their timestamps
+struct Matcher {
+    tolerance: Duration,
+    /// Frames waiting for partners, per camera, oldest first
+    pending: Vec<VecDeque<(Duration, Frame)>>,
+    /// Timestamp of the last frame of every camera which was used or given up
+    consumed: Vec<Option<Duration>>,
+    sets: u64,
+    unmatched: Vec<u64>,
+    late: Vec<u64>,
+}
+
+impl Matcher {
+    fn new(cameras: usize, tolerance: Duration) -> Self {
+        Matcher {
+            tolerance,
+            pending: (0..cameras).map(|_| VecDeque::new()).collect(),
+            consumed: vec![None; cameras],
+            sets: 0,
+            unmatched: vec![0; cameras],
+            late: vec![0; cameras],
+        }
+    }
+
+    fn stats(&self, streams: Vec<StreamStats>) -> GroupStats {
+        GroupStats {
+            sets: self.sets,
+            unmatched: self.unmatched.clone(),
+            late: self.late.clone(),
+            streams,
+        }
+    }
+
+    fn pop(&mut self, camera: usize) -> Option<(Duration, Frame)> {
+        let (timestamp, frame) = self.pending[camera].pop_front()?;
+        self.consumed[camera] = Some(timestamp);
+        Some((timestamp, frame))
+    }
+
+    /// Queues a frame, returning the sets it completed
+    fn push(&mut self, camera: usize, timestamp: Duration, frame: Frame) -> Vec<FrameSet> {
+        // Every frame another camera could still offer is later than what it consumed
+        let late = self.consumed.iter().enumerate().any(|(other, consumed)| {
+            other != camera && consumed.is_some_and(|c| c >= timestamp + self.tolerance)
+        });
+        if late {
+            self.late[camera] += 1;
+            return Vec::new();
+        }
+        self.pending[camera].push_back((timestamp, frame));
+        if self.pending[camera].len() > PENDING {
+            self.pop(camera);
+            self.unmatched[camera] += 1;
+        }
+
+        let mut sets = Vec::new();
+        loop {
+            let heads: Option<Vec<Duration>> = self
+                .pending
+                .iter()
+                .map(|queue| queue.front().map(|(timestamp, _)| *timestamp))
+                .collect();
+            let Some(heads) = heads else {
+                return sets;
+            };
+            let latest = heads.iter().copied().max().unwrap_or_default();
+            let earliest = heads.iter().copied().min().unwrap_or_default();
+            if latest - earliest <= self.tolerance {
+                let (timestamps, frames) = (0..heads.len()).filter_map(|i| self.pop(i)).unzip();
+                self.sets += 1;
+                sets.push(FrameSet { frames, timestamps });
+                continue;
+            }
+            // Heads too far before the latest can not match its camera anymore
+            for (i, head) in heads.into_iter().enumerate() {
+                if head + self.tolerance < latest {
+                    self.pop(i);
+                    self.unmatched[i] += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Starts streams on several cameras together, and aligns their frames into `FrameSet`s
+///
+/// Frames of the cameras are matched when their timestamps lie within the
+/// tolerance, frames without partners are counted in `GroupStats`.
+/// Every frame is copied out of its stream callback.
+///
+/// ```
+/// use std::time::Duration;
+/// use uvc::{Backend, CaptureGroup, VirtualCamera};
+///
+/// let left = VirtualCamera::webcam().jitter(Duration::from_millis(4));
+/// let right = VirtualCamera::webcam().jitter(Duration::from_millis(4));
+/// let format = left.formats().unwrap()[0];
+/// let tolerance = format.interval.as_duration() / 2;
+///
+/// let (group, sets) = CaptureGroup::new(tolerance)
+///     .camera(&left, format)
+///     .camera(&right, format)
+///     .start()
+///     .unwrap();
+///
+/// for set in sets.iter().take(10) {
+///     assert_eq!(set.frames().len(), 2);
+///     assert!(set.skew() <= tolerance);
+/// }
+/// assert!(group.stats().sets >= 10);
+/// ```
+pub struct CaptureGroup<'a> {
+    cameras: Vec<(&'a dyn Backend, StreamFormat)>,
+    tolerance: Duration,
+    timestamps: Timestamps,
+    policy: PanicPolicy,
+    capacity: usize,
+    overflow: Overflow,
+}
+
+impl<'a> std::fmt::Debug for CaptureGroup<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let formats: Vec<_> = self.cameras.iter().map(|(_, format)| format).collect();
+        f.debug_struct("CaptureGroup")
+            .field("formats", &formats)
+            .field("tolerance", &self.tolerance)
+            .field("timestamps", &self.timestamps)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> CaptureGroup<'a> {
+    /// Group matching frames whose timestamps are at most `tolerance` apart
+    #[must_use]
+    pub fn new(tolerance: Duration) -> Self {
+        CaptureGroup {
+            cameras: Vec::new(),
+            tolerance,
+            timestamps: Timestamps::default(),
+            policy: PanicPolicy::default(),
+            capacity: 4,
+            overflow: Overflow::default(),
+        }
+    }
+
+    /// Streams `format` from `camera`, in addition to the cameras given before
+    #[must_use]
+    pub fn camera(mut self, camera: &'a dyn Backend, format: StreamFormat) -> Self {
+        self.cameras.push((camera, format));
+        self
+    }
+
+    /// Aligns frames by these timestamps, the default is `Timestamps::Arrival`
+    #[must_use]
+    pub fn timestamps(mut self, timestamps: Timestamps) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Handle panics of the stream callbacks with this policy, the default is `PanicPolicy::Abort`
+    #[must_use]
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Queue at most `capacity` sets for the receiver, the default is 4 with `Overflow::DropOldest`
+    #[must_use]
+    pub fn queue(mut self, capacity: usize, overflow: Overflow) -> Self {
+        self.capacity = capacity;
+        self.overflow = overflow;
+        self
+    }
+
+    /// Starts the streams, sending every completed set to the returned receiver
+    ///
+    /// If a stream fails to start, the streams already started are stopped.
+    /// This function is non-blocking.
+    pub fn start(self) -> Result<(GroupStream<'a>, Receiver<FrameSet>)> {
+        let (sender, receiver) = channel(self.capacity, self.overflow);
+        let matcher = Arc::new(Mutex::new(Matcher::new(self.cameras.len(), self.tolerance)));
+        let mut group = GroupStream {
+            streams: Vec::with_capacity(self.cameras.len()),
+            matcher: Arc::clone(&matcher),
+            sender: sender.clone(),
+        };
+        let started = Instant::now();
+        for (index, (camera, format)) in self.cameras.into_iter().enumerate() {
+            let matcher = Arc::clone(&matcher);
+            let sender = sender.clone();
+            let timestamps = self.timestamps;
+            let stream = camera.start_stream(
+                format,
+                self.policy,
+                Box::new(move |frame: &Frame| {
+                    let arrival = monotonic_now().unwrap_or_else(|| started.elapsed());
+                    let timestamp = timestamps.of(frame, arrival);
+                    let Ok(frame) = frame.duplicate() else {
+                        return;
+                    };
+                    let mut matcher = lock(&matcher);
+                    // Sent under the lock, so sets are received in order
+                    for set in matcher.push(index, timestamp, frame) {
+                        sender.send(set);
+                    }
+                }),
+            )?;
+            // Without frames of this camera no further set can complete
+            let closing = group.sender.clone();
+            stream.on_end(Box::new(move || closing.close()));
+            group.streams.push(stream);
+        }
+        Ok((group, receiver))
+    }
+}
+
+fn lock(matcher: &Mutex<Matcher>) -> MutexGuard<'_, Matcher> {
+    matcher
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Running streams of a `CaptureGroup`
+///
+/// Dropping this stream will stop the streams. Once it is dropped or one of its
+/// streams has ended, the receiver returns the sets still queued before reporting
+/// the end of the group.
+pub struct GroupStream<'a> {
+    streams: Vec<Box<dyn BackendStream + 'a>>,
+    matcher: Arc<Mutex<Matcher>>,
+    sender: Sender<FrameSet>,
+}
+
+impl<'a> std::fmt::Debug for GroupStream<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("GroupStream")
+            .field("streams", &self.streams.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> GroupStream<'a> {
+    /// Statistics of the sets and frames so far
+    #[must_use]
+    pub fn stats(&self) -> GroupStats {
+        lock(&self.matcher).stats(self.streams.iter().map(|stream| stream.stats()).collect())
+    }
+
+    /// Whether the streams still deliver frames, in the order the cameras were added
+    #[must_use]
+    pub fn status(&self) -> Vec<StreamStatus> {
+        self.streams.iter().map(|stream| stream.status()).collect()
+    }
+
+    /// Stop the streams
+    pub fn stop(self) {
+        // Taking ownership of the streams, which drops them
+    }
+}
+
+impl<'a> Drop for GroupStream<'a> {
+    fn drop(&mut self) {
+        // A callback blocked on a full channel would keep its stream from stopping
+        self.sender.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::RecvError;
+
+    use super::*;
+    use crate::formats::{FrameFormat, FrameInterval};
+    use crate::virtual_camera::{Fault, VirtualCamera};
+
+    const TOLERANCE: Duration = Duration::from_millis(5);
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// Frame holding `tag`, to tell which frames were matched
+    fn frame(tag: u8) -> Frame {
+        Frame::from_bytes(&[tag], 1, 1, FrameFormat::GRAY8, None)
+    }
+
+    fn tags(set: &FrameSet) -> Vec<u8> {
+        set.frames()
+            .iter()
+            .map(|frame| frame.to_bytes()[0])
+            .collect()
+    }
+
+    fn stats(sets: u64, unmatched: [u64; 2], late: [u64; 2]) -> GroupStats {
+        GroupStats {
+            sets,
+            unmatched: unmatched.to_vec(),
+            late: late.to_vec(),
+            streams: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn jittered_frames_are_matched() {
+        let mut matcher = Matcher::new(2, TOLERANCE);
+        let left = [0, 35, 66, 101];
+        let right = [3, 32, 69, 98];
+        let mut sets = Vec::new();
+        for (i, (&l, &r)) in left.iter().zip(&right).enumerate() {
+            let tag = i as u8;
+            // The cameras take turns arriving first
+            if i % 2 == 0 {
+                sets.extend(matcher.push(0, ms(l), frame(tag)));
+                sets.extend(matcher.push(1, ms(r), frame(tag)));
+            } else {
+                sets.extend(matcher.push(1, ms(r), frame(tag)));
+                sets.extend(matcher.push(0, ms(l), frame(tag)));
+            }
+        }
+
+        assert_eq!(sets.len(), 4);
+        for (i, set) in sets.iter().enumerate() {
+            assert_eq!(tags(set), [i as u8; 2]);
+            assert_eq!(set.timestamps(), [ms(left[i]), ms(right[i])]);
+            assert_eq!(set.timestamp(), ms(left[i].min(right[i])));
+            assert_eq!(set.skew(), ms(left[i].abs_diff(right[i])));
+        }
+        assert_eq!(matcher.stats(Vec::new()), stats(4, [0, 0], [0, 0]));
+    }
+
+    #[test]
+    fn frames_without_partners_are_unmatched() {
+        let mut matcher = Matcher::new(2, TOLERANCE);
+        // The right camera lost its first frame, and the left its third
+        assert!(matcher.push(0, ms(0), frame(0)).is_empty());
+        assert!(matcher.push(0, ms(33), frame(1)).is_empty());
+        let sets = matcher.push(1, ms(35), frame(1));
+        assert_eq!(sets.len(), 1);
+        assert_eq!(tags(&sets[0]), [1, 1]);
+        assert!(matcher.push(1, ms(68), frame(2)).is_empty());
+        assert!(matcher.push(1, ms(101), frame(3)).is_empty());
+        let sets = matcher.push(0, ms(99), frame(3));
+        assert_eq!(sets.len(), 1);
+        assert_eq!(tags(&sets[0]), [3, 3]);
+
+        assert_eq!(matcher.stats(Vec::new()), stats(2, [1, 1], [0, 0]));
+    }
+
+    #[test]
+    fn frames_after_their_partners_are_late() {
+        let mut matcher = Matcher::new(2, TOLERANCE);
+        assert!(matcher.push(0, ms(0), frame(0)).is_empty());
+        assert_eq!(matcher.push(1, ms(2), frame(0)).len(), 1);
+        assert!(matcher.push(0, ms(33), frame(1)).is_empty());
+        assert_eq!(matcher.push(1, ms(34), frame(1)).len(), 1);
+        // Delivered out of order, after the left camera moved past it
+        assert!(matcher.push(1, ms(10), frame(2)).is_empty());
+        // Within the tolerance of what the left camera consumed is not late
+        assert!(matcher.push(1, ms(30), frame(3)).is_empty());
+
+        assert_eq!(matcher.stats(Vec::new()), stats(2, [0, 0], [0, 1]));
+    }
+
+    #[test]
+    fn pending_frames_overflow() {
+        let mut matcher = Matcher::new(2, TOLERANCE);
+        // The right camera stalls while the left keeps delivering
+        for i in 0..=PENDING as u64 {
+            assert!(matcher.push(0, ms(i * 33), frame(i as u8)).is_empty());
+        }
+        assert_eq!(matcher.pending[0].len(), PENDING);
+        assert_eq!(matcher.stats(Vec::new()), stats(0, [1, 0], [0, 0]));
+
+        // The oldest frame was given up, the right camera matches the next one
+        let sets = matcher.push(1, ms(34), frame(1));
+        assert_eq!(sets.len(), 1);
+        assert_eq!(tags(&sets[0]), [1, 1]);
+        assert_eq!(matcher.stats(Vec::new()), stats(1, [1, 0], [0, 0]));
+    }
+
+    fn camera() -> (VirtualCamera, StreamFormat) {
+        let format = StreamFormat {
+            width: 8,
+            height: 8,
+            interval: FrameInterval::from_fps(200),
+            format: FrameFormat::GRAY8,
+        };
+        (VirtualCamera::new().format(format), format)
+    }
+
+    #[test]
+    fn unplugging_a_camera_ends_the_receiver() {
+        let (left, format) = camera();
+        let (right, _) = camera();
+        let (group, sets) = CaptureGroup::new(ms(5))
+            .camera(&left, format)
+            .camera(&right, format)
+            .start()
+            .unwrap();
+        right.inject_after(5, Fault::Disconnect);
+        while sets.recv().is_ok() {}
+        assert!(matches!(sets.recv(), Err(RecvError)));
+        assert_eq!(group.status()[1], StreamStatus::Ended);
+    }
+}
@@ -0,0 +1,669 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use jpeg_encoder::{ColorType, Encoder};
+use serde_json::{json, Map, Value};
+
+use crate::backend::{Backend, Control};
+use crate::formats::{FrameFormat, StreamFormat};
+use crate::frame::Frame;
+use crate::mjpeg;
+use crate::streaming::PanicPolicy;
+
+/// Separates the images of `/stream`
+const BOUNDARY: &str = "uvcframe";
+/// How long a client waits for a frame before giving up
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest request body accepted by `/controls`
+const MAX_BODY: usize = 64 * 1024;
+/// Longest request or header line accepted
+const MAX_LINE: usize = 8 * 1024;
+/// Most header lines accepted in a request
+const MAX_HEADERS: usize = 64;
+/// How long reading from or writing to a client may stall before it is dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pause before accepting again after running out of file descriptors or memory
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+const INDEX: &str = "<!DOCTYPE html>
+<html>
+<head><title>uvc</title></head>
+<body style=\"margin: 0; background: black\">
+<img src=\"/stream\" style=\"display: block; margin: auto; max-width: 100%\">
+</body>
+</html>
+";
+
+#[derive(Debug, Default)]
+struct Latest {
+    /// Number of frames encoded so far
+    sequence: u64,
+    jpeg: Option<Arc<[u8]>>,
+    /// The server stopped accepting clients, those left are sent away
+    closed: bool,
+}
+
+#[derive(Debug, Default)]
+/// Latest image, shared between the stream callback and the clients
+struct Shared {
+    latest: Mutex<Latest>,
+    updated: Condvar,
+    /// Clients waiting for images, frames are only encoded while there are any
+    viewers: AtomicUsize,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Latest> {
+        self.latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn publish(&self, jpeg: Vec<u8>) {
+        let mut latest = self.lock();
+        latest.sequence += 1;
+        latest.jpeg = Some(jpeg.into());
+        self.updated.notify_all();
+    }
+
+    /// Waits for an image newer than `seen`, `None` when none arrived in time or the server closed
+    fn next(&self, seen: u64) -> Option<(u64, Arc<[u8]>)> {
+        let (latest, _) = self
+            .updated
+            .wait_timeout_while(self.lock(), FRAME_TIMEOUT, |latest| {
+                latest.sequence <= seen && !latest.closed
+            })
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let jpeg = latest.jpeg.clone()?;
+        (latest.sequence > seen && !latest.closed).then_some((latest.sequence, jpeg))
+    }
+
+    /// Releases the clients waiting for images
+    fn close(&self) {
+        self.lock().closed = true;
+        self.updated.notify_all();
+    }
+}
+
+/// Counts a client as viewer while alive
+struct Viewer<'a>(&'a Shared);
+
+impl<'a> Viewer<'a> {
+    fn new(shared: &'a Shared) -> Self {
+        shared.viewers.fetch_add(1, Ordering::SeqCst);
+        Viewer(shared)
+    }
+}
+
+impl<'a> Drop for Viewer<'a> {
+    fn drop(&mut self) {
+        self.0.viewers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// What a failed `accept` means for the server
+enum AcceptFailure {
+    /// The connection failed before it was accepted
+    Connection,
+    /// The process or system ran out of file descriptors or memory for now
+    Resources,
+    /// The listener can not accept anymore
+    Listener,
+}
+
+fn accept_failure(err: &io::Error) -> AcceptFailure {
+    match err.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted => return AcceptFailure::Connection,
+        io::ErrorKind::OutOfMemory => return AcceptFailure::Resources,
+        _ => {}
+    }
+    #[cfg(unix)]
+    if let Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) = err.raw_os_error() {
+        return AcceptFailure::Resources;
+    }
+    AcceptFailure::Listener
+}
+
+/// Encodes a frame as JPEG, passing MJPEG frames through
+fn encode(frame: &Frame, quality: u8) -> Option<Vec<u8>> {
+    if frame.format() == FrameFormat::MJPEG {
+        return Some(mjpeg::to_jpeg(frame.to_bytes()).into_owned());
+    }
+    let width = u16::try_from(frame.width()).ok()?;
+    let height = u16::try_from(frame.height()).ok()?;
+    let mut jpeg = Vec::new();
+    let encoder = Encoder::new(&mut jpeg, quality);
+    if frame.format() == FrameFormat::GRAY8 {
+        encoder.encode(frame.to_bytes(), width, height, ColorType::Luma)
+    } else {
+        let rgb = frame.to_rgb().ok()?;
+        encoder.encode(rgb.to_bytes(), width, height, ColorType::Rgb)
+    }
+    .ok()?;
+    Some(jpeg)
+}
+
+/// Serves a camera to browsers over HTTP
+///
+/// The server offers
+///
+/// * `/`, a page showing the stream
+/// * `/stream`, the stream as `multipart/x-mixed-replace` MJPEG
+/// * `/snapshot`, the next frame as JPEG
+/// * `/controls`, the controls as JSON; a `POST` of an object such as
+///   `{"brightness": 10}` changes them
+///
+/// MJPEG frames are sent as the camera encoded them, other formats are
+/// converted with `Frame::to_rgb` and encoded. Frames are only encoded while
+/// a client is waiting for them.
+///
+/// ```no_run
+/// use std::net::TcpListener;
+///
+/// # let ctx = uvc::Context::new().unwrap();
+/// # let dev = ctx.find(&uvc::DeviceQuery::new()).unwrap();
+/// # let devh = dev.open().unwrap();
+/// let format = devh.get_preferred_format(|x, _| x).unwrap();
+/// let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
+/// uvc::HttpServer::new(&devh, format).serve(listener).unwrap();
+/// ```
+pub struct HttpServer<'a, B: ?Sized> {
+    camera: &'a B,
+    format: StreamFormat,
+    quality: u8,
+    policy: PanicPolicy,
+}
+
+impl<'a, B: ?Sized> std::fmt::Debug for HttpServer<'a, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HttpServer")
+            .field("format", &self.format)
+            .field("quality", &self.quality)
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, B: Backend + Sync + ?Sized> HttpServer<'a, B> {
+    /// Server streaming `format` from `camera`
+    #[must_use]
+    pub fn new(camera: &'a B, format: StreamFormat) -> Self {
+        HttpServer {
+            camera,
+            format,
+            quality: 85,
+            policy: PanicPolicy::default(),
+        }
+    }
+
+    /// JPEG quality from 1 to 100 for formats which are encoded, the default is 85
+    #[must_use]
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = quality.clamp(1, 100);
+        self
+    }
+
+    /// Handle panics of the stream callback with this policy, the default is `PanicPolicy::Abort`
+    #[must_use]
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Starts the stream and serves clients, every client on its own thread
+    ///
+    /// Connections which fail while being accepted are skipped, as is a lack of
+    /// file descriptors or memory for the moment. Blocks until the listener itself
+    /// fails, then ends the streams to the clients and returns the error once their
+    /// threads are done. A nonblocking listener fails once no client is waiting.
+    pub fn serve(self, listener: TcpListener) -> io::Result<()> {
+        let shared = Arc::new(Shared::default());
+        let stream = {
+            let shared = Arc::clone(&shared);
+            let quality = self.quality;
+            self.camera
+                .start_stream(
+                    self.format,
+                    self.policy,
+                    Box::new(move |frame: &Frame| {
+                        if shared.viewers.load(Ordering::SeqCst) == 0 {
+                            return;
+                        }
+                        if let Some(jpeg) = encode(frame, quality) {
+                            shared.publish(jpeg);
+                        }
+                    }),
+                )
+                .map_err(io::Error::other)?
+        };
+        let (server, shared) = (&self, &*shared);
+        let result = thread::scope(|scope| -> io::Result<()> {
+            let result = loop {
+                let client = match listener.accept() {
+                    Ok((client, _)) => client,
+                    Err(err) => match accept_failure(&err) {
+                        AcceptFailure::Connection => continue,
+                        AcceptFailure::Resources => {
+                            thread::sleep(ACCEPT_BACKOFF);
+                            continue;
+                        }
+                        AcceptFailure::Listener => break Err(err),
+                    },
+                };
+                scope.spawn(move || {
+                    // A client going away is not an error of the server
+                    let _ = server.handle(client, shared);
+                });
+            };
+            // The scope waits for the clients, which leave once released
+            shared.close();
+            result
+        });
+        drop(stream);
+        result
+    }
+
+    fn handle(&self, client: TcpStream, shared: &Shared) -> io::Result<()> {
+        // A stalled client would otherwise hold its thread, and `serve`, forever
+        client.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        client.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut reader = BufReader::new(client.try_clone()?);
+        let mut request = String::new();
+        read_line(&mut reader, &mut request)?;
+        let mut parts = request.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let path = target.split('?').next().unwrap_or("");
+
+        let mut length = 0;
+        for headers in 0.. {
+            let mut header = String::new();
+            if read_line(&mut reader, &mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if headers == MAX_HEADERS {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "too many header lines",
+                ));
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+
+        let mut client = client;
+        match (method, path) {
+            ("GET", "/") => respond(&mut client, "200 OK", "text/html", INDEX.as_bytes()),
+            ("GET", "/stream") => self.stream(&mut client, shared),
+            ("GET", "/snapshot" | "/snapshot.jpg") => {
+                let _viewer = Viewer::new(shared);
+                let seen = shared.lock().sequence;
+                match shared.next(seen) {
+                    Some((_, jpeg)) => respond(&mut client, "200 OK", "image/jpeg", &jpeg),
+                    None => respond_json(
+                        &mut client,
+                        "503 Service Unavailable",
+                        &json!({ "error": "no frame from the camera" }),
+                    ),
+                }
+            }
+            ("GET", "/controls") => respond_json(&mut client, "200 OK", &self.controls()),
+            ("POST" | "PUT", "/controls") => {
+                if length > MAX_BODY {
+                    return respond_json(
+                        &mut client,
+                        "413 Payload Too Large",
+                        &json!({ "error": "request body too large" }),
+                    );
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body)?;
+                match self.set_controls(&body) {
+                    Ok(()) => respond_json(&mut client, "200 OK", &self.controls()),
+                    Err(message) => {
+                        respond_json(&mut client, "400 Bad Request", &json!({ "error": message }))
+                    }
+                }
+            }
+            (_, "/" | "/stream" | "/snapshot" | "/snapshot.jpg" | "/controls") => respond_json(
+                &mut client,
+                "405 Method Not Allowed",
+                &json!({ "error": "method not allowed" }),
+            ),
+            _ => respond_json(
+                &mut client,
+                "404 Not Found",
+                &json!({ "error": "not found" }),
+            ),
+        }
+    }
+
+    /// Sends every new image until the client disconnects or the server closes
+    ///
+    /// When the camera sends no frame in time the latest image is sent again, so a
+    /// client which went away is noticed.
+    fn stream(&self, client: &mut TcpStream, shared: &Shared) -> io::Result<()> {
+        let _viewer = Viewer::new(shared);
+        write!(
+            client,
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: multipart/x-mixed-replace; boundary={BOUNDARY}\r\n\
+             Cache-Control: no-cache\r\n\
+             Connection: close\r\n\r\n"
+        )?;
+        let mut seen = 0;
+        loop {
+            let jpeg = match shared.next(seen) {
+                Some((sequence, jpeg)) => {
+                    seen = sequence;
+                    jpeg
+                }
+                None => {
+                    let latest = shared.lock();
+                    match (&latest.jpeg, latest.closed) {
+                        (_, true) => return Ok(()),
+                        (Some(jpeg), false) => Arc::clone(jpeg),
+                        (None, false) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "no frame from the camera",
+                            ))
+                        }
+                    }
+                }
+            };
+            write!(
+                client,
+                "--{BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                jpeg.len()
+            )?;
+            client.write_all(&jpeg)?;
+            client.write_all(b"\r\n")?;
+            client.flush()?;
+        }
+    }
+
+    /// Current values and ranges of the controls the camera supports
+    fn controls(&self) -> Value {
+        let mut controls = Map::new();
+        for control in Control::ALL {
+            let (Ok(value), Ok(range)) = (
+                self.camera.control(control),
+                self.camera.control_range(control),
+            ) else {
+                continue;
+            };
+            controls.insert(
+                control.name().to_string(),
+                json!({
+                    "value": value,
+                    "min": range.min,
+                    "max": range.max,
+                    "step": range.step,
+                    "default": range.default,
+                }),
+            );
+        }
+        Value::Object(controls)
+    }
+
+    fn set_controls(&self, body: &[u8]) -> Result<(), String> {
+        let values: Map<String, Value> =
+            serde_json::from_slice(body).map_err(|err| err.to_string())?;
+        for (name, value) in values {
+            let control = Control::ALL
+                .into_iter()
+                .find(|control| control.name() == name)
+                .ok_or_else(|| format!("unknown control {name}"))?;
+            let value = value
+                .as_i64()
+                .ok_or_else(|| format!("value of {name} is not an integer"))?;
+            self.camera
+                .set_control(control, value)
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a line of at most `MAX_LINE` bytes
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE as u64 + 1).read_line(line)?;
+    if read > MAX_LINE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(read)
+}
+
+fn respond(
+    client: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        client,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: {content_type}\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-cache\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    )?;
+    client.write_all(body)?;
+    client.flush()
+}
+
+fn respond_json(client: &mut TcpStream, status: &str, body: &Value) -> io::Result<()> {
+    respond(
+        client,
+        status,
+        "application/json",
+        body.to_string().as_bytes(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use super::*;
+    use crate::backend::ControlRange;
+    use crate::formats::FrameInterval;
+    use crate::virtual_camera::VirtualCamera;
+
+    fn camera(format: FrameFormat) -> VirtualCamera {
+        VirtualCamera::new()
+            .format(StreamFormat {
+                width: 32,
+                height: 16,
+                interval: FrameInterval::from_fps(30),
+                format,
+            })
+            .supports(
+                Control::Brightness,
+                ControlRange {
+                    min: -64,
+                    max: 64,
+                    step: 1,
+                    default: 0,
+                },
+            )
+    }
+
+    /// Serves a virtual camera on a loopback port for the rest of the test process
+    fn server(format: FrameFormat) -> SocketAddr {
+        let camera: &'static VirtualCamera = Box::leak(Box::new(camera(format)));
+        let format = camera.formats().unwrap()[0];
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || HttpServer::new(camera, format).serve(listener));
+        addr
+    }
+
+    /// Status line, headers and body of the response to `request`
+    fn request(addr: SocketAddr, request: &str) -> (String, Vec<u8>) {
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        let end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("response has a header");
+        let head = String::from_utf8(response[..end].to_vec()).unwrap();
+        (head, response[end + 4..].to_vec())
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> (String, Vec<u8>) {
+        request(
+            addr,
+            &format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+        )
+    }
+
+    fn post(addr: SocketAddr, path: &str, body: &str) -> (String, Vec<u8>) {
+        request(
+            addr,
+            &format!(
+                "POST {path} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        )
+    }
+
+    #[test]
+    fn snapshot() {
+        for format in [FrameFormat::YUYV, FrameFormat::GRAY8, FrameFormat::MJPEG] {
+            let addr = server(format);
+            let (head, body) = get(addr, "/snapshot");
+            assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+            assert!(head.contains("Content-Type: image/jpeg"));
+            assert!(head.contains(&format!("Content-Length: {}", body.len())));
+            assert_eq!(body[..2], [0xFF, 0xD8]);
+            assert_eq!(body[body.len() - 2..], [0xFF, 0xD9]);
+        }
+    }
+
+    #[test]
+    fn controls() {
+        let addr = server(FrameFormat::YUYV);
+        let (head, body) = get(addr, "/controls");
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+        let controls: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            controls,
+            json!({
+                "brightness": { "value": 0, "min": -64, "max": 64, "step": 1, "default": 0 }
+            })
+        );
+
+        let (head, body) = post(addr, "/controls", r#"{"brightness": 10}"#);
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+        let controls: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(controls["brightness"]["value"], 10);
+
+        let (head, _) = post(addr, "/controls", r#"{"zoom": 1}"#);
+        assert!(head.starts_with("HTTP/1.1 400"), "{head}");
+        let (head, _) = post(addr, "/controls", r#"{"brightness": "bright"}"#);
+        assert!(head.starts_with("HTTP/1.1 400"), "{head}");
+        let (_, body) = get(addr, "/controls");
+        let controls: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(controls["brightness"]["value"], 10);
+    }
+
+    #[test]
+    fn unknown_requests() {
+        let addr = server(FrameFormat::YUYV);
+        let (head, _) = get(addr, "/missing");
+        assert!(head.starts_with("HTTP/1.1 404"), "{head}");
+        let (head, _) = post(addr, "/snapshot", "");
+        assert!(head.starts_with("HTTP/1.1 405"), "{head}");
+        let (head, body) = get(addr, "/");
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{head}");
+        assert_eq!(body, INDEX.as_bytes());
+    }
+
+    #[test]
+    fn long_header_lines_are_refused() {
+        let addr = server(FrameFormat::YUYV);
+        let mut client = TcpStream::connect(addr).unwrap();
+        let line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        // The server may close the connection before all of the line is written
+        let _ = client.write_all(line.as_bytes());
+        let mut response = Vec::new();
+        let _ = client.read_to_end(&mut response);
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn stream_sends_images() {
+        let addr = server(FrameFormat::GRAY8);
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET /stream HTTP/1.1\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(client);
+        let mut parts = 0;
+        let mut line = Vec::new();
+        // The images are binary, so the lines are not read as text
+        while parts < 3 {
+            line.clear();
+            assert!(reader.read_until(b'\n', &mut line).unwrap() > 0);
+            if line == format!("--{BOUNDARY}\r\n").as_bytes() {
+                parts += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn temporary_accept_errors() {
+        for kind in [
+            io::ErrorKind::ConnectionAborted,
+            io::ErrorKind::ConnectionReset,
+            io::ErrorKind::Interrupted,
+        ] {
+            assert_eq!(accept_failure(&kind.into()), AcceptFailure::Connection);
+        }
+        #[cfg(unix)]
+        assert_eq!(
+            accept_failure(&io::Error::from_raw_os_error(libc::EMFILE)),
+            AcceptFailure::Resources
+        );
+        assert_eq!(
+            accept_failure(&io::ErrorKind::WouldBlock.into()),
+            AcceptFailure::Listener
+        );
+        assert_eq!(
+            accept_failure(&io::ErrorKind::InvalidInput.into()),
+            AcceptFailure::Listener
+        );
+    }
+
+    #[test]
+    fn accept_error_ends_the_streams() {
+        let camera = camera(FrameFormat::GRAY8);
+        let format = camera.formats().unwrap()[0];
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"GET /stream HTTP/1.1\r\n\r\n").unwrap();
+        // Accepting the waiting client succeeds, the next accept fails
+        listener.set_nonblocking(true).unwrap();
+
+        let err = HttpServer::new(&camera, format)
+            .serve(listener)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(!camera.is_streaming());
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200 OK"));
+    }
+}
@@ -0,0 +1,571 @@
+//! Saving single frames as image files
+//!
+//! Images are written without loss: 16 bit luminance keeps all 16 bits, and
+//! MJPEG frames are stored as the camera encoded them. PNG and TIFF files
+//! are written by the `png` and `tiff` crates.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value};
+
+use crate::backend::{Backend, Control};
+use crate::device::DeviceDescription;
+use crate::formats::{FrameFormat, StreamFormat};
+use crate::frame::Frame;
+use crate::mjpeg;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// File format of a saved frame
+pub enum ImageFormat {
+    /// PNG with 8 or 16 bits per sample
+    Png,
+    /// PGM for luminance, PPM for colour
+    Pnm,
+    /// Uncompressed TIFF with 8 or 16 bits per sample
+    Tiff,
+    /// JPEG, only for MJPEG frames which are stored as they are
+    Jpeg,
+}
+
+impl ImageFormat {
+    /// Lossless format suited to frames in `format`
+    ///
+    /// MJPEG is kept as JPEG and 16 bit luminance goes to TIFF, everything else to PNG.
+    #[must_use]
+    pub fn preferred(format: FrameFormat) -> Self {
+        match format {
+            FrameFormat::MJPEG => ImageFormat::Jpeg,
+            FrameFormat::GRAY16 | FrameFormat::Z16 => ImageFormat::Tiff,
+            _ => ImageFormat::Png,
+        }
+    }
+
+    /// Format of files with the extension of `path`
+    #[must_use]
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "pgm" | "ppm" | "pnm" => Some(ImageFormat::Pnm),
+            "tif" | "tiff" => Some(ImageFormat::Tiff),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            _ => None,
+        }
+    }
+}
+
+/// Samples of an image, 16 bit samples in little endian
+struct Pixels<'a> {
+    width: u32,
+    height: u32,
+    channels: u8,
+    depth: u8,
+    data: Cow<'a, [u8]>,
+}
+
+impl<'a> Pixels<'a> {
+    fn samples(&self) -> usize {
+        self.width as usize * self.height as usize * usize::from(self.channels)
+    }
+
+    /// 16 bit samples in big endian, as PNG and PNM store them
+    fn big_endian(&self) -> Cow<'_, [u8]> {
+        if self.depth == 8 {
+            return Cow::Borrowed(&self.data);
+        }
+        Cow::Owned(
+            self.data
+                .chunks_exact(2)
+                .flat_map(|sample| [sample[1], sample[0]])
+                .collect(),
+        )
+    }
+}
+
+fn unsupported(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+impl Frame {
+    fn pixels(&self) -> io::Result<Pixels<'_>> {
+        let (width, height) = (self.width(), self.height());
+        let (channels, depth, data) = match self.format() {
+            FrameFormat::GRAY8 => (1, 8, Cow::Borrowed(self.to_bytes())),
+            FrameFormat::GRAY16 | FrameFormat::Z16 => (1, 16, Cow::Borrowed(self.to_bytes())),
+            FrameFormat::RGB => (3, 8, Cow::Borrowed(self.to_bytes())),
+            _ => {
+                let rgb = self.to_rgb().map_err(io::Error::other)?;
+                (3, 8, Cow::Owned(rgb.to_bytes().to_vec()))
+            }
+        };
+        let pixels = Pixels {
+            width,
+            height,
+            channels,
+            depth,
+            data,
+        };
+        let size = pixels.samples() * usize::from(depth / 8);
+        if pixels.data.len() < size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame is smaller than its format",
+            ));
+        }
+        Ok(Pixels {
+            data: match pixels.data {
+                Cow::Borrowed(data) => Cow::Borrowed(&data[..size]),
+                Cow::Owned(mut data) => {
+                    data.truncate(size);
+                    Cow::Owned(data)
+                }
+            },
+            ..pixels
+        })
+    }
+
+    /// Encodes the frame as an image file
+    ///
+    /// Formats other than luminance and RGB are converted with `to_rgb`.
+    /// Only MJPEG frames can be saved as JPEG.
+    pub fn encode_image(&self, format: ImageFormat) -> io::Result<Vec<u8>> {
+        if format == ImageFormat::Jpeg {
+            if self.format() != FrameFormat::MJPEG {
+                return Err(unsupported("only MJPEG frames can be saved as JPEG"));
+            }
+            return Ok(mjpeg::to_jpeg(self.to_bytes()).into_owned());
+        }
+        let pixels = self.pixels()?;
+        Ok(match format {
+            ImageFormat::Png => png(&pixels)?,
+            ImageFormat::Pnm => pnm(&pixels),
+            _ => tiff(&pixels)?,
+        })
+    }
+
+    /// Saves the frame as an image, in the format named by the extension of `path`
+    ///
+    /// Without an extension, the frame is saved in `ImageFormat::preferred`.
+    ///
+    /// ```
+    /// use std::sync::mpsc;
+    /// use uvc::{
+    ///     Backend, Frame, FrameFormat, FrameInterval, FrameMetadata, PanicPolicy, StreamFormat,
+    ///     VirtualCamera,
+    /// };
+    ///
+    /// let format = StreamFormat {
+    ///     width: 160,
+    ///     height: 120,
+    ///     interval: FrameInterval::from_fps(30),
+    ///     format: FrameFormat::GRAY16,
+    /// };
+    /// let camera = VirtualCamera::new().format(format);
+    /// let (sender, frames) = mpsc::sync_channel(1);
+    /// let stream = camera
+    ///     .start_stream(
+    ///         format,
+    ///         PanicPolicy::default(),
+    ///         Box::new(move |frame: &Frame| {
+    ///             let _ = sender.try_send(frame.duplicate().unwrap());
+    ///         }),
+    ///     )
+    ///     .unwrap();
+    /// let frame = frames.recv().unwrap();
+    /// drop(stream);
+    /// let samples: Vec<u16> = frame
+    ///     .to_bytes()
+    ///     .chunks_exact(2)
+    ///     .map(|sample| u16::from_le_bytes([sample[0], sample[1]]))
+    ///     .collect();
+    ///
+    /// let dir = std::env::temp_dir().join(format!("uvc-save-{}", std::process::id()));
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let metadata = FrameMetadata::new().format(format).controls(&camera);
+    /// frame.save_with_metadata(dir.join("uvc-frame.tiff"), &metadata).unwrap();
+    /// frame.save(dir.join("uvc-frame.png")).unwrap();
+    ///
+    /// // Read the images back with TIFF and PNG decoders
+    /// let file = std::fs::File::open(dir.join("uvc-frame.tiff")).unwrap();
+    /// let mut decoder = tiff::decoder::Decoder::new(file).unwrap();
+    /// assert_eq!(decoder.dimensions().unwrap(), (160, 120));
+    /// match decoder.read_image().unwrap() {
+    ///     tiff::decoder::DecodingResult::U16(data) => assert_eq!(data, samples),
+    ///     _ => panic!("not 16 bit"),
+    /// }
+    ///
+    /// let file = std::fs::File::open(dir.join("uvc-frame.png")).unwrap();
+    /// let mut reader = png::Decoder::new(file).read_info().unwrap();
+    /// let mut data = vec![0; reader.output_buffer_size()];
+    /// let info = reader.next_frame(&mut data).unwrap();
+    /// assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+    /// let decoded: Vec<u16> = data
+    ///     .chunks_exact(2)
+    ///     .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+    ///     .collect();
+    /// assert_eq!(decoded, samples);
+    ///
+    /// let sidecar = std::fs::read_to_string(dir.join("uvc-frame.json")).unwrap();
+    /// assert!(sidecar.contains("\"format\": \"GRAY16\""));
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let format = match path.extension() {
+            None => ImageFormat::preferred(self.format()),
+            Some(_) => ImageFormat::from_path(path)
+                .ok_or_else(|| unsupported("unknown image file extension"))?,
+        };
+        fs::write(path, self.encode_image(format)?)
+    }
+
+    /// Saves the frame as `save` does, with `metadata` in a JSON file next to it
+    ///
+    /// The sidecar has the name of the image with the extension `json`.
+    pub fn save_with_metadata<P: AsRef<Path>>(
+        &self,
+        path: P,
+        metadata: &FrameMetadata,
+    ) -> io::Result<()> {
+        let path = path.as_ref();
+        self.save(path)?;
+        fs::write(path.with_extension("json"), metadata.to_json(self))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Description of a saved frame, written to a sidecar by `Frame::save_with_metadata`
+///
+/// The size, format, sequence number and timestamps of the frame are
+/// always included, along with the time the frame was saved.
+pub struct FrameMetadata {
+    entries: Map<String, Value>,
+}
+
+impl FrameMetadata {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.entries.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// Identifies the device, as described by `Device::description`
+    #[must_use]
+    pub fn device(mut self, description: &DeviceDescription) -> Self {
+        self = self
+            .with("vendor_id", description.vendor_id)
+            .with("product_id", description.product_id);
+        for (key, value) in [
+            ("manufacturer", &description.manufacturer),
+            ("product", &description.product),
+            ("serial_number", &description.serial_number),
+        ] {
+            if let Some(value) = value {
+                self = self.with(key, value.as_str());
+            }
+        }
+        self
+    }
+
+    /// Records the negotiated format: its size, frame format and frame interval
+    #[must_use]
+    pub fn format(self, format: StreamFormat) -> Self {
+        let (rate, scale) = format.interval.rate();
+        self.with("stream_width", format.width)
+            .with("stream_height", format.height)
+            .with("stream_format", format!("{:?}", format.format))
+            .with("interval_100ns", format.interval.as_100ns())
+            .with("frame_rate", format!("{rate}/{scale}"))
+    }
+
+    /// Records the current value of every control the camera supports
+    #[must_use]
+    pub fn controls(mut self, camera: &dyn Backend) -> Self {
+        for control in Control::ALL {
+            if let Ok(value) = camera.control(control) {
+                self = self.with(control.name(), value);
+            }
+        }
+        self
+    }
+
+    /// Adds an entry of the caller's own
+    #[must_use]
+    pub fn text(self, key: &str, value: &str) -> Self {
+        self.with(key, value)
+    }
+
+    fn to_json(&self, frame: &Frame) -> String {
+        let mut entries = Map::new();
+        entries.insert("width".to_string(), frame.width().into());
+        entries.insert("height".to_string(), frame.height().into());
+        entries.insert("format".to_string(), format!("{:?}", frame.format()).into());
+        entries.insert("sequence".to_string(), frame.sequence().into());
+        if let Some(time) = frame.capture_time() {
+            let nanos = u64::try_from(time.as_nanos()).unwrap_or(u64::MAX);
+            entries.insert("capture_time_ns".to_string(), nanos.into());
+        }
+        if let Ok(since_epoch) = SystemTime::now().duration_since(UNIX_EPOCH) {
+            let millis = u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX);
+            entries.insert("saved_unix_ms".to_string(), millis.into());
+        }
+        entries.extend(self.entries.clone());
+
+        let mut json = serde_json::to_string_pretty(&entries).unwrap_or_default();
+        json.push('\n');
+        json
+    }
+}
+
+fn pnm(pixels: &Pixels) -> Vec<u8> {
+    let magic = if pixels.channels == 1 { "P5" } else { "P6" };
+    let max = if pixels.depth == 8 { 255 } else { 65535 };
+    let mut file = format!("{}\n{} {}\n{}\n", magic, pixels.width, pixels.height, max).into_bytes();
+    file.extend_from_slice(&pixels.big_endian());
+    file
+}
+
+fn png(pixels: &Pixels) -> io::Result<Vec<u8>> {
+    let mut file = Vec::new();
+    let mut encoder = png::Encoder::new(&mut file, pixels.width, pixels.height);
+    encoder.set_color(match pixels.channels {
+        1 => png::ColorType::Grayscale,
+        _ => png::ColorType::Rgb,
+    });
+    encoder.set_depth(match pixels.depth {
+        8 => png::BitDepth::Eight,
+        _ => png::BitDepth::Sixteen,
+    });
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer
+        .write_image_data(&pixels.big_endian())
+        .map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)?;
+    Ok(file)
+}
+
+fn tiff(pixels: &Pixels) -> io::Result<Vec<u8>> {
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    let mut file = Cursor::new(Vec::new());
+    let mut encoder = TiffEncoder::new(&mut file).map_err(io::Error::other)?;
+    let (width, height) = (pixels.width, pixels.height);
+    match (pixels.channels, pixels.depth) {
+        (1, 8) => encoder.write_image::<colortype::Gray8>(width, height, &pixels.data),
+        (1, _) => {
+            let samples: Vec<u16> = pixels
+                .data
+                .chunks_exact(2)
+                .map(|sample| u16::from_le_bytes([sample[0], sample[1]]))
+                .collect();
+            encoder.write_image::<colortype::Gray16>(width, height, &samples)
+        }
+        _ => encoder.write_image::<colortype::RGB8>(width, height, &pixels.data),
+    }
+    .map_err(io::Error::other)?;
+    Ok(file.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::formats::FrameInterval;
+
+    fn frame(data: &[u8], width: u32, height: u32, format: FrameFormat) -> Frame {
+        Frame::from_bytes(data, width, height, format, None)
+    }
+
+    fn gray16(width: u32, height: u32) -> (Frame, Vec<u16>) {
+        let samples: Vec<u16> = (0..width * height).map(|i| (i * 4099) as u16).collect();
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        (frame(&bytes, width, height, FrameFormat::GRAY16), samples)
+    }
+
+    /// Directory of its own for a test, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("uvc-{name}-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn decode_png(file: &[u8]) -> (png::OutputInfo, Vec<u8>) {
+        let mut reader = png::Decoder::new(file).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        data.truncate(info.buffer_size());
+        (info, data)
+    }
+
+    fn decode_tiff(file: &[u8]) -> ((u32, u32), tiff::ColorType, tiff::decoder::DecodingResult) {
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new(file)).unwrap();
+        (
+            decoder.dimensions().unwrap(),
+            decoder.colortype().unwrap(),
+            decoder.read_image().unwrap(),
+        )
+    }
+
+    #[test]
+    fn png_keeps_the_samples() {
+        let gray: Vec<u8> = (0..48).collect();
+        let png = frame(&gray, 8, 6, FrameFormat::GRAY8)
+            .encode_image(ImageFormat::Png)
+            .unwrap();
+        let (info, data) = decode_png(&png);
+        assert_eq!((info.width, info.height), (8, 6));
+        assert_eq!(info.color_type, png::ColorType::Grayscale);
+        assert_eq!(info.bit_depth, png::BitDepth::Eight);
+        assert_eq!(data, gray);
+
+        let (frame16, samples) = gray16(5, 3);
+        let (info, data) = decode_png(&frame16.encode_image(ImageFormat::Png).unwrap());
+        assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+        let decoded: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+            .collect();
+        assert_eq!(decoded, samples);
+
+        let rgb: Vec<u8> = (0..4 * 2 * 3).map(|i| i as u8 * 5).collect();
+        let png = frame(&rgb, 4, 2, FrameFormat::RGB)
+            .encode_image(ImageFormat::Png)
+            .unwrap();
+        let (info, data) = decode_png(&png);
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(data, rgb);
+    }
+
+    #[test]
+    fn tiff_keeps_the_samples() {
+        use tiff::decoder::DecodingResult;
+
+        let gray: Vec<u8> = (0..48).collect();
+        let tiff = frame(&gray, 8, 6, FrameFormat::GRAY8)
+            .encode_image(ImageFormat::Tiff)
+            .unwrap();
+        let (size, color, data) = decode_tiff(&tiff);
+        assert_eq!(size, (8, 6));
+        assert_eq!(color, tiff::ColorType::Gray(8));
+        assert!(matches!(data, DecodingResult::U8(data) if data == gray));
+
+        let (frame16, samples) = gray16(5, 3);
+        let (size, color, data) = decode_tiff(&frame16.encode_image(ImageFormat::Tiff).unwrap());
+        assert_eq!(size, (5, 3));
+        assert_eq!(color, tiff::ColorType::Gray(16));
+        assert!(matches!(data, DecodingResult::U16(data) if data == samples));
+
+        let rgb: Vec<u8> = (0..4 * 2 * 3).map(|i| i as u8 * 5).collect();
+        let tiff = frame(&rgb, 4, 2, FrameFormat::RGB)
+            .encode_image(ImageFormat::Tiff)
+            .unwrap();
+        let (_, color, data) = decode_tiff(&tiff);
+        assert_eq!(color, tiff::ColorType::RGB(8));
+        assert!(matches!(data, DecodingResult::U8(data) if data == rgb));
+    }
+
+    #[test]
+    fn pnm_stores_big_endian() {
+        let (frame16, _) = gray16(2, 1);
+        let pnm = frame16.encode_image(ImageFormat::Pnm).unwrap();
+        assert_eq!(pnm, b"P5\n2 1\n65535\n\x00\x00\x10\x03");
+
+        let rgb = frame(&[1, 2, 3], 1, 1, FrameFormat::RGB);
+        assert_eq!(
+            rgb.encode_image(ImageFormat::Pnm).unwrap(),
+            b"P6\n1 1\n255\n\x01\x02\x03"
+        );
+    }
+
+    #[test]
+    fn only_mjpeg_is_saved_as_jpeg() {
+        let gray = frame(&[0; 4], 2, 2, FrameFormat::GRAY8);
+        let err = gray.encode_image(ImageFormat::Jpeg).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let short = frame(&[0; 3], 2, 2, FrameFormat::GRAY8);
+        let err = short.encode_image(ImageFormat::Png).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn formats_from_paths() {
+        assert_eq!(ImageFormat::from_path("a.PNG"), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path("a.pgm"), Some(ImageFormat::Pnm));
+        assert_eq!(ImageFormat::from_path("a.tif"), Some(ImageFormat::Tiff));
+        assert_eq!(ImageFormat::from_path("a.jpeg"), Some(ImageFormat::Jpeg));
+        assert_eq!(ImageFormat::from_path("a.bmp"), None);
+        assert_eq!(ImageFormat::from_path("a"), None);
+        assert_eq!(
+            ImageFormat::preferred(FrameFormat::MJPEG),
+            ImageFormat::Jpeg
+        );
+        assert_eq!(ImageFormat::preferred(FrameFormat::Z16), ImageFormat::Tiff);
+        assert_eq!(ImageFormat::preferred(FrameFormat::YUYV), ImageFormat::Png);
+    }
+
+    #[test]
+    fn metadata_records_the_format() {
+        let format = StreamFormat {
+            width: 640,
+            height: 480,
+            interval: FrameInterval::from_rate(30000, 1001),
+            format: FrameFormat::YUYV,
+        };
+        let metadata = FrameMetadata::new()
+            .format(format)
+            .text("note", "say \"hi\"\n");
+        let json = metadata.to_json(&frame(&[0; 4], 2, 2, FrameFormat::GRAY8));
+        let json: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["width"], 2);
+        assert_eq!(json["format"], "GRAY8");
+        assert_eq!(json["stream_width"], 640);
+        assert_eq!(json["stream_height"], 480);
+        assert_eq!(json["stream_format"], "YUYV");
+        assert_eq!(json["interval_100ns"], 333667);
+        assert_eq!(json["frame_rate"], "10000000/333667");
+        assert_eq!(json["note"], "say \"hi\"\n");
+    }
+
+    #[test]
+    fn save_picks_the_format_by_extension() {
+        let dir = TempDir::new("image-save");
+        let gray = frame(&[7; 4], 2, 2, FrameFormat::GRAY8);
+        gray.save_with_metadata(dir.0.join("frame.pgm"), &FrameMetadata::new())
+            .unwrap();
+        assert_eq!(
+            fs::read(dir.0.join("frame.pgm")).unwrap(),
+            b"P5\n2 2\n255\n\x07\x07\x07\x07"
+        );
+        assert!(fs::read_to_string(dir.0.join("frame.json"))
+            .unwrap()
+            .contains("\"height\": 2"));
+
+        // Without an extension, in the preferred format
+        gray.save(dir.0.join("frame")).unwrap();
+        let (info, _) = decode_png(&fs::read(dir.0.join("frame")).unwrap());
+        assert_eq!((info.width, info.height), (2, 2));
+
+        let err = gray.save(dir.0.join("frame.bmp")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::device::{DescriptionSubtype, FormatDescriptor};
+use crate::error::{Error, ErrorKind, Operation, Result};
+use crate::formats::FrameFormat;
+use crate::frame::Frame;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Field of an interlaced frame
+pub enum Field {
+    /// Field 1, holding the even lines
+    Top,
+    /// Field 2, holding the odd lines
+    Bottom,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Which fields a stream sends
+pub enum FieldPattern {
+    Field1Only,
+    Field2Only,
+    Regular,
+    Random,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+/// Interlace flags of a format descriptor (`bmInterlaceFlags`)
+///
+/// The default describes a progressive stream
+pub struct Interlace {
+    flags: u8,
+}
+
+impl Interlace {
+    pub(crate) fn from_flags(flags: u8) -> Self {
+        Self { flags }
+    }
+
+    /// Flags as sent by the device
+    #[must_use]
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Whether the stream is interlaced, or may be
+    #[must_use]
+    pub fn is_interlaced(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    /// Whether a frame holds both fields, or a single one
+    #[must_use]
+    pub fn fields_per_frame(&self) -> u8 {
+        if self.flags & 0x02 != 0 {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Field which is captured first
+    #[must_use]
+    pub fn first_field(&self) -> Field {
+        if self.flags & 0x04 != 0 {
+            Field::Top
+        } else {
+            Field::Bottom
+        }
+    }
+
+    /// Which fields the stream sends
+    #[must_use]
+    pub fn pattern(&self) -> FieldPattern {
+        match (self.flags >> 4) & 0x03 {
+            0 => FieldPattern::Field1Only,
+            1 => FieldPattern::Field2Only,
+            2 => FieldPattern::Regular,
+            _ => FieldPattern::Random,
+        }
+    }
+}
+
+impl<'a> FormatDescriptor<'a> {
+    /// Interlacing of the frames described
+    #[must_use]
+    pub fn interlace(&self) -> Interlace {
+        match self.subtype() {
+            DescriptionSubtype::FormatUncompressed
+            | DescriptionSubtype::FormatMJPEG
+            | DescriptionSubtype::FormatFrameBased => {
+                Interlace::from_flags(unsafe { (*self.format_desc.as_ptr()).bmInterlaceFlags })
+            }
+            _ => Interlace::default(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Method used to turn an interlaced frame into a progressive one
+pub enum Deinterlace {
+    /// Keep one field, and interpolate the lines of the other
+    Bob(Field),
+    /// Blend each line with its neighbours, `(above + 2 * line + below) / 4`
+    LinearBlend,
+}
+
+/// Layout of a frame which can be processed line by line
+struct Lines<'a> {
+    data: &'a [u8],
+    stride: usize,
+    len: usize,
+    height: usize,
+    /// Samples are 16 bit little endian
+    wide: bool,
+}
+
+impl<'a> Lines<'a> {
+    fn new(frame: &'a Frame) -> Option<Self> {
+        let (bytes_per_pixel, wide) = match frame.format() {
+            FrameFormat::GRAY8 => (1, false),
+            FrameFormat::YUYV | FrameFormat::UYVY => (2, false),
+            FrameFormat::GRAY16 => (2, true),
+            _ => return None,
+        };
+        let len = frame.width() as usize * bytes_per_pixel;
+        let height = frame.height() as usize;
+        let stride = frame.step().max(len);
+        let data = frame.to_bytes();
+        if len == 0 || height == 0 || data.len() < stride * (height - 1) + len {
+            return None;
+        }
+        Some(Lines {
+            data,
+            stride,
+            len,
+            height,
+            wide,
+        })
+    }
+
+    fn line(&self, y: usize) -> &'a [u8] {
+        let y = y.min(self.height - 1);
+        &self.data[y * self.stride..y * self.stride + self.len]
+    }
+}
+
+/// Weighted average of lines, sample by sample
+fn combine(out: &mut [u8], lines: &[&[u8]], weights: &[u32], wide: bool) {
+    let total: u32 = weights.iter().sum();
+    if wide {
+        for (i, sample) in out.chunks_exact_mut(2).enumerate() {
+            let sum: u32 = lines
+                .iter()
+                .zip(weights)
+                .map(|(line, w)| u32::from(u16::from_le_bytes([line[2 * i], line[2 * i + 1]])) * w)
+                .sum();
+            sample.copy_from_slice(&(((sum + total / 2) / total) as u16).to_le_bytes());
+        }
+    } else {
+        for (i, sample) in out.iter_mut().enumerate() {
+            let sum: u32 = lines
+                .iter()
+                .zip(weights)
+                .map(|(line, w)| u32::from(line[i]) * w)
+                .sum();
+            *sample = ((sum + total / 2) / total) as u8;
+        }
+    }
+}
+
+impl Frame {
+    /// Deinterlaces a frame holding both fields
+    ///
+    /// Supports YUYV, UYVY, GRAY8 and GRAY16 frames
+    pub fn deinterlace(&self, method: Deinterlace) -> Result<Frame> {
+        let format = self.format();
+        let lines = Lines::new(self).ok_or_else(|| {
+            Error::from(ErrorKind::NotSupported).during(Operation::Deinterlace { format })
+        })?;
+        let bytes_per_pixel = (lines.len / self.width().max(1) as usize) as u32;
+
+        let mut new_frame = self.new_derived(
+            self.width(),
+            self.height(),
+            bytes_per_pixel,
+            format,
+            self.progressive_hints(),
+        );
+        let out = new_frame.data_mut();
+        for (y, out) in out.chunks_exact_mut(lines.len).enumerate() {
+            let above = lines.line(y.saturating_sub(1));
+            let below = lines.line(y + 1);
+            match method {
+                Deinterlace::Bob(field) => {
+                    let kept = match field {
+                        Field::Top => y % 2 == 0,
+                        Field::Bottom => y % 2 == 1,
+                    };
+                    if kept {
+                        out.copy_from_slice(lines.line(y));
+                    } else if y == 0 {
+                        out.copy_from_slice(below);
+                    } else if y + 1 == lines.height {
+                        out.copy_from_slice(above);
+                    } else {
+                        combine(out, &[above, below], &[1, 1], lines.wide);
+                    }
+                }
+                Deinterlace::LinearBlend => {
+                    combine(out, &[above, lines.line(y), below], &[1, 2, 1], lines.wide);
+                }
+            }
+        }
+        Ok(new_frame)
+    }
+
+    /// Interleaves two fields into a frame of twice their height
+    ///
+    /// Both fields must have the same format and size
+    pub fn weave(top: &Frame, bottom: &Frame) -> Result<Frame> {
+        let format = top.format();
+        let error = |kind| Error::from(kind).during(Operation::Deinterlace { format });
+        if bottom.format() != format
+            || bottom.width() != top.width()
+            || bottom.height() != top.height()
+        {
+            return Err(error(ErrorKind::InvalidParam));
+        }
+        let top_lines = Lines::new(top).ok_or_else(|| error(ErrorKind::NotSupported))?;
+        let bottom_lines = Lines::new(bottom).ok_or_else(|| error(ErrorKind::NotSupported))?;
+        let bytes_per_pixel = (top_lines.len / top.width().max(1) as usize) as u32;
+
+        let mut new_frame = top.new_derived(
+            top.width(),
+            top.height() * 2,
+            bytes_per_pixel,
+            format,
+            top.progressive_hints(),
+        );
+        let out = new_frame.data_mut();
+        for (y, out) in out.chunks_exact_mut(top_lines.len).enumerate() {
+            let field = if y % 2 == 0 {
+                &top_lines
+            } else {
+                &bottom_lines
+            };
+            out.copy_from_slice(field.line(y / 2));
+        }
+        Ok(new_frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GRAY8 frame of width 2 whose lines hold the given values
+    fn gray8(lines: &[u8]) -> Frame {
+        let data: Vec<u8> = lines.iter().flat_map(|&value| [value, value]).collect();
+        Frame::from_bytes(&data, 2, lines.len() as u32, FrameFormat::GRAY8, None)
+    }
+
+    fn lines(frame: &Frame) -> Vec<u8> {
+        frame
+            .to_bytes()
+            .chunks_exact(2)
+            .map(|line| line[0])
+            .collect()
+    }
+
+    #[test]
+    fn flags() {
+        let progressive = Interlace::default();
+        assert!(!progressive.is_interlaced());
+        assert_eq!(progressive.fields_per_frame(), 2);
+
+        // Interlaced, one field per frame, field 1 first, regular pattern
+        let interlace = Interlace::from_flags(0b0010_0111);
+        assert!(interlace.is_interlaced());
+        assert_eq!(interlace.fields_per_frame(), 1);
+        assert_eq!(interlace.first_field(), Field::Top);
+        assert_eq!(interlace.pattern(), FieldPattern::Regular);
+        assert_eq!(Interlace::from_flags(0x01).first_field(), Field::Bottom);
+        assert_eq!(Interlace::from_flags(0x30).pattern(), FieldPattern::Random);
+    }
+
+    #[test]
+    fn bob() {
+        let frame = gray8(&[10, 100, 20, 200]);
+        let top = frame.deinterlace(Deinterlace::Bob(Field::Top)).unwrap();
+        assert_eq!(lines(&top), [10, 15, 20, 20]);
+        let bottom = frame.deinterlace(Deinterlace::Bob(Field::Bottom)).unwrap();
+        assert_eq!(lines(&bottom), [100, 100, 150, 200]);
+        assert_eq!(top.format(), FrameFormat::GRAY8);
+        assert_eq!((top.width(), top.height()), (2, 4));
+    }
+
+    #[test]
+    fn blend() {
+        let frame = gray8(&[0, 100, 0, 101]);
+        let blended = frame.deinterlace(Deinterlace::LinearBlend).unwrap();
+        // (above + 2 * line + below) / 4, rounded, with the edges repeated
+        assert_eq!(lines(&blended), [25, 50, 50, 76]);
+    }
+
+    #[test]
+    fn blend_16_bit() {
+        let samples: [u16; 3] = [0, 60000, 1000];
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let frame = Frame::from_bytes(&data, 1, 3, FrameFormat::GRAY16, None);
+        let blended = frame.deinterlace(Deinterlace::LinearBlend).unwrap();
+        let out: Vec<u16> = blended
+            .to_bytes()
+            .chunks_exact(2)
+            .map(|s| u16::from_le_bytes([s[0], s[1]]))
+            .collect();
+        assert_eq!(out, [15000, 30250, 15750]);
+    }
+
+    #[test]
+    fn weave_fields() {
+        let top = gray8(&[1, 3]);
+        let bottom = gray8(&[2, 4]);
+        let frame = Frame::weave(&top, &bottom).unwrap();
+        assert_eq!(lines(&frame), [1, 2, 3, 4]);
+        assert_eq!((frame.width(), frame.height()), (2, 4));
+    }
+
+    #[test]
+    fn unsupported_frames() {
+        let mjpeg = Frame::from_bytes(&[0xff, 0xd8], 2, 2, FrameFormat::MJPEG, None);
+        let err = mjpeg.deinterlace(Deinterlace::LinearBlend).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotSupported);
+
+        let err = Frame::weave(&gray8(&[1, 3]), &gray8(&[2])).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidParam);
+
+        // Shorter than its size
+        let short = Frame::from_bytes(&[0; 3], 2, 2, FrameFormat::GRAY8, None);
+        assert!(short.deinterlace(Deinterlace::LinearBlend).is_err());
+    }
+}
@@ -16,7 +16,7 @@
 
   // Get a default device
   let dev = ctx
-      .find_device(None, None, None)
+      .find(&uvc::DeviceQuery::new())
       .expect("Could not find device");
 
   // Or create an iterator over all available devices
@@ -29,7 +29,7 @@
   let format = uvc::StreamFormat {
       width: 640,
       height: 480,
-      fps: 30,
+      interval: uvc::FrameInterval::from_fps(30),
       format: uvc::FrameFormat::YUYV,
   };
 
@@ -64,22 +64,74 @@
   See also `mirror.rs` in the examples to get an example of how to capture and display a stream
 */
 
+mod backend;
+mod channel;
+mod color;
 mod context;
 mod controls;
 mod device;
 mod error;
 mod formats;
 mod frame;
+mod group;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "image")]
+mod image;
+mod interlace;
+mod mjpeg;
+mod nal;
+mod owned;
+mod pool;
+mod query;
+mod record;
+mod replay;
+mod rtp;
+mod scope;
+mod selector;
+mod stats;
+mod stream_control;
 mod streaming;
+mod strings;
+mod usb;
+mod virtual_camera;
 
-pub use streaming::{ActiveStream, StreamHandle};
+pub use stream_control::StreamControl;
+pub use streaming::{
+    ActiveStream, ChannelStream, PanicPayload, PanicPolicy, StreamHandle, StreamStatus,
+};
+pub use strings::{DescriptorString, LanguageId, StringDecoding};
 
+pub use backend::{
+    Backend, BackendStream, Control, ControlRange, FormatDescription, FrameCallback,
+    FrameDescription,
+};
+pub use channel::{Overflow, Receiver};
+pub use color::{ColorMatching, ColorPrimaries, MatrixCoefficients, TransferCharacteristics};
 pub use context::Context;
 pub use controls::{AutoExposureMode, AutoExposurePriority, ScanningMode};
 pub use device::{
-    DescriptionSubtype, Device, DeviceDescription, DeviceHandle, DeviceList, FormatDescriptor,
-    FormatDescriptors, FrameDescriptor, FrameDescriptors,
+    DescriptionSubtype, Device, DeviceDescription, DeviceHandle, DeviceInfo, DeviceList,
+    FormatDescriptor, FormatDescriptors, FrameDescriptor, FrameDescriptors, FrameIntervals,
+    PortPath, UsbSpeed,
 };
-pub use error::{Error, Result};
-pub use formats::{FrameFormat, StreamFormat};
-pub use frame::Frame;
+pub use error::{Code, Error, ErrorKind, Operation, Result};
+pub use formats::{FrameFormat, FrameInterval, Guid, StreamFormat};
+pub use frame::{Frame, OwnedFrame};
+pub use group::{CaptureGroup, FrameSet, GroupStats, GroupStream, Timestamps};
+#[cfg(feature = "http")]
+pub use http::HttpServer;
+#[cfg(feature = "image")]
+pub use image::{FrameMetadata, ImageFormat};
+pub use interlace::{Deinterlace, Field, FieldPattern, Interlace};
+pub use nal::{NalUnit, NalUnits};
+pub use owned::{OwnedContext, OwnedDevice, OwnedDeviceHandle, OwnedStream};
+pub use pool::FramePool;
+pub use query::DeviceQuery;
+pub use record::{AviWriter, Y4mWriter};
+pub use replay::{ActiveReplay, Recorder, ReplaySource, ReplaySpeed};
+pub use rtp::{RtpDepacketizer, RtpPacketizer, RtpSender};
+pub use scope::{scope, ScopedStream, StreamScope};
+pub use selector::{FormatSelector, Ranking, RejectReason, Rejection};
+pub use stats::{Percentiles, StreamStats};
+pub use virtual_camera::{Fault, TestPattern, VirtualCamera, VirtualStream};
@@ -0,0 +1,160 @@
+use std::borrow::Cow;
+
+/// Code lengths of the tables from Annex K of the JPEG specification
+pub(crate) const DC_LUMINANCE_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+pub(crate) const DC_CHROMINANCE_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const AC_LUMINANCE_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const AC_CHROMINANCE_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+
+#[rustfmt::skip]
+const AC_LUMINANCE_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+#[rustfmt::skip]
+const AC_CHROMINANCE_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+pub(crate) const SOF0: u8 = 0xc0;
+pub(crate) const DHT: u8 = 0xc4;
+pub(crate) const SOS: u8 = 0xda;
+pub(crate) const DQT: u8 = 0xdb;
+pub(crate) const DRI: u8 = 0xdd;
+
+#[derive(Debug, Copy, Clone)]
+/// Marker segment of a JPEG image
+pub(crate) struct Segment<'a> {
+    pub(crate) marker: u8,
+    /// Position of the marker in the image
+    pub(crate) offset: usize,
+    pub(crate) payload: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    /// Position of the first byte after the segment, the entropy coded data after `SOS`
+    pub(crate) fn end(&self) -> usize {
+        self.offset + 4 + self.payload.len()
+    }
+}
+
+/// Marker segments up to and including the start of scan
+///
+/// Ends early at data which can not be parsed. Markers without a payload are skipped.
+pub(crate) fn segments(data: &[u8]) -> impl Iterator<Item = Segment<'_>> {
+    let mut i = if data.starts_with(&[0xff, 0xd8]) {
+        2
+    } else {
+        data.len()
+    };
+    std::iter::from_fn(move || loop {
+        if i + 1 >= data.len() || data[i] != 0xff {
+            return None;
+        }
+        let marker = data[i + 1];
+        match marker {
+            // Fill bytes
+            0xff => i += 1,
+            0x01 | 0xd0..=0xd7 => i += 2,
+            _ => {
+                let &[high, low] = data.get(i + 2..i + 4)? else {
+                    return None;
+                };
+                let length = usize::from(u16::from_be_bytes([high, low]));
+                let payload = data.get(i + 4..i + 2 + length.max(2))?;
+                let segment = Segment {
+                    marker,
+                    offset: i,
+                    payload,
+                };
+                // Nothing after the start of scan is a marker segment
+                i = if marker == SOS {
+                    data.len()
+                } else {
+                    segment.end()
+                };
+                return Some(segment);
+            }
+        }
+    })
+}
+
+/// DHT segment holding the tables of Annex K, which MJPEG cameras leave out
+///
+/// Tables 0 are for luminance, tables 1 for chrominance.
+pub(crate) fn default_tables() -> Vec<u8> {
+    let dc_values: Vec<u8> = (0..12).collect();
+    let tables: [(u8, &[u8; 16], &[u8]); 4] = [
+        (0x00, &DC_LUMINANCE_BITS, &dc_values),
+        (0x10, &AC_LUMINANCE_BITS, &AC_LUMINANCE_VALUES),
+        (0x01, &DC_CHROMINANCE_BITS, &dc_values),
+        (0x11, &AC_CHROMINANCE_BITS, &AC_CHROMINANCE_VALUES),
+    ];
+    let mut payload = Vec::new();
+    for (class_and_id, bits, values) in tables {
+        payload.push(class_and_id);
+        payload.extend_from_slice(bits);
+        payload.extend_from_slice(values);
+    }
+    let mut segment = vec![0xff, DHT];
+    segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    segment.extend_from_slice(&payload);
+    segment
+}
+
+/// Codes and lengths of the values of a Huffman table, in the order of the values
+pub(crate) fn huffman_codes(bits: &[u8; 16]) -> Vec<(u16, u8)> {
+    let mut codes = Vec::new();
+    let mut code = 0u16;
+    for (i, &count) in bits.iter().enumerate() {
+        for _ in 0..count {
+            codes.push((code, i as u8 + 1));
+            code += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+/// Makes an MJPEG frame a complete JPEG image
+///
+/// UVC cameras may leave out the Huffman tables and use the tables of the
+/// JPEG specification, which other decoders do not assume. Those are inserted
+/// before the start of scan. Data which can not be parsed is returned as is.
+pub(crate) fn to_jpeg(data: &[u8]) -> Cow<'_, [u8]> {
+    for segment in segments(data) {
+        match segment.marker {
+            DHT => break,
+            SOS => {
+                let tables = default_tables();
+                let mut jpeg = Vec::with_capacity(data.len() + tables.len());
+                jpeg.extend_from_slice(&data[..segment.offset]);
+                jpeg.extend_from_slice(&tables);
+                jpeg.extend_from_slice(&data[segment.offset..]);
+                return Cow::Owned(jpeg);
+            }
+            _ => {}
+        }
+    }
+    Cow::Borrowed(data)
+}
@@ -0,0 +1,148 @@
+use crate::formats::FrameFormat;
+
+const H264_NAL_IDR: u8 = 5;
+/// IRAP pictures: BLA, IDR and CRA
+const HEVC_NAL_IRAP: std::ops::RangeInclusive<u8> = 16..=21;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// NAL unit of an H.264 or H.265 access unit, without its start code
+pub struct NalUnit<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> NalUnit<'a> {
+    /// Header and payload of the unit
+    #[must_use]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// `nal_unit_type` when the stream is H.264
+    #[must_use]
+    pub fn h264_type(&self) -> u8 {
+        self.data.first().map_or(0, |header| header & 0x1f)
+    }
+
+    /// `nal_unit_type` when the stream is H.265
+    #[must_use]
+    pub fn hevc_type(&self) -> u8 {
+        self.data.first().map_or(0, |header| (header >> 1) & 0x3f)
+    }
+
+    /// Whether the unit starts a picture which can be decoded on its own
+    #[must_use]
+    pub fn is_keyframe(&self, format: FrameFormat) -> bool {
+        match format {
+            FrameFormat::H264 => self.h264_type() == H264_NAL_IDR,
+            FrameFormat::HEVC => HEVC_NAL_IRAP.contains(&self.hevc_type()),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Iterates over the NAL units of an Annex B byte stream
+pub struct NalUnits<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> NalUnits<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        let data = match find_start_code(data) {
+            Some((_, end)) => &data[end..],
+            None => &[],
+        };
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for NalUnits<'a> {
+    type Item = NalUnit<'a>;
+
+    fn next(&mut self) -> Option<NalUnit<'a>> {
+        loop {
+            if self.data.is_empty() {
+                return None;
+            }
+            let (unit, rest) = match find_start_code(self.data) {
+                Some((start, end)) => (&self.data[..start], &self.data[end..]),
+                None => (self.data, &[][..]),
+            };
+            self.data = rest;
+
+            // Trailing zero bytes belong to the next start code
+            let len = unit.len() - unit.iter().rev().take_while(|&&b| b == 0).count();
+            if len > 0 {
+                return Some(NalUnit { data: &unit[..len] });
+            }
+        }
+    }
+}
+
+/// Position of the first `00 00 01` start code, and the position after it
+fn find_start_code(data: &[u8]) -> Option<(usize, usize)> {
+    data.windows(3)
+        .position(|window| window == [0, 0, 1])
+        .map(|start| (start, start + 3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units(data: &[u8]) -> Vec<&[u8]> {
+        NalUnits::new(data).map(|unit| unit.data()).collect()
+    }
+
+    #[test]
+    fn three_and_four_byte_start_codes() {
+        let stream = [
+            0, 0, 0, 1, 0x67, 0x42, // SPS after a 4 byte start code
+            0, 0, 1, 0x68, 0xce, // PPS after a 3 byte start code
+            0, 0, 0, 1, 0x65, 0x88, 0x84,
+        ];
+        assert_eq!(
+            units(&stream),
+            [&[0x67, 0x42][..], &[0x68, 0xce], &[0x65, 0x88, 0x84]]
+        );
+    }
+
+    #[test]
+    fn trailing_zeros_are_dropped() {
+        let stream = [0, 0, 1, 0x67, 0x42, 0, 0, 0, 0, 1, 0x65, 0x88, 0, 0];
+        assert_eq!(units(&stream), [&[0x67, 0x42][..], &[0x65, 0x88]]);
+    }
+
+    #[test]
+    fn empty_and_missing_units() {
+        assert!(units(&[]).is_empty());
+        assert!(units(&[0, 0]).is_empty());
+        assert!(units(&[0x65, 0x88]).is_empty());
+        assert!(units(&[0, 0, 1]).is_empty());
+        assert!(units(&[0, 0, 1, 0, 0, 1, 0, 0, 0]).is_empty());
+        // Data before the first start code is not a unit
+        assert_eq!(units(&[0x12, 0, 0, 1, 0x09, 0xf0]), [&[0x09, 0xf0][..]]);
+    }
+
+    #[test]
+    fn unit_types() {
+        let idr = NalUnit { data: &[0x65] };
+        assert_eq!(idr.h264_type(), 5);
+        assert!(idr.is_keyframe(FrameFormat::H264));
+        assert!(!NalUnit { data: &[0x41] }.is_keyframe(FrameFormat::H264));
+
+        // IDR_W_RADL, then TRAIL_R
+        let irap = NalUnit {
+            data: &[0x26, 0x01],
+        };
+        assert_eq!(irap.hevc_type(), 19);
+        assert!(irap.is_keyframe(FrameFormat::HEVC));
+        assert!(!NalUnit {
+            data: &[0x02, 0x01]
+        }
+        .is_keyframe(FrameFormat::HEVC));
+
+        assert!(!idr.is_keyframe(FrameFormat::MJPEG));
+        assert_eq!(NalUnit { data: &[] }.h264_type(), 0);
+    }
+}
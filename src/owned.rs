@@ -0,0 +1,340 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::backend::BackendStream;
+use crate::channel::{Overflow, Receiver};
+use crate::context::Context;
+use crate::device::{Device, DeviceHandle};
+use crate::error::Result;
+use crate::formats::StreamFormat;
+use crate::frame::{Frame, OwnedFrame};
+use crate::query::DeviceQuery;
+use crate::stats::StreamStats;
+use crate::stream_control::StreamControl;
+use crate::streaming::{start_channel, start_streaming, PanicPolicy, StreamOptions, StreamStatus};
+
+#[derive(Debug, Clone)]
+/// Reference-counted `Context`
+///
+/// Unlike `Context`, the owned types do not borrow from each other:
+/// devices, handles and streams keep what they depend on alive, so they
+/// are `'static` and can be stored in structs or moved to other threads.
+///
+/// ```no_run
+/// use std::thread;
+///
+/// let ctx = uvc::OwnedContext::new().unwrap();
+/// let devh = ctx.find(&uvc::DeviceQuery::new()).unwrap().open().unwrap();
+/// let format = devh.handle().get_preferred_format(|x, _| x).unwrap();
+///
+/// let (stream, frames) = devh
+///     .start_stream_channel(format, 4, uvc::Overflow::DropOldest)
+///     .unwrap();
+///
+/// let worker = thread::spawn(move || {
+///     for frame in frames.iter().take(100) {
+///         println!("{} bytes", frame.to_bytes().len());
+///     }
+///     stream.stop();
+/// });
+/// worker.join().unwrap();
+/// ```
+pub struct OwnedContext {
+    inner: Arc<Context<'static>>,
+}
+
+impl OwnedContext {
+    /// Creates a new context
+    pub fn new() -> Result<Self> {
+        Ok(OwnedContext {
+            inner: Arc::new(Context::new()?),
+        })
+    }
+
+    /// Borrowed view of the context
+    #[must_use]
+    pub fn context(&self) -> &Context<'_> {
+        &self.inner
+    }
+
+    /// The context, borrowed for as long as the `Arc` lives
+    ///
+    /// # Safety
+    ///
+    /// Whatever borrows from the returned context must keep a clone of `self` alive,
+    /// and be dropped before it.
+    unsafe fn detached(&self) -> &'static Context<'static> {
+        &*Arc::as_ptr(&self.inner)
+    }
+
+    fn adopt(&self, device: Device<'static>) -> OwnedDevice {
+        OwnedDevice {
+            inner: Arc::new(DeviceInner {
+                device,
+                _ctx: self.clone(),
+            }),
+        }
+    }
+
+    /// Enumerates the available devices
+    pub fn devices(&self) -> Result<Vec<OwnedDevice>> {
+        // Every device holds a clone of the context
+        let devices = unsafe { self.detached() }.devices()?;
+        Ok(devices.map(|device| self.adopt(device)).collect())
+    }
+
+    /// All devices matching `query`
+    pub fn query(&self, query: &DeviceQuery) -> Result<Vec<OwnedDevice>> {
+        let devices = unsafe { self.detached() }.query(query)?;
+        Ok(devices
+            .into_iter()
+            .map(|device| self.adopt(device))
+            .collect())
+    }
+
+    /// First device matching `query`
+    pub fn find(&self, query: &DeviceQuery) -> Result<OwnedDevice> {
+        let device = unsafe { self.detached() }.find(query)?;
+        Ok(self.adopt(device))
+    }
+}
+
+#[derive(Debug)]
+struct DeviceInner {
+    // Declared first, so the device is released before the context
+    device: Device<'static>,
+    _ctx: OwnedContext,
+}
+
+#[derive(Debug, Clone)]
+/// Reference-counted `Device`, keeping its context alive
+pub struct OwnedDevice {
+    inner: Arc<DeviceInner>,
+}
+
+impl OwnedDevice {
+    /// Borrowed view of the device
+    #[must_use]
+    pub fn device(&self) -> &Device<'_> {
+        &self.inner.device
+    }
+
+    /// Create handle to a device
+    pub fn open(&self) -> Result<OwnedDeviceHandle> {
+        // The handle holds a clone of the device
+        let device: &'static Device<'static> = unsafe { &*(&self.inner.device as *const _) };
+        Ok(OwnedDeviceHandle {
+            inner: Arc::new(HandleInner {
+                handle: device.open()?,
+                _device: self.clone(),
+            }),
+            options: StreamOptions::default(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct HandleInner {
+    // Declared first, so the handle is closed before the device is released
+    handle: DeviceHandle<'static>,
+    _device: OwnedDevice,
+}
+
+#[derive(Debug, Clone)]
+/// Reference-counted `DeviceHandle`, keeping its device and context alive
+///
+/// Controls and descriptors are reached through `handle`.
+pub struct OwnedDeviceHandle {
+    inner: Arc<HandleInner>,
+    options: StreamOptions,
+}
+
+impl OwnedDeviceHandle {
+    /// Borrowed view of the handle
+    #[must_use]
+    pub fn handle(&self) -> &DeviceHandle<'_> {
+        &self.inner.handle
+    }
+
+    /// Handle panics of stream callbacks with this policy, the default is `PanicPolicy::Abort`
+    #[must_use]
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.options.policy = policy;
+        self
+    }
+
+    /// Check every `interval` whether the device was unplugged, as `StreamHandle::detect_unplug`
+    #[must_use]
+    pub fn detect_unplug(mut self, interval: Duration) -> Self {
+        self.options.unplug = Some(interval);
+        self
+    }
+
+    /// The handle, borrowed for as long as the `Arc` lives
+    ///
+    /// # Safety
+    ///
+    /// Streams borrowing the returned handle must be stored in an `OwnedStream`,
+    /// which drops them before its clone of `self`.
+    unsafe fn detached(&self) -> &'static DeviceHandle<'static> {
+        &*(&self.inner.handle as *const _)
+    }
+
+    /// Negotiates `format` and begins a stream, calling `cb` for every frame
+    ///
+    /// This function is non-blocking
+    pub fn start_stream<F, U>(
+        &self,
+        format: StreamFormat,
+        cb: F,
+        user_data: U,
+    ) -> Result<OwnedStream>
+    where
+        F: 'static + Send + FnMut(&Frame, &mut U),
+        U: 'static + Send + Sync,
+    {
+        let control = self.handle().get_stream_control_with_format(format)?;
+        self.start_stream_with_control(control, cb, user_data)
+    }
+
+    /// Begins a stream with previously negotiated parameters, calling `cb` for every frame
+    ///
+    /// This function is non-blocking
+    pub fn start_stream_with_control<F, U>(
+        &self,
+        mut control: StreamControl,
+        cb: F,
+        user_data: U,
+    ) -> Result<OwnedStream>
+    where
+        F: 'static + Send + FnMut(&Frame, &mut U),
+        U: 'static + Send + Sync,
+    {
+        let devh = unsafe { self.detached() };
+        let stream = start_streaming(devh, &mut control, self.options, cb, user_data)?;
+        Ok(OwnedStream {
+            stream: Box::new(stream),
+            _handle: self.clone(),
+        })
+    }
+
+    /// Negotiates `format` and begins a stream, sending every frame to the returned receiver
+    ///
+    /// At most `capacity` frames are queued, `overflow` decides what happens to
+    /// further frames. This function is non-blocking.
+    pub fn start_stream_channel(
+        &self,
+        format: StreamFormat,
+        capacity: usize,
+        overflow: Overflow,
+    ) -> Result<(OwnedStream, Receiver<OwnedFrame>)> {
+        let mut control = self.handle().get_stream_control_with_format(format)?;
+        let devh = unsafe { self.detached() };
+        let (stream, receiver) =
+            start_channel(devh, &mut control, self.options, capacity, overflow)?;
+        Ok((
+            OwnedStream {
+                stream: Box::new(stream),
+                _handle: self.clone(),
+            },
+            receiver,
+        ))
+    }
+}
+
+/// Stream started from an `OwnedDeviceHandle`, keeping the handle open
+///
+/// Dropping this stream will stop the stream
+pub struct OwnedStream {
+    // Declared first, so the stream stops before the handle may close
+    stream: Box<dyn BackendStream + Send + Sync>,
+    _handle: OwnedDeviceHandle,
+}
+
+impl std::fmt::Debug for OwnedStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("OwnedStream")
+            .field("status", &self.stream.status())
+            .finish_non_exhaustive()
+    }
+}
+
+impl OwnedStream {
+    /// Statistics of the frames delivered so far
+    #[must_use]
+    pub fn stats(&self) -> StreamStats {
+        self.stream.stats()
+    }
+
+    /// Whether the stream still delivers frames
+    #[must_use]
+    pub fn status(&self) -> StreamStatus {
+        self.stream.status()
+    }
+
+    /// Blocks until the callback panics under `PanicPolicy::StopStream`,
+    /// then stops the stream and returns the panic payload
+    pub fn join(self) -> std::thread::Result<()> {
+        let OwnedStream { stream, _handle } = self;
+        stream.join()
+    }
+
+    /// Stop the stream
+    pub fn stop(self) {
+        // Taking ownership of the stream, which drops it
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn assert_owned<T: Send + Sync + 'static>() {}
+
+    #[test]
+    fn owned_types_are_static_and_shareable() {
+        assert_owned::<OwnedContext>();
+        assert_owned::<OwnedDevice>();
+        assert_owned::<OwnedDeviceHandle>();
+        assert_owned::<OwnedStream>();
+    }
+
+    /// Records its name in `log` when dropped
+    struct Probe<'a> {
+        name: &'static str,
+        log: &'a Mutex<Vec<&'static str>>,
+    }
+
+    impl<'a> Drop for Probe<'a> {
+        fn drop(&mut self) {
+            self.log.lock().unwrap().push(self.name);
+        }
+    }
+
+    /// Laid out as `DeviceInner`, `HandleInner` and `OwnedStream`
+    struct Inner<'a> {
+        _borrower: Probe<'a>,
+        _owner: Probe<'a>,
+    }
+
+    #[test]
+    fn borrowers_drop_before_their_owners() {
+        // The borrows detached by `OwnedContext::detached`, `OwnedDevice::open` and
+        // `OwnedDeviceHandle::detached` are only sound while fields declared first
+        // are dropped first
+        let log = Mutex::new(Vec::new());
+        drop(Inner {
+            _borrower: Probe {
+                name: "borrower",
+                log: &log,
+            },
+            _owner: Probe {
+                name: "owner",
+                log: &log,
+            },
+        });
+        assert_eq!(*log.lock().unwrap(), ["borrower", "owner"]);
+    }
+}
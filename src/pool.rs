@@ -0,0 +1,175 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::frame::{allocate_frame, Frame, OwnedFrame};
+
+#[derive(Debug)]
+struct Spares {
+    frames: Vec<Frame>,
+    capacity: usize,
+    /// Frames handed to `libuvc` without a buffer, as the pool was empty
+    allocations: u64,
+}
+
+#[derive(Debug, Clone)]
+/// Buffers exchanged with `libuvc`, so frames leave the stream callback without another copy
+///
+/// `libuvc` still copies every frame out of its transfer buffers, the pool saves
+/// the second copy `Frame::duplicate` would make. When a frame is taken, the
+/// callback keeps the buffer `libuvc` filled, and `libuvc` gets a spare buffer
+/// from the pool to fill the next frame into. Dropping the `OwnedFrame` returns
+/// its buffer to the pool. At most `capacity` spare buffers are kept, when the
+/// pool runs dry `libuvc` allocates a new one, counted by `allocations`.
+///
+/// ```no_run
+/// # let ctx = uvc::Context::new().unwrap();
+/// # let dev = ctx.find(&uvc::DeviceQuery::new()).unwrap();
+/// # let devh = dev.open().unwrap();
+/// # let format = devh.get_preferred_format(|x, _| x).unwrap();
+/// use std::sync::mpsc;
+///
+/// let mut streamh = devh.get_stream_handle_with_format(format).unwrap();
+/// let pool = uvc::FramePool::new(4);
+/// let (sender, frames) = mpsc::sync_channel(2);
+///
+/// let stream = streamh
+///     .start_stream_pooled(
+///         &pool,
+///         |frame, sender: &mut mpsc::SyncSender<uvc::OwnedFrame>| {
+///             let _ = sender.try_send(frame);
+///         },
+///         sender,
+///     )
+///     .unwrap();
+///
+/// for frame in frames.iter().take(100) {
+///     println!("{} bytes", frame.to_bytes().len());
+/// }
+/// stream.stop();
+/// ```
+pub struct FramePool {
+    spares: Arc<Mutex<Spares>>,
+}
+
+impl FramePool {
+    /// Pool keeping up to `capacity` spare buffers
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        FramePool {
+            spares: Arc::new(Mutex::new(Spares {
+                frames: Vec::with_capacity(capacity),
+                capacity,
+                allocations: 0,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Spares> {
+        self.spares
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Spare buffers currently in the pool
+    #[must_use]
+    pub fn available(&self) -> usize {
+        self.lock().frames.len()
+    }
+
+    /// Buffers `libuvc` had to allocate because the pool was empty
+    ///
+    /// A count that keeps growing means the frames are held longer than
+    /// the pool's `capacity` allows for.
+    #[must_use]
+    pub fn allocations(&self) -> u64 {
+        self.lock().allocations
+    }
+
+    /// Takes the buffer of `frame`, giving `libuvc` a spare in exchange
+    ///
+    /// # Safety
+    ///
+    /// `frame` must be the frame `libuvc` passes to the stream callback.
+    pub(crate) unsafe fn take(&self, frame: &Frame) -> OwnedFrame {
+        let spare = {
+            let mut spares = self.lock();
+            let spare = spares.frames.pop();
+            if spare.is_none() {
+                spares.allocations += 1;
+            }
+            spare
+        };
+        // An empty frame gets its buffer allocated by libuvc
+        let spare = spare.unwrap_or_else(|| Frame::from_raw(allocate_frame(0)));
+        OwnedFrame::pooled(frame.swap_buffer(spare), self.clone())
+    }
+
+    pub(crate) fn give_back(&self, mut frame: Frame) {
+        let mut spares = self.lock();
+        if spares.frames.len() < spares.capacity {
+            frame.recycle();
+            spares.frames.push(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::FrameFormat;
+
+    /// Frame standing in for the one `libuvc` fills
+    fn filled(byte: u8) -> Frame {
+        Frame::from_bytes(&[byte; 16], 4, 4, FrameFormat::GRAY8, None)
+    }
+
+    #[test]
+    fn empty_pool_counts_allocations() {
+        let pool = FramePool::new(2);
+        let source = filled(1);
+        let taken = unsafe { pool.take(&source) };
+        assert_eq!(taken.to_bytes(), &[1; 16]);
+        assert_eq!((taken.width(), taken.height()), (4, 4));
+        // libuvc allocates the buffer of the empty spare for the next frame
+        assert!(source.to_bytes().is_empty());
+        assert_eq!(pool.allocations(), 1);
+        assert_eq!(pool.available(), 0);
+    }
+
+    #[test]
+    fn dropped_frames_return_their_buffer() {
+        let pool = FramePool::new(2);
+        let source = filled(1);
+        drop(unsafe { pool.take(&source) });
+        assert_eq!(pool.available(), 1);
+
+        let source = filled(2);
+        let taken = unsafe { pool.take(&source) };
+        assert_eq!(taken.to_bytes(), &[2; 16]);
+        // The spare buffer from the pool goes to libuvc, no new allocation
+        assert_eq!(source.to_bytes().len(), 16);
+        assert_eq!(pool.allocations(), 1);
+        assert_eq!(pool.available(), 0);
+    }
+
+    #[test]
+    fn pool_keeps_at_most_capacity() {
+        let pool = FramePool::new(1);
+        let sources = [filled(1), filled(2), filled(3)];
+        let taken: Vec<_> = sources
+            .iter()
+            .map(|source| unsafe { pool.take(source) })
+            .collect();
+        assert_eq!(pool.allocations(), 3);
+        drop(taken);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn into_frame_leaves_the_pool() {
+        let pool = FramePool::new(2);
+        let source = filled(1);
+        let frame = unsafe { pool.take(&source) }.into_frame();
+        drop(frame);
+        assert_eq!(pool.available(), 0);
+    }
+}
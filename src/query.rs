@@ -0,0 +1,344 @@
+use crate::device::{Device, DeviceDescription, PortPath};
+use crate::formats::FrameFormat;
+
+#[derive(Debug, Default, Clone)]
+/// Filters used to select devices
+///
+/// All filters must match for a device to be selected. Filters on
+/// formats and resolutions require opening the device, the other filters
+/// only use information available without opening it.
+///
+/// ```no_run
+/// let ctx = uvc::Context::new().expect("Could not get context");
+/// let query = uvc::DeviceQuery::new()
+///     .vendor_id(0x046d)
+///     .serial_number("A1B2*")
+///     .supports_resolution(1280, 720);
+/// for dev in ctx.query(&query).expect("Could not enumerate devices") {
+///     println!("{:?}", dev.port_path());
+/// }
+/// ```
+pub struct DeviceQuery {
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    serial_number: Option<String>,
+    #[cfg(feature = "regex")]
+    manufacturer: Option<regex::Regex>,
+    #[cfg(feature = "regex")]
+    product: Option<regex::Regex>,
+    bus_number: Option<u8>,
+    port_path: Option<PortPath>,
+    min_bcd_uvc: Option<u16>,
+    formats: Vec<FrameFormat>,
+    resolutions: Vec<(u32, u32)>,
+}
+
+impl DeviceQuery {
+    /// A query matching every device
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match devices with this vendor id
+    #[must_use]
+    pub fn vendor_id(mut self, vendor_id: u16) -> Self {
+        self.vendor_id = Some(vendor_id);
+        self
+    }
+
+    /// Only match devices with this product id
+    #[must_use]
+    pub fn product_id(mut self, product_id: u16) -> Self {
+        self.product_id = Some(product_id);
+        self
+    }
+
+    /// Only match devices with a serial number matching the glob
+    ///
+    /// `*` matches any number of characters and `?` matches a single character.
+    #[must_use]
+    pub fn serial_number(mut self, glob: &str) -> Self {
+        self.serial_number = Some(glob.to_owned());
+        self
+    }
+
+    /// Only match devices with a manufacturer string matching the expression
+    #[cfg(feature = "regex")]
+    #[must_use]
+    pub fn manufacturer(mut self, manufacturer: regex::Regex) -> Self {
+        self.manufacturer = Some(manufacturer);
+        self
+    }
+
+    /// Only match devices with a product string matching the expression
+    #[cfg(feature = "regex")]
+    #[must_use]
+    pub fn product(mut self, product: regex::Regex) -> Self {
+        self.product = Some(product);
+        self
+    }
+
+    /// Only match devices on this bus
+    #[must_use]
+    pub fn bus_number(mut self, bus_number: u8) -> Self {
+        self.bus_number = Some(bus_number);
+        self
+    }
+
+    /// Only match the device connected to this port
+    #[must_use]
+    pub fn port_path(mut self, port_path: PortPath) -> Self {
+        self.port_path = Some(port_path);
+        self
+    }
+
+    /// Only match devices implementing at least this UVC version, in BCD (`0x0150` is 1.5)
+    #[must_use]
+    pub fn min_bcd_uvc(mut self, bcd_uvc: u16) -> Self {
+        self.min_bcd_uvc = Some(bcd_uvc);
+        self
+    }
+
+    /// Only match devices able to stream this format
+    ///
+    /// May be given several times, all formats must be supported.
+    #[must_use]
+    pub fn supports_format(mut self, format: FrameFormat) -> Self {
+        self.formats.push(format);
+        self
+    }
+
+    /// Only match devices able to stream with this resolution
+    ///
+    /// May be given several times, all resolutions must be supported.
+    #[must_use]
+    pub fn supports_resolution(mut self, width: u32, height: u32) -> Self {
+        self.resolutions.push((width, height));
+        self
+    }
+
+    pub(crate) fn matches_description(&self, description: &DeviceDescription) -> bool {
+        if self.vendor_id.is_some_and(|id| id != description.vendor_id) {
+            return false;
+        }
+        if self
+            .product_id
+            .is_some_and(|id| id != description.product_id)
+        {
+            return false;
+        }
+        if self
+            .min_bcd_uvc
+            .is_some_and(|bcd| description.bcd_uvc < bcd)
+        {
+            return false;
+        }
+        if let Some(glob) = &self.serial_number {
+            match &description.serial_number {
+                Some(serial_number) if glob_matches(glob, serial_number) => {}
+                _ => return false,
+            }
+        }
+        #[cfg(feature = "regex")]
+        {
+            if !regex_matches(self.manufacturer.as_ref(), &description.manufacturer) {
+                return false;
+            }
+            if !regex_matches(self.product.as_ref(), &description.product) {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub(crate) fn matches_location(&self, device: &Device) -> bool {
+        if self
+            .bus_number
+            .is_some_and(|bus| bus != device.bus_number())
+        {
+            return false;
+        }
+        match &self.port_path {
+            None => true,
+            Some(port_path) => device.port_path().as_ref() == Ok(port_path),
+        }
+    }
+
+    /// Whether the device must be opened to evaluate this query
+    pub(crate) fn needs_handle(&self) -> bool {
+        !self.formats.is_empty() || !self.resolutions.is_empty()
+    }
+
+    pub(crate) fn matches_formats(&self, device: &Device) -> bool {
+        let devh = match device.open() {
+            Ok(devh) => devh,
+            Err(_) => return false,
+        };
+
+        let mut formats = Vec::new();
+        let mut resolutions = Vec::new();
+        for format_desc in devh.supported_formats() {
+            for frame_desc in format_desc.supported_formats() {
+                formats.push(frame_desc.frame_format());
+                resolutions.push((
+                    u32::from(frame_desc.width()),
+                    u32::from(frame_desc.height()),
+                ));
+            }
+        }
+
+        self.offered(&formats, &resolutions)
+    }
+
+    /// Whether the formats and resolutions offered by a device satisfy the query
+    fn offered(&self, formats: &[FrameFormat], resolutions: &[(u32, u32)]) -> bool {
+        self.formats
+            .iter()
+            .all(|format| formats.iter().any(|offered| format.accepts(*offered)))
+            && self
+                .resolutions
+                .iter()
+                .all(|resolution| resolutions.contains(resolution))
+    }
+}
+
+#[cfg(feature = "regex")]
+fn regex_matches(regex: Option<&regex::Regex>, value: &Option<String>) -> bool {
+    match (regex, value) {
+        (None, _) => true,
+        (Some(regex), Some(value)) => regex.is_match(value),
+        (Some(_), None) => false,
+    }
+}
+
+/// Matches `*` (any sequence) and `?` (any character) against the whole string
+fn glob_matches(glob: &str, s: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+
+    let (mut g, mut i) = (0, 0);
+    // Position of the last `*` in the glob, and where it started matching in `s`
+    let mut backtrack = None;
+    while i < s.len() {
+        match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, i));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == s[i] => {
+                g += 1;
+                i += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    g = star + 1;
+                    i = start + 1;
+                    backtrack = Some((star, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn description(serial_number: Option<&str>) -> DeviceDescription {
+        DeviceDescription {
+            vendor_id: 0x046d,
+            product_id: 0x0825,
+            bcd_uvc: 0x0100,
+            serial_number: serial_number.map(str::to_string),
+            manufacturer: None,
+            product: None,
+            serial_number_raw: None,
+            manufacturer_raw: None,
+            product_raw: None,
+        }
+    }
+
+    #[test]
+    fn glob_literals_match_whole_string() {
+        assert!(glob_matches("", ""));
+        assert!(glob_matches("A1B2", "A1B2"));
+        assert!(!glob_matches("A1B2", "A1B2C"));
+        assert!(!glob_matches("A1B2C", "A1B2"));
+        assert!(!glob_matches("", "A"));
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("*", "anything"));
+        assert!(glob_matches("A1B2*", "A1B2"));
+        assert!(glob_matches("A1B2*", "A1B2C3"));
+        assert!(glob_matches("*C3", "A1B2C3"));
+        assert!(glob_matches("A*C*", "AxxCyy"));
+        assert!(glob_matches("A?C", "ABC"));
+        assert!(!glob_matches("A?C", "AC"));
+        assert!(!glob_matches("A?C", "ABBC"));
+        assert!(glob_matches("**a", "a"));
+        assert!(glob_matches("?*", "é"));
+    }
+
+    #[test]
+    fn glob_backtracks_after_partial_match() {
+        assert!(glob_matches("*ab", "aab"));
+        assert!(glob_matches("*abc", "ababc"));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("*ab", "aba"));
+        assert!(!glob_matches("a*b", "acbc"));
+    }
+
+    #[test]
+    fn description_filters() {
+        let desc = description(Some("A1B2C3"));
+        assert!(DeviceQuery::new().matches_description(&desc));
+        assert!(DeviceQuery::new()
+            .vendor_id(0x046d)
+            .product_id(0x0825)
+            .serial_number("A1B2*")
+            .matches_description(&desc));
+        assert!(!DeviceQuery::new()
+            .vendor_id(0x046e)
+            .matches_description(&desc));
+        assert!(!DeviceQuery::new()
+            .product_id(0x0826)
+            .matches_description(&desc));
+        assert!(!DeviceQuery::new()
+            .min_bcd_uvc(0x0150)
+            .matches_description(&desc));
+        assert!(!DeviceQuery::new()
+            .serial_number("B*")
+            .matches_description(&desc));
+        assert!(!DeviceQuery::new()
+            .serial_number("*")
+            .matches_description(&description(None)));
+    }
+
+    #[test]
+    fn format_classes_match_specific_formats() {
+        let offered = [FrameFormat::NV12, FrameFormat::HEVC];
+        for format in [
+            FrameFormat::Any,
+            FrameFormat::Uncompressed,
+            FrameFormat::Compressed,
+            FrameFormat::NV12,
+        ] {
+            let query = DeviceQuery::new().supports_format(format);
+            assert!(query.offered(&offered, &[]), "{format:?}");
+        }
+        let query = DeviceQuery::new().supports_format(FrameFormat::YUYV);
+        assert!(!query.offered(&offered, &[]));
+        let query = DeviceQuery::new().supports_format(FrameFormat::Uncompressed);
+        assert!(!query.offered(&[FrameFormat::H264], &[]));
+
+        let query = DeviceQuery::new().supports_resolution(640, 480);
+        assert!(query.offered(&offered, &[(1280, 720), (640, 480)]));
+        assert!(!query.offered(&offered, &[(1280, 720)]));
+    }
+}
@@ -0,0 +1,828 @@
+//! Writing streams to files other programs play, without encoding them again
+//!
+//! `AviWriter` stores MJPEG frames in an OpenDML AVI, `Y4mWriter` stores
+//! uncompressed YUV frames in a YUV4MPEG2 file. Both take frames one at a
+//! time, so they can be fed from a stream callback or from a channel.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::formats::{FrameFormat, StreamFormat};
+use crate::frame::Frame;
+use crate::interlace::Field;
+use crate::mjpeg;
+
+/// Largest size of a RIFF list, before the recording continues in an `AVIX` list
+#[cfg(not(test))]
+const RIFF_LIMIT: u64 = 1 << 30;
+/// Small enough for tests to reach the `AVIX` lists
+#[cfg(test)]
+const RIFF_LIMIT: u64 = 64 * 1024;
+/// RIFF lists the super index has room for, each holding up to `RIFF_LIMIT` bytes
+const SUPER_INDEX_ENTRIES: usize = 256;
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+const AVI_INDEX_OF_INDEXES: u8 = 0;
+const AVI_INDEX_OF_CHUNKS: u8 = 1;
+/// Chunk id of the frames of the first stream, compressed video
+const FRAME_CHUNK: &[u8; 4] = b"00dc";
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Positions of the header fields which are only known once the recording ends
+#[derive(Debug, Copy, Clone)]
+struct HeaderFields {
+    max_bytes_per_sec: u64,
+    total_frames: u64,
+    suggested_buffer_size: u64,
+    length: u64,
+    stream_buffer_size: u64,
+    super_index: u64,
+    odml_frames: u64,
+}
+
+/// Writes MJPEG frames to an OpenDML AVI
+///
+/// Frames are stored as the camera sent them, with the Huffman tables the
+/// cameras leave out inserted. The frame rate is that of the negotiated
+/// interval. Recordings grow past 1 GiB in `AVIX` lists, found through
+/// the OpenDML index, while the legacy index covers the first gigabyte.
+///
+/// The file is complete once `finish` returns.
+///
+/// ```
+/// use std::io::Cursor;
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+/// use uvc::{AviWriter, Backend, Frame, FrameFormat, PanicPolicy, VirtualCamera};
+///
+/// let camera = VirtualCamera::webcam();
+/// let format = camera
+///     .formats()
+///     .unwrap()
+///     .into_iter()
+///     .find(|format| format.format == FrameFormat::MJPEG)
+///     .unwrap();
+///
+/// let writer = AviWriter::new(Cursor::new(Vec::new()), format).unwrap();
+/// let writer = Arc::new(Mutex::new(writer));
+/// let stream = {
+///     let writer = Arc::clone(&writer);
+///     camera
+///         .start_stream(
+///             format,
+///             PanicPolicy::default(),
+///             Box::new(move |frame: &Frame| writer.lock().unwrap().record(frame).unwrap()),
+///         )
+///         .unwrap()
+/// };
+/// std::thread::sleep(Duration::from_millis(200));
+/// drop(stream);
+///
+/// let writer = Arc::into_inner(writer).unwrap().into_inner().unwrap();
+/// let frames = writer.frames();
+/// let mut file = writer.finish().unwrap();
+///
+/// // Read the file back with a RIFF parser
+/// let riff = riff::Chunk::read(&mut file, 0).unwrap();
+/// assert_eq!(riff.read_type(&mut file).unwrap().as_str(), "AVI ");
+/// let lists: Vec<_> = riff.iter(&mut file).collect();
+/// let movi = lists
+///     .iter()
+///     .find(|list| {
+///         list.id().as_str() == "LIST" && list.read_type(&mut file).unwrap().as_str() == "movi"
+///     })
+///     .unwrap();
+/// let chunks: Vec<_> = movi.iter(&mut file).collect();
+/// let images = chunks.iter().filter(|chunk| chunk.id().as_str() == "00dc").count();
+/// assert_eq!(images as u32, frames);
+/// let jpeg = chunks[0].read_contents(&mut file).unwrap();
+/// assert!(jpeg.starts_with(&[0xff, 0xd8]));
+/// let idx1 = lists.iter().find(|chunk| chunk.id().as_str() == "idx1").unwrap();
+/// assert_eq!(idx1.len(), 16 * frames);
+/// ```
+pub struct AviWriter<W: Write + Seek> {
+    writer: W,
+    /// Bytes written so far, tracked to spare `writer` from seeking
+    position: u64,
+    /// Frames per second as `(rate, scale)`
+    rate: (u32, u32),
+    header: HeaderFields,
+    /// Start of the RIFF list being written, and of its movi list
+    riff: u64,
+    movi: u64,
+    /// Positions and sizes of the frames of the current RIFF list
+    chunks: Vec<(u64, u32)>,
+    /// Positions, sizes and frame counts of the standard indexes written
+    indexes: Vec<(u64, u32, u32)>,
+    /// Frames in the first RIFF list, counted by the legacy headers
+    first_frames: Option<u32>,
+    frames: u32,
+    largest: u32,
+}
+
+impl<W: Write + Seek> std::fmt::Debug for AviWriter<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AviWriter")
+            .field("position", &self.position)
+            .field("frames", &self.frames)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AviWriter<BufWriter<File>> {
+    /// Creates a recording at `path`, replacing any existing file
+    pub fn create<P: AsRef<Path>>(path: P, format: StreamFormat) -> io::Result<Self> {
+        AviWriter::new(BufWriter::new(File::create(path)?), format)
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Sets the size of the list or chunk starting at `start` to reach the end of `buf`
+fn close_list(buf: &mut [u8], start: usize) {
+    let size = (buf.len() - start - 8) as u32;
+    buf[start + 4..start + 8].copy_from_slice(&size.to_le_bytes());
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    /// Writes the headers of a recording of MJPEG frames in `format`
+    pub fn new(mut writer: W, format: StreamFormat) -> io::Result<Self> {
+        if format.format != FrameFormat::MJPEG {
+            return Err(invalid_input("only MJPEG can be stored in AVI"));
+        }
+        let (width, height) = match (u16::try_from(format.width), u16::try_from(format.height)) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(invalid_input("frame size too large")),
+        };
+        let start = writer.stream_position()?;
+        let rate = format.interval.rate();
+        let at = |buf: &Vec<u8>| start + buf.len() as u64;
+
+        let mut h = Vec::new();
+        h.extend_from_slice(b"RIFF\0\0\0\0AVI ");
+        let hdrl = h.len();
+        h.extend_from_slice(b"LIST\0\0\0\0hdrl");
+
+        h.extend_from_slice(b"avih");
+        put_u32(&mut h, 56);
+        put_u32(&mut h, format.interval.as_100ns() / 10);
+        let max_bytes_per_sec = at(&h);
+        put_u32(&mut h, 0);
+        put_u32(&mut h, 0); // padding granularity
+        put_u32(&mut h, AVIF_HASINDEX);
+        let total_frames = at(&h);
+        put_u32(&mut h, 0);
+        put_u32(&mut h, 0); // initial frames
+        put_u32(&mut h, 1); // streams
+        let suggested_buffer_size = at(&h);
+        put_u32(&mut h, 0);
+        put_u32(&mut h, u32::from(width));
+        put_u32(&mut h, u32::from(height));
+        h.extend_from_slice(&[0; 16]);
+
+        let strl = h.len();
+        h.extend_from_slice(b"LIST\0\0\0\0strl");
+        h.extend_from_slice(b"strh");
+        put_u32(&mut h, 56);
+        h.extend_from_slice(b"vidsMJPG");
+        put_u32(&mut h, 0); // flags
+        put_u16(&mut h, 0); // priority
+        put_u16(&mut h, 0); // language
+        put_u32(&mut h, 0); // initial frames
+        put_u32(&mut h, rate.1);
+        put_u32(&mut h, rate.0);
+        put_u32(&mut h, 0); // start
+        let length = at(&h);
+        put_u32(&mut h, 0);
+        let stream_buffer_size = at(&h);
+        put_u32(&mut h, 0);
+        put_u32(&mut h, u32::MAX); // default quality
+        put_u32(&mut h, 0); // sample size, varying
+        for edge in [0, 0, width, height] {
+            put_u16(&mut h, edge);
+        }
+
+        // BITMAPINFOHEADER
+        h.extend_from_slice(b"strf");
+        put_u32(&mut h, 40);
+        put_u32(&mut h, 40);
+        put_u32(&mut h, u32::from(width));
+        put_u32(&mut h, u32::from(height));
+        put_u16(&mut h, 1); // planes
+        put_u16(&mut h, 24); // bits per pixel
+        h.extend_from_slice(b"MJPG");
+        put_u32(&mut h, u32::from(width) * u32::from(height) * 3);
+        h.extend_from_slice(&[0; 16]);
+
+        h.extend_from_slice(b"indx");
+        put_u32(&mut h, 24 + 16 * SUPER_INDEX_ENTRIES as u32);
+        put_u16(&mut h, 4); // longs per entry
+        h.extend_from_slice(&[0, AVI_INDEX_OF_INDEXES]);
+        let super_index = at(&h);
+        put_u32(&mut h, 0);
+        h.extend_from_slice(FRAME_CHUNK);
+        h.extend_from_slice(&[0; 12]);
+        h.resize(h.len() + 16 * SUPER_INDEX_ENTRIES, 0);
+        close_list(&mut h, strl);
+
+        let odml = h.len();
+        h.extend_from_slice(b"LIST\0\0\0\0odmldmlh");
+        put_u32(&mut h, 248);
+        let odml_frames = at(&h);
+        h.resize(h.len() + 248, 0);
+        close_list(&mut h, odml);
+        close_list(&mut h, hdrl);
+
+        let movi = at(&h);
+        h.extend_from_slice(b"LIST\0\0\0\0movi");
+        writer.write_all(&h)?;
+
+        Ok(AviWriter {
+            writer,
+            position: at(&h),
+            rate,
+            header: HeaderFields {
+                max_bytes_per_sec,
+                total_frames,
+                suggested_buffer_size,
+                length,
+                stream_buffer_size,
+                super_index,
+                odml_frames,
+            },
+            riff: start,
+            movi,
+            chunks: Vec::new(),
+            indexes: Vec::new(),
+            first_frames: None,
+            frames: 0,
+            largest: 0,
+        })
+    }
+
+    /// Frames recorded so far
+    #[must_use]
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Appends an MJPEG frame
+    pub fn record(&mut self, frame: &Frame) -> io::Result<()> {
+        if frame.format() != FrameFormat::MJPEG {
+            return Err(invalid_input("only MJPEG frames can be stored in AVI"));
+        }
+        let jpeg = mjpeg::to_jpeg(frame.to_bytes());
+        let size = u32::try_from(jpeg.len()).map_err(|_| invalid_input("frame too large"))?;
+
+        // Room for the chunk and its entries in the standard index, and in the
+        // legacy index which ends the first RIFF list
+        let entries = self.chunks.len() as u64 + 1;
+        let mut needed = 8 + u64::from(size) + 1 + 32 + 8 * entries;
+        if self.first_frames.is_none() {
+            needed += 8 + 16 * entries;
+        }
+        if !self.chunks.is_empty() && self.position + needed - self.riff > RIFF_LIMIT {
+            self.close_riff()?;
+            self.open_riff()?;
+        }
+
+        self.chunks.push((self.position, size));
+        self.write(FRAME_CHUNK)?;
+        self.write(&size.to_le_bytes())?;
+        self.write(&jpeg)?;
+        if size % 2 == 1 {
+            self.write(&[0])?;
+        }
+        self.frames += 1;
+        self.largest = self.largest.max(size);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    /// Overwrites earlier data, then returns to the end
+    fn patch(&mut self, at: u64, data: &[u8]) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(at))?;
+        self.writer.write_all(data)?;
+        self.writer.seek(SeekFrom::Start(self.position))?;
+        Ok(())
+    }
+
+    fn open_riff(&mut self) -> io::Result<()> {
+        if self.indexes.len() >= SUPER_INDEX_ENTRIES {
+            return Err(io::Error::other("recording exceeds the size of the index"));
+        }
+        self.riff = self.position;
+        self.movi = self.position + 12;
+        self.write(b"RIFF\0\0\0\0AVIXLIST\0\0\0\0movi")
+    }
+
+    /// Ends the movi and RIFF lists with their indexes
+    fn close_riff(&mut self) -> io::Result<()> {
+        // Standard index, with offsets from the start of the RIFF list
+        let mut index = Vec::with_capacity(32 + 8 * self.chunks.len());
+        index.extend_from_slice(b"ix00");
+        put_u32(&mut index, 24 + 8 * self.chunks.len() as u32);
+        put_u16(&mut index, 2); // longs per entry
+        index.extend_from_slice(&[0, AVI_INDEX_OF_CHUNKS]);
+        put_u32(&mut index, self.chunks.len() as u32);
+        index.extend_from_slice(FRAME_CHUNK);
+        index.extend_from_slice(&self.riff.to_le_bytes());
+        put_u32(&mut index, 0);
+        for &(position, size) in &self.chunks {
+            // Pointing at the data, after the chunk header
+            put_u32(&mut index, (position + 8 - self.riff) as u32);
+            put_u32(&mut index, size);
+        }
+        self.indexes
+            .push((self.position, index.len() as u32, self.chunks.len() as u32));
+        self.write(&index)?;
+        let movi_size = (self.position - self.movi - 8) as u32;
+        self.patch(self.movi + 4, &movi_size.to_le_bytes())?;
+
+        if self.first_frames.is_none() {
+            // Legacy index, with offsets from the movi list type
+            let mut idx1 = Vec::with_capacity(8 + 16 * self.chunks.len());
+            idx1.extend_from_slice(b"idx1");
+            put_u32(&mut idx1, 16 * self.chunks.len() as u32);
+            for &(position, size) in &self.chunks {
+                idx1.extend_from_slice(FRAME_CHUNK);
+                put_u32(&mut idx1, AVIIF_KEYFRAME);
+                put_u32(&mut idx1, (position - self.movi - 8) as u32);
+                put_u32(&mut idx1, size);
+            }
+            self.write(&idx1)?;
+            self.first_frames = Some(self.chunks.len() as u32);
+        }
+        let riff_size = (self.position - self.riff - 8) as u32;
+        self.patch(self.riff + 4, &riff_size.to_le_bytes())?;
+        self.chunks.clear();
+        Ok(())
+    }
+
+    /// Writes the indexes and completes the headers, returning the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.close_riff()?;
+
+        let (rate, scale) = self.rate;
+        let bytes_per_sec = u64::from(self.largest) * u64::from(rate) / u64::from(scale.max(1));
+        let first_frames = self.first_frames.unwrap_or(0);
+        let header = self.header;
+        let fields = [
+            (
+                header.max_bytes_per_sec,
+                bytes_per_sec.min(u64::from(u32::MAX)) as u32,
+            ),
+            (header.total_frames, first_frames),
+            (header.suggested_buffer_size, self.largest + 8),
+            (header.length, self.frames),
+            (header.stream_buffer_size, self.largest + 8),
+            (header.super_index, self.indexes.len() as u32),
+            (header.odml_frames, self.frames),
+        ];
+        for (at, value) in fields {
+            self.patch(at, &value.to_le_bytes())?;
+        }
+
+        let mut entries = Vec::with_capacity(16 * self.indexes.len());
+        for &(position, size, frames) in &self.indexes {
+            entries.extend_from_slice(&position.to_le_bytes());
+            put_u32(&mut entries, size);
+            put_u32(&mut entries, frames);
+        }
+        // After the entry count, chunk id and reserved fields
+        self.patch(header.super_index + 20, &entries)?;
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Writes uncompressed YUYV, NV12 and I420 frames to a YUV4MPEG2 file
+///
+/// YUV4MPEG2 stores planes, so YUYV is written as planar 4:2:2 and NV12 as
+/// planar 4:2:0. The header is written with the first frame, whose
+/// interlacing it declares. UVC descriptors don't declare the range of the
+/// samples, so the header leaves it to the reader.
+///
+/// ```
+/// use std::sync::mpsc;
+/// use std::time::Duration;
+/// use uvc::{Backend, Frame, FrameFormat, PanicPolicy, VirtualCamera, Y4mWriter};
+///
+/// let camera = VirtualCamera::webcam();
+/// let format = camera
+///     .formats()
+///     .unwrap()
+///     .into_iter()
+///     .find(|format| format.format == FrameFormat::YUYV)
+///     .unwrap();
+///
+/// let (sender, frames) = mpsc::sync_channel(4);
+/// let stream = camera
+///     .start_stream(
+///         format,
+///         PanicPolicy::default(),
+///         Box::new(move |frame: &Frame| {
+///             let _ = sender.try_send(frame.duplicate().unwrap());
+///         }),
+///     )
+///     .unwrap();
+///
+/// let mut writer = Y4mWriter::new(Vec::new(), format).unwrap();
+/// let recorded: Vec<Frame> = frames.iter().take(3).collect();
+/// drop(stream);
+/// for frame in &recorded {
+///     writer.record(frame).unwrap();
+/// }
+/// let file = writer.finish().unwrap();
+///
+/// // Read the file back with a YUV4MPEG2 parser
+/// let mut decoder = y4m::decode(&file[..]).unwrap();
+/// assert_eq!(decoder.get_width(), 640);
+/// assert!(matches!(decoder.get_colorspace(), y4m::Colorspace::C422));
+/// for frame in &recorded {
+///     let read = decoder.read_frame().unwrap();
+///     let luma: Vec<u8> = frame.to_bytes().iter().step_by(2).copied().collect();
+///     assert_eq!(read.get_y_plane(), &luma[..]);
+/// }
+/// assert!(decoder.read_frame().is_err());
+/// ```
+#[derive(Debug)]
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    format: StreamFormat,
+    header_written: bool,
+}
+
+impl Y4mWriter<BufWriter<File>> {
+    /// Creates a recording at `path`, replacing any existing file
+    pub fn create<P: AsRef<Path>>(path: P, format: StreamFormat) -> io::Result<Self> {
+        Y4mWriter::new(BufWriter::new(File::create(path)?), format)
+    }
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Recording of frames in `format`
+    pub fn new(writer: W, format: StreamFormat) -> io::Result<Self> {
+        match format.format {
+            FrameFormat::YUYV | FrameFormat::NV12 | FrameFormat::I420 => Ok(Y4mWriter {
+                writer,
+                format,
+                header_written: false,
+            }),
+            _ => Err(invalid_input(
+                "only YUYV, NV12 and I420 can be stored in YUV4MPEG2",
+            )),
+        }
+    }
+
+    fn write_header(&mut self, frame: &Frame) -> io::Result<()> {
+        let (rate, scale) = self.format.interval.rate();
+        let interlace = frame.interlace();
+        let interlacing = match (interlace.is_interlaced(), interlace.first_field()) {
+            (false, _) => 'p',
+            (true, Field::Top) => 't',
+            (true, Field::Bottom) => 'b',
+        };
+        let colorspace = match self.format.format {
+            FrameFormat::YUYV => "422",
+            // Chroma sited between the lines and on the columns, as cameras sample it
+            _ => "420mpeg2",
+        };
+        writeln!(
+            self.writer,
+            "YUV4MPEG2 W{} H{} F{}:{} I{} A1:1 C{}",
+            self.format.width, self.format.height, rate, scale, interlacing, colorspace
+        )
+    }
+
+    /// Appends a frame, which must match the format given to `new`
+    pub fn record(&mut self, frame: &Frame) -> io::Result<()> {
+        let (width, height) = (frame.width() as usize, frame.height() as usize);
+        if frame.format() != self.format.format
+            || frame.width() != self.format.width
+            || frame.height() != self.format.height
+        {
+            return Err(invalid_input("frame does not match the recorded format"));
+        }
+        let data = frame.to_bytes();
+        let luma = width * height;
+        let chroma = width.div_ceil(2) * height.div_ceil(2);
+        let expected = match frame.format() {
+            FrameFormat::YUYV => luma * 2,
+            _ => luma + 2 * chroma,
+        };
+        if data.len() < expected || (frame.format() == FrameFormat::YUYV && width % 2 == 1) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame is smaller than its format",
+            ));
+        }
+
+        if !self.header_written {
+            self.write_header(frame)?;
+            self.header_written = true;
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        match frame.format() {
+            FrameFormat::YUYV => {
+                let y: Vec<u8> = data[..expected].iter().step_by(2).copied().collect();
+                let u: Vec<u8> = data[1..expected].iter().step_by(4).copied().collect();
+                let v: Vec<u8> = data[3..expected].iter().step_by(4).copied().collect();
+                for plane in [y, u, v] {
+                    self.writer.write_all(&plane)?;
+                }
+            }
+            FrameFormat::NV12 => {
+                let uv = &data[luma..expected];
+                let u: Vec<u8> = uv.iter().step_by(2).copied().collect();
+                let v: Vec<u8> = uv.iter().skip(1).step_by(2).copied().collect();
+                self.writer.write_all(&data[..luma])?;
+                self.writer.write_all(&u)?;
+                self.writer.write_all(&v)?;
+            }
+            _ => self.writer.write_all(&data[..expected])?,
+        }
+        Ok(())
+    }
+
+    /// Flushes the recording and returns the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::color::ColorMatching;
+    use crate::formats::FrameInterval;
+    use crate::frame::FormatHints;
+    use crate::interlace::Interlace;
+
+    fn stream_format(format: FrameFormat, width: u32, height: u32) -> StreamFormat {
+        StreamFormat {
+            width,
+            height,
+            interval: FrameInterval::from_fps(30),
+            format,
+        }
+    }
+
+    /// JPEG with its Huffman tables, so it is stored as is, holding `len` bytes of scan
+    fn jpeg(len: usize, seed: u8) -> Vec<u8> {
+        let mut jpeg = vec![0xff, 0xd8];
+        jpeg.extend_from_slice(&mjpeg::default_tables());
+        jpeg.extend_from_slice(&[0xff, mjpeg::SOS, 0, 12, 3, 1, 0, 2, 0x11, 3, 0x11, 0, 63, 0]);
+        jpeg.extend((0..len).map(|i| (i as u8 ^ seed) & 0x7f));
+        jpeg.extend_from_slice(&[0xff, 0xd9]);
+        jpeg
+    }
+
+    fn frame(data: &[u8], format: StreamFormat) -> Frame {
+        let hints = FormatHints {
+            format: format.format,
+            color_matching: ColorMatching::default(),
+            interlace: Interlace::default(),
+        };
+        Frame::from_bytes(data, format.width, format.height, format.format, None)
+            .with_hints(Some(hints))
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    fn u64_at(data: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+    }
+
+    /// Payload of the chunk starting at `at`, after checking its id
+    fn chunk<'a>(file: &'a [u8], at: u64, id: &[u8; 4]) -> &'a [u8] {
+        let at = at as usize;
+        assert_eq!(&file[at..at + 4], id);
+        &file[at + 8..at + 8 + u32_at(file, at + 4) as usize]
+    }
+
+    #[test]
+    fn opendml_riff_lists() {
+        let format = stream_format(FrameFormat::MJPEG, 64, 48);
+        // Odd and even sizes, around 1/12 of a RIFF list each
+        let images: Vec<Vec<u8>> = (0..40).map(|i| jpeg(5000 + i * 7, i as u8)).collect();
+        let mut writer = AviWriter::new(Cursor::new(Vec::new()), format).unwrap();
+        for image in &images {
+            writer.record(&frame(image, format)).unwrap();
+        }
+        assert_eq!(writer.frames(), 40);
+        let mut file = writer.finish().unwrap();
+
+        // Top level RIFF lists, each within the limit
+        let mut lists = Vec::new();
+        let mut at = 0;
+        while at < file.get_ref().len() as u64 {
+            let list = riff::Chunk::read(&mut file, at).unwrap();
+            assert_eq!(list.id().as_str(), "RIFF");
+            assert!(u64::from(list.len()) + 8 <= RIFF_LIMIT);
+            let kind = list.read_type(&mut file).unwrap();
+            assert_eq!(kind.as_str(), if at == 0 { "AVI " } else { "AVIX" });
+            at += 8 + u64::from(list.len());
+            lists.push(list);
+        }
+        assert!(lists.len() >= 3);
+
+        // Frames of every movi list, in order
+        let mut stored = Vec::new();
+        let mut first_frames = 0;
+        for (i, list) in lists.iter().enumerate() {
+            let chunks: Vec<_> = list.iter(&mut file).collect();
+            let mut movi = None;
+            for chunk in &chunks {
+                if chunk.id().as_str() == "LIST"
+                    && chunk.read_type(&mut file).unwrap().as_str() == "movi"
+                {
+                    movi = Some(chunk);
+                }
+            }
+            let movi = movi.unwrap();
+            let frames: Vec<_> = movi
+                .iter(&mut file)
+                .filter(|chunk| chunk.id().as_str() == "00dc")
+                .collect();
+            let frames: Vec<Vec<u8>> = frames
+                .iter()
+                .map(|chunk| chunk.read_contents(&mut file).unwrap())
+                .collect();
+            if i == 0 {
+                first_frames = frames.len();
+                // The legacy index ends the first RIFF list, covering its frames
+                let idx1 = chunks.iter().find(|chunk| chunk.id().as_str() == "idx1");
+                assert_eq!(idx1.unwrap().len() as usize, 16 * first_frames);
+            }
+            stored.extend(frames);
+        }
+        assert_eq!(stored, images);
+
+        let file = file.into_inner();
+        let at = |name: &[u8]| file.windows(4).position(|w| w == name).unwrap();
+        // Headers count the frames of the first RIFF list, OpenDML all of them
+        let avih = at(b"avih");
+        assert_eq!(u32_at(&file, avih + 8 + 16) as usize, first_frames);
+        assert_eq!(u32_at(&file, at(b"dmlh") + 8), 40);
+        let strh = at(b"strh");
+        assert_eq!(u32_at(&file, strh + 8 + 32), 40);
+
+        // The super index points at one standard index per RIFF list
+        let indx = at(b"indx");
+        let entries = u32_at(&file, indx + 12) as usize;
+        assert_eq!(entries, lists.len());
+        let mut indexed = Vec::new();
+        for (entry, list) in lists.iter().enumerate() {
+            let entry = indx + 8 + 24 + 16 * entry;
+            let position = u64_at(&file, entry);
+            assert!(position > list.offset());
+            assert!(position < list.offset() + 8 + u64::from(list.len()));
+            let ix00 = chunk(&file, position, b"ix00");
+            assert_eq!(u32_at(&file, entry + 8) as usize, ix00.len() + 8);
+            let frames = u32_at(ix00, 4) as usize;
+            assert_eq!(u32_at(&file, entry + 12) as usize, frames);
+            assert_eq!(&ix00[8..12], b"00dc");
+            let base = u64_at(ix00, 12);
+            assert_eq!(base, list.offset());
+            for frame in ix00[24..].chunks(8).take(frames) {
+                let offset = (base + u64::from(u32_at(frame, 0))) as usize;
+                let size = u32_at(frame, 4) as usize;
+                indexed.push(file[offset..offset + size].to_vec());
+            }
+        }
+        assert_eq!(indexed, images);
+    }
+
+    #[test]
+    fn short_recordings_have_one_riff_list() {
+        let format = stream_format(FrameFormat::MJPEG, 64, 48);
+        let mut writer = AviWriter::new(Cursor::new(Vec::new()), format).unwrap();
+        writer.record(&frame(&jpeg(100, 0), format)).unwrap();
+        let file = writer.finish().unwrap().into_inner();
+        let riff = u32_at(&file, 4) as usize;
+        assert_eq!(riff + 8, file.len());
+        assert!(!file.windows(4).any(|w| w == b"AVIX"));
+
+        let yuyv = stream_format(FrameFormat::YUYV, 64, 48);
+        assert!(AviWriter::new(Cursor::new(Vec::new()), yuyv).is_err());
+    }
+
+    /// Luma counting up, chroma planes of distinct values
+    fn planes(width: usize, height: usize, chroma: usize) -> [Vec<u8>; 3] {
+        [
+            (0..width * height).map(|i| i as u8).collect(),
+            (0..chroma).map(|i| 64 + i as u8).collect(),
+            (0..chroma).map(|i| 160 + i as u8).collect(),
+        ]
+    }
+
+    fn read_back(file: &[u8], frames: usize) -> Vec<[Vec<u8>; 3]> {
+        let mut decoder = y4m::decode(file).unwrap();
+        let planes = (0..frames)
+            .map(|_| {
+                let frame = decoder.read_frame().unwrap();
+                [
+                    frame.get_y_plane().to_vec(),
+                    frame.get_u_plane().to_vec(),
+                    frame.get_v_plane().to_vec(),
+                ]
+            })
+            .collect();
+        assert!(decoder.read_frame().is_err());
+        planes
+    }
+
+    #[test]
+    fn nv12_planes() {
+        let format = stream_format(FrameFormat::NV12, 8, 6);
+        let [y, u, v] = planes(8, 6, 12);
+        let mut nv12 = y.clone();
+        for (&u, &v) in u.iter().zip(&v) {
+            nv12.extend_from_slice(&[u, v]);
+        }
+        let mut writer = Y4mWriter::new(Vec::new(), format).unwrap();
+        writer.record(&frame(&nv12, format)).unwrap();
+        writer.record(&frame(&nv12, format)).unwrap();
+        let file = writer.finish().unwrap();
+
+        let decoder = y4m::decode(&file[..]).unwrap();
+        assert!(matches!(
+            decoder.get_colorspace(),
+            y4m::Colorspace::C420mpeg2
+        ));
+        assert_eq!(
+            read_back(&file, 2),
+            [[y.clone(), u.clone(), v.clone()], [y, u, v]]
+        );
+    }
+
+    #[test]
+    fn i420_planes() {
+        let format = stream_format(FrameFormat::I420, 8, 6);
+        let [y, u, v] = planes(8, 6, 12);
+        let i420 = [y.clone(), u.clone(), v.clone()].concat();
+        let mut writer = Y4mWriter::new(Vec::new(), format).unwrap();
+        writer.record(&frame(&i420, format)).unwrap();
+        let file = writer.finish().unwrap();
+        assert_eq!(read_back(&file, 1), [[y, u, v]]);
+    }
+
+    #[test]
+    fn yuyv_planes() {
+        let format = stream_format(FrameFormat::YUYV, 4, 2);
+        let [y, u, v] = planes(4, 2, 4);
+        let yuyv: Vec<u8> = (0..4)
+            .flat_map(|i| [y[2 * i], u[i], y[2 * i + 1], v[i]])
+            .collect();
+        let mut writer = Y4mWriter::new(Vec::new(), format).unwrap();
+        writer.record(&frame(&yuyv, format)).unwrap();
+        let file = writer.finish().unwrap();
+        assert_eq!(read_back(&file, 1), [[y, u, v]]);
+    }
+
+    #[test]
+    fn header_leaves_out_the_range() {
+        let format = stream_format(FrameFormat::I420, 8, 6);
+        let mut writer = Y4mWriter::new(Vec::new(), format).unwrap();
+        writer.record(&frame(&[0; 72], format)).unwrap();
+        let file = writer.finish().unwrap();
+        let header = String::from_utf8(file).unwrap();
+        assert_eq!(
+            header.lines().next(),
+            Some("YUV4MPEG2 W8 H6 F10000000:333333 Ip A1:1 C420mpeg2")
+        );
+    }
+
+    #[test]
+    fn mismatched_frames_are_refused() {
+        let format = stream_format(FrameFormat::I420, 8, 6);
+        let mut writer = Y4mWriter::new(Vec::new(), format).unwrap();
+        assert!(writer.record(&frame(&[0; 71], format)).is_err());
+        let other = stream_format(FrameFormat::I420, 6, 8);
+        assert!(writer.record(&frame(&[0; 72], other)).is_err());
+        assert!(writer.finish().unwrap().is_empty());
+        assert!(Y4mWriter::new(Vec::new(), stream_format(FrameFormat::MJPEG, 8, 6)).is_err());
+    }
+}
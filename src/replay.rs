@@ -0,0 +1,750 @@
+//! Recording of raw streams, and replaying them without a device
+//!
+//! A recording starts with a header holding the negotiated format and control,
+//! followed by one record per frame. All integers are little endian.
+//!
+//! | Field          | Size                                          |
+//! |----------------|-----------------------------------------------|
+//! | magic          | 8, `UVCREC\0` and the version                 |
+//! | format         | width, height, interval and format, 4 each    |
+//! | control        | 2 byte length, then the control               |
+//!
+//! Each frame record holds the time since the recording started (8 bytes),
+//! sequence (4), capture time (8 + 8), capture end (8 + 8), width, height
+//! and format (4 each), colour matching (3), interlace flags (1), then the
+//! data and the metadata, each preceded by a 4 byte length.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::channel::{channel, Overflow, Receiver, Sender};
+use crate::color::ColorMatching;
+use crate::formats::{FrameFormat, FrameInterval, StreamFormat};
+use crate::frame::{FormatHints, Frame};
+use crate::interlace::Interlace;
+use crate::stream_control::StreamControl;
+
+const MAGIC: &[u8; 7] = b"UVCREC\0";
+const VERSION: u8 = 1;
+/// Longest sleep before checking whether a replay was stopped
+const STOP_POLL: Duration = Duration::from_millis(50);
+
+/// Stable numbering of formats in recordings
+const FORMAT_CODES: &[(FrameFormat, u32)] = &[
+    (FrameFormat::Unknown, 0),
+    (FrameFormat::Any, 1),
+    (FrameFormat::Uncompressed, 2),
+    (FrameFormat::Compressed, 3),
+    (FrameFormat::YUYV, 4),
+    (FrameFormat::UYVY, 5),
+    (FrameFormat::RGB, 6),
+    (FrameFormat::BGR, 7),
+    (FrameFormat::MJPEG, 8),
+    (FrameFormat::GRAY8, 9),
+    (FrameFormat::GRAY16, 10),
+    (FrameFormat::BY8, 11),
+    (FrameFormat::BA81, 12),
+    (FrameFormat::SGRBG8, 13),
+    (FrameFormat::SGBRG8, 14),
+    (FrameFormat::SRGGB8, 15),
+    (FrameFormat::SBGGR8, 16),
+    (FrameFormat::NV12, 17),
+    (FrameFormat::I420, 18),
+    (FrameFormat::YV12, 19),
+    (FrameFormat::P010, 20),
+    (FrameFormat::RGBP, 21),
+    (FrameFormat::Z16, 22),
+    (FrameFormat::H264, 23),
+    (FrameFormat::HEVC, 24),
+    (FrameFormat::FrameBased, 25),
+];
+
+fn format_code(format: FrameFormat) -> u32 {
+    FORMAT_CODES
+        .iter()
+        .find(|&&(known, _)| known == format)
+        .map_or(0, |&(_, code)| code)
+}
+
+fn format_from_code(code: u32) -> FrameFormat {
+    FORMAT_CODES
+        .iter()
+        .find(|&&(_, known)| known == code)
+        .map_or(FrameFormat::Unknown, |&(format, _)| format)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes frames of a stream to a recording
+///
+/// ```no_run
+/// # let ctx = uvc::Context::new().unwrap();
+/// # let dev = ctx.find(&uvc::DeviceQuery::new()).unwrap();
+/// # let devh = dev.open().unwrap();
+/// # let format = devh.get_preferred_format(|x, _| x).unwrap();
+/// use std::sync::Mutex;
+///
+/// let mut streamh = devh.get_stream_handle_with_format(format).unwrap();
+/// let recorder =
+///     uvc::Recorder::create("capture.uvcrec", format, streamh.control()).unwrap();
+/// let stream = streamh
+///     .start_stream(
+///         |frame, recorder| {
+///             recorder.lock().unwrap().record(frame).unwrap();
+///         },
+///         Mutex::new(recorder),
+///     )
+///     .unwrap();
+/// ```
+pub struct Recorder<W: Write> {
+    writer: W,
+    started: Instant,
+}
+
+impl Recorder<BufWriter<File>> {
+    /// Creates a recording at `path`, replacing any existing file
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        format: StreamFormat,
+        control: &StreamControl,
+    ) -> io::Result<Self> {
+        Recorder::new(BufWriter::new(File::create(path)?), format, control)
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    /// Writes the header of a recording
+    pub fn new(mut writer: W, format: StreamFormat, control: &StreamControl) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        for field in [
+            format.width,
+            format.height,
+            format.interval.as_100ns(),
+            format_code(format.format),
+        ] {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+        let control = control.to_bytes();
+        writer.write_all(&(control.len() as u16).to_le_bytes())?;
+        writer.write_all(&control)?;
+        Ok(Recorder {
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    /// Appends a frame
+    // `c_long` is only 32 bits on some platforms
+    #[allow(clippy::unnecessary_cast)]
+    pub fn record(&mut self, frame: &Frame) -> io::Result<()> {
+        let raw = frame.raw();
+        let offset = u64::try_from(self.started.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        let color = frame.color_matching();
+
+        let w = &mut self.writer;
+        w.write_all(&offset.to_le_bytes())?;
+        w.write_all(&frame.sequence().to_le_bytes())?;
+        for field in [
+            raw.capture_time.tv_sec as i64,
+            raw.capture_time.tv_usec as i64,
+            raw.capture_time_finished.tv_sec as i64,
+            raw.capture_time_finished.tv_nsec as i64,
+        ] {
+            w.write_all(&field.to_le_bytes())?;
+        }
+        for field in [frame.width(), frame.height(), format_code(frame.format())] {
+            w.write_all(&field.to_le_bytes())?;
+        }
+        w.write_all(&[
+            color.primaries.into(),
+            color.transfer.into(),
+            color.matrix.into(),
+            frame.interlace().flags(),
+        ])?;
+        for data in [frame.to_bytes(), frame.metadata()] {
+            w.write_all(&(data.len() as u32).to_le_bytes())?;
+            w.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the recording and returns the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// How fast a recording is replayed
+pub enum ReplaySpeed {
+    /// With the timing of the recording
+    Recorded,
+    /// Faster by the given factor, `2.0` replays at twice the recorded speed
+    Scaled(f64),
+    /// As fast as frames can be read
+    Unthrottled,
+}
+
+/// Reads a little endian field
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    read_array(reader).map(u32::from_le_bytes)
+}
+
+fn read_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    read_array(reader).map(i64::from_le_bytes)
+}
+
+fn read_vec<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_u32(reader)? as usize;
+    let mut data = Vec::new();
+    reader.take(len as u64).read_to_end(&mut data)?;
+    if data.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(data)
+}
+
+/// Frames of a recording, delivered as if they came from a device
+pub struct ReplaySource<R: Read> {
+    reader: R,
+    format: StreamFormat,
+    control: StreamControl,
+    speed: ReplaySpeed,
+    /// Offset of the first frame, and when it was replayed
+    start: Option<(Duration, Instant)>,
+}
+
+impl ReplaySource<BufReader<File>> {
+    /// Opens the recording at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        ReplaySource::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> ReplaySource<R> {
+    /// Reads the header of a recording
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let magic: [u8; 8] = read_array(&mut reader)?;
+        if magic[..7] != MAGIC[..] {
+            return Err(invalid_data("not a stream recording"));
+        }
+        if magic[7] != VERSION {
+            return Err(invalid_data("unsupported recording version"));
+        }
+        let format = StreamFormat {
+            width: read_u32(&mut reader)?,
+            height: read_u32(&mut reader)?,
+            interval: FrameInterval(read_u32(&mut reader)?),
+            format: format_from_code(read_u32(&mut reader)?),
+        };
+        let len = u16::from_le_bytes(read_array(&mut reader)?);
+        let mut control = vec![0; usize::from(len)];
+        reader.read_exact(&mut control)?;
+        let control =
+            StreamControl::from_bytes(&control).ok_or_else(|| invalid_data("invalid control"))?;
+
+        Ok(ReplaySource {
+            reader,
+            format,
+            control,
+            speed: ReplaySpeed::Recorded,
+            start: None,
+        })
+    }
+
+    /// Format the recorded stream was negotiated with
+    #[must_use]
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    /// Control the recorded stream was negotiated with
+    #[must_use]
+    pub fn control(&self) -> &StreamControl {
+        &self.control
+    }
+
+    /// Replay at this speed, the default is `ReplaySpeed::Recorded`
+    #[must_use]
+    pub fn speed(mut self, speed: ReplaySpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Reads the next frame, `None` at the end of the recording
+    ///
+    /// Blocks until the frame is due at the chosen speed
+    pub fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        self.next_frame_until(|| false)
+    }
+
+    fn next_frame_until(&mut self, stopped: impl Fn() -> bool) -> io::Result<Option<Frame>> {
+        let offset: [u8; 8] = match read_array(&mut self.reader) {
+            Ok(offset) => offset,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let offset = Duration::from_nanos(u64::from_le_bytes(offset));
+        let frame = self.read_frame()?;
+
+        let (first, started) = *self.start.get_or_insert((offset, Instant::now()));
+        let factor = match self.speed {
+            ReplaySpeed::Recorded => 1.0,
+            ReplaySpeed::Scaled(factor) if factor > 0.0 => factor,
+            _ => return Ok(Some(frame)),
+        };
+        let due = started + offset.saturating_sub(first).div_f64(factor);
+        loop {
+            let now = Instant::now();
+            if now >= due || stopped() {
+                break;
+            }
+            thread::sleep((due - now).min(STOP_POLL));
+        }
+        Ok(Some(frame))
+    }
+
+    fn read_frame(&mut self) -> io::Result<Frame> {
+        let r = &mut self.reader;
+        let sequence = read_u32(r)?;
+        let times = [read_i64(r)?, read_i64(r)?, read_i64(r)?, read_i64(r)?];
+        let width = read_u32(r)?;
+        let height = read_u32(r)?;
+        let format = format_from_code(read_u32(r)?);
+        let [primaries, transfer, matrix, interlace]: [u8; 4] = read_array(r)?;
+        let data = read_vec(r)?;
+        let metadata = read_vec(r)?;
+
+        let metadata = if metadata.is_empty() {
+            None
+        } else {
+            Some(metadata.into_boxed_slice())
+        };
+        let hints = FormatHints {
+            format,
+            color_matching: ColorMatching {
+                primaries: primaries.into(),
+                transfer: transfer.into(),
+                matrix: matrix.into(),
+            },
+            interlace: Interlace::from_flags(interlace),
+        };
+        let mut frame =
+            Frame::from_bytes(&data, width, height, format, metadata).with_hints(Some(hints));
+        let raw = frame.raw_mut();
+        raw.sequence = sequence;
+        raw.capture_time.tv_sec = times[0] as _;
+        raw.capture_time.tv_usec = times[1] as _;
+        raw.capture_time_finished.tv_sec = times[2] as _;
+        raw.capture_time_finished.tv_nsec = times[3] as _;
+        Ok(frame)
+    }
+}
+
+impl<R: Read> Iterator for ReplaySource<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<io::Result<Frame>> {
+        self.next_frame().transpose()
+    }
+}
+
+impl<R: Read + Send + 'static> ReplaySource<R> {
+    /// Delivers the frames to a callback on a separate thread, like `StreamHandle::start_stream`
+    ///
+    /// This function is non-blocking
+    pub fn start_stream<F, U>(self, mut cb: F, user_data: U) -> ActiveReplay<U>
+    where
+        F: 'static + Send + FnMut(&Frame, &mut U),
+        U: 'static + Send + Sync,
+    {
+        self.spawn(
+            move |frame, user_data| cb(&frame, user_data),
+            user_data,
+            None,
+        )
+    }
+
+    /// Sends the frames to the returned receiver, like `StreamHandle::start_stream_channel`
+    ///
+    /// The receiver can be used blocking or from async tasks through `Receiver::recv_async`,
+    /// it is disconnected at the end of the recording. This function is non-blocking
+    ///
+    /// ```no_run
+    /// let replay = uvc::ReplaySource::open("capture.uvcrec").unwrap();
+    /// let (replay, frames) = replay.start_stream_channel(4, uvc::Overflow::Block);
+    /// for frame in frames.iter() {
+    ///     println!("{} bytes", frame.to_bytes().len());
+    /// }
+    /// replay.join().unwrap();
+    /// ```
+    pub fn start_stream_channel(
+        self,
+        capacity: usize,
+        overflow: Overflow,
+    ) -> (ActiveReplay<()>, Receiver<Frame>) {
+        let (sender, receiver) = channel(capacity, overflow);
+        let closer = CloseOnDrop(sender.clone());
+        let replay = self.spawn(
+            move |frame, _: &mut ()| closer.0.send(frame),
+            (),
+            Some(sender),
+        );
+        (replay, receiver)
+    }
+
+    fn spawn<F, U>(
+        mut self,
+        mut deliver: F,
+        mut user_data: U,
+        sender: Option<Sender<Frame>>,
+    ) -> ActiveReplay<U>
+    where
+        F: 'static + Send + FnMut(Frame, &mut U),
+        U: 'static + Send,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let is_stopped = || stopped.load(Ordering::Relaxed);
+            while !is_stopped() {
+                match self.next_frame_until(is_stopped)? {
+                    Some(frame) if !is_stopped() => deliver(frame, &mut user_data),
+                    _ => break,
+                }
+            }
+            Ok(user_data)
+        });
+        ActiveReplay {
+            stop,
+            sender,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Disconnects the receiver once the replay thread drops its callback
+struct CloseOnDrop(Sender<Frame>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+#[derive(Debug)]
+/// Replay running on a separate thread
+///
+/// Dropping this replay will stop it
+pub struct ActiveReplay<U> {
+    stop: Arc<AtomicBool>,
+    /// Channel to close on stop, releasing a blocked `send`
+    sender: Option<Sender<Frame>>,
+    thread: Option<thread::JoinHandle<io::Result<U>>>,
+}
+
+impl<U> ActiveReplay<U> {
+    /// Whether all frames have been delivered, or reading failed
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+    }
+
+    /// Waits for the end of the recording, returning the user data
+    pub fn join(mut self) -> io::Result<U> {
+        self.wait()
+    }
+
+    /// Stops the replay, returning the user data
+    pub fn stop(mut self) -> io::Result<U> {
+        self.signal_stop();
+        self.wait()
+    }
+
+    fn signal_stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(sender) = &self.sender {
+            sender.close();
+        }
+    }
+
+    fn wait(&mut self) -> io::Result<U> {
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+impl<U> Drop for ActiveReplay<U> {
+    fn drop(&mut self) {
+        self.signal_stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{ColorPrimaries, MatrixCoefficients, TransferCharacteristics};
+    use crate::stream_control::CONTROL_BYTES;
+
+    fn control() -> StreamControl {
+        let mut bytes = [0; CONTROL_BYTES];
+        bytes[2] = 1;
+        bytes[3] = 2;
+        bytes[4..8].copy_from_slice(&333_333u32.to_le_bytes());
+        StreamControl::from_bytes(&bytes).unwrap()
+    }
+
+    fn format() -> StreamFormat {
+        StreamFormat {
+            width: 4,
+            height: 2,
+            interval: FrameInterval::from_fps(30),
+            format: FrameFormat::NV12,
+        }
+    }
+
+    fn frames() -> Vec<Frame> {
+        let hints = FormatHints {
+            format: FrameFormat::NV12,
+            color_matching: ColorMatching {
+                primaries: ColorPrimaries::Bt709,
+                transfer: TransferCharacteristics::Bt709,
+                matrix: MatrixCoefficients::Bt709,
+            },
+            interlace: Interlace::from_flags(0b0010_0111),
+        };
+        let mjpeg = FormatHints {
+            format: FrameFormat::MJPEG,
+            ..hints
+        };
+        let nv12: Vec<u8> = (0..12).collect();
+        let frames = vec![
+            Frame::from_bytes(&nv12, 4, 2, FrameFormat::NV12, None),
+            // Devices send empty payloads when a frame is lost
+            Frame::from_bytes(&[], 4, 2, FrameFormat::NV12, None),
+            Frame::from_bytes(
+                &[0xff, 0xd8],
+                4,
+                2,
+                FrameFormat::MJPEG,
+                Some(Box::new([1, 2])),
+            ),
+        ];
+        let mut frames: Vec<Frame> = frames
+            .into_iter()
+            .zip([hints, hints, mjpeg])
+            .map(|(frame, hints)| frame.with_hints(Some(hints)))
+            .collect();
+        for (sequence, frame) in (7..).zip(&mut frames) {
+            let raw = frame.raw_mut();
+            raw.sequence = sequence;
+            raw.capture_time.tv_sec = 1_000 + sequence as libc::time_t;
+            raw.capture_time.tv_usec = 500;
+            raw.capture_time_finished.tv_sec = 2_000;
+            raw.capture_time_finished.tv_nsec = 250;
+        }
+        frames
+    }
+
+    fn recording() -> Vec<u8> {
+        let mut recorder = Recorder::new(Vec::new(), format(), &control()).unwrap();
+        for frame in frames() {
+            recorder.record(&frame).unwrap();
+        }
+        recorder.finish().unwrap()
+    }
+
+    fn assert_same(replayed: &Frame, recorded: &Frame) {
+        assert_eq!(replayed.to_bytes(), recorded.to_bytes());
+        assert_eq!(replayed.metadata(), recorded.metadata());
+        assert_eq!(replayed.sequence(), recorded.sequence());
+        assert_eq!(replayed.capture_time(), recorded.capture_time());
+        assert_eq!(replayed.width(), recorded.width());
+        assert_eq!(replayed.height(), recorded.height());
+        assert_eq!(replayed.format(), recorded.format());
+        assert_eq!(replayed.step(), recorded.step());
+        assert_eq!(replayed.color_matching(), recorded.color_matching());
+        assert_eq!(replayed.interlace(), recorded.interlace());
+        let raw = replayed.raw();
+        let recorded_raw = recorded.raw();
+        assert_eq!(
+            raw.capture_time_finished.tv_sec,
+            recorded_raw.capture_time_finished.tv_sec
+        );
+        assert_eq!(
+            raw.capture_time_finished.tv_nsec,
+            recorded_raw.capture_time_finished.tv_nsec
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let recording = recording();
+        let replay = ReplaySource::new(recording.as_slice())
+            .unwrap()
+            .speed(ReplaySpeed::Unthrottled);
+        assert_eq!(replay.format(), format());
+        assert_eq!(replay.control().to_bytes(), control().to_bytes());
+
+        let replayed: Vec<Frame> = replay.collect::<io::Result<_>>().unwrap();
+        let recorded = frames();
+        assert_eq!(replayed.len(), recorded.len());
+        for (replayed, recorded) in replayed.iter().zip(&recorded) {
+            assert_same(replayed, recorded);
+        }
+        // The luma plane of NV12 has one byte per pixel
+        assert_eq!(replayed[0].step(), 4);
+        assert!(replayed[1].to_bytes().is_empty());
+    }
+
+    #[test]
+    fn callback_and_channel() {
+        let replay = ReplaySource::new(io::Cursor::new(recording()))
+            .unwrap()
+            .speed(ReplaySpeed::Unthrottled);
+        let sequences = replay
+            .start_stream(
+                |frame, seen: &mut Vec<u32>| seen.push(frame.sequence()),
+                Vec::new(),
+            )
+            .join()
+            .unwrap();
+        assert_eq!(sequences, [7, 8, 9]);
+
+        let replay = ReplaySource::new(io::Cursor::new(recording()))
+            .unwrap()
+            .speed(ReplaySpeed::Unthrottled);
+        let (replay, frames) = replay.start_stream_channel(1, Overflow::Block);
+        let replayed: Vec<Frame> = frames.iter().collect();
+        replay.join().unwrap();
+        for (replayed, recorded) in replayed.iter().zip(&self::frames()) {
+            assert_same(replayed, recorded);
+        }
+        assert_eq!(replayed.len(), 3);
+    }
+
+    /// Runs `future` on the current thread
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        struct Unpark(thread::Thread);
+
+        impl std::task::Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Arc::new(Unpark(thread::current())).into();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn async_channel() {
+        let replay = ReplaySource::new(io::Cursor::new(recording()))
+            .unwrap()
+            .speed(ReplaySpeed::Scaled(100.0));
+        let (replay, frames) = replay.start_stream_channel(1, Overflow::Block);
+        let sequences = block_on(async {
+            let mut sequences = Vec::new();
+            while let Ok(frame) = frames.recv_async().await {
+                sequences.push(frame.sequence());
+            }
+            sequences
+        });
+        replay.join().unwrap();
+        assert_eq!(sequences, [7, 8, 9]);
+    }
+
+    #[test]
+    fn stop_blocked_channel() {
+        let replay = ReplaySource::new(io::Cursor::new(recording()))
+            .unwrap()
+            .speed(ReplaySpeed::Unthrottled);
+        let (replay, frames) = replay.start_stream_channel(1, Overflow::Block);
+        // The replay thread blocks on the second frame until stopped
+        let first = frames.recv().unwrap();
+        assert_eq!(first.sequence(), 7);
+        replay.stop().unwrap();
+        while frames.try_recv().is_ok() {}
+        assert!(frames.recv().is_err());
+    }
+
+    #[test]
+    fn scaled_speed() {
+        let mut recording = recording();
+        // Space the frames 40 ms apart in the recording
+        let mut offset = 8 + 16 + 2 + CONTROL_BYTES;
+        for i in 0..3u64 {
+            let nanos = i * 40_000_000;
+            recording[offset..offset + 8].copy_from_slice(&nanos.to_le_bytes());
+            offset += record_len(&recording[offset..]);
+        }
+        assert_eq!(offset, recording.len());
+
+        let started = Instant::now();
+        let replay = ReplaySource::new(recording.as_slice())
+            .unwrap()
+            .speed(ReplaySpeed::Scaled(2.0));
+        assert_eq!(replay.count(), 3);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(40), "{elapsed:?}");
+    }
+
+    /// Length of the frame record at the start of `bytes`
+    fn record_len(bytes: &[u8]) -> usize {
+        // Offset, sequence, timestamps, size and format, colour and interlace
+        let fixed = 8 + 4 + 32 + 12 + 4;
+        let len_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap()) as usize;
+        let data = len_at(fixed);
+        let metadata = len_at(fixed + 4 + data);
+        fixed + 4 + data + 4 + metadata
+    }
+
+    #[test]
+    fn invalid_recordings() {
+        let err = |bytes: &[u8]| ReplaySource::new(bytes).err().unwrap().kind();
+        assert_eq!(err(b"not a recording"), io::ErrorKind::InvalidData);
+        let mut recording = recording();
+        recording[7] = VERSION + 1;
+        assert_eq!(err(&recording), io::ErrorKind::InvalidData);
+
+        // A truncated frame is an error, not the end of the recording
+        let recording = self::recording();
+        let mut replay = ReplaySource::new(&recording[..recording.len() - 2])
+            .unwrap()
+            .speed(ReplaySpeed::Unthrottled);
+        assert!(replay.next().unwrap().is_ok());
+        assert!(replay.next().unwrap().is_ok());
+        assert!(replay.next().unwrap().is_err());
+    }
+}
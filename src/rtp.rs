@@ -0,0 +1,761 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::formats::FrameFormat;
+use crate::frame::{monotonic_now, Frame};
+use crate::mjpeg::{self, Segment, DHT, DQT, DRI, SOF0, SOS};
+
+/// Payload type of JPEG, from RFC 3551
+const PAYLOAD_TYPE: u8 = 26;
+/// Clock rate of the timestamps of video
+const CLOCK_RATE: u128 = 90_000;
+const RTP_HEADER: usize = 12;
+const JPEG_HEADER: usize = 8;
+const RESTART_HEADER: usize = 4;
+const QUANTIZATION_HEADER: usize = 4;
+/// Q value signalling that the quantization tables are sent in the first packet
+const DYNAMIC_TABLES: u8 = 255;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Parts of a baseline JPEG image carried by RFC 2435
+#[derive(Debug)]
+struct Parsed<'a> {
+    /// 0 for 4:2:2, 1 for 4:2:0, plus 64 with restart markers
+    kind: u8,
+    width: u8,
+    height: u8,
+    /// Luminance table followed by the chrominance table, in zigzag order
+    tables: Vec<u8>,
+    restart_interval: u16,
+    scan: &'a [u8],
+}
+
+/// Huffman tables of a DHT payload, each with its class and id
+fn huffman_tables(payload: &[u8]) -> io::Result<Vec<&[u8]>> {
+    let mut tables = Vec::new();
+    let mut rest = payload;
+    while !rest.is_empty() {
+        let length = rest
+            .get(1..17)
+            .map(|bits| 17 + bits.iter().map(|&count| usize::from(count)).sum::<usize>())
+            .filter(|&length| length <= rest.len())
+            .ok_or_else(|| invalid_data("truncated Huffman table"))?;
+        let (table, tail) = rest.split_at(length);
+        tables.push(table);
+        rest = tail;
+    }
+    Ok(tables)
+}
+
+fn parse(jpeg: &[u8]) -> io::Result<Parsed<'_>> {
+    let defaults = mjpeg::default_tables();
+    let defaults = huffman_tables(&defaults[4..])?;
+    let mut tables: [Option<&[u8]>; 4] = [None; 4];
+    let mut frame = None;
+    let mut restart_interval = 0;
+    let mut scan = None;
+    for segment in mjpeg::segments(jpeg) {
+        let Segment {
+            marker, payload, ..
+        } = segment;
+        match marker {
+            DQT => {
+                let mut rest = payload;
+                while let [pq_tq, table @ ..] = rest {
+                    if pq_tq >> 4 != 0 {
+                        return Err(invalid_data("16 bit quantization tables are not supported"));
+                    }
+                    let table = table
+                        .get(..64)
+                        .ok_or_else(|| invalid_data("truncated quantization table"))?;
+                    tables[usize::from(pq_tq & 3)] = Some(table);
+                    rest = &rest[65..];
+                }
+            }
+            // RFC 2435 carries no Huffman tables, receivers assume the defaults
+            DHT if !huffman_tables(payload)?
+                .iter()
+                .all(|table| defaults.contains(table)) =>
+            {
+                return Err(invalid_data(
+                    "only the Huffman tables of the JPEG specification can be sent",
+                ));
+            }
+            SOF0 => frame = Some(payload),
+            0xc1..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                return Err(invalid_data("only baseline JPEG can be sent"));
+            }
+            DRI => {
+                if let [high, low] = payload {
+                    restart_interval = u16::from_be_bytes([*high, *low]);
+                }
+            }
+            SOS => scan = Some(&jpeg[segment.end()..]),
+            _ => {}
+        }
+    }
+    let frame = frame.ok_or_else(|| invalid_data("missing start of frame"))?;
+    let scan = scan.ok_or_else(|| invalid_data("missing start of scan"))?;
+    let scan = scan.strip_suffix(&[0xff, 0xd9]).unwrap_or(scan);
+
+    let [8, h0, h1, w0, w1, 3, 1, luma, luma_table, _, chroma, cb_table, _, chroma2, cr_table] =
+        *frame
+    else {
+        return Err(invalid_data(
+            "only 8 bit images with three components can be sent",
+        ));
+    };
+    let kind = match (luma, chroma, chroma2) {
+        (0x21, 0x11, 0x11) => 0,
+        (0x22, 0x11, 0x11) => 1,
+        _ => return Err(invalid_data("only 4:2:2 and 4:2:0 subsampling can be sent")),
+    };
+    if cb_table != cr_table {
+        return Err(invalid_data(
+            "both chrominance components must use the same table",
+        ));
+    }
+    let table = |id: u8| {
+        tables[usize::from(id & 3)].ok_or_else(|| invalid_data("missing quantization table"))
+    };
+    let mut both = table(luma_table)?.to_vec();
+    both.extend_from_slice(table(cb_table)?);
+
+    let blocks = |pixels: u16| {
+        u8::try_from(pixels.div_ceil(8))
+            .map_err(|_| invalid_data("images can be at most 2040 pixels wide and high"))
+    };
+    Ok(Parsed {
+        kind: kind + if restart_interval > 0 { 64 } else { 0 },
+        width: blocks(u16::from_be_bytes([w0, w1]))?,
+        height: blocks(u16::from_be_bytes([h0, h1]))?,
+        tables: both,
+        restart_interval,
+        scan,
+    })
+}
+
+#[derive(Debug)]
+/// Splits MJPEG frames into RTP packets, as described by RFC 2435
+///
+/// The quantization tables are sent with every frame. Huffman tables are not
+/// sent, receivers use the tables of the JPEG specification as UVC cameras do,
+/// so images coded with other tables are refused.
+/// Timestamps are taken from `Frame::capture_time` where available.
+pub struct RtpPacketizer {
+    ssrc: u32,
+    sequence: u16,
+    /// Random start of the timestamps, as RFC 3550 asks
+    timestamp_offset: u32,
+    payload_size: usize,
+    started: Instant,
+}
+
+impl Default for RtpPacketizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RtpPacketizer {
+    /// Packetizer with a random synchronization source and starting sequence number
+    #[must_use]
+    pub fn new() -> Self {
+        let seed = random();
+        RtpPacketizer {
+            ssrc: seed as u32,
+            sequence: (seed >> 32) as u16,
+            timestamp_offset: random() as u32,
+            payload_size: 1400,
+            started: Instant::now(),
+        }
+    }
+
+    /// Largest RTP packet to create, the default of 1400 bytes fits the MTU of Ethernet
+    #[must_use]
+    pub fn payload_size(mut self, payload_size: usize) -> Self {
+        // The first packet must hold the headers and both quantization tables
+        self.payload_size = payload_size.max(RTP_HEADER + JPEG_HEADER + RESTART_HEADER + 256);
+        self
+    }
+
+    /// Synchronization source identifier of the packets
+    #[must_use]
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    /// Splits an MJPEG frame into packets
+    pub fn packetize(&mut self, frame: &Frame) -> io::Result<Vec<Vec<u8>>> {
+        if frame.format() != FrameFormat::MJPEG {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only MJPEG frames can be sent",
+            ));
+        }
+        let time = frame
+            .capture_time()
+            .or_else(monotonic_now)
+            .unwrap_or_else(|| self.started.elapsed());
+        self.packetize_jpeg(frame.to_bytes(), time)
+    }
+
+    /// Splits a baseline JPEG image into packets, stamped with `time` on any clock
+    pub fn packetize_jpeg(&mut self, jpeg: &[u8], time: Duration) -> io::Result<Vec<Vec<u8>>> {
+        let parsed = parse(jpeg)?;
+        let timestamp = ((time.as_nanos() * CLOCK_RATE / 1_000_000_000) as u32)
+            .wrapping_add(self.timestamp_offset);
+
+        let mut packets = Vec::new();
+        let mut offset = 0;
+        loop {
+            let mut packet = Vec::with_capacity(self.payload_size);
+            packet.extend_from_slice(&[0x80, PAYLOAD_TYPE]);
+            packet.extend_from_slice(&self.sequence.to_be_bytes());
+            packet.extend_from_slice(&timestamp.to_be_bytes());
+            packet.extend_from_slice(&self.ssrc.to_be_bytes());
+            self.sequence = self.sequence.wrapping_add(1);
+
+            packet.push(0);
+            packet.extend_from_slice(&(offset as u32).to_be_bytes()[1..]);
+            packet.extend_from_slice(&[parsed.kind, DYNAMIC_TABLES, parsed.width, parsed.height]);
+            if parsed.restart_interval > 0 {
+                packet.extend_from_slice(&parsed.restart_interval.to_be_bytes());
+                // Packets are not aligned with the restart intervals
+                packet.extend_from_slice(&[0xff, 0xff]);
+            }
+            if offset == 0 {
+                packet.extend_from_slice(&[0, 0]);
+                packet.extend_from_slice(&(parsed.tables.len() as u16).to_be_bytes());
+                packet.extend_from_slice(&parsed.tables);
+            }
+            let room = self.payload_size.saturating_sub(packet.len()).max(1);
+            let end = (offset + room).min(parsed.scan.len());
+            packet.extend_from_slice(&parsed.scan[offset..end]);
+            offset = end;
+            packets.push(packet);
+            if end == parsed.scan.len() {
+                break;
+            }
+        }
+        if let Some(last) = packets.last_mut() {
+            // The marker bit ends the frame
+            last[1] |= 0x80;
+        }
+        Ok(packets)
+    }
+}
+
+#[derive(Debug, Default)]
+/// Reassembles JPEG images from RTP packets created as described by RFC 2435
+///
+/// Only images which carry their quantization tables are reassembled,
+/// as `RtpPacketizer` sends them. Images missing a packet are dropped.
+///
+/// ```
+/// use std::net::UdpSocket;
+/// use uvc::{Backend, Frame, FrameFormat, PanicPolicy, RtpDepacketizer, RtpSender, VirtualCamera};
+///
+/// let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let mut sender = RtpSender::new(receiver.local_addr().unwrap()).unwrap();
+///
+/// let camera = VirtualCamera::webcam();
+/// let format = camera
+///     .formats()
+///     .unwrap()
+///     .into_iter()
+///     .find(|format| format.format == FrameFormat::MJPEG)
+///     .unwrap();
+/// let stream = camera
+///     .start_stream(
+///         format,
+///         PanicPolicy::default(),
+///         Box::new(move |frame: &Frame| sender.send(frame).unwrap()),
+///     )
+///     .unwrap();
+///
+/// let mut depacketizer = RtpDepacketizer::new();
+/// let mut packet = [0; 2048];
+/// let jpeg = loop {
+///     let len = receiver.recv(&mut packet).unwrap();
+///     if let Some(jpeg) = depacketizer.push(&packet[..len]) {
+///         break jpeg;
+///     }
+/// };
+/// assert!(jpeg.starts_with(&[0xff, 0xd8]) && jpeg.ends_with(&[0xff, 0xd9]));
+/// ```
+pub struct RtpDepacketizer {
+    timestamp: u32,
+    /// Whether the packets of the current image so far are complete
+    complete: bool,
+    kind: u8,
+    width: u8,
+    height: u8,
+    restart_interval: u16,
+    tables: Vec<u8>,
+    scan: Vec<u8>,
+}
+
+impl RtpDepacketizer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a packet, returning the image it completes
+    pub fn push(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        let mut packet = packet;
+        let (header, mut payload) = split(packet, RTP_HEADER)?;
+        let (first, second) = (header[0], header[1]);
+        if first >> 6 != 2 || second & 0x7f != PAYLOAD_TYPE {
+            return None;
+        }
+        let marker = second & 0x80 != 0;
+        let timestamp = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        if first & 0x20 != 0 {
+            // Padding, the last byte counts its length
+            let padding = usize::from(*packet.last()?);
+            packet = packet.get(..packet.len().checked_sub(padding)?)?;
+            payload = packet.get(RTP_HEADER..)?;
+        }
+        payload = payload.get(4 * usize::from(first & 0x0f)..)?;
+        if first & 0x10 != 0 {
+            let (extension, rest) = split(payload, 4)?;
+            let words = usize::from(u16::from_be_bytes([extension[2], extension[3]]));
+            payload = rest.get(4 * words..)?;
+        }
+
+        let (jpeg, mut payload) = split(payload, JPEG_HEADER)?;
+        let offset = u32::from_be_bytes([0, jpeg[1], jpeg[2], jpeg[3]]) as usize;
+        let (kind, q, width, height) = (jpeg[4], jpeg[5], jpeg[6], jpeg[7]);
+        if kind >= 64 {
+            let (restart, rest) = split(payload, RESTART_HEADER)?;
+            self.restart_interval = u16::from_be_bytes([restart[0], restart[1]]);
+            payload = rest;
+        }
+        if offset == 0 {
+            self.timestamp = timestamp;
+            self.complete = q >= 128;
+            self.kind = kind;
+            self.width = width;
+            self.height = height;
+            self.scan.clear();
+            if q >= 128 {
+                let (header, rest) = split(payload, QUANTIZATION_HEADER)?;
+                let length = usize::from(u16::from_be_bytes([header[2], header[3]]));
+                let (tables, rest) = split(rest, length)?;
+                self.complete &= header[1] == 0 && (length == 64 || length == 128);
+                self.tables = tables.to_vec();
+                payload = rest;
+            }
+        } else if timestamp != self.timestamp || offset != self.scan.len() {
+            self.complete = false;
+        }
+        if !self.complete {
+            return None;
+        }
+        self.scan.extend_from_slice(payload);
+        if !marker {
+            return None;
+        }
+        self.complete = false;
+        Some(self.to_jpeg())
+    }
+
+    fn to_jpeg(&self) -> Vec<u8> {
+        let mut jpeg = vec![0xff, 0xd8];
+        let mut dqt = Vec::new();
+        for (id, table) in self.tables.chunks(64).enumerate() {
+            dqt.push(id as u8);
+            dqt.extend_from_slice(table);
+        }
+        push_segment(&mut jpeg, DQT, &dqt);
+
+        // With a single table, chrominance is quantized like luminance
+        let chroma_table = u8::from(self.tables.len() == 128);
+        let luma = if self.kind & 63 == 0 { 0x21 } else { 0x22 };
+        let mut sof = vec![8];
+        sof.extend_from_slice(&(u16::from(self.height) * 8).to_be_bytes());
+        sof.extend_from_slice(&(u16::from(self.width) * 8).to_be_bytes());
+        sof.extend_from_slice(&[3, 1, luma, 0, 2, 0x11, chroma_table, 3, 0x11, chroma_table]);
+        push_segment(&mut jpeg, SOF0, &sof);
+        if self.kind >= 64 {
+            push_segment(&mut jpeg, DRI, &self.restart_interval.to_be_bytes());
+        }
+        jpeg.extend_from_slice(&mjpeg::default_tables());
+        push_segment(&mut jpeg, SOS, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+        jpeg.extend_from_slice(&self.scan);
+        jpeg.extend_from_slice(&[0xff, 0xd9]);
+        jpeg
+    }
+}
+
+fn push_segment(jpeg: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    jpeg.extend_from_slice(&[0xff, marker]);
+    jpeg.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    jpeg.extend_from_slice(payload);
+}
+
+/// Splits off the first `n` bytes
+fn split(data: &[u8], n: usize) -> Option<(&[u8], &[u8])> {
+    (data.len() >= n).then(|| data.split_at(n))
+}
+
+#[derive(Debug)]
+/// Sends MJPEG frames as RTP over UDP
+///
+/// `sdp` describes the stream for receivers such as `ffplay` and GStreamer.
+///
+/// ```no_run
+/// # let ctx = uvc::Context::new().unwrap();
+/// # let dev = ctx.find(&uvc::DeviceQuery::new()).unwrap();
+/// # let devh = dev.open().unwrap();
+/// # let format = devh.get_preferred_format(|x, _| x).unwrap();
+/// let sender = uvc::RtpSender::new("192.168.1.20:5004").unwrap();
+/// // Receive with `ffplay -protocol_whitelist file,udp,rtp stream.sdp`
+/// std::fs::write("stream.sdp", sender.sdp().unwrap()).unwrap();
+///
+/// let mut streamh = devh.get_stream_handle_with_format(format).unwrap();
+/// let stream = streamh
+///     .start_stream(
+///         |frame, sender: &mut uvc::RtpSender| {
+///             let _ = sender.send(frame);
+///         },
+///         sender,
+///     )
+///     .unwrap();
+/// ```
+pub struct RtpSender {
+    socket: UdpSocket,
+    packetizer: RtpPacketizer,
+}
+
+impl RtpSender {
+    /// Sends to `destination` from an unused port
+    pub fn new(destination: impl ToSocketAddrs) -> io::Result<Self> {
+        let destination = destination
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address"))?;
+        let local: SocketAddr = match destination {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(destination)?;
+        Self::from_socket(socket)
+    }
+
+    /// Sends through a socket connected to the receiver
+    pub fn from_socket(socket: UdpSocket) -> io::Result<Self> {
+        socket.peer_addr()?;
+        Ok(RtpSender {
+            socket,
+            packetizer: RtpPacketizer::new(),
+        })
+    }
+
+    /// Largest UDP payload to send, see `RtpPacketizer::payload_size`
+    #[must_use]
+    pub fn payload_size(mut self, payload_size: usize) -> Self {
+        self.packetizer = self.packetizer.payload_size(payload_size);
+        self
+    }
+
+    /// Sends an MJPEG frame
+    pub fn send(&mut self, frame: &Frame) -> io::Result<()> {
+        for packet in self.packetizer.packetize(frame)? {
+            self.socket.send(&packet)?;
+        }
+        Ok(())
+    }
+
+    /// Session description of the stream, as read by receivers from a `.sdp` file
+    pub fn sdp(&self) -> io::Result<String> {
+        let local = self.socket.local_addr()?;
+        let destination = self.socket.peer_addr()?;
+        let family = |ip: IpAddr| if ip.is_ipv4() { "IP4" } else { "IP6" };
+        let mut connection = destination.ip().to_string();
+        if let IpAddr::V4(ip) = destination.ip() {
+            if ip.is_multicast() {
+                connection = format!("{}/{}", ip, self.socket.multicast_ttl_v4()?);
+            }
+        }
+        Ok(format!(
+            "v=0\r\n\
+             o=- {ssrc} 0 IN {} {}\r\n\
+             s=uvc\r\n\
+             c=IN {} {connection}\r\n\
+             t=0 0\r\n\
+             m=video {} RTP/AVP {PAYLOAD_TYPE}\r\n\
+             a=rtpmap:{PAYLOAD_TYPE} JPEG/90000\r\n",
+            family(local.ip()),
+            local.ip(),
+            family(destination.ip()),
+            destination.port(),
+            ssrc = self.packetizer.ssrc(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::{FrameInterval, StreamFormat};
+    use crate::virtual_camera::VirtualCamera;
+    use crate::{Backend, PanicPolicy};
+
+    const WIDTH: u16 = 48;
+    const HEIGHT: u16 = 16;
+
+    /// Distinct quantization tables, so swapping them is noticed
+    fn quantization_tables() -> Vec<u8> {
+        (0..128).map(|i| i as u8 + 1).collect()
+    }
+
+    /// Entropy coded data holding `0xff` only as stuffed bytes and restart markers
+    fn scan(len: usize, restart_every: Option<usize>) -> Vec<u8> {
+        let mut scan = Vec::new();
+        let mut marker = 0;
+        for i in 0..len {
+            scan.push((i * 7 % 255) as u8);
+            if i % 97 == 0 {
+                scan.extend_from_slice(&[0xff, 0x00]);
+            }
+            if restart_every.is_some_and(|every| i % every == every - 1) {
+                scan.extend_from_slice(&[0xff, 0xd0 + marker]);
+                marker = (marker + 1) % 8;
+            }
+        }
+        scan
+    }
+
+    /// Baseline 4:2:0 image, as sent by MJPEG cameras
+    fn image(restart_interval: u16, dht: Option<&[u8]>, scan: &[u8]) -> Vec<u8> {
+        let mut jpeg = vec![0xff, 0xd8];
+        let tables = quantization_tables();
+        let mut dqt = vec![0];
+        dqt.extend_from_slice(&tables[..64]);
+        dqt.push(1);
+        dqt.extend_from_slice(&tables[64..]);
+        push_segment(&mut jpeg, DQT, &dqt);
+        let mut sof = vec![8];
+        sof.extend_from_slice(&HEIGHT.to_be_bytes());
+        sof.extend_from_slice(&WIDTH.to_be_bytes());
+        sof.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+        push_segment(&mut jpeg, SOF0, &sof);
+        if restart_interval > 0 {
+            push_segment(&mut jpeg, DRI, &restart_interval.to_be_bytes());
+        }
+        if let Some(dht) = dht {
+            jpeg.extend_from_slice(dht);
+        }
+        push_segment(&mut jpeg, SOS, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+        jpeg.extend_from_slice(scan);
+        jpeg.extend_from_slice(&[0xff, 0xd9]);
+        jpeg
+    }
+
+    fn packetize(jpeg: &[u8], time: Duration) -> Vec<Vec<u8>> {
+        RtpPacketizer::new()
+            .payload_size(400)
+            .packetize_jpeg(jpeg, time)
+            .unwrap()
+    }
+
+    /// Pushes the packets, returning the images they completed
+    fn depacketize<'a>(packets: impl IntoIterator<Item = &'a Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut depacketizer = RtpDepacketizer::new();
+        packets
+            .into_iter()
+            .filter_map(|packet| depacketizer.push(packet))
+            .collect()
+    }
+
+    fn assert_same_image(received: &[u8], sent: &[u8]) {
+        let (received, sent) = (parse(received).unwrap(), parse(sent).unwrap());
+        assert_eq!(received.tables, sent.tables);
+        assert_eq!(received.scan, sent.scan);
+        assert_eq!(received.restart_interval, sent.restart_interval);
+        assert_eq!(received.kind, sent.kind);
+        assert_eq!((received.width, received.height), (sent.width, sent.height));
+    }
+
+    #[test]
+    fn round_trip() {
+        let jpeg = image(0, None, &scan(2000, None));
+        let packets = packetize(&jpeg, Duration::ZERO);
+        assert!(packets.len() > 4);
+        assert!(packets.iter().all(|packet| packet.len() <= 400));
+        // Only the last packet carries the marker bit
+        let markers: Vec<bool> = packets.iter().map(|p| p[1] & 0x80 != 0).collect();
+        assert_eq!(markers.iter().filter(|&&marker| marker).count(), 1);
+        assert!(markers[markers.len() - 1]);
+
+        let images = depacketize(&packets);
+        assert_eq!(images.len(), 1);
+        assert_same_image(&images[0], &jpeg);
+        assert_eq!(parse(&images[0]).unwrap().kind, 1);
+        assert_eq!(&mjpeg::to_jpeg(&images[0])[..], &images[0][..]);
+    }
+
+    #[test]
+    fn restart_markers() {
+        let jpeg = image(3, None, &scan(2000, Some(40)));
+        let packets = packetize(&jpeg, Duration::ZERO);
+        for packet in &packets {
+            let header = &packet[RTP_HEADER..RTP_HEADER + JPEG_HEADER + RESTART_HEADER];
+            assert_eq!(header[4], 64 + 1);
+            assert_eq!(header[8..], [0, 3, 0xff, 0xff]);
+        }
+
+        let images = depacketize(&packets);
+        assert_eq!(images.len(), 1);
+        assert_same_image(&images[0], &jpeg);
+        assert_eq!(parse(&images[0]).unwrap().restart_interval, 3);
+    }
+
+    #[test]
+    fn lost_packets_drop_the_image() {
+        let jpeg = image(0, None, &scan(2000, None));
+        let mut packetizer = RtpPacketizer::new().payload_size(400);
+        let frames: Vec<Vec<Vec<u8>>> = (0..4)
+            .map(|i| {
+                packetizer
+                    .packetize_jpeg(&jpeg, Duration::from_millis(33 * i))
+                    .unwrap()
+            })
+            .collect();
+
+        // Frame 0 loses a middle packet, frame 1 its first and frame 2 its last
+        let mut sent = Vec::new();
+        for (i, packets) in frames.iter().enumerate() {
+            let lost = match i {
+                0 => Some(packets.len() / 2),
+                1 => Some(0),
+                2 => Some(packets.len() - 1),
+                _ => None,
+            };
+            sent.extend(
+                packets
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| Some(j) != lost)
+                    .map(|(_, packet)| packet),
+            );
+        }
+        let images = depacketize(sent);
+        assert_eq!(images.len(), 1);
+        assert_same_image(&images[0], &jpeg);
+    }
+
+    #[test]
+    fn reordered_packets_drop_the_image() {
+        let jpeg = image(0, None, &scan(2000, None));
+        let mut packets = packetize(&jpeg, Duration::ZERO);
+        packets.swap(1, 2);
+        assert!(depacketize(&packets).is_empty());
+    }
+
+    #[test]
+    fn default_huffman_tables_are_accepted() {
+        let scan = scan(500, None);
+        let jpeg = image(0, Some(&mjpeg::default_tables()), &scan);
+        let images = depacketize(&packetize(&jpeg, Duration::ZERO));
+        assert_eq!(images.len(), 1);
+        assert_same_image(&images[0], &jpeg);
+    }
+
+    #[test]
+    fn other_huffman_tables_are_refused() {
+        let mut dht = mjpeg::default_tables();
+        // The last value of the chrominance AC table
+        let last = dht.len() - 1;
+        dht[last] ^= 1;
+        let jpeg = image(0, Some(&dht), &scan(500, None));
+        let err = RtpPacketizer::new()
+            .packetize_jpeg(&jpeg, Duration::ZERO)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A table with an id the defaults don't define
+        let mut dht = vec![0xff, DHT, 0, 19, 0x02, 1];
+        dht.extend_from_slice(&[0; 15]);
+        dht.push(0);
+        let jpeg = image(0, Some(&dht), &scan(500, None));
+        assert!(RtpPacketizer::new()
+            .packetize_jpeg(&jpeg, Duration::ZERO)
+            .is_err());
+    }
+
+    #[test]
+    fn unsupported_images_are_refused() {
+        let mut jpeg = image(0, None, &scan(100, None));
+        // Progressive instead of baseline
+        let sof = jpeg.windows(2).position(|w| w == [0xff, SOF0]).unwrap();
+        jpeg[sof + 1] = 0xc2;
+        assert!(RtpPacketizer::new()
+            .packetize_jpeg(&jpeg, Duration::ZERO)
+            .is_err());
+        assert!(RtpPacketizer::new()
+            .packetize_jpeg(&[0xff, 0xd8, 0xff, 0xd9], Duration::ZERO)
+            .is_err());
+    }
+
+    #[test]
+    fn loopback() {
+        let camera = VirtualCamera::new().format(StreamFormat {
+            width: 320,
+            height: 240,
+            interval: FrameInterval::from_fps(30),
+            format: FrameFormat::MJPEG,
+        });
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut sender = RtpSender::new(receiver.local_addr().unwrap())
+            .unwrap()
+            .payload_size(500);
+        let (frames, sent) = std::sync::mpsc::channel();
+        let frames = std::sync::Mutex::new(frames);
+        let stream = camera
+            .start_stream(
+                camera.formats().unwrap()[0],
+                PanicPolicy::default(),
+                Box::new(move |frame: &Frame| {
+                    sender.send(frame).unwrap();
+                    let _ = frames.lock().unwrap().send(frame.to_bytes().to_vec());
+                }),
+            )
+            .unwrap();
+
+        let mut depacketizer = RtpDepacketizer::new();
+        let mut packet = [0; 2048];
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            let len = receiver.recv(&mut packet).unwrap();
+            received.extend(depacketizer.push(&packet[..len]));
+        }
+        drop(stream);
+        // Loopback keeps the order, so images match the frames sent
+        let sent: Vec<Vec<u8>> = sent.iter().collect();
+        let first = sent
+            .iter()
+            .position(|frame| parse(frame).unwrap().scan == parse(&received[0]).unwrap().scan)
+            .unwrap();
+        for (image, frame) in received.iter().zip(&sent[first..]) {
+            assert_same_image(image, frame);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::backend::{Backend, BackendStream, FrameCallback};
+use crate::error::Result;
+use crate::formats::StreamFormat;
+use crate::frame::Frame;
+use crate::stats::StreamStats;
+use crate::streaming::{PanicPolicy, StreamStatus};
+
+/// Creates a scope for streams whose callbacks borrow local state
+///
+/// Unlike `Backend::start_stream`, the callback does not need to be
+/// `'static`: it may borrow buffers, encoders or `&mut` state from the
+/// enclosing function. Every stream started in the scope is stopped before
+/// `scope` returns, also when `f` panics.
+///
+/// ```no_run
+/// # let ctx = uvc::Context::new().unwrap();
+/// # let dev = ctx.find(&uvc::DeviceQuery::new()).unwrap();
+/// # let devh = dev.open().unwrap();
+/// # let format = devh.get_preferred_format(|x, _| x).unwrap();
+/// let mut bytes = 0;
+/// let mut sequences = Vec::new();
+///
+/// uvc::scope(|s| {
+///     s.start_stream(&devh, format, uvc::PanicPolicy::StopStream, |frame| {
+///         bytes += frame.to_bytes().len();
+///         sequences.push(frame.sequence());
+///     })
+///     .unwrap();
+///     std::thread::sleep(std::time::Duration::from_secs(1));
+/// });
+///
+/// println!("{} frames, {} bytes", sequences.len(), bytes);
+/// ```
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope StreamScope<'scope, 'env>) -> T,
+{
+    let scope = StreamScope {
+        streams: RefCell::new(Vec::new()),
+        _scope: PhantomData,
+        _env: PhantomData,
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    scope.streams.borrow_mut().clear();
+    result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+/// Scope of streams, created by `scope`
+///
+/// Streams can only be started from the thread which created the scope.
+pub struct StreamScope<'scope, 'env: 'scope> {
+    /// Owned by the scope, so they can not be leaked past it
+    ///
+    /// The callbacks borrow from `'scope`, the streams are dropped by `scope` before it ends.
+    streams: RefCell<Vec<Option<Box<dyn BackendStream + 'env>>>>,
+    _scope: PhantomData<&'scope mut &'scope ()>,
+    _env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> StreamScope<'scope, 'env> {
+    /// Begin a stream of `format` from `camera`, calling `cb` for every frame
+    /// until the stream is stopped or the scope ends
+    ///
+    /// This function is non-blocking
+    pub fn start_stream<C>(
+        &'scope self,
+        camera: &'env dyn Backend,
+        format: StreamFormat,
+        policy: PanicPolicy,
+        cb: C,
+    ) -> Result<ScopedStream<'scope, 'env>>
+    where
+        C: FnMut(&Frame) + Send + 'scope,
+    {
+        let cb: Box<dyn FnMut(&Frame) + Send + 'scope> = Box::new(cb);
+        // The scope owns the stream and drops it before 'scope ends, and the `Backend`
+        // contract guarantees the callback is gone once the stream is dropped
+        let cb: FrameCallback = unsafe { std::mem::transmute(cb) };
+        let stream = camera.start_stream(format, policy, cb)?;
+        let mut streams = self.streams.borrow_mut();
+        streams.push(Some(stream));
+        Ok(ScopedStream {
+            scope: self,
+            index: streams.len() - 1,
+        })
+    }
+
+    fn with<R>(&self, index: usize, f: impl FnOnce(&dyn BackendStream) -> R) -> Option<R> {
+        self.streams.borrow()[index].as_deref().map(f)
+    }
+
+    fn take(&self, index: usize) -> Option<Box<dyn BackendStream + 'env>> {
+        self.streams.borrow_mut()[index].take()
+    }
+}
+
+/// Stream started in a `StreamScope`
+///
+/// The stream keeps running when this handle is dropped, until the scope ends.
+pub struct ScopedStream<'scope, 'env> {
+    scope: &'scope StreamScope<'scope, 'env>,
+    index: usize,
+}
+
+impl<'scope, 'env> ScopedStream<'scope, 'env> {
+    /// Statistics of the frames delivered so far
+    #[must_use]
+    pub fn stats(&self) -> StreamStats {
+        self.scope
+            .with(self.index, |stream| stream.stats())
+            .expect("stream is owned by the scope until stopped")
+    }
+
+    /// Whether the stream still delivers frames
+    #[must_use]
+    pub fn status(&self) -> StreamStatus {
+        self.scope
+            .with(self.index, |stream| stream.status())
+            .expect("stream is owned by the scope until stopped")
+    }
+
+    /// Blocks until the stream ends, returning the payload of a panic which ended it
+    ///
+    /// A stream ends when its device is unplugged, or when the callback panics under
+    /// `PanicPolicy::StopStream`.
+    pub fn join(self) -> std::thread::Result<()> {
+        match self.scope.take(self.index) {
+            Some(stream) => stream.join(),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop the stream before the scope ends
+    pub fn stop(self) {
+        drop(self.scope.take(self.index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::formats::{FrameFormat, FrameInterval};
+    use crate::virtual_camera::VirtualCamera;
+
+    fn gray() -> StreamFormat {
+        StreamFormat {
+            width: 8,
+            height: 8,
+            interval: FrameInterval::from_fps(200),
+            format: FrameFormat::GRAY8,
+        }
+    }
+
+    fn camera() -> VirtualCamera {
+        VirtualCamera::new().format(gray())
+    }
+
+    /// Waits until the stream delivered `frames` frames
+    fn wait_for(stream: &ScopedStream<'_, '_>, frames: u64) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while stream.stats().frames < frames {
+            assert!(Instant::now() < deadline, "stream delivered no frames");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn callback_borrows_the_stack() {
+        let camera = camera();
+        let mut count = 0;
+        let mut sizes = Vec::new();
+
+        scope(|s| {
+            let stream = s
+                .start_stream(&camera, gray(), PanicPolicy::StopStream, |frame| {
+                    count += 1;
+                    sizes.push(frame.to_bytes().len());
+                })
+                .unwrap();
+            wait_for(&stream, 3);
+        });
+
+        assert!(!camera.is_streaming());
+        assert!(count >= 3);
+        assert_eq!(sizes.len(), count);
+        assert!(sizes.iter().all(|&size| size == 64));
+        // Nothing writes to the borrowed state after the scope
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(sizes.len(), count);
+    }
+
+    #[test]
+    fn panic_in_scope_stops_streams() {
+        let camera = camera();
+        let mut count = 0;
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            scope(|s| {
+                let stream = s
+                    .start_stream(&camera, gray(), PanicPolicy::StopStream, |_| count += 1)
+                    .unwrap();
+                wait_for(&stream, 1);
+                panic!("expected panic");
+            })
+        }));
+
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"expected panic"));
+        assert!(!camera.is_streaming());
+        let frames = count;
+        assert!(frames >= 1);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(count, frames);
+    }
+
+    #[test]
+    fn streams_outlive_their_handles() {
+        let camera = camera();
+        let mut count = 0;
+
+        scope(|s| {
+            // The handle is dropped right away, the stream runs until the scope ends
+            s.start_stream(&camera, gray(), PanicPolicy::StopStream, |_| count += 1)
+                .unwrap();
+            assert!(camera.is_streaming());
+        });
+
+        assert!(!camera.is_streaming());
+        let frames = count;
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(count, frames);
+    }
+
+    #[test]
+    fn stop_ends_the_stream_in_the_scope() {
+        let first = camera();
+        let second = camera();
+        let (mut a, mut b) = (0, 0);
+
+        scope(|s| {
+            let stream_a = s
+                .start_stream(&first, gray(), PanicPolicy::StopStream, |_| a += 1)
+                .unwrap();
+            let stream_b = s
+                .start_stream(&second, gray(), PanicPolicy::StopStream, |_| b += 1)
+                .unwrap();
+            wait_for(&stream_a, 1);
+            stream_a.stop();
+            assert!(!first.is_streaming());
+            assert!(second.is_streaming());
+            assert_eq!(stream_b.status(), StreamStatus::Running);
+        });
+
+        assert!(!second.is_streaming());
+        assert!(a >= 1);
+    }
+}
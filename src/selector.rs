@@ -0,0 +1,581 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::backend::{Backend, FormatDescription};
+use crate::device::{FrameIntervals, UsbSpeed};
+use crate::formats::{FrameFormat, FrameInterval, StreamFormat};
+
+#[derive(Debug, Default, Clone)]
+/// Requirements and preferences used to choose a stream format
+///
+/// Requirements reject formats, preferences rank the remaining ones.
+/// Ranking is a total order, so the same device always gives the same result:
+///
+/// 1. uncompressed formats, if preferred at the negotiated USB speed
+/// 2. the order in which formats were given to `format`
+/// 3. closest to the target resolution, otherwise the largest
+/// 4. closest to the target frame rate, otherwise the fastest
+/// 5. the order the device lists its descriptors in
+///
+/// ```no_run
+/// # let ctx = uvc::Context::new().unwrap();
+/// # let dev = ctx.find(&uvc::DeviceQuery::new()).unwrap();
+/// # let devh = dev.open().unwrap();
+/// let selector = uvc::FormatSelector::new()
+///     .format(uvc::FrameFormat::MJPEG)
+///     .format(uvc::FrameFormat::YUYV)
+///     .min_resolution(1280, 720)
+///     .closest_fps(30)
+///     .prefer_uncompressed_from(uvc::UsbSpeed::Super);
+/// let ranking = selector.rank(&devh);
+/// for rejection in ranking.rejected() {
+///     println!("{}", rejection);
+/// }
+/// let format = ranking.best().expect("No matching format");
+/// ```
+pub struct FormatSelector {
+    formats: Vec<FrameFormat>,
+    min_resolution: Option<(u32, u32)>,
+    max_resolution: Option<(u32, u32)>,
+    max_interval: Option<FrameInterval>,
+    target_resolution: Option<(u32, u32)>,
+    target_interval: Option<FrameInterval>,
+    uncompressed_from: Option<UsbSpeed>,
+}
+
+impl FormatSelector {
+    /// A selector accepting every format
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept this format, earlier formats are preferred over later ones
+    ///
+    /// If no format is given, all formats are accepted.
+    #[must_use]
+    pub fn format(mut self, format: FrameFormat) -> Self {
+        self.formats.push(format);
+        self
+    }
+
+    /// Reject frames narrower or lower than this
+    #[must_use]
+    pub fn min_resolution(mut self, width: u32, height: u32) -> Self {
+        self.min_resolution = Some((width, height));
+        self
+    }
+
+    /// Reject frames wider or higher than this
+    #[must_use]
+    pub fn max_resolution(mut self, width: u32, height: u32) -> Self {
+        self.max_resolution = Some((width, height));
+        self
+    }
+
+    /// Reject frame rates below this
+    #[must_use]
+    pub fn min_fps(self, fps: u32) -> Self {
+        self.max_interval(FrameInterval::from_fps(fps))
+    }
+
+    /// Reject frame intervals longer than this
+    #[must_use]
+    pub fn max_interval(mut self, interval: FrameInterval) -> Self {
+        self.max_interval = Some(interval);
+        self
+    }
+
+    /// Prefer the resolution closest to this one, instead of the largest
+    #[must_use]
+    pub fn closest_resolution(mut self, width: u32, height: u32) -> Self {
+        self.target_resolution = Some((width, height));
+        self
+    }
+
+    /// Prefer the frame rate closest to this one, instead of the fastest
+    #[must_use]
+    pub fn closest_fps(self, fps: u32) -> Self {
+        self.closest_interval(FrameInterval::from_fps(fps))
+    }
+
+    /// Prefer the frame interval closest to this one, instead of the shortest
+    #[must_use]
+    pub fn closest_interval(mut self, interval: FrameInterval) -> Self {
+        self.target_interval = Some(interval);
+        self
+    }
+
+    /// Prefer uncompressed formats when the device is connected at least at this speed
+    #[must_use]
+    pub fn prefer_uncompressed_from(mut self, speed: UsbSpeed) -> Self {
+        self.uncompressed_from = Some(speed);
+        self
+    }
+
+    /// The best format the device offers, if any is acceptable
+    #[must_use]
+    pub fn best<B: Backend + ?Sized>(&self, camera: &B) -> Option<StreamFormat> {
+        self.rank(camera).best()
+    }
+
+    /// Ranks all formats the device offers
+    ///
+    /// A camera whose descriptors cannot be read offers no formats.
+    #[must_use]
+    pub fn rank<B: Backend + ?Sized>(&self, camera: &B) -> Ranking {
+        let offered = camera
+            .descriptors()
+            .map(|descriptors| offered_formats(&descriptors, self.target_interval))
+            .unwrap_or_default();
+        self.rank_formats(offered, camera.speed())
+    }
+
+    fn rank_formats(&self, offered: Vec<StreamFormat>, speed: UsbSpeed) -> Ranking {
+        let prefer_uncompressed = self
+            .uncompressed_from
+            .is_some_and(|from| from != UsbSpeed::Unknown && speed >= from);
+
+        let mut candidates = Vec::new();
+        let mut rejected = Vec::new();
+        for (order, format) in offered.into_iter().enumerate() {
+            match self.check(&format) {
+                Err(reason) => rejected.push(Rejection { format, reason }),
+                Ok(preference) => candidates.push((preference, order, format)),
+            }
+        }
+
+        candidates.sort_by(|(pref_x, order_x, x), (pref_y, order_y, y)| {
+            let uncompressed = |format: &StreamFormat| {
+                prefer_uncompressed && FrameFormat::Uncompressed.accepts(format.format)
+            };
+            uncompressed(y)
+                .cmp(&uncompressed(x))
+                .then(pref_x.cmp(pref_y))
+                .then_with(|| self.compare_resolution(x, y))
+                .then_with(|| self.compare_interval(x, y))
+                .then(order_x.cmp(order_y))
+        });
+
+        Ranking {
+            candidates: candidates
+                .into_iter()
+                .map(|(_, _, format)| format)
+                .collect(),
+            rejected,
+        }
+    }
+
+    /// Checks the requirements, returning the position of the accepted format
+    fn check(&self, format: &StreamFormat) -> Result<usize, RejectReason> {
+        let preference = if self.formats.is_empty() {
+            0
+        } else {
+            self.formats
+                .iter()
+                .position(|accepted| accepted.accepts(format.format))
+                .ok_or(RejectReason::Format)?
+        };
+        if let Some((width, height)) = self.min_resolution {
+            if format.width < width || format.height < height {
+                return Err(RejectReason::TooSmall { width, height });
+            }
+        }
+        if let Some((width, height)) = self.max_resolution {
+            if format.width > width || format.height > height {
+                return Err(RejectReason::TooLarge { width, height });
+            }
+        }
+        if let Some(max) = self.max_interval {
+            if format.interval > max {
+                return Err(RejectReason::TooSlow { min: max });
+            }
+        }
+        Ok(preference)
+    }
+
+    fn compare_resolution(&self, x: &StreamFormat, y: &StreamFormat) -> Ordering {
+        let area = |format: &StreamFormat| u64::from(format.width) * u64::from(format.height);
+        match self.target_resolution {
+            Some((width, height)) => {
+                let distance = |format: &StreamFormat| {
+                    u64::from(format.width.abs_diff(width))
+                        + u64::from(format.height.abs_diff(height))
+                };
+                distance(x).cmp(&distance(y)).then(area(y).cmp(&area(x)))
+            }
+            None => area(y).cmp(&area(x)),
+        }
+    }
+
+    fn compare_interval(&self, x: &StreamFormat, y: &StreamFormat) -> Ordering {
+        match self.target_interval {
+            Some(target) => {
+                let distance = |format: &StreamFormat| (format.interval.fps() - target.fps()).abs();
+                distance(x)
+                    .total_cmp(&distance(y))
+                    .then(x.interval.cmp(&y.interval))
+            }
+            None => x.interval.cmp(&y.interval),
+        }
+    }
+}
+
+/// Every format and interval the device offers, in descriptor order
+///
+/// Continuous ranges contribute their shortest interval, and the one
+/// nearest to the target.
+fn offered_formats(
+    descriptors: &[FormatDescription],
+    target: Option<FrameInterval>,
+) -> Vec<StreamFormat> {
+    let mut offered = Vec::new();
+    for format_desc in descriptors {
+        for frame_desc in format_desc.frames() {
+            let intervals = frame_desc.frame_intervals();
+            let intervals = match intervals {
+                FrameIntervals::Discrete(intervals) => {
+                    intervals.iter().copied().map(FrameInterval).collect()
+                }
+                FrameIntervals::Continuous { min, .. } => {
+                    let mut list = vec![FrameInterval(min)];
+                    list.extend(target.and_then(|target| intervals.nearest(target)));
+                    list.dedup();
+                    list
+                }
+            };
+            for interval in intervals {
+                offered.push(StreamFormat {
+                    width: u32::from(frame_desc.width()),
+                    height: u32::from(frame_desc.height()),
+                    interval,
+                    format: format_desc.frame_format(),
+                });
+            }
+        }
+    }
+    offered
+}
+
+#[derive(Debug, Clone)]
+/// Formats ranked by a `FormatSelector`
+pub struct Ranking {
+    candidates: Vec<StreamFormat>,
+    rejected: Vec<Rejection>,
+}
+
+impl Ranking {
+    /// The best acceptable format
+    #[must_use]
+    pub fn best(&self) -> Option<StreamFormat> {
+        self.candidates.first().copied()
+    }
+
+    /// Acceptable formats, best first
+    #[must_use]
+    pub fn candidates(&self) -> &[StreamFormat] {
+        &self.candidates
+    }
+
+    /// Formats failing a requirement, in descriptor order
+    #[must_use]
+    pub fn rejected(&self) -> &[Rejection] {
+        &self.rejected
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Format which failed a requirement
+pub struct Rejection {
+    pub format: StreamFormat,
+    pub reason: RejectReason,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rejected {}x{} {:?}: ",
+            self.format.width, self.format.height, self.format.format
+        )?;
+        match self.reason {
+            RejectReason::Format => write!(f, "format not accepted"),
+            RejectReason::TooSmall { width, height } => write!(
+                f,
+                "{}x{} < {}x{}",
+                self.format.width, self.format.height, width, height
+            ),
+            RejectReason::TooLarge { width, height } => write!(
+                f,
+                "{}x{} > {}x{}",
+                self.format.width, self.format.height, width, height
+            ),
+            RejectReason::TooSlow { min } => write!(
+                f,
+                "{} fps < {}",
+                DisplayFps(self.format.interval),
+                DisplayFps(min)
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Requirement a format failed
+pub enum RejectReason {
+    /// The format is not among the accepted ones
+    Format,
+    /// Smaller than the minimum resolution
+    TooSmall { width: u32, height: u32 },
+    /// Larger than the maximum resolution
+    TooLarge { width: u32, height: u32 },
+    /// Slower than the minimum frame rate
+    TooSlow { min: FrameInterval },
+}
+
+/// Frame rate without decimals when it is the interval of a whole rate
+struct DisplayFps(FrameInterval);
+
+impl fmt::Display for DisplayFps {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fps = self.0.fps().round();
+        if fps >= 1.0 && FrameInterval::from_fps(fps as u32) == self.0 {
+            write!(f, "{}", fps)
+        } else {
+            write!(f, "{:.2}", self.0.fps())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(format: FrameFormat, width: u32, height: u32, fps: u32) -> StreamFormat {
+        StreamFormat {
+            width,
+            height,
+            interval: FrameInterval::from_fps(fps),
+            format,
+        }
+    }
+
+    fn offered() -> Vec<StreamFormat> {
+        vec![
+            format(FrameFormat::YUYV, 640, 480, 30),
+            format(FrameFormat::YUYV, 1280, 720, 10),
+            format(FrameFormat::MJPEG, 1280, 720, 30),
+            format(FrameFormat::MJPEG, 1920, 1080, 30),
+            format(FrameFormat::MJPEG, 1920, 1080, 15),
+            format(FrameFormat::H264, 1920, 1080, 30),
+            format(FrameFormat::NV12, 640, 480, 60),
+        ]
+    }
+
+    #[test]
+    fn largest_then_fastest_by_default() {
+        let ranking = FormatSelector::new().rank_formats(offered(), UsbSpeed::High);
+        assert!(ranking.rejected().is_empty());
+        let candidates = ranking.candidates();
+        assert_eq!(candidates[0], format(FrameFormat::MJPEG, 1920, 1080, 30));
+        assert_eq!(candidates[1], format(FrameFormat::H264, 1920, 1080, 30));
+        assert_eq!(candidates[2], format(FrameFormat::MJPEG, 1920, 1080, 15));
+        assert_eq!(candidates.len(), offered().len());
+    }
+
+    #[test]
+    fn formats_in_given_order() {
+        let ranking = FormatSelector::new()
+            .format(FrameFormat::YUYV)
+            .format(FrameFormat::MJPEG)
+            .rank_formats(offered(), UsbSpeed::High);
+        let formats: Vec<_> = ranking.candidates().iter().map(|f| f.format).collect();
+        assert_eq!(
+            formats,
+            [
+                FrameFormat::YUYV,
+                FrameFormat::YUYV,
+                FrameFormat::MJPEG,
+                FrameFormat::MJPEG,
+                FrameFormat::MJPEG
+            ]
+        );
+        assert_eq!(
+            ranking.best(),
+            Some(format(FrameFormat::YUYV, 1280, 720, 10))
+        );
+        assert!(ranking
+            .rejected()
+            .iter()
+            .all(|rejection| rejection.reason == RejectReason::Format));
+        assert_eq!(ranking.rejected().len(), 2);
+    }
+
+    #[test]
+    fn requirements_reject() {
+        let ranking = FormatSelector::new()
+            .min_resolution(1280, 720)
+            .max_resolution(1280, 720)
+            .min_fps(15)
+            .rank_formats(offered(), UsbSpeed::High);
+        assert_eq!(
+            ranking.candidates(),
+            [format(FrameFormat::MJPEG, 1280, 720, 30)]
+        );
+        let reasons: Vec<_> = ranking.rejected().iter().map(|r| r.reason).collect();
+        assert_eq!(
+            reasons,
+            [
+                RejectReason::TooSmall {
+                    width: 1280,
+                    height: 720
+                },
+                RejectReason::TooSlow {
+                    min: FrameInterval::from_fps(15)
+                },
+                RejectReason::TooLarge {
+                    width: 1280,
+                    height: 720
+                },
+                RejectReason::TooLarge {
+                    width: 1280,
+                    height: 720
+                },
+                RejectReason::TooLarge {
+                    width: 1280,
+                    height: 720
+                },
+                RejectReason::TooSmall {
+                    width: 1280,
+                    height: 720
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn closest_targets() {
+        let ranking = FormatSelector::new()
+            .closest_resolution(1280, 720)
+            .closest_fps(10)
+            .rank_formats(offered(), UsbSpeed::High);
+        assert_eq!(
+            ranking.best(),
+            Some(format(FrameFormat::YUYV, 1280, 720, 10))
+        );
+        assert_eq!(
+            ranking.candidates()[1],
+            format(FrameFormat::MJPEG, 1280, 720, 30)
+        );
+    }
+
+    #[test]
+    fn uncompressed_only_at_fast_enough_speed() {
+        let selector = FormatSelector::new().prefer_uncompressed_from(UsbSpeed::Super);
+        let best = |speed| selector.rank_formats(offered(), speed).best().unwrap();
+        assert_eq!(
+            best(UsbSpeed::Super),
+            format(FrameFormat::YUYV, 1280, 720, 10)
+        );
+        assert_eq!(
+            best(UsbSpeed::High),
+            format(FrameFormat::MJPEG, 1920, 1080, 30)
+        );
+        assert_eq!(
+            best(UsbSpeed::Unknown),
+            format(FrameFormat::MJPEG, 1920, 1080, 30)
+        );
+    }
+
+    #[test]
+    fn ties_keep_descriptor_order() {
+        let offered = vec![
+            format(FrameFormat::MJPEG, 640, 480, 30),
+            format(FrameFormat::YUYV, 640, 480, 30),
+        ];
+        let ranking = FormatSelector::new().rank_formats(offered.clone(), UsbSpeed::High);
+        assert_eq!(ranking.candidates(), offered);
+    }
+
+    #[test]
+    fn rejection_message() {
+        let rejection = Rejection {
+            format: format(FrameFormat::MJPEG, 640, 480, 10),
+            reason: RejectReason::TooSlow {
+                min: FrameInterval::from_fps(15),
+            },
+        };
+        assert_eq!(rejection.to_string(), "rejected 640x480 MJPEG: 10 fps < 15");
+
+        let rejection = Rejection {
+            format: format(FrameFormat::MJPEG, 640, 480, 10),
+            reason: RejectReason::TooSlow {
+                min: FrameInterval::from_rate(30_000, 1001),
+            },
+        };
+        assert_eq!(
+            rejection.to_string(),
+            "rejected 640x480 MJPEG: 10 fps < 29.97"
+        );
+    }
+
+    #[test]
+    fn ranks_a_virtual_camera() {
+        use crate::virtual_camera::VirtualCamera;
+
+        let selector = FormatSelector::new()
+            .format(FrameFormat::MJPEG)
+            .format(FrameFormat::YUYV)
+            .prefer_uncompressed_from(UsbSpeed::Super);
+        let camera = VirtualCamera::new()
+            .format(format(FrameFormat::MJPEG, 1280, 720, 30))
+            .format(format(FrameFormat::YUYV, 1280, 720, 10))
+            .format(format(FrameFormat::YUYV, 1280, 720, 5))
+            .format(format(FrameFormat::SGRBG8, 1280, 720, 30));
+        let best = |camera: &VirtualCamera| selector.best(camera);
+        assert_eq!(
+            best(&camera),
+            Some(format(FrameFormat::MJPEG, 1280, 720, 30))
+        );
+        let camera = camera.connected_at(UsbSpeed::Super);
+        assert_eq!(
+            best(&camera),
+            Some(format(FrameFormat::YUYV, 1280, 720, 10))
+        );
+        let ranking = selector.rank(&camera.connected_at(UsbSpeed::High));
+        assert_eq!(ranking.candidates().len(), 3);
+        assert_eq!(ranking.rejected()[0].reason, RejectReason::Format);
+
+        let camera = VirtualCamera::new().format(format(FrameFormat::MJPEG, 640, 480, 30));
+        camera.inject(crate::Fault::Disconnect);
+        assert_eq!(selector.rank(&camera).candidates(), []);
+    }
+
+    #[test]
+    fn continuous_intervals_offer_the_nearest() {
+        use crate::backend::{FormatDescription, FrameDescription};
+
+        let descriptors =
+            [
+                FormatDescription::new(FrameFormat::MJPEG).with_frame(
+                    FrameDescription::continuous(
+                        640,
+                        480,
+                        FrameInterval::from_fps(60),
+                        FrameInterval::from_fps(1),
+                        0,
+                    ),
+                ),
+            ];
+        let offered = offered_formats(&descriptors, Some(FrameInterval::from_fps(24)));
+        assert_eq!(
+            offered,
+            [
+                format(FrameFormat::MJPEG, 640, 480, 60),
+                format(FrameFormat::MJPEG, 640, 480, 24),
+            ]
+        );
+        let offered = offered_formats(&descriptors, None);
+        assert_eq!(offered, [format(FrameFormat::MJPEG, 640, 480, 60)]);
+    }
+}
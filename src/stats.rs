@@ -0,0 +1,294 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::frame::Frame;
+
+/// Buckets of a histogram, bucket `i` holds values below `2^i` nanoseconds
+const BUCKETS: usize = 64;
+/// Marks that no frame has been seen yet
+const NO_SEQUENCE: u64 = u64::MAX;
+
+#[derive(Debug)]
+/// Lock free histogram with logarithmic buckets
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, value: Duration) {
+        let nanos = u64::try_from(value.as_nanos()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - nanos.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile
+    fn quantile(&self, q: f64) -> Duration {
+        let count = self.count.load(Ordering::Relaxed);
+        let max = self.max.load(Ordering::Relaxed);
+        let target = ((count as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= target {
+                return Duration::from_nanos((1u64 << i).min(max));
+            }
+        }
+        Duration::from_nanos(max)
+    }
+
+    fn snapshot(&self) -> Option<Percentiles> {
+        if self.count.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        Some(Percentiles {
+            p50: self.quantile(0.5),
+            p90: self.quantile(0.9),
+            p99: self.quantile(0.99),
+            max: Duration::from_nanos(self.max.load(Ordering::Relaxed)),
+        })
+    }
+}
+
+#[derive(Debug)]
+/// Counters updated by the stream callback, and read by `ActiveStream::stats`
+pub(crate) struct StreamCounters {
+    started: Instant,
+    frames: AtomicU64,
+    bytes: AtomicU64,
+    missed: AtomicU64,
+    incomplete: AtomicU64,
+    panics: AtomicU64,
+    last_sequence: AtomicU64,
+    callback: Histogram,
+    latency: Histogram,
+}
+
+impl StreamCounters {
+    pub(crate) fn new() -> Self {
+        StreamCounters {
+            started: Instant::now(),
+            frames: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            missed: AtomicU64::new(0),
+            incomplete: AtomicU64::new(0),
+            panics: AtomicU64::new(0),
+            last_sequence: AtomicU64::new(NO_SEQUENCE),
+            callback: Histogram::new(),
+            latency: Histogram::new(),
+        }
+    }
+
+    /// Records a frame before it is handed to the callback
+    pub(crate) fn record_frame(&self, frame: &Frame) {
+        let data = frame.to_bytes();
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+
+        let sequence = frame.sequence();
+        let last = self
+            .last_sequence
+            .swap(u64::from(sequence), Ordering::Relaxed);
+        if last != NO_SEQUENCE {
+            // Sequence numbers wrap around, a step back is a restart rather than a gap
+            let step = sequence.wrapping_sub(last as u32);
+            if step > 1 && step <= u32::MAX / 2 {
+                self.missed
+                    .fetch_add(u64::from(step - 1), Ordering::Relaxed);
+            }
+        }
+
+        // Only fixed size formats tell whether all payloads of a frame arrived,
+        // compressed frames are never counted as incomplete
+        if let Some(bits) = frame.format().bits_per_pixel() {
+            let expected =
+                u64::from(frame.width()) * u64::from(frame.height()) * u64::from(bits) / 8;
+            if (data.len() as u64) < expected {
+                self.incomplete.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        if let Some(latency) = frame.capture_latency() {
+            self.latency.record(latency);
+        }
+    }
+
+    pub(crate) fn record_panic(&self) {
+        self.panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_callback(&self, duration: Duration) {
+        self.callback.record(duration);
+    }
+
+    pub(crate) fn snapshot(&self) -> StreamStats {
+        let elapsed = self.started.elapsed();
+        let frames = self.frames.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let seconds = elapsed.as_secs_f64();
+        let rate = |count: u64| {
+            if seconds > 0.0 {
+                count as f64 / seconds
+            } else {
+                0.0
+            }
+        };
+        StreamStats {
+            elapsed,
+            frames,
+            missed_frames: self.missed.load(Ordering::Relaxed),
+            incomplete_frames: self.incomplete.load(Ordering::Relaxed),
+            callback_panics: self.panics.load(Ordering::Relaxed),
+            bytes,
+            fps: rate(frames),
+            bytes_per_second: rate(bytes),
+            callback_duration: self.callback.snapshot(),
+            latency: self.latency.snapshot(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Approximate percentiles, rounded up to a power of two nanoseconds
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Statistics of a stream since it was started
+pub struct StreamStats {
+    /// Time since the stream was started
+    pub elapsed: Duration,
+    /// Frames delivered to the callback
+    pub frames: u64,
+    /// Frames missing from the sequence numbers
+    pub missed_frames: u64,
+    /// Frames of a fixed size format delivered with less data than expected
+    ///
+    /// Compressed formats such as MJPEG and H.264 have no expected size,
+    /// so their frames are never counted here.
+    pub incomplete_frames: u64,
+    /// Panics of the callback caught by its `PanicPolicy`
+    pub callback_panics: u64,
+    /// Bytes delivered to the callback
+    pub bytes: u64,
+    /// Frames per second, measured
+    pub fps: f64,
+    pub bytes_per_second: f64,
+    /// Time spent in the callback, `None` before the first frame
+    pub callback_duration: Option<Percentiles>,
+    /// Time from the end of capture to the callback, where the platform reports it
+    pub latency: Option<Percentiles>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::FrameFormat;
+
+    fn frame(sequence: u32, format: FrameFormat, len: usize) -> Frame {
+        let mut frame = Frame::from_bytes(&vec![0; len], 4, 2, format, None);
+        frame.raw_mut().sequence = sequence;
+        frame
+    }
+
+    fn nanos(values: &[u64]) -> Histogram {
+        let histogram = Histogram::new();
+        for &value in values {
+            histogram.record(Duration::from_nanos(value));
+        }
+        histogram
+    }
+
+    #[test]
+    fn empty_histogram() {
+        assert_eq!(Histogram::new().snapshot(), None);
+    }
+
+    #[test]
+    fn buckets_round_up_to_powers_of_two() {
+        let histogram = nanos(&[0, 1, 2, 3, 4, 1000]);
+        assert_eq!(histogram.buckets[0].load(Ordering::Relaxed), 1);
+        assert_eq!(histogram.buckets[1].load(Ordering::Relaxed), 1);
+        assert_eq!(histogram.buckets[2].load(Ordering::Relaxed), 2);
+        assert_eq!(histogram.buckets[3].load(Ordering::Relaxed), 1);
+        assert_eq!(histogram.buckets[10].load(Ordering::Relaxed), 1);
+
+        let huge = nanos(&[u64::MAX]);
+        assert_eq!(huge.buckets[BUCKETS - 1].load(Ordering::Relaxed), 1);
+        assert_eq!(huge.snapshot().unwrap().max, Duration::from_nanos(u64::MAX));
+    }
+
+    #[test]
+    fn percentiles() {
+        // 90 fast values, 9 slower ones and one outlier
+        let mut values = vec![100; 90];
+        values.extend([3000; 9]);
+        values.push(1_000_000);
+        let percentiles = nanos(&values).snapshot().unwrap();
+        assert_eq!(percentiles.p50, Duration::from_nanos(128));
+        assert_eq!(percentiles.p90, Duration::from_nanos(128));
+        assert_eq!(percentiles.p99, Duration::from_nanos(4096));
+        assert_eq!(percentiles.max, Duration::from_nanos(1_000_000));
+
+        // Bounds never exceed the largest value
+        let single = nanos(&[1000]).snapshot().unwrap();
+        assert_eq!(single.p50, Duration::from_nanos(1000));
+        assert_eq!(single.p99, Duration::from_nanos(1000));
+    }
+
+    #[test]
+    fn missed_frames() {
+        let counters = StreamCounters::new();
+        for sequence in [1, 2, 5, 6, 10] {
+            counters.record_frame(&frame(sequence, FrameFormat::MJPEG, 10));
+        }
+        let stats = counters.snapshot();
+        assert_eq!(stats.frames, 5);
+        assert_eq!(stats.bytes, 50);
+        assert_eq!(stats.missed_frames, 5);
+    }
+
+    #[test]
+    fn sequence_wraps_around() {
+        let counters = StreamCounters::new();
+        for sequence in [u32::MAX - 1, u32::MAX, 0, 2] {
+            counters.record_frame(&frame(sequence, FrameFormat::MJPEG, 10));
+        }
+        assert_eq!(counters.snapshot().missed_frames, 1);
+
+        // A restart of the sequence is not a gap
+        let counters = StreamCounters::new();
+        for sequence in [100, 101, 0, 1] {
+            counters.record_frame(&frame(sequence, FrameFormat::MJPEG, 10));
+        }
+        assert_eq!(counters.snapshot().missed_frames, 0);
+    }
+
+    #[test]
+    fn incomplete_fixed_size_frames() {
+        let counters = StreamCounters::new();
+        // 4x2 YUYV needs 16 bytes
+        counters.record_frame(&frame(0, FrameFormat::YUYV, 16));
+        counters.record_frame(&frame(1, FrameFormat::YUYV, 12));
+        // Compressed frames have no expected size
+        counters.record_frame(&frame(2, FrameFormat::MJPEG, 1));
+        let stats = counters.snapshot();
+        assert_eq!(stats.incomplete_frames, 1);
+        assert_eq!(stats.missed_frames, 0);
+    }
+}
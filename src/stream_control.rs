@@ -0,0 +1,291 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::device::DeviceHandle;
+use crate::error::{Error, Operation, Result, ResultExt};
+use crate::streaming::{PanicPolicy, StreamHandle};
+
+use uvc_sys::*;
+
+/// Length of a serialised control
+pub(crate) const CONTROL_BYTES: usize = 35;
+
+#[derive(Copy, Clone)]
+/// Streaming parameters negotiated with the device
+///
+/// Fields follow the video probe and commit controls of the UVC specification.
+/// Modify the control and call `DeviceHandle::probe_stream_control` to learn
+/// which parameters the device would accept, before committing them with
+/// `DeviceHandle::get_stream_handle_with_control`.
+pub struct StreamControl {
+    pub(crate) ctrl: uvc_stream_ctrl_t,
+}
+
+impl StreamControl {
+    pub(crate) fn from_raw(ctrl: uvc_stream_ctrl_t) -> Self {
+        Self { ctrl }
+    }
+
+    /// Fields in the order of the probe and commit controls, followed by the interface number
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        let c = &self.ctrl;
+        let mut bytes = Vec::with_capacity(CONTROL_BYTES);
+        bytes.extend_from_slice(&c.bmHint.to_le_bytes());
+        bytes.extend_from_slice(&[c.bFormatIndex, c.bFrameIndex]);
+        bytes.extend_from_slice(&c.dwFrameInterval.to_le_bytes());
+        for field in [
+            c.wKeyFrameRate,
+            c.wPFrameRate,
+            c.wCompQuality,
+            c.wCompWindowSize,
+            c.wDelay,
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [
+            c.dwMaxVideoFrameSize,
+            c.dwMaxPayloadTransferSize,
+            c.dwClockFrequency,
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[
+            c.bmFramingInfo,
+            c.bPreferedVersion,
+            c.bMinVersion,
+            c.bMaxVersion,
+            c.bInterfaceNumber,
+        ]);
+        bytes
+    }
+
+    /// Inverse of `to_bytes`
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < CONTROL_BYTES {
+            return None;
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+        let mut ctrl: uvc_stream_ctrl_t = unsafe { std::mem::zeroed() };
+        ctrl.bmHint = u16_at(0);
+        ctrl.bFormatIndex = bytes[2];
+        ctrl.bFrameIndex = bytes[3];
+        ctrl.dwFrameInterval = u32_at(4);
+        ctrl.wKeyFrameRate = u16_at(8);
+        ctrl.wPFrameRate = u16_at(10);
+        ctrl.wCompQuality = u16_at(12);
+        ctrl.wCompWindowSize = u16_at(14);
+        ctrl.wDelay = u16_at(16);
+        ctrl.dwMaxVideoFrameSize = u32_at(18);
+        ctrl.dwMaxPayloadTransferSize = u32_at(22);
+        ctrl.dwClockFrequency = u32_at(26);
+        ctrl.bmFramingInfo = bytes[30];
+        ctrl.bPreferedVersion = bytes[31];
+        ctrl.bMinVersion = bytes[32];
+        ctrl.bMaxVersion = bytes[33];
+        ctrl.bInterfaceNumber = bytes[34];
+        Some(Self { ctrl })
+    }
+
+    /// Which fields the device should keep fixed during negotiation
+    #[must_use]
+    pub fn hint(&self) -> u16 {
+        self.ctrl.bmHint
+    }
+
+    /// Index of the format descriptor
+    #[must_use]
+    pub fn format_index(&self) -> u8 {
+        self.ctrl.bFormatIndex
+    }
+
+    /// Index of the frame descriptor
+    #[must_use]
+    pub fn frame_index(&self) -> u8 {
+        self.ctrl.bFrameIndex
+    }
+
+    /// Frame interval in 100 ns units
+    #[must_use]
+    pub fn frame_interval(&self) -> u32 {
+        self.ctrl.dwFrameInterval
+    }
+
+    /// Frame interval as a duration
+    #[must_use]
+    pub fn frame_interval_duration(&self) -> Duration {
+        Duration::from_nanos(u64::from(self.ctrl.dwFrameInterval) * 100)
+    }
+
+    /// Key frame rate, for compressed formats
+    #[must_use]
+    pub fn key_frame_rate(&self) -> u16 {
+        self.ctrl.wKeyFrameRate
+    }
+
+    /// Predicted frame rate, for compressed formats
+    #[must_use]
+    pub fn p_frame_rate(&self) -> u16 {
+        self.ctrl.wPFrameRate
+    }
+
+    /// Compression quality, from 0 to 10000
+    #[must_use]
+    pub fn comp_quality(&self) -> u16 {
+        self.ctrl.wCompQuality
+    }
+
+    /// Window size for average bit rate control
+    #[must_use]
+    pub fn comp_window_size(&self) -> u16 {
+        self.ctrl.wCompWindowSize
+    }
+
+    /// Internal latency of the device in milliseconds
+    #[must_use]
+    pub fn delay(&self) -> u16 {
+        self.ctrl.wDelay
+    }
+
+    /// Maximum size of a frame in bytes
+    #[must_use]
+    pub fn max_video_frame_size(&self) -> u32 {
+        self.ctrl.dwMaxVideoFrameSize
+    }
+
+    /// Maximum number of bytes the device transfers in a single payload
+    #[must_use]
+    pub fn max_payload_transfer_size(&self) -> u32 {
+        self.ctrl.dwMaxPayloadTransferSize
+    }
+
+    /// Frequency of the device clock in Hz, used for timestamps
+    #[must_use]
+    pub fn clock_frequency(&self) -> u32 {
+        self.ctrl.dwClockFrequency
+    }
+
+    /// Framing information for stream based formats
+    #[must_use]
+    pub fn framing_info(&self) -> u8 {
+        self.ctrl.bmFramingInfo
+    }
+
+    /// Preferred payload format version
+    #[must_use]
+    pub fn preferred_version(&self) -> u8 {
+        self.ctrl.bPreferedVersion
+    }
+
+    /// Minimum payload format version
+    #[must_use]
+    pub fn min_version(&self) -> u8 {
+        self.ctrl.bMinVersion
+    }
+
+    /// Maximum payload format version
+    #[must_use]
+    pub fn max_version(&self) -> u8 {
+        self.ctrl.bMaxVersion
+    }
+
+    /// Number of the video streaming interface
+    #[must_use]
+    pub fn interface_number(&self) -> u8 {
+        self.ctrl.bInterfaceNumber
+    }
+
+    pub fn set_hint(&mut self, hint: u16) {
+        self.ctrl.bmHint = hint;
+    }
+
+    pub fn set_format_index(&mut self, format_index: u8) {
+        self.ctrl.bFormatIndex = format_index;
+    }
+
+    pub fn set_frame_index(&mut self, frame_index: u8) {
+        self.ctrl.bFrameIndex = frame_index;
+    }
+
+    /// Request a frame interval in 100 ns units
+    pub fn set_frame_interval(&mut self, frame_interval: u32) {
+        self.ctrl.dwFrameInterval = frame_interval;
+    }
+
+    pub fn set_key_frame_rate(&mut self, key_frame_rate: u16) {
+        self.ctrl.wKeyFrameRate = key_frame_rate;
+    }
+
+    pub fn set_p_frame_rate(&mut self, p_frame_rate: u16) {
+        self.ctrl.wPFrameRate = p_frame_rate;
+    }
+
+    pub fn set_comp_quality(&mut self, comp_quality: u16) {
+        self.ctrl.wCompQuality = comp_quality;
+    }
+
+    pub fn set_comp_window_size(&mut self, comp_window_size: u16) {
+        self.ctrl.wCompWindowSize = comp_window_size;
+    }
+
+    /// Request a smaller payload size, to reduce the bandwidth reserved on the bus
+    pub fn set_max_payload_transfer_size(&mut self, max_payload_transfer_size: u32) {
+        self.ctrl.dwMaxPayloadTransferSize = max_payload_transfer_size;
+    }
+}
+
+/// Same fields as `uvc_print_stream_ctrl`, and the remaining fields of the control
+impl fmt::Debug for StreamControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StreamControl")
+            .field("bmHint", &format_args!("{:04x}", self.hint()))
+            .field("bFormatIndex", &self.format_index())
+            .field("bFrameIndex", &self.frame_index())
+            .field("dwFrameInterval", &self.frame_interval())
+            .field("wKeyFrameRate", &self.key_frame_rate())
+            .field("wPFrameRate", &self.p_frame_rate())
+            .field("wCompQuality", &self.comp_quality())
+            .field("wCompWindowSize", &self.comp_window_size())
+            .field("wDelay", &self.delay())
+            .field("dwMaxVideoFrameSize", &self.max_video_frame_size())
+            .field(
+                "dwMaxPayloadTransferSize",
+                &self.max_payload_transfer_size(),
+            )
+            .field("bInterfaceNumber", &self.interface_number())
+            .field("dwClockFrequency", &self.clock_frequency())
+            .field(
+                "bmFramingInfo",
+                &format_args!("{:02x}", self.framing_info()),
+            )
+            .field("bPreferedVersion", &self.preferred_version())
+            .field("bMinVersion", &self.min_version())
+            .field("bMaxVersion", &self.max_version())
+            .finish()
+    }
+}
+
+impl<'a> DeviceHandle<'a> {
+    /// Ask the device which parameters it would use for this control
+    ///
+    /// The device adjusts the fields it can not satisfy, and fills in
+    /// the fields it decides, such as the maximum payload size.
+    /// Nothing is committed until a stream is started.
+    pub fn probe_stream_control(&self, control: &mut StreamControl) -> Result<()> {
+        Error::check(unsafe { uvc_probe_stream_ctrl(self.devh.as_ptr(), &mut control.ctrl) })
+            .during(Operation::ProbeStreamControl)
+    }
+
+    /// Creates a stream handle using previously negotiated parameters
+    #[must_use]
+    pub fn get_stream_handle_with_control(&'a self, control: StreamControl) -> StreamHandle<'a> {
+        StreamHandle {
+            handle: control,
+            devh: self,
+            policy: PanicPolicy::default(),
+            unplug: None,
+        }
+    }
+}
@@ -1,23 +1,256 @@
 use uvc_sys::*;
 
+use crate::channel::{channel, Overflow, Receiver, Sender};
 use crate::device::DeviceHandle;
-use crate::error::{Error, Result};
-use crate::frame::Frame;
+use crate::error::{Error, Operation, Result, ResultExt};
+use crate::frame::{FormatHints, Frame, OwnedFrame};
+use crate::pool::FramePool;
+use crate::stats::{StreamCounters, StreamStats};
+use crate::stream_control::StreamControl;
+use crate::usb;
 
+use std::any::Any;
 use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
 
 unsafe impl<'a> Send for StreamHandle<'a> {}
 unsafe impl<'a> Sync for StreamHandle<'a> {}
 #[derive(Debug)]
 /// Stream handle
 pub struct StreamHandle<'a> {
-    pub(crate) handle: uvc_stream_ctrl_t,
+    pub(crate) handle: StreamControl,
     pub(crate) devh: &'a DeviceHandle<'a>,
+    pub(crate) policy: PanicPolicy,
+    pub(crate) unplug: Option<Duration>,
 }
 
+#[derive(Debug, Copy, Clone, Default)]
+/// How a stream started from a `DeviceHandle` is watched
+pub(crate) struct StreamOptions {
+    pub(crate) policy: PanicPolicy,
+    /// Interval of checking whether the device was unplugged, if at all
+    pub(crate) unplug: Option<Duration>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+/// What happens when the frame callback panics
+///
+/// ```
+/// use std::time::Duration;
+/// use uvc::{Backend, Frame, PanicPolicy, StreamStatus, VirtualCamera};
+///
+/// let camera = VirtualCamera::webcam();
+/// let format = camera.formats().unwrap()[0];
+///
+/// // The first panic stops the stream, and is reported by `join`
+/// let stream = camera
+///     .start_stream(
+///         format,
+///         PanicPolicy::StopStream,
+///         Box::new(|frame: &Frame| assert!(frame.sequence() < 2, "too many frames")),
+///     )
+///     .unwrap();
+/// let payload = stream.join().unwrap_err();
+/// assert_eq!(*payload.downcast_ref::<&str>().unwrap(), "too many frames");
+///
+/// // Panics are counted, and frames keep arriving
+/// let stream = camera
+///     .start_stream(
+///         format,
+///         PanicPolicy::LogAndContinue,
+///         Box::new(|frame: &Frame| assert!(frame.sequence() % 2 == 0)),
+///     )
+///     .unwrap();
+/// std::thread::sleep(Duration::from_millis(200));
+/// assert_eq!(stream.status(), StreamStatus::Running);
+/// assert!(stream.stats().callback_panics > 0);
+/// ```
+///
+/// Under every policy the panic is first reported by the panic hook, which prints
+/// it to stderr unless replaced with `std::panic::set_hook`.
+pub enum PanicPolicy {
+    /// Abort the process, as a panic must not unwind into `libuvc`
+    #[default]
+    Abort,
+    /// Stop the stream, and report the panic through `status` and `join`
+    StopStream,
+    /// Count the panic in `StreamStats::callback_panics` and keep delivering frames
+    ///
+    /// The user data may be left in an inconsistent state by the panicking callback.
+    LogAndContinue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Whether a stream still delivers frames
+pub enum StreamStatus {
+    Running,
+    /// The stream ended without a panic, such as a virtual camera which was disconnected
+    Ended,
+    /// The callback panicked with this message, and no more frames are delivered
+    Panicked(String),
+}
+
+/// Payload of a panic, as returned by `std::panic::catch_unwind`
+pub type PanicPayload = Box<dyn Any + Send>;
+
+#[derive(Debug)]
+struct Ending {
+    /// Message of the panic which ended the stream
+    message: Option<String>,
+    /// Taken by `join`
+    payload: Option<PanicPayload>,
+}
+
+/// Run once a stream ended, such as closing the channel its frames are sent to
+pub(crate) type EndHook = Box<dyn FnOnce() + Send>;
+
+/// Calls the frame callback of a stream according to its `PanicPolicy`
+pub(crate) struct CallbackGuard {
+    policy: PanicPolicy,
+    ending: Mutex<Option<Ending>>,
+    ended: Condvar,
+    hooks: Mutex<Vec<EndHook>>,
+}
+
+impl std::fmt::Debug for CallbackGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CallbackGuard")
+            .field("policy", &self.policy)
+            .field("ending", &self.ending)
+            .finish_non_exhaustive()
+    }
+}
+
+fn panic_message(payload: &PanicPayload) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
+}
+
+impl CallbackGuard {
+    pub(crate) fn new(policy: PanicPolicy) -> Self {
+        CallbackGuard {
+            policy,
+            ending: Mutex::new(None),
+            ended: Condvar::new(),
+            hooks: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Ending>> {
+        self.ending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Calls `f`, returning whether it panicked
+    pub(crate) fn call(&self, f: impl FnOnce()) -> bool {
+        let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) else {
+            return false;
+        };
+        match self.policy {
+            // The panic hook has already reported the panic
+            PanicPolicy::Abort => std::process::abort(),
+            PanicPolicy::StopStream => {
+                let message = Some(panic_message(&payload));
+                self.end(Ending {
+                    message,
+                    payload: Some(payload),
+                });
+            }
+            PanicPolicy::LogAndContinue => {}
+        }
+        true
+    }
+
+    fn end(&self, ending: Ending) {
+        let mut current = self.lock();
+        if current.is_some() {
+            return;
+        }
+        *current = Some(ending);
+        self.ended.notify_all();
+        drop(current);
+        let hooks = std::mem::take(&mut *self.lock_hooks());
+        for hook in hooks {
+            hook();
+        }
+    }
+
+    fn lock_hooks(&self) -> MutexGuard<'_, Vec<EndHook>> {
+        self.hooks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs `hook` once the stream ended, or right away if it already has
+    pub(crate) fn on_end(&self, hook: EndHook) {
+        let mut hooks = self.lock_hooks();
+        if self.has_ended() {
+            drop(hooks);
+            hook();
+        } else {
+            hooks.push(hook);
+        }
+    }
+
+    /// Marks a stream which ended without a panic, such as one whose device was unplugged
+    pub(crate) fn finish(&self) {
+        self.end(Ending {
+            message: None,
+            payload: None,
+        });
+    }
+
+    /// Whether frames should no longer be delivered
+    pub(crate) fn has_ended(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    pub(crate) fn status(&self) -> StreamStatus {
+        match &*self.lock() {
+            None => StreamStatus::Running,
+            Some(Ending { message: None, .. }) => StreamStatus::Ended,
+            Some(Ending {
+                message: Some(message),
+                ..
+            }) => StreamStatus::Panicked(message.clone()),
+        }
+    }
+
+    /// Blocks until the stream ended, returning the payload of the panic which ended it
+    pub(crate) fn wait(&self) -> std::thread::Result<()> {
+        let mut ending = self
+            .ended
+            .wait_while(self.lock(), |ending| ending.is_none())
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match ending.as_mut() {
+            Some(Ending {
+                message: Some(message),
+                payload,
+            }) => Err(payload.take().unwrap_or_else(|| Box::new(message.clone()))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Callback of a stream, called with every frame and the user data
+type Callback<U> = Box<dyn FnMut(&Frame, &mut U) + Send>;
+
 struct Vtable<U> {
-    func: Box<dyn Fn(&Frame, &mut U)>,
+    func: Callback<U>,
     data: U,
+    hints: Option<FormatHints>,
+    stats: Arc<StreamCounters>,
+    guard: Arc<CallbackGuard>,
 }
 
 unsafe impl<'a, U: Send + Sync> Send for ActiveStream<'a, U> {}
@@ -26,21 +259,104 @@ unsafe impl<'a, U: Send + Sync> Sync for ActiveStream<'a, U> {}
 /// Active stream
 ///
 /// Dropping this stream will stop the stream
-pub struct ActiveStream<'a, U: Send + Sync> {
+pub struct ActiveStream<'a, U> {
     devh: &'a crate::DeviceHandle<'a>,
     #[allow(unused)]
     vtable: *mut Vtable<U>,
+    stats: Arc<StreamCounters>,
+    guard: Arc<CallbackGuard>,
+    watcher: Arc<Watcher>,
+    watch_thread: Option<thread::JoinHandle<()>>,
 }
 
-impl<'a, U: Send + Sync> ActiveStream<'a, U> {
+impl<'a, U> ActiveStream<'a, U> {
+    /// Statistics of the frames delivered so far
+    #[must_use]
+    pub fn stats(&self) -> StreamStats {
+        self.stats.snapshot()
+    }
+
+    /// Whether the stream still delivers frames
+    #[must_use]
+    pub fn status(&self) -> StreamStatus {
+        self.guard.status()
+    }
+
+    /// Blocks until the stream ends, returning the payload of a panic which ended it
+    ///
+    /// A stream ends when the callback panics under `PanicPolicy::StopStream`, or when
+    /// its device is unplugged if `StreamHandle::detect_unplug` is set. Use `status` to
+    /// check without blocking.
+    pub fn join(self) -> std::thread::Result<()> {
+        self.guard.wait()
+    }
+
+    /// Runs `hook` once the stream ended, or right away if it already has
+    pub(crate) fn on_end(&self, hook: EndHook) {
+        self.guard.on_end(hook);
+    }
+
     /// Stop the stream
     pub fn stop(self) {
         // Taking ownership of the stream, which drops it
     }
 }
 
-impl<'a, U: Send + Sync> Drop for ActiveStream<'a, U> {
+#[derive(Debug)]
+/// Stream delivering its frames to a `Receiver`
+///
+/// Dropping this stream will stop the stream. Once it is dropped or has ended,
+/// the receiver returns the frames still queued before reporting the end of the stream.
+pub struct ChannelStream<'a> {
+    sender: Sender<OwnedFrame>,
+    stream: ActiveStream<'a, Sender<OwnedFrame>>,
+}
+
+impl<'a> ChannelStream<'a> {
+    /// Statistics of the frames delivered so far
+    #[must_use]
+    pub fn stats(&self) -> StreamStats {
+        self.stream.stats()
+    }
+
+    /// Whether the stream still delivers frames
+    #[must_use]
+    pub fn status(&self) -> StreamStatus {
+        self.stream.status()
+    }
+
+    /// Blocks until the stream ends, returning the payload of a panic which ended it
+    ///
+    /// A stream ends when the callback panics under `PanicPolicy::StopStream`, or when
+    /// its device is unplugged if `StreamHandle::detect_unplug` is set.
+    pub fn join(self) -> std::thread::Result<()> {
+        self.stream.guard.wait()
+    }
+
+    /// Runs `hook` once the stream ended, or right away if it already has
+    pub(crate) fn on_end(&self, hook: EndHook) {
+        self.stream.on_end(hook);
+    }
+
+    /// Stop the stream
+    pub fn stop(self) {
+        // Taking ownership of the stream, which drops it
+    }
+}
+
+impl<'a> Drop for ChannelStream<'a> {
     fn drop(&mut self) {
+        // A callback blocked on a full channel would keep `libuvc` from stopping
+        self.sender.close();
+    }
+}
+
+impl<'a, U> Drop for ActiveStream<'a, U> {
+    fn drop(&mut self) {
+        self.watcher.stop();
+        if let Some(thread) = self.watch_thread.take() {
+            let _ = thread.join();
+        }
         unsafe {
             uvc_stop_streaming(self.devh.devh.as_ptr());
             let _vtable = Box::from_raw(self.vtable);
@@ -48,68 +364,523 @@ impl<'a, U: Send + Sync> Drop for ActiveStream<'a, U> {
     }
 }
 
-unsafe extern "C" fn trampoline<F, U>(frame: *mut uvc_frame, userdata: *mut c_void)
-where
-    F: 'static + Send + Sync + Fn(&Frame, &mut U),
-    U: 'static + Send + Sync,
-{
+/// Device handle of a stream, moved to its watcher thread
+struct WatchedDevice(NonNull<uvc_device_handle>);
+
+unsafe impl Send for WatchedDevice {}
+
+#[derive(Debug)]
+/// Thread ending the stream once the callback panicked or its device is unplugged
+///
+/// `uvc_stop_streaming` waits for the callback to return, so a panicking callback
+/// can't stop the stream itself. `libuvc` does not report unplugged devices, they
+/// are only noticed when `StreamHandle::detect_unplug` asks for polling.
+struct Watcher {
+    stopped: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl Watcher {
+    fn lock(&self) -> MutexGuard<'_, bool> {
+        self.stopped
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Ends the thread, before the stream is stopped by its owner
+    fn stop(&self) {
+        *self.lock() = true;
+        self.wake.notify_all();
+    }
+
+    /// Wakes the thread once the guard has ended
+    fn notify(&self) {
+        // Taken so the thread can not miss the wakeup between checking and waiting
+        let _stopped = self.lock();
+        self.wake.notify_all();
+    }
+
+    /// Waits for the stream to end, then ends the guard or stops `libuvc`
+    ///
+    /// With an `unplug` interval, the device is also polled for being unplugged.
+    fn watch(
+        &self,
+        devh: WatchedDevice,
+        open: &Mutex<bool>,
+        guard: &CallbackGuard,
+        unplug: Option<Duration>,
+    ) {
+        loop {
+            let waiting = |stopped: &mut bool| !*stopped && !guard.has_ended();
+            let stopped = match unplug {
+                Some(interval) => {
+                    self.wake
+                        .wait_timeout_while(self.lock(), interval, waiting)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .0
+                }
+                None => self
+                    .wake
+                    .wait_while(self.lock(), waiting)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+            if *stopped {
+                return;
+            }
+            drop(stopped);
+
+            // A leaked stream must not reach a closed handle
+            let open = open.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if !*open {
+                return;
+            }
+            if guard.has_ended() {
+                // Only a panic under `PanicPolicy::StopStream` ends the guard here
+                unsafe { uvc_stop_streaming(devh.0.as_ptr()) };
+                return;
+            }
+            if !usb::is_connected(unsafe { uvc_get_libusb_handle(devh.0.as_ptr()) }) {
+                guard.finish();
+                return;
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn trampoline<U>(frame: *mut uvc_frame, userdata: *mut c_void) {
     let panic = std::panic::catch_unwind(|| {
         if frame.is_null() {
             panic!("Frame is null");
         }
-        let frame = std::mem::ManuallyDrop::new(Frame::from_raw(frame));
-
         if userdata.is_null() {
             panic!("Userdata is null");
         }
 
         let vtable = userdata as *mut Vtable<U>;
 
-        let func = &(*vtable).func;
+        let frame = std::mem::ManuallyDrop::new(Frame::from_raw(frame).with_hints((*vtable).hints));
+
+        let func = &mut (*vtable).func;
         let data = &mut (*vtable).data;
+        let stats = &(*vtable).stats;
+        let guard = &(*vtable).guard;
 
-        func(&frame, data);
+        if guard.has_ended() {
+            return;
+        }
+        stats.record_frame(&frame);
+        let start = Instant::now();
+        if guard.call(|| func(&frame, data)) {
+            stats.record_panic();
+        }
+        stats.record_callback(start.elapsed());
     });
 
     if panic.is_err() {
-        eprintln!("User defined function panicked");
         std::process::abort();
     }
 }
 
 impl<'a> StreamHandle<'a> {
+    /// Parameters negotiated for this stream
+    #[must_use]
+    pub fn control(&self) -> &StreamControl {
+        &self.handle
+    }
+
+    /// Handle panics of the callback with this policy, the default is `PanicPolicy::Abort`
+    #[must_use]
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Check every `interval` whether the device was unplugged, and end the stream if so
+    ///
+    /// `libuvc` keeps an unplugged stream running without frames, so without this
+    /// `join` and the receiver of `start_stream_channel` only return once the
+    /// stream is dropped. Every check is a `GET_CONFIGURATION` control transfer
+    /// to the device, so checking is off by default.
+    #[must_use]
+    pub fn detect_unplug(mut self, interval: Duration) -> Self {
+        self.unplug = Some(interval);
+        self
+    }
+
     /// Begin a stream, use the callback to save the frames
     ///
     /// This function is non-blocking
     pub fn start_stream<F, U>(&'a mut self, cb: F, user_data: U) -> Result<ActiveStream<'a, U>>
     where
-        F: 'static + Send + Sync + Fn(&Frame, &mut U),
+        F: 'static + Send + FnMut(&Frame, &mut U),
         U: 'static + Send + Sync,
     {
-        let tuple = Box::new(Vtable::<U> {
-            func: Box::new(cb),
-            data: user_data,
-        });
+        let options = StreamOptions {
+            policy: self.policy,
+            unplug: self.unplug,
+        };
+        start_streaming(self.devh, &mut self.handle, options, cb, user_data)
+    }
 
-        let tuple = Box::into_raw(tuple);
+    /// Begin a stream, handing every frame to the callback without copying it again
+    ///
+    /// The buffer filled by `libuvc` is exchanged for a spare from `pool`,
+    /// so the callback can keep the frame or send it to another thread.
+    /// `FramePool::allocations` counts the buffers allocated when the pool was empty.
+    /// This function is non-blocking
+    pub fn start_stream_pooled<F, U>(
+        &'a mut self,
+        pool: &FramePool,
+        mut cb: F,
+        user_data: U,
+    ) -> Result<ActiveStream<'a, U>>
+    where
+        F: 'static + Send + FnMut(OwnedFrame, &mut U),
+        U: 'static + Send + Sync,
+    {
+        let pool = pool.clone();
+        self.start_stream(
+            // The frame is the one passed to the callback by libuvc
+            move |frame, data| cb(unsafe { pool.take(frame) }, data),
+            user_data,
+        )
+    }
 
-        unsafe {
-            let err = uvc_start_streaming(
-                self.devh.devh.as_ptr(),
-                &mut self.handle,
-                Some(trampoline::<F, U>),
-                tuple as *mut c_void,
-                0,
+    /// Begin a stream, sending every frame to the returned receiver
+    ///
+    /// At most `capacity` frames are queued, `overflow` decides what happens to
+    /// further frames. This function is non-blocking.
+    ///
+    /// ```no_run
+    /// # let ctx = uvc::Context::new().unwrap();
+    /// # let dev = ctx.find(&uvc::DeviceQuery::new()).unwrap();
+    /// # let devh = dev.open().unwrap();
+    /// # let format = devh.get_preferred_format(|x, _| x).unwrap();
+    /// let mut streamh = devh.get_stream_handle_with_format(format).unwrap();
+    /// let (stream, frames) = streamh
+    ///     .start_stream_channel(4, uvc::Overflow::DropOldest)
+    ///     .unwrap();
+    ///
+    /// for frame in frames.iter().take(100) {
+    ///     println!("{} bytes", frame.to_bytes().len());
+    /// }
+    /// stream.stop();
+    /// ```
+    pub fn start_stream_channel(
+        &'a mut self,
+        capacity: usize,
+        overflow: Overflow,
+    ) -> Result<(ChannelStream<'a>, Receiver<OwnedFrame>)> {
+        let options = StreamOptions {
+            policy: self.policy,
+            unplug: self.unplug,
+        };
+        start_channel(self.devh, &mut self.handle, options, capacity, overflow)
+    }
+}
+
+/// Starts streaming the negotiated `ctrl` from `devh` into a channel
+pub(crate) fn start_channel<'a>(
+    devh: &'a DeviceHandle<'a>,
+    ctrl: &mut StreamControl,
+    options: StreamOptions,
+    capacity: usize,
+    overflow: Overflow,
+) -> Result<(ChannelStream<'a>, Receiver<OwnedFrame>)> {
+    let (sender, receiver) = channel(capacity, overflow);
+    // The queued frames and the one being sent hold buffers of the pool
+    let pool = FramePool::new(capacity.max(1) + 1);
+    let stream = start_streaming(
+        devh,
+        ctrl,
+        options,
+        move |frame, sender: &mut Sender<OwnedFrame>| {
+            // The frame is the one passed to the callback by libuvc
+            sender.send(unsafe { pool.take(frame) });
+        },
+        sender.clone(),
+    )?;
+    // A panic under `PanicPolicy::StopStream` or a detected unplug ends the receiver too
+    let closing = sender.clone();
+    stream.on_end(Box::new(move || closing.close()));
+    Ok((ChannelStream { sender, stream }, receiver))
+}
+
+/// Starts streaming the negotiated `ctrl` from `devh`
+pub(crate) fn start_streaming<'a, F, U>(
+    devh: &'a DeviceHandle<'a>,
+    ctrl: &mut StreamControl,
+    options: StreamOptions,
+    cb: F,
+    user_data: U,
+) -> Result<ActiveStream<'a, U>>
+where
+    F: 'static + Send + FnMut(&Frame, &mut U),
+    U: 'static + Send + Sync,
+{
+    // The callback and user data are 'static, leaking the stream can not leave them dangling
+    unsafe { start_streaming_unchecked(devh, ctrl, options, Box::new(cb), user_data) }
+}
+
+/// Starts streaming without requiring the callback state to be `'static`
+///
+/// # Safety
+///
+/// The returned stream must be dropped before the borrows held by `user_data` end,
+/// as `libuvc` keeps calling the callback until then.
+pub(crate) unsafe fn start_streaming_unchecked<'a, U: Send>(
+    devh: &'a DeviceHandle<'a>,
+    ctrl: &mut StreamControl,
+    options: StreamOptions,
+    func: Callback<U>,
+    user_data: U,
+) -> Result<ActiveStream<'a, U>> {
+    let stats = Arc::new(StreamCounters::new());
+    let guard = Arc::new(CallbackGuard::new(options.policy));
+    let tuple = Box::new(Vtable::<U> {
+        func,
+        data: user_data,
+        hints: devh
+            .supported_formats()
+            .find(|desc| desc.format_index() == ctrl.format_index())
+            .map(|desc| FormatHints::from_descriptor(&desc)),
+        stats: Arc::clone(&stats),
+        guard: Arc::clone(&guard),
+    });
+
+    let tuple = Box::into_raw(tuple);
+
+    unsafe {
+        let err = uvc_start_streaming(
+            devh.devh.as_ptr(),
+            &mut ctrl.ctrl,
+            Some(trampoline::<U>),
+            tuple as *mut c_void,
+            0,
+        );
+        if let Err(err) = Error::check(err).during(Operation::StartStream) {
+            let _vtable = Box::from_raw(tuple);
+            return Err(err);
+        }
+    }
+
+    let watcher = Arc::new(Watcher {
+        stopped: Mutex::new(false),
+        wake: Condvar::new(),
+    });
+    let watch_thread = {
+        let device = WatchedDevice(devh.devh);
+        let open = Arc::clone(&devh.open);
+        let guard = Arc::clone(&guard);
+        let watcher = Arc::clone(&watcher);
+        thread::spawn(move || watcher.watch(device, &open, &guard, options.unplug))
+    };
+    let notified = Arc::clone(&watcher);
+    guard.on_end(Box::new(move || notified.notify()));
+    Ok(ActiveStream {
+        devh,
+        vtable: tuple,
+        stats,
+        guard,
+        watcher,
+        watch_thread: Some(watch_thread),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::mpsc::{RecvError, TryRecvError};
+
+    use super::*;
+    use crate::backend::Backend;
+    use crate::formats::{FrameFormat, FrameInterval, StreamFormat};
+    use crate::virtual_camera::{Fault, VirtualCamera};
+
+    fn camera() -> (VirtualCamera, StreamFormat) {
+        let format = StreamFormat {
+            width: 8,
+            height: 8,
+            interval: FrameInterval::from_fps(200),
+            format: FrameFormat::GRAY8,
+        };
+        (VirtualCamera::new().format(format), format)
+    }
+
+    /// Waits for `condition`, failing the test after a few seconds
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn stop_stream_ends_on_the_first_panic() {
+        let (camera, format) = camera();
+        let calls = Arc::new(AtomicU32::new(0));
+        let counted = Arc::clone(&calls);
+        let stream = camera
+            .start_stream(
+                format,
+                PanicPolicy::StopStream,
+                Box::new(move |frame| {
+                    counted.fetch_add(1, Ordering::Relaxed);
+                    assert!(frame.sequence() < 2, "too many frames");
+                }),
             )
-            .into();
-            if err == Error::Success {
-                Ok(ActiveStream {
-                    devh: self.devh,
-                    vtable: tuple,
-                })
-            } else {
-                Err(err)
-            }
+            .unwrap();
+        wait_for(|| stream.status() != StreamStatus::Running);
+        assert_eq!(
+            stream.status(),
+            StreamStatus::Panicked("too many frames".to_owned())
+        );
+        assert_eq!(stream.stats().callback_panics, 1);
+        wait_for(|| !camera.is_streaming());
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+        let payload = stream.join().unwrap_err();
+        assert_eq!(*payload.downcast_ref::<&str>().unwrap(), "too many frames");
+    }
+
+    #[test]
+    fn log_and_continue_counts_panics() {
+        let (camera, format) = camera();
+        let stream = camera
+            .start_stream(
+                format,
+                PanicPolicy::LogAndContinue,
+                Box::new(|frame| assert!(frame.sequence() % 2 == 0)),
+            )
+            .unwrap();
+        wait_for(|| stream.stats().frames >= 6);
+        assert_eq!(stream.status(), StreamStatus::Running);
+        assert!(stream.stats().callback_panics >= 2);
+        assert!(camera.is_streaming());
+
+        // Unplugging ends the stream, so `join` returns
+        camera.inject(Fault::Disconnect);
+        assert!(stream.join().is_ok());
+        assert!(!camera.is_streaming());
+    }
+
+    #[test]
+    fn disconnect_ends_join() {
+        let (camera, format) = camera();
+        let stream = camera
+            .start_stream(format, PanicPolicy::Abort, Box::new(|_| {}))
+            .unwrap();
+        camera.inject_after(3, Fault::Disconnect);
+        assert!(stream.join().is_ok());
+        assert!(!camera.is_connected());
+    }
+
+    #[test]
+    fn end_hooks_close_channels() {
+        // Closed as `start_channel` does, on a panic under `PanicPolicy::StopStream`
+        let (panicking, format) = camera();
+        let (sender, receiver) = channel(4, Overflow::Block);
+        let sending = sender.clone();
+        let stream = panicking
+            .start_stream(
+                format,
+                PanicPolicy::StopStream,
+                Box::new(move |frame| {
+                    assert!(frame.sequence() < 2, "too many frames");
+                    sending.send(frame.sequence());
+                }),
+            )
+            .unwrap();
+        stream.on_end(Box::new(move || sender.close()));
+        assert_eq!(receiver.iter().collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(receiver.recv(), Err(RecvError));
+
+        // And once the device is unplugged
+        let (unplugged, format) = camera();
+        let (sender, receiver) = channel(4, Overflow::DropOldest);
+        let stream = unplugged
+            .start_stream(format, PanicPolicy::Abort, Box::new(|_| {}))
+            .unwrap();
+        unplugged.inject_after(3, Fault::Disconnect);
+        stream.on_end(Box::new(move || sender.close()));
+        assert_eq!(receiver.recv(), Err::<(), _>(RecvError));
+
+        // Hooks added after the end run right away
+        let (sender, receiver) = channel::<()>(1, Overflow::Block);
+        stream.on_end(Box::new(move || sender.close()));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    /// Watches a stream of a closed handle, so the watcher returns before reaching `libuvc`
+    fn watch_closed(guard: &Arc<CallbackGuard>) -> (Arc<Watcher>, thread::JoinHandle<()>) {
+        let watcher = Arc::new(Watcher {
+            stopped: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+        let thread = {
+            let watcher = Arc::clone(&watcher);
+            let guard = Arc::clone(guard);
+            let device = WatchedDevice(NonNull::dangling());
+            thread::spawn(move || watcher.watch(device, &Mutex::new(false), &guard, None))
+        };
+        let notified = Arc::clone(&watcher);
+        guard.on_end(Box::new(move || notified.notify()));
+        (watcher, thread)
+    }
+
+    #[test]
+    fn watcher_waits_without_polling() {
+        let guard = Arc::new(CallbackGuard::new(PanicPolicy::StopStream));
+        let (watcher, thread) = watch_closed(&guard);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!thread.is_finished());
+        watcher.stop();
+        wait_for(|| thread.is_finished());
+
+        // Woken by the end of the guard
+        let guard = Arc::new(CallbackGuard::new(PanicPolicy::StopStream));
+        let (_watcher, thread) = watch_closed(&guard);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!thread.is_finished());
+        assert!(guard.call(|| panic!("stop")));
+        wait_for(|| thread.is_finished());
+    }
+
+    /// Set in the process started by `abort_ends_the_process`
+    const ABORT_CHILD: &str = "UVC_TEST_ABORT_CHILD";
+
+    #[test]
+    fn abort_ends_the_process() {
+        if std::env::var_os(ABORT_CHILD).is_some() {
+            let (camera, format) = camera();
+            let stream = camera
+                .start_stream(
+                    format,
+                    PanicPolicy::Abort,
+                    Box::new(|_| panic!("expected panic")),
+                )
+                .unwrap();
+            let _ = stream.join();
+            unreachable!("the process should have aborted");
+        }
+
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "streaming::tests::abort_ends_the_process",
+                "--nocapture",
+            ])
+            .env(ABORT_CHILD, "1")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            assert_eq!(output.status.signal(), Some(libc::SIGABRT));
         }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("expected panic"), "{stderr}");
     }
 }
@@ -0,0 +1,251 @@
+use crate::device::DeviceHandle;
+use crate::error::{Error, ErrorKind, Operation, Result, ResultExt};
+use crate::usb;
+
+use uvc_sys::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+/// How to treat strings which are not properly encoded
+pub enum StringDecoding {
+    /// Replace invalid sequences with `U+FFFD`
+    #[default]
+    Lossy,
+    /// Fail with `ErrorKind::InvalidString`
+    Strict,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// USB language identifier, as used by string descriptors
+pub struct LanguageId(pub u16);
+
+impl LanguageId {
+    pub const ENGLISH_US: LanguageId = LanguageId(0x0409);
+    pub const ENGLISH_UK: LanguageId = LanguageId(0x0809);
+    pub const GERMAN: LanguageId = LanguageId(0x0407);
+    pub const FRENCH: LanguageId = LanguageId(0x040c);
+    pub const JAPANESE: LanguageId = LanguageId(0x0411);
+    pub const CHINESE_SIMPLIFIED: LanguageId = LanguageId(0x0804);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Contents of a string descriptor
+///
+/// The raw UTF-16LE data is kept as sent by the device
+pub struct DescriptorString {
+    raw: Vec<u8>,
+}
+
+impl DescriptorString {
+    /// Data as sent by the device, UTF-16LE encoded
+    #[must_use]
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    fn code_units(&self) -> impl Iterator<Item = u16> + '_ {
+        self.raw
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+    }
+
+    /// Decodes the string, replacing invalid data with `U+FFFD`
+    #[must_use]
+    pub fn to_string_lossy(&self) -> String {
+        let mut s: String = char::decode_utf16(self.code_units())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        if !self.raw.len().is_multiple_of(2) {
+            s.push(char::REPLACEMENT_CHARACTER);
+        }
+        s
+    }
+
+    /// Decodes the string, failing on invalid data
+    pub fn to_string_strict(&self) -> Result<String> {
+        if !self.raw.len().is_multiple_of(2) {
+            return Err(ErrorKind::InvalidString.into());
+        }
+        char::decode_utf16(self.code_units())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| ErrorKind::InvalidString.into())
+    }
+
+    /// Decodes the string as requested
+    pub fn decode(&self, decoding: StringDecoding) -> Result<String> {
+        match decoding {
+            StringDecoding::Lossy => Ok(self.to_string_lossy()),
+            StringDecoding::Strict => self.to_string_strict(),
+        }
+    }
+}
+
+/// Decodes a string which is expected to be UTF-8
+pub(crate) fn decode_utf8(
+    bytes: Option<&[u8]>,
+    decoding: StringDecoding,
+) -> Result<Option<String>> {
+    let bytes = match bytes {
+        None => return Ok(None),
+        Some(bytes) => bytes,
+    };
+    match decoding {
+        StringDecoding::Lossy => Ok(Some(String::from_utf8_lossy(bytes).into_owned())),
+        StringDecoding::Strict => std::str::from_utf8(bytes)
+            .map(|s| Some(s.to_owned()))
+            .map_err(|_| ErrorKind::InvalidString.into()),
+    }
+}
+
+impl<'a> DeviceHandle<'a> {
+    /// Languages the string descriptors of the device are available in
+    pub fn languages(&self) -> Result<Vec<LanguageId>> {
+        let raw = unsafe {
+            usb::string_descriptor(uvc_get_libusb_handle(self.devh.as_ptr()), 0, 0)
+                .during(Operation::ReadString { index: 0 })?
+        };
+        Ok(raw
+            .chunks_exact(2)
+            .map(|id| LanguageId(u16::from_le_bytes([id[0], id[1]])))
+            .collect())
+    }
+
+    /// Reads the string descriptor with the given index in the chosen language
+    pub fn string_descriptor(&self, index: u8, language: LanguageId) -> Result<DescriptorString> {
+        if index == 0 {
+            // Index zero holds the supported languages
+            return Err(
+                Error::from(ErrorKind::InvalidParam).during(Operation::ReadString { index })
+            );
+        }
+        let raw = unsafe {
+            usb::string_descriptor(uvc_get_libusb_handle(self.devh.as_ptr()), index, language.0)
+                .during(Operation::ReadString { index })?
+        };
+        Ok(DescriptorString { raw })
+    }
+
+    fn device_string(
+        &self,
+        index: impl Fn(&libusb_device_descriptor) -> u8,
+        language: LanguageId,
+    ) -> Result<Option<DescriptorString>> {
+        let descriptor = unsafe {
+            usb::device_descriptor(uvc_get_libusb_handle(self.devh.as_ptr()))
+                .during(Operation::ReadDescriptor)?
+        };
+        match index(&descriptor) {
+            0 => Ok(None),
+            index => self.string_descriptor(index, language).map(Some),
+        }
+    }
+
+    /// Manufacturer string in the chosen language
+    pub fn manufacturer_string(&self, language: LanguageId) -> Result<Option<DescriptorString>> {
+        self.device_string(|desc| desc.iManufacturer, language)
+    }
+
+    /// Product string in the chosen language
+    pub fn product_string(&self, language: LanguageId) -> Result<Option<DescriptorString>> {
+        self.device_string(|desc| desc.iProduct, language)
+    }
+
+    /// Serial number string in the chosen language
+    pub fn serial_number_string(&self, language: LanguageId) -> Result<Option<DescriptorString>> {
+        self.device_string(|desc| desc.iSerialNumber, language)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb::parse_string_descriptor;
+    use proptest::prelude::*;
+
+    fn string(units: &[u16]) -> DescriptorString {
+        DescriptorString {
+            raw: units.iter().flat_map(|unit| unit.to_le_bytes()).collect(),
+        }
+    }
+
+    #[test]
+    fn valid_utf16() {
+        let s = string(&[0x0048, 0x00e9, 0xd83d, 0xde00]);
+        assert_eq!(s.to_string_strict().unwrap(), "Hé😀");
+        assert_eq!(s.to_string_lossy(), "Hé😀");
+    }
+
+    #[test]
+    fn unpaired_surrogates() {
+        for units in [&[0x0041, 0xd800][..], &[0xdc00, 0x0041], &[0xd800, 0xd800]] {
+            let s = string(units);
+            assert!(s.to_string_strict().is_err());
+            assert!(s.to_string_lossy().contains(char::REPLACEMENT_CHARACTER));
+        }
+    }
+
+    #[test]
+    fn odd_length() {
+        let s = DescriptorString {
+            raw: vec![0x41, 0x00, 0x42],
+        };
+        assert!(s.to_string_strict().is_err());
+        assert_eq!(s.to_string_lossy(), "A\u{fffd}");
+    }
+
+    #[test]
+    fn truncated_descriptors() {
+        assert!(parse_string_descriptor(&[]).is_err());
+        assert!(parse_string_descriptor(&[4]).is_err());
+        // Wrong descriptor type
+        assert!(parse_string_descriptor(&[4, 0x02, 0x41, 0x00]).is_err());
+        // Declares more than was transferred
+        assert_eq!(
+            parse_string_descriptor(&[8, 0x03, 0x41, 0x00]).unwrap(),
+            [0x41, 0x00]
+        );
+        // Declares less than was transferred, or less than its own header
+        assert_eq!(
+            parse_string_descriptor(&[4, 0x03, 0x41, 0x00, 0x42, 0x00]).unwrap(),
+            [0x41, 0x00]
+        );
+        assert!(parse_string_descriptor(&[0, 0x03, 0x41])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn strict_utf8() {
+        assert_eq!(
+            decode_utf8(Some(b"Cam"), StringDecoding::Strict).unwrap(),
+            Some("Cam".to_string())
+        );
+        assert!(decode_utf8(Some(b"C\xffm"), StringDecoding::Strict).is_err());
+        assert_eq!(
+            decode_utf8(Some(b"C\xffm"), StringDecoding::Lossy).unwrap(),
+            Some("C\u{fffd}m".to_string())
+        );
+        assert_eq!(decode_utf8(None, StringDecoding::Strict).unwrap(), None);
+    }
+
+    proptest! {
+        #[test]
+        fn decoding_never_panics(buf in proptest::collection::vec(any::<u8>(), 0..300)) {
+            if let Ok(raw) = parse_string_descriptor(&buf) {
+                prop_assert!(raw.len() <= buf.len().saturating_sub(2));
+                let s = DescriptorString { raw };
+                let lossy = s.to_string_lossy();
+                if let Ok(strict) = s.decode(StringDecoding::Strict) {
+                    prop_assert_eq!(strict, lossy);
+                }
+            }
+        }
+
+        #[test]
+        fn utf8_decoding_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..300)) {
+            let lossy = decode_utf8(Some(&bytes), StringDecoding::Lossy).unwrap();
+            if let Ok(strict) = decode_utf8(Some(&bytes), StringDecoding::Strict) {
+                prop_assert_eq!(strict, lossy);
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::os::raw::c_int;
+use std::ptr::NonNull;
+use std::slice;
+
+use crate::error::{Error, ErrorKind, Result};
+use uvc_sys::*;
+
+/// Maximum depth of a USB topology (USB 3.0 spec, section 4.8)
+const MAX_PORT_DEPTH: usize = 7;
+
+const USB_CLASS_VIDEO: u8 = 0x0e;
+const USB_SUBCLASS_VIDEOCONTROL: u8 = 0x01;
+const USB_SUBCLASS_VIDEOSTREAMING: u8 = 0x02;
+
+const USB_ENDPOINT_IN: u8 = 0x80;
+const USB_REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+const USB_DT_STRING: u8 = 0x03;
+const CONTROL_TIMEOUT_MS: u32 = 1000;
+/// `LIBUSB_ERROR_NO_DEVICE`
+const ERROR_NO_DEVICE: c_int = -4;
+
+/// Whether the device of an opened handle is still plugged in
+///
+/// `libusb` answers with `LIBUSB_ERROR_NO_DEVICE` once the device is gone.
+pub(crate) fn is_connected(devh: *mut libusb_device_handle) -> bool {
+    let mut config = 0;
+    unsafe { libusb_get_configuration(devh, &mut config) != ERROR_NO_DEVICE }
+}
+
+#[derive(Debug)]
+/// Reference counted `libusb` device, used for information `libuvc` does not expose
+pub(crate) struct UsbDevice {
+    dev: NonNull<libusb_device>,
+}
+
+impl Drop for UsbDevice {
+    fn drop(&mut self) {
+        unsafe { libusb_unref_device(self.dev.as_ptr()) }
+    }
+}
+
+impl UsbDevice {
+    /// Finds the device with the given bus number and address
+    pub(crate) fn find(ctx: NonNull<libusb_context>, bus: u8, address: u8) -> Option<Self> {
+        unsafe {
+            let mut list = std::mem::MaybeUninit::uninit();
+            let len = libusb_get_device_list(ctx.as_ptr(), list.as_mut_ptr());
+            if len < 0 {
+                return None;
+            }
+            let list = list.assume_init();
+
+            let device = slice::from_raw_parts(list, len as usize)
+                .iter()
+                .copied()
+                .find(|&dev| {
+                    libusb_get_bus_number(dev) == bus && libusb_get_device_address(dev) == address
+                })
+                .map(|dev| UsbDevice {
+                    dev: NonNull::new(libusb_ref_device(dev)).unwrap(),
+                });
+
+            libusb_free_device_list(list, 1);
+
+            device
+        }
+    }
+
+    /// Device an opened handle belongs to
+    pub(crate) fn from_handle(devh: *mut libusb_device_handle) -> Self {
+        unsafe {
+            UsbDevice {
+                dev: NonNull::new(libusb_ref_device(libusb_get_device(devh))).unwrap(),
+            }
+        }
+    }
+
+    /// Ports on the path from the root hub to the device
+    pub(crate) fn port_numbers(&self) -> Vec<u8> {
+        let mut ports = [0; MAX_PORT_DEPTH];
+        let len = unsafe {
+            libusb_get_port_numbers(self.dev.as_ptr(), ports.as_mut_ptr(), ports.len() as c_int)
+        };
+        if len < 0 {
+            return Vec::new();
+        }
+        ports[..len as usize].to_vec()
+    }
+
+    /// Negotiated speed, as `enum libusb_speed`
+    pub(crate) fn speed(&self) -> c_int {
+        unsafe { libusb_get_device_speed(self.dev.as_ptr()) }
+    }
+
+    /// The currently active configuration descriptor
+    pub(crate) fn active_config(&self) -> Option<ConfigDescriptor> {
+        unsafe {
+            let mut config = std::mem::MaybeUninit::uninit();
+            if libusb_get_active_config_descriptor(self.dev.as_ptr(), config.as_mut_ptr()) != 0 {
+                return None;
+            }
+            NonNull::new(config.assume_init()).map(|config| ConfigDescriptor { config })
+        }
+    }
+}
+
+/// Configuration descriptor, freed on drop
+pub(crate) struct ConfigDescriptor {
+    config: NonNull<libusb_config_descriptor>,
+}
+
+impl Drop for ConfigDescriptor {
+    fn drop(&mut self) {
+        unsafe { libusb_free_config_descriptor(self.config.as_ptr()) }
+    }
+}
+
+impl ConfigDescriptor {
+    /// All alternate settings of all interfaces in this configuration
+    pub(crate) fn interfaces(&self) -> impl Iterator<Item = &libusb_interface_descriptor> {
+        let interfaces = unsafe {
+            let config = self.config.as_ref();
+            if config.interface.is_null() {
+                &[]
+            } else {
+                slice::from_raw_parts(config.interface, usize::from(config.bNumInterfaces))
+            }
+        };
+        interfaces.iter().flat_map(|interface| unsafe {
+            if interface.altsetting.is_null() || interface.num_altsetting <= 0 {
+                &[]
+            } else {
+                slice::from_raw_parts(interface.altsetting, interface.num_altsetting as usize)
+            }
+        })
+    }
+
+    /// Number of the first video control interface
+    pub(crate) fn video_control_interface(&self) -> Option<u8> {
+        self.interfaces()
+            .find(|alt| {
+                alt.bInterfaceClass == USB_CLASS_VIDEO
+                    && alt.bInterfaceSubClass == USB_SUBCLASS_VIDEOCONTROL
+            })
+            .map(|alt| alt.bInterfaceNumber)
+    }
+
+    /// Class specific descriptors of every video streaming interface, with its number
+    pub(crate) fn video_streaming_extras(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        self.interfaces()
+            .filter(|alt| {
+                alt.bInterfaceClass == USB_CLASS_VIDEO
+                    && alt.bInterfaceSubClass == USB_SUBCLASS_VIDEOSTREAMING
+                    // Only the default setting holds the format descriptors
+                    && alt.bAlternateSetting == 0
+            })
+            .filter_map(|alt| {
+                if alt.extra.is_null() || alt.extra_length <= 0 {
+                    return None;
+                }
+                let extra = unsafe { slice::from_raw_parts(alt.extra, alt.extra_length as usize) };
+                Some((alt.bInterfaceNumber, extra))
+            })
+    }
+}
+
+/// Device descriptor of an opened device
+pub(crate) unsafe fn device_descriptor(
+    devh: *mut libusb_device_handle,
+) -> Result<libusb_device_descriptor> {
+    let mut desc = std::mem::MaybeUninit::uninit();
+    Error::check(libusb_get_device_descriptor(
+        libusb_get_device(devh),
+        desc.as_mut_ptr(),
+    ))?;
+    Ok(desc.assume_init())
+}
+
+/// Contents of a string descriptor, without the descriptor header
+pub(crate) unsafe fn string_descriptor(
+    devh: *mut libusb_device_handle,
+    index: u8,
+    language: u16,
+) -> Result<Vec<u8>> {
+    let mut buf = [0u8; 255];
+    let len = libusb_control_transfer(
+        devh,
+        USB_ENDPOINT_IN,
+        USB_REQUEST_GET_DESCRIPTOR,
+        (u16::from(USB_DT_STRING) << 8) | u16::from(index),
+        language,
+        buf.as_mut_ptr(),
+        buf.len() as u16,
+        CONTROL_TIMEOUT_MS,
+    );
+    Error::check(len)?;
+    parse_string_descriptor(&buf[..len as usize])
+}
+
+/// Contents of a transferred string descriptor, without the descriptor header
+pub(crate) fn parse_string_descriptor(buf: &[u8]) -> Result<Vec<u8>> {
+    if buf.len() < 2 || buf[1] != USB_DT_STRING {
+        return Err(ErrorKind::IO.into());
+    }
+    // Trust the shorter of the transferred and the declared length
+    let len = buf.len().min(usize::from(buf[0])).max(2);
+    Ok(buf[2..len].to_vec())
+}
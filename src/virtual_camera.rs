@@ -0,0 +1,991 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backend::{
+    Backend, BackendStream, Control, ControlRange, FormatDescription, FrameCallback,
+};
+use crate::device::UsbSpeed;
+use crate::error::{Error, ErrorKind, Operation, Result};
+use crate::formats::{FrameFormat, FrameInterval, StreamFormat};
+use crate::frame::{monotonic_now, FormatHints, Frame};
+use crate::stats::{StreamCounters, StreamStats};
+use crate::streaming::{CallbackGuard, PanicPolicy, StreamStatus};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Image a `VirtualCamera` sends
+pub enum TestPattern {
+    /// Eight vertical bars: white, yellow, cyan, green, magenta, red, blue and black
+    ColorBars,
+    /// Black and white squares with sides of the given length
+    Checkerboard(u32),
+    /// A single colour, as RGB
+    Solid([u8; 3]),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Failure a `VirtualCamera` can be told to simulate
+pub enum Fault {
+    /// The camera is unplugged, streams end and every request fails with `ErrorKind::NoDevice`
+    Disconnect,
+    /// The next control request or stream start fails with `ErrorKind::Timeout`
+    Timeout,
+    /// The next frame is delivered truncated to half its size, with scrambled data
+    CorruptFrame,
+    /// The next frame is lost, leaving a gap in the sequence numbers
+    DropFrame,
+}
+
+#[derive(Debug)]
+struct State {
+    connected: bool,
+    streaming: bool,
+    /// Frames generated so far, the sequence number of the next frame
+    frames: u64,
+    timeouts: u32,
+    corrupt_frames: u32,
+    dropped_frames: u32,
+    /// Faults waiting for a frame number
+    scheduled: Vec<(u64, Fault)>,
+    controls: Vec<(Control, ControlRange, i64)>,
+}
+
+impl State {
+    fn apply(&mut self, fault: Fault) {
+        match fault {
+            Fault::Disconnect => self.connected = false,
+            Fault::Timeout => self.timeouts += 1,
+            Fault::CorruptFrame => self.corrupt_frames += 1,
+            Fault::DropFrame => self.dropped_frames += 1,
+        }
+    }
+
+    /// Fails requests on a disconnected camera, and consumes an injected timeout
+    fn request(&mut self, operation: Operation) -> Result<()> {
+        if !self.connected {
+            return Err(Error::from(ErrorKind::NoDevice).during(operation));
+        }
+        if self.timeouts > 0 {
+            self.timeouts -= 1;
+            return Err(Error::from(ErrorKind::Timeout).during(operation));
+        }
+        Ok(())
+    }
+
+    fn control(&mut self, control: Control) -> Result<&mut (Control, ControlRange, i64)> {
+        self.controls
+            .iter_mut()
+            .find(|(known, ..)| *known == control)
+            .ok_or_else(|| {
+                Error::from(ErrorKind::NotSupported).during(Operation::GetControl {
+                    name: control.name(),
+                })
+            })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Camera implemented in software, for testing without hardware
+///
+/// Clones share the same state, so one clone can inject faults
+/// while another is streaming.
+///
+/// ```
+/// use uvc::{Backend, Control, Fault, VirtualCamera};
+///
+/// let camera = VirtualCamera::webcam();
+/// camera.set_control(Control::Brightness, 10).unwrap();
+/// assert_eq!(camera.control(Control::Brightness).unwrap(), 10);
+///
+/// camera.inject(Fault::Disconnect);
+/// assert!(camera.control(Control::Brightness).is_err());
+/// ```
+pub struct VirtualCamera {
+    descriptors: Vec<FormatDescription>,
+    speed: UsbSpeed,
+    pattern: TestPattern,
+    jitter: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for VirtualCamera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualCamera {
+    /// Camera without formats or controls
+    #[must_use]
+    pub fn new() -> Self {
+        VirtualCamera {
+            descriptors: Vec::new(),
+            speed: UsbSpeed::Unknown,
+            pattern: TestPattern::ColorBars,
+            jitter: Duration::ZERO,
+            state: Arc::new(Mutex::new(State {
+                connected: true,
+                streaming: false,
+                frames: 0,
+                timeouts: 0,
+                corrupt_frames: 0,
+                dropped_frames: 0,
+                scheduled: Vec::new(),
+                controls: Vec::new(),
+            })),
+        }
+    }
+
+    /// Camera with the formats and controls of a typical webcam
+    #[must_use]
+    pub fn webcam() -> Self {
+        let format = |width, height, fps, format| StreamFormat {
+            width,
+            height,
+            interval: FrameInterval::from_fps(fps),
+            format,
+        };
+        let range = |min, max, default| ControlRange {
+            min,
+            max,
+            step: 1,
+            default,
+        };
+        VirtualCamera::new()
+            .format(format(640, 480, 30, FrameFormat::YUYV))
+            .format(format(640, 480, 15, FrameFormat::YUYV))
+            .format(format(1280, 720, 30, FrameFormat::MJPEG))
+            .format(format(640, 480, 30, FrameFormat::MJPEG))
+            .format(format(640, 480, 30, FrameFormat::SGRBG8))
+            .supports(Control::ScanningMode, range(0, 1, 1))
+            .supports(Control::AePriority, range(0, 1, 0))
+            .supports(Control::ExposureAbs, range(3, 2047, 250))
+            .supports(Control::FocusAbs, range(0, 250, 0))
+            .supports(Control::Brightness, range(-64, 64, 0))
+            .supports(Control::Contrast, range(0, 95, 32))
+            .supports(Control::Gain, range(0, 100, 0))
+    }
+
+    /// Offers `format`, in addition to the formats given before
+    ///
+    /// The format is added to the descriptor of its frame format, as a discrete
+    /// interval of the frame of its size.
+    #[must_use]
+    pub fn format(mut self, format: StreamFormat) -> Self {
+        let known = self
+            .descriptors
+            .iter()
+            .position(|desc| desc.frame_format() == format.format);
+        let index = match known {
+            Some(index) => index,
+            None => {
+                self = self.descriptor(FormatDescription::new(format.format));
+                self.descriptors.len() - 1
+            }
+        };
+        self.descriptors[index].add_stream_format(&format);
+        self
+    }
+
+    /// Offers the formats of `descriptor`, numbered after the descriptors given before
+    #[must_use]
+    pub fn descriptor(mut self, mut descriptor: FormatDescription) -> Self {
+        descriptor.set_format_index(self.descriptors.len() as u8 + 1);
+        self.descriptors.push(descriptor);
+        self
+    }
+
+    /// Reports this connection speed, the default is `UsbSpeed::Unknown`
+    #[must_use]
+    pub fn connected_at(mut self, speed: UsbSpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Supports `control`, starting at the default of its range
+    #[must_use]
+    pub fn supports(self, control: Control, range: ControlRange) -> Self {
+        {
+            let mut state = self.lock();
+            state.controls.retain(|(known, ..)| *known != control);
+            state.controls.push((control, range, range.default));
+        }
+        self
+    }
+
+    /// Sends `pattern`, the default is `TestPattern::ColorBars`
+    #[must_use]
+    pub fn pattern(mut self, pattern: TestPattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    /// Delays every frame by a random time of up to `jitter` after it was captured
+    ///
+    /// Frames are stamped with their capture time before the delay, as `libuvc` does
+    /// on platforms with a monotonic clock.
+    #[must_use]
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Simulates a failure now
+    pub fn inject(&self, fault: Fault) {
+        self.lock().apply(fault);
+    }
+
+    /// Simulates a failure once `frames` more frames have been generated
+    pub fn inject_after(&self, frames: u64, fault: Fault) {
+        let mut state = self.lock();
+        let at = state.frames + frames;
+        state.scheduled.push((at, fault));
+    }
+
+    /// Plugs a disconnected camera back in
+    pub fn reconnect(&self) {
+        self.lock().connected = true;
+    }
+
+    /// Whether the camera is plugged in
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        self.lock().connected
+    }
+
+    /// Whether a stream is running
+    #[must_use]
+    pub fn is_streaming(&self) -> bool {
+        self.lock().streaming
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// Dropping a `VirtualStream` joins the thread owning the callback
+unsafe impl Backend for VirtualCamera {
+    fn descriptors(&self) -> Result<Vec<FormatDescription>> {
+        self.lock().request(Operation::ReadDescriptor)?;
+        Ok(self.descriptors.clone())
+    }
+
+    fn speed(&self) -> UsbSpeed {
+        self.speed
+    }
+
+    fn control(&self, control: Control) -> Result<i64> {
+        let mut state = self.lock();
+        state.request(Operation::GetControl {
+            name: control.name(),
+        })?;
+        Ok(state.control(control)?.2)
+    }
+
+    fn control_range(&self, control: Control) -> Result<ControlRange> {
+        let mut state = self.lock();
+        state.request(Operation::GetControl {
+            name: control.name(),
+        })?;
+        Ok(state.control(control)?.1)
+    }
+
+    fn set_control(&self, control: Control, value: i64) -> Result<()> {
+        let operation = Operation::SetControl {
+            name: control.name(),
+            value,
+        };
+        let mut state = self.lock();
+        state.request(operation.clone())?;
+        let (_, range, current) = state
+            .control(control)
+            .map_err(|err| Error::from(err.kind()).during(operation.clone()))?;
+        if !range.contains(value) {
+            return Err(Error::from(ErrorKind::InvalidParam).during(operation));
+        }
+        *current = value;
+        Ok(())
+    }
+
+    fn start_stream(
+        &self,
+        format: StreamFormat,
+        policy: PanicPolicy,
+        mut cb: FrameCallback,
+    ) -> Result<Box<dyn BackendStream + '_>> {
+        let mut state = self.lock();
+        state.request(Operation::StartStream)?;
+        let desc = self
+            .descriptors
+            .iter()
+            .find(|desc| desc.offers(&format))
+            .ok_or_else(|| {
+                Error::from(ErrorKind::InvalidMode).during(Operation::NegotiateFormat { format })
+            })?;
+        if state.streaming {
+            return Err(Error::from(ErrorKind::Busy).during(Operation::StartStream));
+        }
+        let rgb = render(self.pattern, format.width, format.height);
+        let data = encode(&rgb, format.width, format.height, format.format)
+            .ok_or_else(|| Error::from(ErrorKind::NotSupported).during(Operation::StartStream))?;
+        state.streaming = true;
+        drop(state);
+
+        let hints = FormatHints {
+            format: format.format,
+            color_matching: desc.color_matching(),
+            interlace: desc.interlace(),
+        };
+        let shared = Arc::new(Shared {
+            stop: AtomicBool::new(false),
+            stats: StreamCounters::new(),
+            guard: CallbackGuard::new(policy),
+        });
+        let thread = {
+            let camera = self.clone();
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                camera.generate(format, &data, hints, &mut cb, &shared);
+                camera.lock().streaming = false;
+                shared.guard.finish();
+            })
+        };
+        Ok(Box::new(VirtualStream {
+            shared,
+            thread: Some(thread),
+        }))
+    }
+}
+
+impl VirtualCamera {
+    /// Delivers frames at the interval of `format` until stopped or disconnected
+    fn generate(
+        &self,
+        format: StreamFormat,
+        data: &[u8],
+        hints: FormatHints,
+        cb: &mut FrameCallback,
+        shared: &Shared,
+    ) {
+        let mut due = Instant::now();
+        let mut random = Random::new();
+        while !shared.stop.load(Ordering::Relaxed) && !shared.guard.has_ended() {
+            let (sequence, dropped, corrupt) = {
+                let mut state = self.lock();
+                let frames = state.frames;
+                let mut due_faults = Vec::new();
+                state.scheduled.retain(|&(at, fault)| {
+                    if at <= frames {
+                        due_faults.push(fault);
+                    }
+                    at > frames
+                });
+                for fault in due_faults {
+                    state.apply(fault);
+                }
+                if !state.connected {
+                    break;
+                }
+                state.frames += 1;
+                let dropped = state.dropped_frames > 0;
+                state.dropped_frames = state.dropped_frames.saturating_sub(1);
+                let corrupt = !dropped && state.corrupt_frames > 0;
+                if corrupt {
+                    state.corrupt_frames -= 1;
+                }
+                (frames, dropped, corrupt)
+            };
+
+            if !dropped {
+                let mut frame = if corrupt {
+                    let scrambled: Vec<u8> =
+                        data[..data.len() / 2].iter().map(|b| b ^ 0x5a).collect();
+                    Frame::from_bytes(&scrambled, format.width, format.height, format.format, None)
+                } else {
+                    Frame::from_bytes(data, format.width, format.height, format.format, None)
+                }
+                .with_hints(Some(hints));
+                frame.raw_mut().sequence = sequence as u32;
+                if let Some(now) = monotonic_now() {
+                    let finished = &mut frame.raw_mut().capture_time_finished;
+                    finished.tv_sec = now.as_secs() as _;
+                    finished.tv_nsec = now.subsec_nanos() as _;
+                }
+                if !self.jitter.is_zero() {
+                    thread::sleep(self.jitter.mul_f64(random.unit()));
+                }
+
+                shared.stats.record_frame(&frame);
+                let start = Instant::now();
+                if shared.guard.call(|| cb(&frame)) {
+                    shared.stats.record_panic();
+                }
+                shared.stats.record_callback(start.elapsed());
+            }
+
+            due += format.interval.as_duration();
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+        }
+    }
+}
+
+/// Xorshift generator for the jitter of frames, seeded differently for every stream
+struct Random(u64);
+
+impl Random {
+    fn new() -> Self {
+        use std::hash::{BuildHasher, Hasher};
+        let seed = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        Random(seed | 1)
+    }
+
+    /// Uniform in `[0, 1)`
+    fn unit(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug)]
+/// Stream of a `VirtualCamera`
+///
+/// Dropping this stream will stop the stream
+pub struct VirtualStream {
+    shared: Arc<Shared>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+/// State of a stream shared with the thread generating its frames
+struct Shared {
+    stop: AtomicBool,
+    stats: StreamCounters,
+    guard: CallbackGuard,
+}
+
+impl BackendStream for VirtualStream {
+    fn stats(&self) -> StreamStats {
+        self.shared.stats.snapshot()
+    }
+
+    fn status(&self) -> StreamStatus {
+        self.shared.guard.status()
+    }
+
+    /// Blocks until the callback panicked or the camera was disconnected
+    fn join(self: Box<Self>) -> std::thread::Result<()> {
+        self.shared.guard.wait()
+    }
+
+    fn on_end(&self, hook: Box<dyn FnOnce() + Send>) {
+        self.shared.guard.on_end(hook);
+    }
+}
+
+impl Drop for VirtualStream {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Renders `pattern` as packed RGB
+fn render(pattern: TestPattern, width: u32, height: u32) -> Vec<u8> {
+    const BARS: [[u8; 3]; 8] = [
+        [255, 255, 255],
+        [255, 255, 0],
+        [0, 255, 255],
+        [0, 255, 0],
+        [255, 0, 255],
+        [255, 0, 0],
+        [0, 0, 255],
+        [0, 0, 0],
+    ];
+    let mut rgb = Vec::with_capacity(width as usize * height as usize * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = match pattern {
+                TestPattern::ColorBars => BARS[(x * 8 / width) as usize],
+                TestPattern::Checkerboard(size) => {
+                    let size = size.max(1);
+                    if (x / size + y / size) % 2 == 0 {
+                        [255; 3]
+                    } else {
+                        [0; 3]
+                    }
+                }
+                TestPattern::Solid(color) => color,
+            };
+            rgb.extend_from_slice(&pixel);
+        }
+    }
+    rgb
+}
+
+/// Full range BT.601, the inverse of the conversion in `Frame::to_rgb`
+fn rgb_to_ycbcr([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128.0 + (b - y) / 1.772;
+    let cr = 128.0 + (r - y) / 1.402;
+    [y, cb, cr]
+}
+
+fn pixel(rgb: &[u8], i: usize) -> [f32; 3] {
+    let p = &rgb[3 * i..3 * i + 3];
+    [f32::from(p[0]), f32::from(p[1]), f32::from(p[2])]
+}
+
+/// Encodes packed RGB in `format`, `None` for formats which cannot be generated
+fn encode(rgb: &[u8], width: u32, height: u32, format: FrameFormat) -> Option<Vec<u8>> {
+    let (w, h) = (width as usize, height as usize);
+    let to_u8 = |x: f32| x.round().clamp(0.0, 255.0) as u8;
+    let data = match format {
+        FrameFormat::RGB => rgb.to_vec(),
+        FrameFormat::BGR => rgb
+            .chunks_exact(3)
+            .flat_map(|p| [p[2], p[1], p[0]])
+            .collect(),
+        FrameFormat::GRAY8 => (0..w * h)
+            .map(|i| to_u8(rgb_to_ycbcr(pixel(rgb, i))[0]))
+            .collect(),
+        FrameFormat::GRAY16 => (0..w * h)
+            .flat_map(|i| (u16::from(to_u8(rgb_to_ycbcr(pixel(rgb, i))[0])) << 8).to_le_bytes())
+            .collect(),
+        FrameFormat::YUYV | FrameFormat::UYVY => {
+            let mut data = Vec::with_capacity(w * h * 2);
+            for i in (0..w * h).step_by(2) {
+                let [y0, cb0, cr0] = rgb_to_ycbcr(pixel(rgb, i));
+                let [y1, cb1, cr1] = rgb_to_ycbcr(pixel(rgb, (i + 1).min(w * h - 1)));
+                let (cb, cr) = (to_u8((cb0 + cb1) / 2.0), to_u8((cr0 + cr1) / 2.0));
+                if format == FrameFormat::YUYV {
+                    data.extend_from_slice(&[to_u8(y0), cb, to_u8(y1), cr]);
+                } else {
+                    data.extend_from_slice(&[cb, to_u8(y0), cr, to_u8(y1)]);
+                }
+            }
+            data
+        }
+        FrameFormat::SGRBG8
+        | FrameFormat::SGBRG8
+        | FrameFormat::SRGGB8
+        | FrameFormat::SBGGR8
+        | FrameFormat::BA81 => {
+            // Channel of the top left 2x2 cell, in reading order
+            let cell: [usize; 4] = match format {
+                FrameFormat::SGRBG8 => [1, 0, 2, 1],
+                FrameFormat::SGBRG8 => [1, 2, 0, 1],
+                FrameFormat::SRGGB8 => [0, 1, 1, 2],
+                _ => [2, 1, 1, 0],
+            };
+            (0..w * h)
+                .map(|i| rgb[3 * i + cell[(i / w % 2) * 2 + i % w % 2]])
+                .collect()
+        }
+        FrameFormat::MJPEG => jpeg::encode(rgb, w, h),
+        _ => return None,
+    };
+    Some(data)
+}
+
+/// Baseline JPEG encoder keeping only the average of each block
+///
+/// Enough for test patterns, the output is blocky but decodes with any decoder.
+/// Like MJPEG cameras, it subsamples chrominance to 4:2:2 and uses the
+/// Huffman tables of the JPEG specification.
+mod jpeg {
+    use crate::mjpeg::{self, DC_CHROMINANCE_BITS, DC_LUMINANCE_BITS};
+
+    /// End of block in the AC tables of the specification
+    const EOB_LUMINANCE: (u16, u8) = (0b1010, 4);
+    const EOB_CHROMINANCE: (u16, u8) = (0b00, 2);
+
+    struct BitWriter {
+        out: Vec<u8>,
+        acc: u32,
+        len: u8,
+    }
+
+    impl BitWriter {
+        fn put(&mut self, (bits, len): (u16, u8)) {
+            for i in (0..len).rev() {
+                self.acc = (self.acc << 1) | u32::from((bits >> i) & 1);
+                self.len += 1;
+                if self.len == 8 {
+                    let byte = self.acc as u8;
+                    self.out.push(byte);
+                    if byte == 0xff {
+                        self.out.push(0);
+                    }
+                    self.acc = 0;
+                    self.len = 0;
+                }
+            }
+        }
+
+        /// Codes a block holding only a DC coefficient
+        fn block(&mut self, diff: i32, dc_codes: &[(u16, u8)], eob: (u16, u8)) {
+            let size = (32 - diff.unsigned_abs().leading_zeros()) as u8;
+            self.put(dc_codes[usize::from(size)]);
+            if size > 0 {
+                let value = if diff < 0 { diff - 1 } else { diff };
+                self.put(((value as u16) & ((1 << size) - 1), size));
+            }
+            self.put(eob);
+        }
+
+        /// Pads the last byte with ones
+        fn finish(mut self) -> Vec<u8> {
+            if self.len > 0 {
+                self.put((0xff, 8 - self.len));
+            }
+            self.out
+        }
+    }
+
+    fn segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+        out.extend_from_slice(&[0xff, marker]);
+        out.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(payload);
+    }
+
+    /// Average colour of the pixels in the given rectangle, as YCbCr
+    fn average(rgb: &[u8], width: usize, height: usize, x: usize, y: usize, w: usize) -> [f32; 3] {
+        let mut sum = [0.0f32; 3];
+        let mut count = 0.0;
+        for y in y..(y + 8).min(height) {
+            for x in x..(x + w).min(width) {
+                let p = super::pixel(rgb, y * width + x);
+                for c in 0..3 {
+                    sum[c] += p[c];
+                }
+                count += 1.0;
+            }
+        }
+        if count == 0.0 {
+            return [0.0, 128.0, 128.0];
+        }
+        super::rgb_to_ycbcr(sum.map(|s| s / count))
+    }
+
+    pub(super) fn encode(rgb: &[u8], width: usize, height: usize) -> Vec<u8> {
+        let mut out = vec![0xff, 0xd8];
+        segment(&mut out, 0xe0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        // One quantisation table of ones, DC coefficients are kept exactly
+        let mut dqt = vec![0];
+        dqt.extend_from_slice(&[1; 64]);
+        segment(&mut out, mjpeg::DQT, &dqt);
+        let mut sof = vec![8];
+        sof.extend_from_slice(&(height as u16).to_be_bytes());
+        sof.extend_from_slice(&(width as u16).to_be_bytes());
+        sof.extend_from_slice(&[3, 1, 0x21, 0, 2, 0x11, 0, 3, 0x11, 0]);
+        segment(&mut out, mjpeg::SOF0, &sof);
+        out.extend_from_slice(&mjpeg::default_tables());
+        segment(
+            &mut out,
+            mjpeg::SOS,
+            &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0],
+        );
+
+        let luminance = mjpeg::huffman_codes(&DC_LUMINANCE_BITS);
+        let chrominance = mjpeg::huffman_codes(&DC_CHROMINANCE_BITS);
+        let mut bits = BitWriter {
+            out,
+            acc: 0,
+            len: 0,
+        };
+        let mut predictions = [0i32; 3];
+        let mut code = |bits: &mut BitWriter, c: usize, value: f32| {
+            let dc = ((value - 128.0) * 8.0).round() as i32;
+            let diff = dc - predictions[c];
+            predictions[c] = dc;
+            match c {
+                0 => bits.block(diff, &luminance, EOB_LUMINANCE),
+                _ => bits.block(diff, &chrominance, EOB_CHROMINANCE),
+            }
+        };
+        // Minimum coded units of two luminance blocks side by side, and one block of each chrominance
+        for y in (0..height).step_by(8) {
+            for x in (0..width).step_by(16) {
+                for bx in [x, x + 8] {
+                    code(&mut bits, 0, average(rgb, width, height, bx, y, 8)[0]);
+                }
+                let colour = average(rgb, width, height, x, y, 16);
+                code(&mut bits, 1, colour[1]);
+                code(&mut bits, 2, colour[2]);
+            }
+        }
+        let mut out = bits.finish();
+        out.extend_from_slice(&[0xff, 0xd9]);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::backend::FrameDescription;
+    use crate::color::{
+        ColorMatching, ColorPrimaries, MatrixCoefficients, TransferCharacteristics,
+    };
+    use crate::device::FrameIntervals;
+    use crate::formats::Guid;
+    use crate::interlace::Interlace;
+
+    fn format(format: FrameFormat, width: u32, height: u32, fps: u32) -> StreamFormat {
+        StreamFormat {
+            width,
+            height,
+            interval: FrameInterval::from_fps(fps),
+            format,
+        }
+    }
+
+    /// First frame of a stream of `format`
+    fn first_frame(camera: &VirtualCamera, format: StreamFormat) -> Frame {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let stream = camera
+            .start_stream(
+                format,
+                PanicPolicy::default(),
+                Box::new(move |frame| {
+                    let _ = sender.lock().unwrap().send(frame.duplicate().unwrap());
+                }),
+            )
+            .unwrap();
+        let frame = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        drop(stream);
+        frame
+    }
+
+    #[test]
+    fn formats_are_grouped_into_descriptors() {
+        let descriptors = VirtualCamera::webcam().descriptors().unwrap();
+        let summary: Vec<_> = descriptors
+            .iter()
+            .map(|desc| {
+                (
+                    desc.format_index(),
+                    desc.frame_format(),
+                    desc.frames().len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, FrameFormat::YUYV, 1),
+                (2, FrameFormat::MJPEG, 2),
+                (3, FrameFormat::SGRBG8, 1),
+            ]
+        );
+
+        let yuyv = &descriptors[0];
+        assert_eq!(yuyv.guid().and_then(|guid| guid.fourcc()), Some(*b"YUY2"));
+        let frame = &yuyv.frames()[0];
+        assert_eq!((frame.width(), frame.height()), (640, 480));
+        assert_eq!(frame.default_interval(), FrameInterval::from_fps(30));
+        assert_eq!(
+            frame.frame_intervals(),
+            FrameIntervals::Discrete(&[333_333, 666_667])
+        );
+
+        let mjpeg = &descriptors[1];
+        assert_eq!(mjpeg.guid(), None);
+        assert_eq!(mjpeg.frames()[1].frame_index(), 2);
+        assert_eq!(descriptors[2].guid(), FrameFormat::SGRBG8.guid());
+
+        let formats = VirtualCamera::webcam().formats().unwrap();
+        assert_eq!(formats.len(), 5);
+        assert_eq!(formats[1], format(FrameFormat::YUYV, 640, 480, 15));
+    }
+
+    #[test]
+    fn unknown_guids_and_continuous_intervals() {
+        let guid = Guid::from_fourcc(*b"XYZW");
+        let camera = VirtualCamera::new()
+            .format(format(FrameFormat::MJPEG, 320, 240, 30))
+            .descriptor(
+                FormatDescription::new(FrameFormat::GRAY8)
+                    .with_frame(FrameDescription::continuous(
+                        64,
+                        48,
+                        FrameInterval::from_fps(60),
+                        FrameInterval::from_fps(5),
+                        0,
+                    ))
+                    .with_frame(FrameDescription::discrete(32, 24, [])),
+            )
+            .descriptor(FormatDescription::new(FrameFormat::YUYV).with_guid(guid));
+        let descriptors = camera.descriptors().unwrap();
+        assert_eq!(descriptors[1].format_index(), 2);
+        assert_eq!(descriptors[2].guid(), Some(guid));
+        assert_eq!(descriptors[2].frame_format(), FrameFormat::Uncompressed);
+
+        let formats = camera.formats().unwrap();
+        assert_eq!(
+            formats[1..],
+            [
+                format(FrameFormat::GRAY8, 64, 48, 60),
+                format(FrameFormat::GRAY8, 64, 48, 5),
+            ]
+        );
+
+        // Any interval of the continuous range can be streamed
+        let frame = first_frame(&camera, format(FrameFormat::GRAY8, 64, 48, 24));
+        assert_eq!(frame.to_bytes().len(), 64 * 48);
+        let err = camera
+            .start_stream(
+                format(FrameFormat::GRAY8, 64, 48, 90),
+                PanicPolicy::default(),
+                Box::new(|_| {}),
+            )
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidMode);
+    }
+
+    #[test]
+    fn frames_carry_the_descriptor_colour_space() {
+        let bt709 = ColorMatching {
+            primaries: ColorPrimaries::Bt709,
+            transfer: TransferCharacteristics::Bt709,
+            matrix: MatrixCoefficients::Bt709,
+        };
+        let interlace = Interlace::from_flags(0b0000_0001);
+        let camera = VirtualCamera::new()
+            .descriptor(
+                FormatDescription::new(FrameFormat::YUYV)
+                    .with_color_matching(bt709)
+                    .with_interlace(interlace)
+                    .with_frame(FrameDescription::discrete(
+                        16,
+                        8,
+                        [FrameInterval::from_fps(100)],
+                    )),
+            )
+            .pattern(TestPattern::Solid([255, 255, 255]));
+        let frame = first_frame(&camera, format(FrameFormat::YUYV, 16, 8, 100));
+        assert_eq!(frame.format(), FrameFormat::YUYV);
+        assert_eq!(frame.color_matching(), bt709);
+        assert_eq!(frame.interlace(), interlace);
+        assert_eq!(frame.to_bytes()[..4], [255, 128, 255, 128]);
+        assert!(!camera.is_streaming());
+    }
+
+    #[test]
+    fn controls() {
+        let camera = VirtualCamera::webcam();
+        let range = camera.control_range(Control::Brightness).unwrap();
+        assert_eq!((range.min, range.max, range.default), (-64, 64, 0));
+        camera.set_control(Control::Brightness, -64).unwrap();
+        assert_eq!(camera.control(Control::Brightness).unwrap(), -64);
+
+        let err = camera.set_control(Control::Brightness, 65).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidParam);
+        let err = camera.control(Control::AeMode).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotSupported);
+        let err = camera.set_control(Control::AeMode, 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotSupported);
+    }
+
+    #[test]
+    fn faults() {
+        let camera = VirtualCamera::webcam();
+        camera.inject(Fault::Timeout);
+        let err = camera.descriptors().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+        assert!(camera.descriptors().is_ok());
+
+        camera.inject(Fault::Disconnect);
+        assert!(!camera.is_connected());
+        let err = camera.control(Control::Gain).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NoDevice);
+        camera.reconnect();
+        assert_eq!(camera.control(Control::Gain).unwrap(), 0);
+    }
+
+    #[test]
+    fn corrupt_and_dropped_frames() {
+        let camera = VirtualCamera::new().format(format(FrameFormat::GRAY8, 8, 8, 200));
+        camera.inject_after(1, Fault::DropFrame);
+        camera.inject_after(2, Fault::CorruptFrame);
+        camera.inject_after(4, Fault::Disconnect);
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let stream = camera
+            .start_stream(
+                format(FrameFormat::GRAY8, 8, 8, 200),
+                PanicPolicy::default(),
+                Box::new(move |frame| {
+                    let _ = sender
+                        .lock()
+                        .unwrap()
+                        .send((frame.sequence(), frame.to_bytes().len()));
+                }),
+            )
+            .unwrap();
+        let frames: Vec<_> = receiver.iter().collect();
+        assert_eq!(frames, [(0, 64), (2, 32), (3, 64)]);
+        assert!(stream.join().is_ok());
+        assert_eq!(stream_start_after_disconnect(&camera), ErrorKind::NoDevice);
+    }
+
+    fn stream_start_after_disconnect(camera: &VirtualCamera) -> ErrorKind {
+        camera
+            .start_stream(
+                format(FrameFormat::GRAY8, 8, 8, 200),
+                PanicPolicy::default(),
+                Box::new(|_| {}),
+            )
+            .err()
+            .unwrap()
+            .kind()
+    }
+
+    #[test]
+    fn test_patterns() {
+        let bars = render(TestPattern::ColorBars, 8, 1);
+        assert_eq!(bars[..6], [255, 255, 255, 255, 255, 0]);
+        assert_eq!(bars[21..], [0, 0, 0]);
+        let board = render(TestPattern::Checkerboard(2), 4, 4);
+        let luma: Vec<u8> = board.chunks(3).map(|p| p[0]).collect();
+        assert_eq!(luma[..8], [255, 255, 0, 0, 255, 255, 0, 0]);
+        assert_eq!(luma[8..12], [0, 0, 255, 255]);
+
+        // Red, green, green, blue
+        let rgb = render(TestPattern::Solid([10, 20, 30]), 2, 2);
+        let bayer = encode(&rgb, 2, 2, FrameFormat::SRGGB8).unwrap();
+        assert_eq!(bayer, [10, 20, 20, 30]);
+        let bayer = encode(&rgb, 2, 2, FrameFormat::SGRBG8).unwrap();
+        assert_eq!(bayer, [20, 10, 30, 20]);
+        let bgr = encode(&rgb, 2, 2, FrameFormat::BGR).unwrap();
+        assert_eq!(bgr[..3], [30, 20, 10]);
+        assert_eq!(encode(&rgb, 2, 2, FrameFormat::H264), None);
+
+        let jpeg = encode(
+            &render(TestPattern::ColorBars, 32, 16),
+            32,
+            16,
+            FrameFormat::MJPEG,
+        )
+        .unwrap();
+        assert_eq!(jpeg[..2], [0xff, 0xd8]);
+        assert_eq!(jpeg[jpeg.len() - 2..], [0xff, 0xd9]);
+    }
+}
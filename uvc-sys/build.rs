@@ -9,6 +9,8 @@ fn main() {
         includedir = Some(std::env::var("DEP_UVCSRC_INCLUDE").unwrap());
     } else {
         println!("cargo:rustc-link-lib=uvc");
+        // libusb is called directly for topology and raw descriptor access
+        println!("cargo:rustc-link-lib=usb-1.0");
         if cfg!(target_os = "freebsd") {
             includedir = Some("/usr/local/include".to_owned());
         }
@@ -24,6 +26,8 @@ fn main() {
         .header("wrapper.h")
         .allowlist_function("uvc_.*")
         .allowlist_type("uvc_.*")
+        .allowlist_function("libusb_.*")
+        .allowlist_type("libusb_.*")
         .generate()
         .expect("Failed to generate bindings");
 